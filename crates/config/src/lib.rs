@@ -0,0 +1,820 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use indexmap::IndexMap;
+
+#[cfg(test)]
+mod tests;
+
+/// Environment variables starting with this override config file values,
+/// like `GODSVAGN_SERVER_BIND` for `bind` in `[server]`
+pub const ENV_PREFIX: &str = "GODSVAGN_";
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Config {
+    /// only required by godsvagn-server
+    pub server: Option<ServerConfig>,
+    pub release: ReleaseConfig,
+    pub metalink: Option<MetalinkConfig>,
+    /// extra places repogen copies the generated repository to, as `[[publish]]` tables
+    #[serde(default)]
+    pub publish: Vec<PublishDestination>,
+    /// publish several suites under `dists/` as `[[suites]]` tables, instead of the single
+    /// suite in `[release]`. They share its origin, label and options.
+    #[serde(default)]
+    pub suites: Vec<SuiteConfig>,
+    #[serde(default)]
+    pub compression_levels: CompressionLevelsConfig,
+    /// publish `Packages.diff/` patches, so clients on slow links only download what changed
+    pub pdiff: Option<PdiffConfig>,
+    /// publish a package installing the signing keys, so users get new keys by upgrading it
+    pub keyring_package: Option<KeyringPackageConfig>,
+    /// publish a dated snapshot of each suite once a day, for clients to pin to
+    pub snapshots: Option<SnapshotsConfig>,
+    /// environment variables starting with [`ENV_PREFIX`] that don't override anything, which
+    /// are ignored so unrelated ones can't stop anything from starting, but worth a warning
+    #[serde(skip)]
+    pub unknown_overrides: Vec<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct SnapshotsConfig {
+    /// where repogen keeps every snapshot it published and the pool files they list, to put
+    /// back into each repository it generates after that. Hard links are used when it's on the
+    /// same filesystem as the output, so nothing is copied.
+    pub directory: PathBuf,
+    /// advertised in Release for apt's `--snapshot`, with `@SNAPSHOTID@` in place of the date
+    pub url: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct KeyringPackageConfig {
+    /// defaults to `<origin>-archive-keyring`
+    pub name: Option<String>,
+    /// raise this whenever the keys change, or apt won't upgrade to the package with the new ones
+    #[serde(default = "default_keyring_version")]
+    pub version: String,
+    pub maintainer: String,
+}
+
+fn default_keyring_version() -> String {
+    "1".to_owned()
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct PdiffConfig {
+    /// where earlier versions of each Packages index are kept between runs of repogen
+    pub state_directory: PathBuf,
+    /// how many earlier versions clients can update from with a patch
+    #[serde(default = "default_pdiff_keep")]
+    pub keep: usize,
+}
+
+fn default_pdiff_keep() -> usize {
+    14
+}
+
+/// How hard indexes are compressed: a preset, which any of the per-format levels override
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default)]
+pub struct CompressionLevelsConfig {
+    #[serde(default)]
+    pub preset: CompressionPreset,
+    /// 0 to 9
+    pub gzip: Option<u32>,
+    /// 0 to 9
+    pub xz: Option<u32>,
+    /// 1 to 22
+    pub zstd: Option<i32>,
+    /// 1 to 9
+    pub bzip2: Option<u32>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionPreset {
+    /// the smallest indexes
+    #[default]
+    Best,
+    /// slightly bigger indexes in a fraction of the time, for big repositories
+    Fast,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct SuiteConfig {
+    /// also the directory of repogen's input that holds this suite's debs
+    pub suite: String,
+    pub codename: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub not_automatic: bool,
+    #[serde(default)]
+    pub but_automatic_upgrades: bool,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PublishDestination {
+    Directory {
+        path: PathBuf,
+    },
+    /// synced with the `aws` CLI, which picks up credentials the usual way
+    S3 {
+        bucket: String,
+        prefix: Option<String>,
+        /// for S3-compatible services other than AWS
+        endpoint_url: Option<String>,
+    },
+    /// anything rsync accepts as a destination, like `user@host:/srv/repo`
+    Rsync {
+        target: String,
+    },
+    /// pushed as a single ORAS artifact with the `oras` CLI, like `ghcr.io/example/apt:latest`
+    Oci {
+        reference: String,
+    },
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct ServerConfig {
+    pub bind: Bind,
+    /// permissions of the socket when `bind` is a unix socket, like `0o660`, or `660` in
+    /// `GODSVAGN_SERVER_SOCKET_MODE`. Otherwise they come from the umask.
+    pub socket_mode: Option<u32>,
+    pub deb_directory: PathBuf,
+    pub repo_directory: PathBuf,
+    pub audiences: Box<[String]>,
+    pub keyfile: PathBuf,
+    /// more keys to sign Release with alongside `keyfile`, so clients trusting either an old or
+    /// a new key keep working while the repository moves from one to the other
+    #[serde(default)]
+    pub additional_keyfiles: Vec<PathBuf>,
+    /// regenerate by running this instead of in the server, to keep a crash or runaway memory
+    /// use in regeneration from taking the server down with it. It's run like
+    /// godsvagn-repogen would be.
+    pub repogen_command: Option<String>,
+    /// public url the repository is published at, used in generated install scripts
+    pub repo_url: Option<String>,
+    /// accept GitHub App installation tokens in addition to Actions OIDC tokens
+    pub github_app: Option<GithubAppConfig>,
+    /// where dput-style uploads are staged until their .changes file arrives
+    pub incoming_directory: Option<PathBuf>,
+    /// armored public keys of everyone allowed to sign .changes uploads
+    pub uploader_keyring: Option<PathBuf>,
+    /// logins dput can upload to `/incoming/` with using basic auth, since it can't send a
+    /// token. The .changes signature still decides what gets stored.
+    #[serde(default)]
+    pub incoming_users: Vec<ServeUser>,
+    /// armored public keys, one of which must have made a debsigs signature embedded in every deb
+    pub deb_signature_keyring: Option<PathBuf>,
+    /// periodically import debs and .changes sets dropped into a directory
+    pub process_incoming: Option<IncomingConfig>,
+    /// serve a repository published with an `oci` destination under `/apt/`
+    pub oci: Option<OciConfig>,
+    /// serve `repo_directory` under `/apt/`, instead of running another web server for it
+    pub serve: Option<ServeConfig>,
+    /// serve HTTPS instead of HTTP
+    pub tls: Option<TlsConfig>,
+    /// which logs to write, like `info` or `godsvagn_server=debug,warn`
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    #[serde(default)]
+    pub log_format: LogFormat,
+}
+
+/// Where the server listens, either an address like `0.0.0.0:8080` or a unix socket like
+/// `unix:/run/godsvagn.sock`, which only unix has
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Bind {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for Bind {
+    type Err = std::net::AddrParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix("unix:") {
+            Some(path) => Ok(Self::Unix(path.into())),
+            None => value.parse().map(Self::Tcp),
+        }
+    }
+}
+
+impl TryFrom<String> for Bind {
+    type Error = std::net::AddrParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for Bind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(address) => address.fmt(f),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM, the certificate followed by the rest of its chain. On unix, it and the key are read
+    /// again when the server gets SIGHUP, so they can be renewed without a restart. Other
+    /// platforms have no SIGHUP, so there they're only read when the server starts.
+    pub certificate: PathBuf,
+    /// PEM, in PKCS#8, PKCS#1 or SEC1
+    pub key: PathBuf,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// a line per event, for people to read
+    #[default]
+    Pretty,
+    /// a JSON object per line, for log collectors
+    Json,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct ServeConfig {
+    /// the only logins apt can download the repository with. Anyone can when there are none.
+    #[serde(default)]
+    pub users: Vec<ServeUser>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct ServeUser {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct OciConfig {
+    /// the same reference the repository was published to, like `ghcr.io/example/apt:latest`
+    pub reference: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct IncomingConfig {
+    pub directory: PathBuf,
+    /// rejected uploads are moved here instead of being deleted
+    pub quarantine_directory: PathBuf,
+    /// every accepted and rejected upload is appended here
+    pub log_file: Option<PathBuf>,
+    #[serde(default = "default_scan_interval")]
+    pub scan_interval_secs: u64,
+    /// files modified more recently than this might still be being written, and are skipped
+    #[serde(default = "default_settle")]
+    pub settle_secs: u64,
+    /// a .changes whose files still haven't all arrived this long after it was written is
+    /// rejected, instead of waiting for them forever
+    #[serde(default = "default_incomplete_timeout")]
+    pub incomplete_timeout_secs: u64,
+    /// only accept packages for these architectures
+    pub architectures: Option<Vec<String>>,
+    /// only accept packages whose entire name matches this regex
+    pub package_pattern: Option<String>,
+}
+
+fn default_log_level() -> String {
+    "info".to_owned()
+}
+
+fn default_scan_interval() -> u64 {
+    60
+}
+
+fn default_settle() -> u64 {
+    10
+}
+
+fn default_incomplete_timeout() -> u64 {
+    60 * 60
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct GithubAppConfig {
+    /// `owner/name` of every repository whose installation tokens may publish
+    pub repositories: Box<[String]>,
+}
+
+impl ServerConfig {
+    /// Every key Release is signed with, `keyfile` first
+    pub fn keyfiles(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(&self.keyfile)
+            .chain(&self.additional_keyfiles)
+            .map(PathBuf::as_path)
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct ReleaseConfig {
+    pub origin: String,
+    pub label: String,
+    pub suite: String,
+    pub codename: String,
+    pub version: String,
+    pub description: String,
+    /// write indexes the way apt-ftparchive does, for mirrors migrating from it
+    #[serde(default)]
+    pub apt_ftparchive_compat: bool,
+    /// work out Installed-Size for packages that leave it out, which means reading their data tarballs
+    #[serde(default)]
+    pub compute_installed_size: bool,
+    /// accept control files with CRLF line endings, no final newline, or a field repeated with
+    /// the same value. The server checks uploads the same way, so anything it takes can be indexed.
+    #[serde(default)]
+    pub lenient_control: bool,
+    /// check Maintainer, Priority and Section values, which both the server and repogen honour
+    #[serde(default)]
+    pub field_checks: FieldChecks,
+    /// refuse packages marked `Essential: yes`. apt won't remove those and insists on keeping them
+    /// installed, so one published by mistake from a third-party repository is hard to get rid of.
+    #[serde(default)]
+    pub reject_essential: bool,
+    /// publish long descriptions in `i18n/Translation-en` instead of the Packages indexes,
+    /// like Debian does
+    #[serde(default)]
+    pub translations: bool,
+    /// list `Architecture: all` packages only in `binary-all`, instead of in every
+    /// architecture's Packages index too, which clients that don't fetch `binary-all` need
+    #[serde(default)]
+    pub separate_arch_all: bool,
+    /// publish a flat repository, with one Packages index next to Release at the top of it,
+    /// which clients add as `deb https://host/repo ./`
+    #[serde(default)]
+    pub flat: bool,
+    /// publish DEP-11 AppStream metadata and icons for the apps in each component, so they show
+    /// up in software centers. Means reading the data tarball of every package. Flat
+    /// repositories can't have it.
+    #[serde(default)]
+    pub dep11: bool,
+    /// url clients reach the repository's `changelogs/` directory at, like
+    /// `https://deb.example.com/changelogs/`. Setting it publishes the changelog of every source
+    /// package there for `apt changelog`, which means reading the data tarball of every package.
+    /// Flat repositories can't have them.
+    pub changelogs: Option<String>,
+    /// url clients reach the top of the repository at, like `https://deb.example.com/`. Setting it
+    /// publishes `<origin>.sources` there, for users to copy into `/etc/apt/sources.list.d/`.
+    pub sources: Option<String>,
+    /// where that file tells apt the keyring is, instead of where the keyring package installs
+    /// it or `/usr/share/keyrings/<origin>-archive-keyring.pgp`
+    pub sources_signed_by: Option<String>,
+    /// date the repository and its signatures from `SOURCE_DATE_EPOCH`, which has to be set, so
+    /// the same debs always make the same repository
+    #[serde(default)]
+    pub reproducible: bool,
+    /// write `manifest.json` at the top of the repository, listing every generated file with its
+    /// size and checksums, so deployments can sync only what changed
+    #[serde(default)]
+    pub manifest: bool,
+    /// refuse to publish a suite where a `Multi-Arch: same` package isn't at the same version on
+    /// every architecture, which apt can't keep installed for several of them
+    #[serde(default)]
+    pub strict_multi_arch: bool,
+    /// file repogen keeps the digests of every deb in between runs, so only debs whose size or
+    /// modification time changed are hashed again
+    pub hash_cache: Option<PathBuf>,
+    /// every component of the repository. repogen puts debs found under a top-level directory of
+    /// its input named after one into that component, and everything else into the first.
+    #[serde(default = "default_components")]
+    pub components: Vec<String>,
+    /// compressed copies of each index to publish next to the uncompressed one
+    #[serde(default = "default_compression")]
+    pub compression: Vec<IndexCompression>,
+    /// apt won't upgrade to packages from this suite unless asked to, like Debian's experimental
+    #[serde(default)]
+    pub not_automatic: bool,
+    /// along with `not_automatic`, apt still upgrades packages that came from this suite
+    #[serde(default)]
+    pub but_automatic_upgrades: bool,
+    /// fingerprints of the only keys apt should accept the Release signatures from
+    #[serde(default)]
+    pub signed_by: Vec<String>,
+    /// the hash Release is signed over. By default whatever the key prefers, unless apt would
+    /// reject it.
+    #[serde(default)]
+    pub signature_hash: SignatureHash,
+    /// any other fields to write to Release, as a `[release.extra_headers]` table. They're
+    /// written in the order they're listed in.
+    #[serde(default)]
+    pub extra_headers: IndexMap<String, String>,
+}
+
+/// Fields repogen writes to Release itself, which extra headers can't replace
+const RELEASE_FIELDS: &[&str] = &[
+    "Origin",
+    "Label",
+    "Suite",
+    "Version",
+    "Codename",
+    "Date",
+    "NotAutomatic",
+    "ButAutomaticUpgrades",
+    "Signed-By",
+    "Architectures",
+    "Components",
+    "Description",
+    "Acquire-By-Hash",
+    "Changelogs",
+    "Snapshots",
+    "MD5Sum",
+    "SHA1",
+    "SHA256",
+    "SHA512",
+];
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexCompression {
+    #[serde(rename = "gz")]
+    Gzip,
+    #[serde(rename = "xz")]
+    Xz,
+    #[serde(rename = "zst")]
+    Zstd,
+    #[serde(rename = "bz2")]
+    Bzip2,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureHash {
+    #[default]
+    Default,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+fn default_compression() -> Vec<IndexCompression> {
+    vec![IndexCompression::Gzip, IndexCompression::Xz]
+}
+
+fn default_components() -> Vec<String> {
+    vec!["main".to_owned()]
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldChecks {
+    #[default]
+    Off,
+    /// accept the package, reporting what's wrong as warnings
+    Warn,
+    /// refuse the package
+    Reject,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct MetalinkConfig {
+    /// base urls of every mirror of the repository
+    pub mirrors: Vec<String>,
+    #[serde(default = "default_metalink_min_size")]
+    pub min_size: usize,
+}
+
+fn default_metalink_min_size() -> usize {
+    64 * 1024 * 1024
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ValueKind {
+    String,
+    Bool,
+    Integer,
+    /// an integer written in octal in the environment, like `660`, for file modes
+    Octal,
+    /// comma separated in the environment
+    List,
+}
+
+/// Every key that can be overridden from the environment, and how to interpret its value
+const OVERRIDABLE: &[(&str, &str, ValueKind)] = &[
+    ("server", "bind", ValueKind::String),
+    ("server", "socket_mode", ValueKind::Octal),
+    ("server", "deb_directory", ValueKind::String),
+    ("server", "repo_directory", ValueKind::String),
+    ("server", "audiences", ValueKind::List),
+    ("server", "keyfile", ValueKind::String),
+    ("server", "additional_keyfiles", ValueKind::List),
+    ("server", "repogen_command", ValueKind::String),
+    ("server", "repo_url", ValueKind::String),
+    ("server", "log_level", ValueKind::String),
+    ("server", "log_format", ValueKind::String),
+    ("server", "incoming_directory", ValueKind::String),
+    ("server", "uploader_keyring", ValueKind::String),
+    ("server", "deb_signature_keyring", ValueKind::String),
+    ("release", "origin", ValueKind::String),
+    ("release", "label", ValueKind::String),
+    ("release", "suite", ValueKind::String),
+    ("release", "codename", ValueKind::String),
+    ("release", "version", ValueKind::String),
+    ("release", "description", ValueKind::String),
+    ("release", "apt_ftparchive_compat", ValueKind::Bool),
+    ("release", "compute_installed_size", ValueKind::Bool),
+    ("release", "lenient_control", ValueKind::Bool),
+    ("release", "field_checks", ValueKind::String),
+    ("release", "reject_essential", ValueKind::Bool),
+    ("release", "translations", ValueKind::Bool),
+    ("release", "separate_arch_all", ValueKind::Bool),
+    ("release", "flat", ValueKind::Bool),
+    ("release", "dep11", ValueKind::Bool),
+    ("release", "changelogs", ValueKind::String),
+    ("release", "sources", ValueKind::String),
+    ("release", "sources_signed_by", ValueKind::String),
+    ("release", "reproducible", ValueKind::Bool),
+    ("release", "manifest", ValueKind::Bool),
+    ("release", "strict_multi_arch", ValueKind::Bool),
+    ("release", "hash_cache", ValueKind::String),
+    ("release", "components", ValueKind::List),
+    ("release", "compression", ValueKind::List),
+    ("release", "not_automatic", ValueKind::Bool),
+    ("release", "but_automatic_upgrades", ValueKind::Bool),
+    ("release", "signed_by", ValueKind::List),
+    ("release", "signature_hash", ValueKind::String),
+    ("compression_levels", "preset", ValueKind::String),
+    ("compression_levels", "gzip", ValueKind::Integer),
+    ("compression_levels", "xz", ValueKind::Integer),
+    ("compression_levels", "zstd", ValueKind::Integer),
+    ("compression_levels", "bzip2", ValueKind::Integer),
+    ("metalink", "mirrors", ValueKind::List),
+    ("metalink", "min_size", ValueKind::Integer),
+    ("pdiff", "state_directory", ValueKind::String),
+    ("pdiff", "keep", ValueKind::Integer),
+    ("keyring_package", "name", ValueKind::String),
+    ("keyring_package", "version", ValueKind::String),
+    ("keyring_package", "maintainer", ValueKind::String),
+    ("snapshots", "directory", ValueKind::String),
+    ("snapshots", "url", ValueKind::String),
+];
+
+impl Config {
+    /// Read the config file at `path`, apply overrides from the process environment, and validate it.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| Error::Read(path.to_owned(), e))?;
+        Self::from_toml_with_env(&contents, std::env::vars())
+    }
+
+    pub fn from_toml_with_env(
+        contents: &str,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, Error> {
+        let mut table: toml::Table = toml::from_str(contents)?;
+        let mut unknown_overrides = Vec::new();
+        for (name, value) in env {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX)
+                && !apply_override(&mut table, &name, key, &value)?
+            {
+                unknown_overrides.push(name);
+            }
+        }
+        let mut config: Self = toml::Value::Table(table).try_into()?;
+        config.unknown_overrides = unknown_overrides;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Get the server section, which is optional for repogen but required for the server
+    pub fn server(&self) -> Result<&ServerConfig, Error> {
+        self.server.as_ref().ok_or(Error::MissingSection("server"))
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        let release = &self.release;
+        for (name, value) in [
+            ("release.origin", &release.origin),
+            ("release.suite", &release.suite),
+            ("release.codename", &release.codename),
+        ] {
+            if value.trim().is_empty() {
+                return Err(Error::Empty(name));
+            }
+        }
+        if release.components.is_empty() {
+            return Err(Error::Empty("release.components"));
+        }
+        if let Some(component) = release.components.iter().find(|component| {
+            component.is_empty()
+                || !component
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        }) {
+            return Err(Error::InvalidComponent(component.clone()));
+        }
+        for (name, value) in &release.extra_headers {
+            let valid_name = !name.is_empty()
+                && !name.starts_with(['#', '-'])
+                && name.chars().all(|c| c.is_ascii_graphic() && c != ':')
+                && !RELEASE_FIELDS.iter().any(|f| f.eq_ignore_ascii_case(name));
+            if !valid_name || value.contains('\n') {
+                return Err(Error::InvalidHeader(name.clone()));
+            }
+        }
+        if release.flat && !self.suites.is_empty() {
+            return Err(Error::FlatWithSuites);
+        }
+        for (i, suite) in self.suites.iter().enumerate() {
+            let name = &suite.suite;
+            if name.trim().is_empty()
+                || matches!(name.as_str(), "." | "..")
+                || name.contains(['/', '\\'])
+            {
+                return Err(Error::InvalidSuite(name.clone()));
+            }
+            if self.suites[..i].iter().any(|other| other.suite == *name) {
+                return Err(Error::InvalidSuite(name.clone()));
+            }
+        }
+
+        if let Some(server) = &self.server {
+            if server.audiences.is_empty() {
+                return Err(Error::Empty("server.audiences"));
+            }
+            if server.deb_directory == server.repo_directory {
+                return Err(Error::SameDirectories);
+            }
+            if let Some(incoming) = &server.process_incoming {
+                if incoming.directory == incoming.quarantine_directory
+                    || incoming.directory == server.deb_directory
+                {
+                    return Err(Error::SameDirectories);
+                }
+                if incoming.scan_interval_secs == 0 {
+                    return Err(Error::Empty("server.process_incoming.scan_interval_secs"));
+                }
+            }
+            if server.oci.is_some() && server.serve.is_some() {
+                return Err(Error::Conflicting("server.oci", "server.serve"));
+            }
+            if cfg!(not(unix)) && matches!(server.bind, Bind::Unix(_)) {
+                return Err(Error::UnixSocketUnsupported);
+            }
+            if server.tls.is_some() && matches!(server.bind, Bind::Unix(_)) {
+                return Err(Error::Conflicting(
+                    "server.tls",
+                    "a unix socket in server.bind",
+                ));
+            }
+            if server
+                .github_app
+                .as_ref()
+                .is_some_and(|app| app.repositories.is_empty())
+            {
+                return Err(Error::Empty("server.github_app.repositories"));
+            }
+        }
+
+        let levels = &self.compression_levels;
+        for (name, level, range) in [
+            ("compression_levels.gzip", levels.gzip.map(i64::from), 0..=9),
+            ("compression_levels.xz", levels.xz.map(i64::from), 0..=9),
+            (
+                "compression_levels.zstd",
+                levels.zstd.map(i64::from),
+                1..=22,
+            ),
+            (
+                "compression_levels.bzip2",
+                levels.bzip2.map(i64::from),
+                1..=9,
+            ),
+        ] {
+            if level.is_some_and(|level| !range.contains(&level)) {
+                return Err(Error::InvalidLevel(name));
+            }
+        }
+
+        if let Some(metalink) = &self.metalink
+            && metalink.mirrors.is_empty()
+        {
+            return Err(Error::Empty("metalink.mirrors"));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `key` is one of [`OVERRIDABLE`], which it's only applied if it is
+fn apply_override(
+    table: &mut toml::Table,
+    name: &str,
+    key: &str,
+    value: &str,
+) -> Result<bool, Error> {
+    let key = key.to_ascii_lowercase();
+    let Some((section, field, kind)) = OVERRIDABLE
+        .iter()
+        .find(|(section, field, _)| {
+            key.strip_prefix(section)
+                .and_then(|rest| rest.strip_prefix('_'))
+                .is_some_and(|rest| rest == *field)
+        })
+        .copied()
+    else {
+        return Ok(false);
+    };
+
+    let value = match kind {
+        ValueKind::String => toml::Value::String(value.to_owned()),
+        ValueKind::Bool => match value.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" => toml::Value::Boolean(true),
+            "false" | "0" | "no" => toml::Value::Boolean(false),
+            _ => return Err(Error::InvalidOverride(name.to_owned())),
+        },
+        ValueKind::Integer => toml::Value::Integer(
+            value
+                .trim()
+                .parse()
+                .map_err(|_| Error::InvalidOverride(name.to_owned()))?,
+        ),
+        ValueKind::Octal => {
+            let value = value.trim();
+            let value = value.strip_prefix("0o").unwrap_or(value);
+            toml::Value::Integer(
+                i64::from_str_radix(value, 8)
+                    .map_err(|_| Error::InvalidOverride(name.to_owned()))?,
+            )
+        }
+        ValueKind::List => toml::Value::Array(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(|v| toml::Value::String(v.to_owned()))
+                .collect(),
+        ),
+    };
+
+    let section = table
+        .entry(section)
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    let toml::Value::Table(section) = section else {
+        return Err(Error::InvalidOverride(name.to_owned()));
+    };
+    section.insert(field.to_owned(), value);
+    Ok(true)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("could not read config file {}: {}", .0.display(), .1)]
+    Read(PathBuf, std::io::Error),
+    #[error("invalid config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("environment override {0} has an invalid value")]
+    InvalidOverride(String),
+    #[error("config is missing the [{0}] section")]
+    MissingSection(&'static str),
+    #[error("{0} must not be empty")]
+    Empty(&'static str),
+    #[error(
+        "server.deb_directory, server.repo_directory, and incoming directories must all be different"
+    )]
+    SameDirectories,
+    #[error("component {0:?} may only contain lowercase letters, digits and hyphens")]
+    InvalidComponent(String),
+    #[error("suite {0:?} is listed twice or can't be used as a directory name")]
+    InvalidSuite(String),
+    #[error("{0} is out of range for that format")]
+    InvalidLevel(&'static str),
+    #[error("extra Release header {0:?} is a field repogen writes itself, or isn't a valid field")]
+    InvalidHeader(String),
+    #[error("a flat repository can't have [[suites]]")]
+    FlatWithSuites,
+    #[error("{0} and {1} can't both be set")]
+    Conflicting(&'static str, &'static str),
+    #[error("server.bind can only be a unix socket on unix")]
+    UnixSocketUnsupported,
+}
+
+impl Error {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Read(..) => "GV4001",
+            Self::Parse(_) => "GV4002",
+            Self::InvalidOverride(_) => "GV4004",
+            Self::MissingSection(_) => "GV4005",
+            Self::Empty(_) => "GV4006",
+            Self::SameDirectories => "GV4007",
+            Self::InvalidComponent(_) => "GV4008",
+            Self::InvalidSuite(_) => "GV4009",
+            Self::InvalidLevel(_) => "GV4010",
+            Self::InvalidHeader(_) => "GV4011",
+            Self::FlatWithSuites => "GV4012",
+            Self::Conflicting(..) => "GV4013",
+            Self::UnixSocketUnsupported => "GV4014",
+        }
+    }
+}
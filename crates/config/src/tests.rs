@@ -0,0 +1,285 @@
+use super::*;
+
+const EXAMPLE: &str = include_str!("../../../config.toml");
+
+fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+        .collect()
+}
+
+#[test]
+fn example_config() {
+    let config = Config::from_toml_with_env(EXAMPLE, []).unwrap();
+    let server = config.server().unwrap();
+    assert_eq!(server.repogen_command, None);
+    assert_eq!(config.release.codename, "salolampi");
+    assert!(config.metalink.is_none());
+    assert_eq!(
+        config.release.compression,
+        [IndexCompression::Gzip, IndexCompression::Xz]
+    );
+}
+
+#[test]
+fn env_overrides() {
+    let config = Config::from_toml_with_env(
+        EXAMPLE,
+        env(&[
+            ("GODSVAGN_SERVER_BIND", "127.0.0.1:9000"),
+            (
+                "GODSVAGN_SERVER_AUDIENCES",
+                "https://a.example, https://b.example",
+            ),
+            ("GODSVAGN_RELEASE_VERSION", "2"),
+            ("GODSVAGN_RELEASE_APT_FTPARCHIVE_COMPAT", "true"),
+            ("GODSVAGN_METALINK_MIRRORS", "https://mirror.example"),
+            ("GODSVAGN_RELEASE_COMPRESSION", "xz, zst"),
+            ("UNRELATED", "ignored"),
+        ]),
+    )
+    .unwrap();
+    let server = config.server().unwrap();
+    assert_eq!(server.bind, "127.0.0.1:9000".parse().unwrap());
+    assert_eq!(
+        &*server.audiences,
+        ["https://a.example", "https://b.example"]
+    );
+    assert_eq!(config.release.version, "2");
+    assert!(config.release.apt_ftparchive_compat);
+    assert_eq!(
+        config.release.compression,
+        [IndexCompression::Xz, IndexCompression::Zstd]
+    );
+    assert_eq!(config.metalink.unwrap().mirrors, ["https://mirror.example"]);
+}
+
+#[test]
+fn bad_overrides() {
+    let config =
+        Config::from_toml_with_env(EXAMPLE, env(&[("GODSVAGN_SERVER_BINDD", "x")])).unwrap();
+    assert_eq!(config.unknown_overrides, ["GODSVAGN_SERVER_BINDD"]);
+    assert_eq!(config.server().unwrap().bind.to_string(), "0.0.0.0:8080");
+
+    let err = Config::from_toml_with_env(
+        EXAMPLE,
+        env(&[("GODSVAGN_RELEASE_APT_FTPARCHIVE_COMPAT", "maybe")]),
+    )
+    .unwrap_err();
+    assert!(matches!(err, Error::InvalidOverride(_)));
+}
+
+#[test]
+fn validation() {
+    let err =
+        Config::from_toml_with_env(EXAMPLE, env(&[("GODSVAGN_RELEASE_SUITE", " ")])).unwrap_err();
+    assert!(matches!(err, Error::Empty("release.suite")));
+
+    let err = Config::from_toml_with_env(
+        EXAMPLE,
+        env(&[("GODSVAGN_SERVER_REPO_DIRECTORY", "debstore")]),
+    )
+    .unwrap_err();
+    assert!(matches!(err, Error::SameDirectories));
+
+    let config = Config::from_toml_with_env(
+        EXAMPLE,
+        env(&[("GODSVAGN_RELEASE_COMPONENTS", "main,contrib")]),
+    )
+    .unwrap();
+    assert_eq!(config.release.components, ["main", "contrib"]);
+    let err = Config::from_toml_with_env(
+        EXAMPLE,
+        env(&[("GODSVAGN_RELEASE_COMPONENTS", "main,../up")]),
+    )
+    .unwrap_err();
+    assert!(matches!(err, Error::InvalidComponent(c) if c == "../up"));
+}
+
+#[test]
+fn publish_destinations() {
+    let config = Config::from_toml_with_env(
+        &format!(
+            r#"{EXAMPLE}
+
+[[publish]]
+type = "directory"
+path = "/srv/mirror"
+
+[[publish]]
+type = "s3"
+bucket = "apt"
+prefix = "debian"
+
+[[publish]]
+type = "rsync"
+target = "mirror@example.com:/srv/apt"
+"#
+        ),
+        [],
+    )
+    .unwrap();
+    assert!(matches!(
+        &config.publish[..],
+        [
+            PublishDestination::Directory { .. },
+            PublishDestination::S3 {
+                endpoint_url: None,
+                ..
+            },
+            PublishDestination::Rsync { .. },
+        ]
+    ));
+}
+
+#[test]
+fn process_incoming() {
+    let incoming = |section: &str| {
+        Config::from_toml_with_env(
+            &EXAMPLE.replacen(
+                "[release]",
+                &format!("[server.process_incoming]\n{section}\n\n[release]"),
+                1,
+            ),
+            [],
+        )
+    };
+    let config = incoming("directory = \"incoming\"\nquarantine_directory = \"rejected\"").unwrap();
+    let process_incoming = config.server().unwrap().process_incoming.as_ref().unwrap();
+    assert_eq!(process_incoming.scan_interval_secs, 60);
+    assert_eq!(process_incoming.incomplete_timeout_secs, 3600);
+    assert!(process_incoming.architectures.is_none());
+
+    let err =
+        incoming("directory = \"incoming\"\nquarantine_directory = \"incoming\"").unwrap_err();
+    assert!(matches!(err, Error::SameDirectories));
+}
+
+#[test]
+fn suites() {
+    let suites = "\n[[suites]]\nsuite = \"stable\"\ncodename = \"one\"\n\n[[suites]]\nsuite = \"testing\"\ncodename = \"two\"\n";
+    let config = Config::from_toml_with_env(&format!("{EXAMPLE}{suites}"), []).unwrap();
+    let names: Vec<&str> = config.suites.iter().map(|s| &*s.suite).collect();
+    assert_eq!(names, ["stable", "testing"]);
+    assert!(
+        Config::from_toml_with_env(EXAMPLE, [])
+            .unwrap()
+            .suites
+            .is_empty()
+    );
+
+    for bad in ["../up", "..", "stable"] {
+        let extra = format!("\n[[suites]]\nsuite = \"{bad}\"\ncodename = \"x\"\n");
+        let err = Config::from_toml_with_env(&format!("{EXAMPLE}{suites}{extra}"), []).unwrap_err();
+        assert!(matches!(err, Error::InvalidSuite(s) if s == bad));
+    }
+
+    let flat = env(&[("GODSVAGN_RELEASE_FLAT", "true")]);
+    assert!(
+        Config::from_toml_with_env(EXAMPLE, flat.clone())
+            .unwrap()
+            .release
+            .flat
+    );
+    let err = Config::from_toml_with_env(&format!("{EXAMPLE}{suites}"), flat).unwrap_err();
+    assert!(matches!(err, Error::FlatWithSuites));
+}
+
+#[test]
+fn compression_levels() {
+    let config = Config::from_toml_with_env(EXAMPLE, []).unwrap();
+    assert_eq!(config.compression_levels.preset, CompressionPreset::Best);
+
+    let config = Config::from_toml_with_env(
+        EXAMPLE,
+        env(&[
+            ("GODSVAGN_COMPRESSION_LEVELS_PRESET", "fast"),
+            ("GODSVAGN_COMPRESSION_LEVELS_XZ", "6"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(config.compression_levels.preset, CompressionPreset::Fast);
+    assert_eq!(config.compression_levels.xz, Some(6));
+
+    let err =
+        Config::from_toml_with_env(EXAMPLE, env(&[("GODSVAGN_COMPRESSION_LEVELS_ZSTD", "0")]))
+            .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::InvalidLevel("compression_levels.zstd")
+    ));
+}
+
+#[test]
+fn release_headers() {
+    let headers = "\n[release.extra_headers]\nX-Policy = \"weekly\"\nX-Contact = \"admin\"\n";
+    let with_headers = EXAMPLE.replace("\n# copy every", &format!("{headers}\n# copy every"));
+    let config = Config::from_toml_with_env(
+        &with_headers,
+        env(&[
+            ("GODSVAGN_RELEASE_NOT_AUTOMATIC", "yes"),
+            ("GODSVAGN_RELEASE_SIGNED_BY", "AAAA,BBBB"),
+        ]),
+    )
+    .unwrap();
+    assert!(config.release.not_automatic);
+    assert_eq!(config.release.signed_by, ["AAAA", "BBBB"]);
+    assert_eq!(config.release.extra_headers["X-Policy"], "weekly");
+    let names: Vec<&str> = config.release.extra_headers.keys().map(|k| &**k).collect();
+    assert_eq!(names, ["X-Policy", "X-Contact"]);
+
+    for bad in ["\"Suite\" = \"x\"", "\"a:b\" = \"x\"", "X-Ok = \"a\\nb\""] {
+        let headers = format!("\n[release.extra_headers]\n{bad}\n");
+        let config = EXAMPLE.replace("\n# copy every", &format!("{headers}\n# copy every"));
+        let err = Config::from_toml_with_env(&config, []).unwrap_err();
+        assert!(matches!(err, Error::InvalidHeader(_)), "{bad}: {err}");
+    }
+}
+
+#[test]
+fn signature_hash() {
+    let config = Config::from_toml_with_env(EXAMPLE, []).unwrap();
+    assert_eq!(config.release.signature_hash, SignatureHash::Default);
+    let config = Config::from_toml_with_env(
+        EXAMPLE,
+        env(&[("GODSVAGN_RELEASE_SIGNATURE_HASH", "sha512")]),
+    )
+    .unwrap();
+    assert_eq!(config.release.signature_hash, SignatureHash::Sha512);
+    let err =
+        Config::from_toml_with_env(EXAMPLE, env(&[("GODSVAGN_RELEASE_SIGNATURE_HASH", "md5")]));
+    assert!(err.is_err());
+}
+
+#[test]
+#[cfg(unix)]
+fn unix_bind() {
+    let config = Config::from_toml_with_env(
+        EXAMPLE,
+        env(&[
+            ("GODSVAGN_SERVER_BIND", "unix:/run/godsvagn.sock"),
+            ("GODSVAGN_SERVER_SOCKET_MODE", "660"),
+        ]),
+    )
+    .unwrap();
+    let server = config.server().unwrap();
+    assert_eq!(server.bind, Bind::Unix("/run/godsvagn.sock".into()));
+    assert_eq!(server.socket_mode, Some(0o660));
+    assert_eq!(server.bind.to_string(), "unix:/run/godsvagn.sock");
+
+    let toml = EXAMPLE.replace("bind = \"0.0.0.0:8080\"", "bind = \"unix:godsvagn.sock\"")
+        + "\n[server.tls]\ncertificate = \"a\"\nkey = \"b\"\n";
+    let err = Config::from_toml_with_env(&toml, []).unwrap_err();
+    assert!(matches!(err, Error::Conflicting("server.tls", _)));
+}
+
+#[test]
+#[cfg(not(unix))]
+fn unix_bind_unsupported() {
+    let err = Config::from_toml_with_env(
+        EXAMPLE,
+        env(&[("GODSVAGN_SERVER_BIND", "unix:/run/godsvagn.sock")]),
+    )
+    .unwrap_err();
+    assert!(matches!(err, Error::UnixSocketUnsupported));
+}
@@ -0,0 +1,52 @@
+//! Hashing many files at once, for callers going over a whole pool
+
+use std::{
+    fs::File,
+    io::BufReader,
+    num::NonZeroUsize,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{FileSums, FileSumsBuilder};
+
+impl FileSumsBuilder {
+    /// Hash every file in `paths`, up to `workers` of them at a time, and return the digests
+    /// of each in the same order as `paths`. Files are read, not mapped, so they can change
+    /// while they're hashed. Big ones still get a thread per digest each, like with
+    /// [`hash`](Self::hash).
+    pub fn hash_files<P: AsRef<Path> + Sync>(
+        &self,
+        paths: &[P],
+        workers: NonZeroUsize,
+    ) -> Vec<Result<FileSums, std::io::Error>> {
+        let next = AtomicUsize::new(0);
+        let hash = |path: &Path| self.hash(BufReader::new(File::open(path)?));
+        let mut hashed: Vec<(usize, Result<FileSums, std::io::Error>)> =
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..workers.get().min(paths.len()))
+                    .map(|_| {
+                        scope.spawn(|| {
+                            let mut hashed = Vec::new();
+                            loop {
+                                let i = next.fetch_add(1, Ordering::Relaxed);
+                                let Some(path) = paths.get(i) else {
+                                    return hashed;
+                                };
+                                hashed.push((i, hash(path.as_ref())));
+                            }
+                        })
+                    })
+                    .collect();
+                workers
+                    .into_iter()
+                    .flat_map(|worker| match worker.join() {
+                        Ok(hashed) => hashed,
+                        Err(panic) => std::panic::resume_unwind(panic),
+                    })
+                    .collect()
+            });
+        hashed.sort_unstable_by_key(|(i, _)| *i);
+        hashed.into_iter().map(|(_, sums)| sums).collect()
+    }
+}
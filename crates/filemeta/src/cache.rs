@@ -0,0 +1,181 @@
+//! The digests of files that were hashed before, so hashing a big pool again only reads the
+//! files that changed. A file counts as unchanged while its size and modification time are.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::Metadata,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use crate::{DigestKind, FileSums, FileSumsBuilder};
+
+/// The first line of the cache file, which changes whenever the format does. Caches in any
+/// other format are ignored and start over.
+const HEADER: &str = "godsvagn hash cache 1";
+
+/// Digests kept in a small text file between runs, a line per file. Only the files looked up
+/// or added while it was loaded are saved again, so files that are gone drop out of it.
+#[derive(Debug)]
+pub struct HashCache {
+    path: PathBuf,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    loaded: HashMap<Box<str>, Entry>,
+    used: HashMap<Box<str>, Entry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    size: u64,
+    /// nanoseconds since the epoch
+    modified: u128,
+    sums: FileSums,
+}
+
+impl HashCache {
+    /// The cache saved at `path`, or an empty one if there's nothing there yet
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        let loaded = match std::fs::read_to_string(&path) {
+            Ok(text) => parse(&text),
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path,
+            entries: Mutex::new(Entries {
+                loaded,
+                used: HashMap::new(),
+            }),
+        })
+    }
+
+    /// The digests of the file at `path`, if it hasn't changed since they were added and they
+    /// include every digest `digests` asks for
+    pub fn get(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        digests: &FileSumsBuilder,
+    ) -> Option<FileSums> {
+        let key = path.to_str()?;
+        let (size, modified) = stamp(metadata)?;
+        let mut entries = self.lock();
+        let entry = match entries.used.get(key) {
+            Some(entry) => *entry,
+            None => *entries.loaded.get(key)?,
+        };
+        let complete = DigestKind::ALL
+            .into_iter()
+            .all(|kind| !digests.includes(kind) || entry.sums.get(kind).is_some());
+        if entry.size != size || entry.modified != modified || !complete {
+            return None;
+        }
+        entries.used.insert(key.into(), entry);
+        Some(entry.sums)
+    }
+
+    /// Remember `sums` for the file at `path`, whose metadata was read before it was hashed.
+    /// Paths that aren't UTF-8 or have a line break in them aren't cached.
+    pub fn insert(&self, path: &Path, metadata: &Metadata, sums: FileSums) {
+        let (Some(key), Some((size, modified))) = (path.to_str(), stamp(metadata)) else {
+            return;
+        };
+        if key.contains(['\n', '\r']) {
+            return;
+        }
+        let entry = Entry {
+            size,
+            modified,
+            sums,
+        };
+        self.lock().used.insert(key.into(), entry);
+    }
+
+    /// Write every file looked up or added since loading back to where the cache was loaded
+    /// from. It's written next to it first and renamed over it, so a run that's interrupted
+    /// never leaves half a cache behind.
+    pub fn save(&self) -> Result<(), std::io::Error> {
+        let mut text = String::with_capacity(HEADER.len() + 1);
+        text.push_str(HEADER);
+        text.push('\n');
+        let entries = self.lock();
+        let mut used: Vec<_> = entries.used.iter().collect();
+        used.sort_unstable_by_key(|(path, _)| *path);
+        for (path, entry) in used {
+            let _ = write!(text, "{}\t{}\t", entry.size, entry.modified);
+            for (i, (kind, digest)) in entry.sums.iter().enumerate() {
+                if i > 0 {
+                    text.push(',');
+                }
+                text.push_str(kind.name());
+                text.push('=');
+                for byte in digest {
+                    let _ = write!(text, "{byte:02x}");
+                }
+            }
+            let _ = writeln!(text, "\t{path}");
+        }
+        drop(entries);
+
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(".part");
+        std::fs::write(&partial, text)?;
+        std::fs::rename(&partial, &self.path)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        // a panic while it was held was never in the middle of changing an entry
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The size and modification time a file is checked against
+fn stamp(metadata: &Metadata) -> Option<(u64, u128)> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_nanos()))
+}
+
+/// Lines that can't be read are skipped, like ones from a newer digest this doesn't know
+fn parse(text: &str) -> HashMap<Box<str>, Entry> {
+    let mut lines = text.lines();
+    if lines.next() != Some(HEADER) {
+        return HashMap::new();
+    }
+    lines
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let size = fields.next()?.parse().ok()?;
+            let modified = fields.next()?.parse().ok()?;
+            let mut sums = FileSums::default();
+            for digest in fields.next()?.split(',').filter(|d| !d.is_empty()) {
+                let (name, hex) = digest.split_once('=')?;
+                let kind = DigestKind::ALL.into_iter().find(|k| k.name() == name)?;
+                sums.set(kind, &decode_hex(hex)?);
+            }
+            let entry = Entry {
+                size,
+                modified,
+                sums,
+            };
+            Some((fields.next()?.into(), entry))
+        })
+        .collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
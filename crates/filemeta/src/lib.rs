@@ -1,9 +1,26 @@
-use std::io::BufRead;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    sync::{Arc, mpsc},
+};
 
-use digest::Digest;
+use blake2::Blake2b512;
+use digest::{Digest, DynDigest};
 use md5::Md5;
 use sha1::Sha1;
-use sha2::Sha256;
+use sha2::{Sha256, Sha512};
+
+mod batch;
+mod cache;
+#[cfg(test)]
+mod tests;
+
+pub use cache::HashCache;
+
+/// Files are read this much at a time. Ones bigger than that are hashed with a thread per
+/// digest, since for anything smaller starting the threads takes longer than hashing.
+const CHUNK: usize = 1024 * 1024;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FileMeta {
@@ -13,44 +30,332 @@ pub struct FileMeta {
 }
 
 impl FileMeta {
+    /// With the digests [`FileSums::new`] computes
     pub fn new(path: Box<str>, file: &[u8]) -> Result<Self, std::io::Error> {
+        Self::with_digests(path, file, &FileSumsBuilder::APT)
+    }
+
+    /// Hash the file at `path` with every digest in `expected`, and return the ones that don't
+    /// match, which is none of them if it's intact
+    pub fn verify(path: &Path, expected: &FileSums) -> Result<Vec<DigestKind>, std::io::Error> {
+        let digests = expected
+            .iter()
+            .fold(FileSums::builder(), |digests, (kind, _)| digests.with(kind));
+        let actual = digests.hash(BufReader::new(File::open(path)?))?;
+        let mismatched = expected
+            .iter()
+            .filter(|(kind, digest)| actual.get(*kind) != Some(*digest))
+            .map(|(kind, _)| kind);
+        Ok(mismatched.collect())
+    }
+
+    pub fn with_digests(
+        path: Box<str>,
+        file: &[u8],
+        digests: &FileSumsBuilder,
+    ) -> Result<Self, std::io::Error> {
         Ok(Self {
             size: file.len(),
             path,
-            sums: FileSums::new(file)?,
+            sums: digests.hash_slice(file),
         })
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DigestKind {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    Blake2b,
+}
+
+impl DigestKind {
+    pub const ALL: [Self; 5] = [
+        Self::Md5,
+        Self::Sha1,
+        Self::Sha256,
+        Self::Sha512,
+        Self::Blake2b,
+    ];
+
+    /// Lowercase, like `sha256`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Blake2b => "blake2b",
+        }
+    }
+
+    fn hasher(self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Self::Md5 => Box::new(Md5::new()),
+            Self::Sha1 => Box::new(Sha1::new()),
+            Self::Sha256 => Box::new(Sha256::new()),
+            Self::Sha512 => Box::new(Sha512::new()),
+            Self::Blake2b => Box::new(Blake2b512::new()),
+        }
+    }
+}
+
+/// The digests of a file. Each is only there if the [`FileSumsBuilder`] it was made with asked
+/// for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub struct FileSums {
-    pub sha1: [u8; 20],
-    pub sha256: [u8; 32],
-    pub md5: [u8; 16],
+    pub sha1: Option<[u8; 20]>,
+    pub sha256: Option<[u8; 32]>,
+    pub sha512: Option<[u8; 64]>,
+    pub md5: Option<[u8; 16]>,
+    /// BLAKE2b-512
+    pub blake2b: Option<[u8; 64]>,
 }
 
 impl FileSums {
-    pub fn new(mut r: impl BufRead) -> Result<Self, std::io::Error> {
-        let mut sha1 = Sha1::new();
-        let mut md5 = Md5::new();
-        let mut sha256 = Sha256::new();
-        let mut buf = [0; 1024 * 64];
+    /// md5, sha1, sha256 and sha512, which are what apt checks
+    pub fn new(r: impl BufRead) -> Result<Self, std::io::Error> {
+        FileSumsBuilder::APT.hash(r)
+    }
+
+    /// [`FileSums::new`], reading `r` asynchronously, like an upload as it comes in
+    #[cfg(feature = "tokio")]
+    pub async fn new_async(
+        r: impl tokio::io::AsyncBufRead + Unpin,
+    ) -> Result<Self, std::io::Error> {
+        FileSumsBuilder::APT.hash_async(r).await
+    }
+
+    /// A builder computing no digests until they're added to it
+    pub fn builder() -> FileSumsBuilder {
+        FileSumsBuilder::default()
+    }
+
+    /// Leaves the digest out if it isn't as long as digests of `kind` are
+    fn set(&mut self, kind: DigestKind, digest: &[u8]) {
+        match kind {
+            DigestKind::Md5 => self.md5 = digest.try_into().ok(),
+            DigestKind::Sha1 => self.sha1 = digest.try_into().ok(),
+            DigestKind::Sha256 => self.sha256 = digest.try_into().ok(),
+            DigestKind::Sha512 => self.sha512 = digest.try_into().ok(),
+            DigestKind::Blake2b => self.blake2b = digest.try_into().ok(),
+        }
+    }
+
+    pub fn get(&self, kind: DigestKind) -> Option<&[u8]> {
+        match kind {
+            DigestKind::Md5 => self.md5.as_ref().map(|d| &d[..]),
+            DigestKind::Sha1 => self.sha1.as_ref().map(|d| &d[..]),
+            DigestKind::Sha256 => self.sha256.as_ref().map(|d| &d[..]),
+            DigestKind::Sha512 => self.sha512.as_ref().map(|d| &d[..]),
+            DigestKind::Blake2b => self.blake2b.as_ref().map(|d| &d[..]),
+        }
+    }
+
+    /// Every digest there is, in the order of [`DigestKind::ALL`]
+    pub fn iter(&self) -> impl Iterator<Item = (DigestKind, &[u8])> {
+        DigestKind::ALL
+            .into_iter()
+            .filter_map(|kind| Some((kind, self.get(kind)?)))
+    }
+}
+
+/// Which digests to compute for [`FileSums`], so stronger ones can be added and ones a policy
+/// forbids left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileSumsBuilder {
+    md5: bool,
+    sha1: bool,
+    sha256: bool,
+    sha512: bool,
+    blake2b: bool,
+}
+
+impl FileSumsBuilder {
+    /// What [`FileSums::new`] computes
+    pub const APT: Self = Self {
+        md5: true,
+        sha1: true,
+        sha256: true,
+        sha512: true,
+        blake2b: false,
+    };
+
+    pub fn with(mut self, kind: DigestKind) -> Self {
+        *self.enabled(kind) = true;
+        self
+    }
+
+    pub fn without(mut self, kind: DigestKind) -> Self {
+        *self.enabled(kind) = false;
+        self
+    }
+
+    fn enabled(&mut self, kind: DigestKind) -> &mut bool {
+        match kind {
+            DigestKind::Md5 => &mut self.md5,
+            DigestKind::Sha1 => &mut self.sha1,
+            DigestKind::Sha256 => &mut self.sha256,
+            DigestKind::Sha512 => &mut self.sha512,
+            DigestKind::Blake2b => &mut self.blake2b,
+        }
+    }
+
+    pub fn includes(&self, kind: DigestKind) -> bool {
+        let mut builder = *self;
+        *builder.enabled(kind)
+    }
+
+    fn hashers(&self) -> Vec<(DigestKind, Box<dyn DynDigest + Send>)> {
+        DigestKind::ALL
+            .into_iter()
+            .filter(|kind| self.includes(*kind))
+            .map(|kind| (kind, kind.hasher()))
+            .collect()
+    }
+
+    /// [`hash`](Self::hash) for a file that's already in memory, or mapped into it
+    pub fn hash_slice(&self, data: &[u8]) -> FileSums {
+        let mut hashers = self.hashers();
+        if data.len() < CHUNK || hashers.len() < 2 {
+            for (_, hasher) in &mut hashers {
+                hasher.update(data);
+            }
+        } else {
+            std::thread::scope(|scope| {
+                for (_, hasher) in &mut hashers {
+                    scope.spawn(move || hasher.update(data));
+                }
+            });
+        }
+        finish(hashers)
+    }
+
+    /// The file at `path`, mapped into memory with the `mmap` feature so it isn't copied out a
+    /// chunk at a time. Files that can't be mapped, like empty ones, and every file without
+    /// the feature, are read instead.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified, or truncated, while it's hashed. Files that are only
+    /// ever replaced by renaming a new file over them are fine.
+    pub unsafe fn hash_file(&self, path: &Path) -> Result<FileSums, std::io::Error> {
+        let file = File::open(path)?;
+        // SAFETY: upheld by the caller
+        match unsafe { map(&file) } {
+            Some(map) => Ok(self.hash_slice(&map)),
+            None => self.hash(BufReader::new(file)),
+        }
+    }
+
+    pub fn hash(&self, mut r: impl BufRead) -> Result<FileSums, std::io::Error> {
+        let mut hashers = self.hashers();
+        let mut chunk = read_chunk(&mut r)?;
+        if chunk.len() < CHUNK || hashers.len() < 2 {
+            loop {
+                for (_, hasher) in &mut hashers {
+                    hasher.update(&chunk);
+                }
+                if chunk.len() < CHUNK {
+                    break;
+                }
+                chunk = read_chunk(&mut r)?;
+            }
+        } else {
+            std::thread::scope(|scope| {
+                // a couple of chunks can wait for each thread, so reading goes on while the
+                // slowest digest catches up
+                let workers: Vec<mpsc::SyncSender<Arc<[u8]>>> = hashers
+                    .iter_mut()
+                    .map(|(_, hasher)| {
+                        let (sender, chunks) = mpsc::sync_channel::<Arc<[u8]>>(2);
+                        scope.spawn(move || chunks.iter().for_each(|c| hasher.update(&c)));
+                        sender
+                    })
+                    .collect();
+                let mut chunk: Arc<[u8]> = std::mem::take(&mut chunk).into();
+                loop {
+                    for worker in &workers {
+                        // only fails if the thread panicked, which the scope passes on
+                        let _ = worker.send(chunk.clone());
+                    }
+                    if chunk.len() < CHUNK {
+                        return Ok::<_, std::io::Error>(());
+                    }
+                    chunk = read_chunk(&mut r)?.into();
+                }
+            })?;
+        }
+        Ok(finish(hashers))
+    }
+
+    /// [`hash`](Self::hash), reading `r` asynchronously. Every digest is computed on the task
+    /// reading, a buffer of `r` at a time.
+    #[cfg(feature = "tokio")]
+    pub async fn hash_async(
+        &self,
+        mut r: impl tokio::io::AsyncBufRead + Unpin,
+    ) -> Result<FileSums, std::io::Error> {
+        use tokio::io::AsyncBufReadExt;
+
+        let mut hashers = self.hashers();
         loop {
-            let valid_buf_len = r.read(&mut buf)?;
-            if valid_buf_len == 0 {
+            let buf = r.fill_buf().await?;
+            if buf.is_empty() {
                 break;
             }
-            let valid_buf = &buf[..valid_buf_len];
-
-            sha1.update(valid_buf);
-            md5.update(valid_buf);
-            sha256.update(valid_buf);
+            for (_, hasher) in &mut hashers {
+                hasher.update(buf);
+            }
+            let len = buf.len();
+            r.consume(len);
         }
+        Ok(finish(hashers))
+    }
+}
 
-        Ok(Self {
-            sha1: sha1.finalize().into(),
-            sha256: sha256.finalize().into(),
-            md5: md5.finalize().into(),
-        })
+fn finish(hashers: Vec<(DigestKind, Box<dyn DynDigest + Send>)>) -> FileSums {
+    let mut sums = FileSums::default();
+    for (kind, hasher) in hashers {
+        sums.set(kind, &hasher.finalize());
+    }
+    sums
+}
+
+#[cfg(feature = "mmap")]
+unsafe fn map(file: &File) -> Option<memmap2::Mmap> {
+    // SAFETY: upheld by the caller
+    unsafe { memmap2::Mmap::map(file) }.ok()
+}
+
+#[cfg(not(feature = "mmap"))]
+unsafe fn map(_file: &File) -> Option<Vec<u8>> {
+    None
+}
+
+/// Up to [`CHUNK`] bytes, which is less only at the end of `r`
+fn read_chunk(r: &mut impl BufRead) -> Result<Vec<u8>, std::io::Error> {
+    let mut chunk = Vec::with_capacity(CHUNK);
+    r.take(CHUNK as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// Just the sha256 of `r`, which is all most checks compare
+pub fn sha256(r: impl BufRead) -> Result<[u8; 32], std::io::Error> {
+    let mut sha256 = Sha256::new();
+    read_chunks(r, |valid_buf| Digest::update(&mut sha256, valid_buf))?;
+    Ok(sha256.finalize().into())
+}
+
+fn read_chunks(mut r: impl BufRead, mut f: impl FnMut(&[u8])) -> Result<(), std::io::Error> {
+    let mut buf = [0; 1024 * 64];
+    loop {
+        let valid_buf_len = r.read(&mut buf)?;
+        if valid_buf_len == 0 {
+            return Ok(());
+        }
+        f(&buf[..valid_buf_len]);
     }
 }
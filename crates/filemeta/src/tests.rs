@@ -0,0 +1,188 @@
+use super::*;
+
+#[test]
+fn digest_set() {
+    let sums = FileSums::new(&b"abc"[..]).unwrap();
+    let kinds: Vec<DigestKind> = sums.iter().map(|(kind, _)| kind).collect();
+    let apt = [
+        DigestKind::Md5,
+        DigestKind::Sha1,
+        DigestKind::Sha256,
+        DigestKind::Sha512,
+    ];
+    assert_eq!(kinds, apt);
+    assert_eq!(sums.sha256, Some(sha256(&b"abc"[..]).unwrap()));
+
+    let sums = FileSumsBuilder::APT
+        .without(DigestKind::Md5)
+        .with(DigestKind::Blake2b)
+        .hash(&b"abc"[..])
+        .unwrap();
+    assert_eq!(sums.md5, None);
+    assert_eq!(sums.get(DigestKind::Md5), None);
+    let blake2b = sums.blake2b.unwrap();
+    assert_eq!(
+        format!(
+            "{:02x}{:02x}{:02x}{:02x}",
+            blake2b[0], blake2b[1], blake2b[2], blake2b[3]
+        ),
+        "ba80a53f"
+    );
+
+    let sums = FileSums::builder()
+        .with(DigestKind::Sha256)
+        .hash(&b"abc"[..])
+        .unwrap();
+    assert_eq!(
+        sums.sha256.unwrap()[..4],
+        [0xBA, 0x78, 0x16, 0xBF],
+        "sha256 of abc"
+    );
+    assert_eq!(sums.iter().count(), 1);
+}
+
+#[test]
+fn parallel() {
+    // past several chunks, so each digest gets a thread, and exactly a whole number of them
+    for len in [CHUNK * 3 + 12345, CHUNK * 2] {
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let sums = FileSumsBuilder::APT
+            .with(DigestKind::Blake2b)
+            .hash(&data[..])
+            .unwrap();
+        assert_eq!(sums.iter().count(), 5);
+        for (kind, digest) in sums.iter() {
+            let alone = FileSums::builder().with(kind).hash(&data[..]).unwrap();
+            assert_eq!(alone.get(kind), Some(digest), "{}", kind.name());
+        }
+        assert_eq!(sums.sha256, Some(sha256(&data[..]).unwrap()));
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn hash_async() {
+    let data: Vec<u8> = (0..CHUNK + 5).map(|i| (i % 7) as u8).collect();
+    let sums = FileSums::new_async(&data[..]).await.unwrap();
+    assert_eq!(sums, FileSums::new(&data[..]).unwrap());
+
+    let blake2b = FileSums::builder().with(DigestKind::Blake2b);
+    let sums = blake2b.hash_async(&b""[..]).await.unwrap();
+    assert_eq!(sums, blake2b.hash(&b""[..]).unwrap());
+    assert!(sums.blake2b.is_some());
+}
+
+#[test]
+fn hash_file() {
+    let data: Vec<u8> = (0..CHUNK * 2 + 3).map(|i| (i % 13) as u8).collect();
+    let sums = FileSums::new(&data[..]).unwrap();
+    assert_eq!(FileSumsBuilder::APT.hash_slice(&data), sums);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.deb");
+    std::fs::write(&path, &data).unwrap();
+    // SAFETY: nothing else has the file
+    assert_eq!(
+        unsafe { FileSumsBuilder::APT.hash_file(&path) }.unwrap(),
+        sums
+    );
+
+    // empty files can't be mapped
+    let empty = dir.path().join("empty.deb");
+    std::fs::write(&empty, b"").unwrap();
+    let sums = unsafe { FileSumsBuilder::APT.hash_file(&empty) }.unwrap();
+    assert_eq!(sums, FileSums::new(&b""[..]).unwrap());
+}
+
+#[test]
+fn hash_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let deb = dir.path().join("hello_1.0_all.deb");
+    let gone = dir.path().join("gone_1.0_all.deb");
+    std::fs::write(&deb, b"hello").unwrap();
+    std::fs::write(&gone, b"gone").unwrap();
+    let cache_path = dir.path().join("hash-cache");
+    let apt = FileSumsBuilder::APT;
+    let sums = FileSums::new(&b"hello"[..]).unwrap();
+
+    let cache = HashCache::load(&cache_path).unwrap();
+    let metadata = std::fs::metadata(&deb).unwrap();
+    assert_eq!(cache.get(&deb, &metadata, &apt), None);
+    cache.insert(&deb, &metadata, sums);
+    let gone_metadata = std::fs::metadata(&gone).unwrap();
+    cache.insert(&gone, &gone_metadata, FileSums::new(&b"gone"[..]).unwrap());
+    cache.save().unwrap();
+
+    // only what was looked up is saved again
+    let cache = HashCache::load(&cache_path).unwrap();
+    assert_eq!(cache.get(&deb, &metadata, &apt), Some(sums));
+    let blake2b = apt.with(DigestKind::Blake2b);
+    assert_eq!(cache.get(&deb, &metadata, &blake2b), None);
+    cache.save().unwrap();
+    let cache = HashCache::load(&cache_path).unwrap();
+    assert_eq!(cache.get(&gone, &gone_metadata, &apt), None);
+    assert_eq!(cache.get(&deb, &metadata, &apt), Some(sums));
+
+    std::fs::write(&deb, b"hello, changed").unwrap();
+    let changed = std::fs::metadata(&deb).unwrap();
+    assert_eq!(cache.get(&deb, &changed, &apt), None);
+
+    std::fs::write(&cache_path, "some other format\n").unwrap();
+    let cache = HashCache::load(&cache_path).unwrap();
+    assert_eq!(cache.get(&deb, &metadata, &apt), None);
+}
+
+#[test]
+fn verify() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hello_1.0_all.deb");
+    std::fs::write(&path, b"hello").unwrap();
+    let mut expected = FileSums::new(&b"hello"[..]).unwrap();
+    assert_eq!(FileMeta::verify(&path, &expected).unwrap(), []);
+
+    expected.sha1 = FileSums::new(&b"other"[..]).unwrap().sha1;
+    expected.md5 = None;
+    assert_eq!(
+        FileMeta::verify(&path, &expected).unwrap(),
+        [DigestKind::Sha1]
+    );
+
+    std::fs::write(&path, b"corrupted").unwrap();
+    let expected = FileSums::builder()
+        .with(DigestKind::Blake2b)
+        .hash(&b"hello"[..])
+        .unwrap();
+    assert_eq!(
+        FileMeta::verify(&path, &expected).unwrap(),
+        [DigestKind::Blake2b]
+    );
+    let missing = FileMeta::verify(&dir.path().join("missing.deb"), &expected);
+    assert_eq!(missing.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn hash_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut paths = Vec::new();
+    for i in 0..20 {
+        let path = dir.path().join(format!("{i}.deb"));
+        std::fs::write(&path, i.to_string().repeat(i * 1000)).unwrap();
+        paths.push(path);
+    }
+    paths.insert(7, dir.path().join("missing.deb"));
+
+    let workers = std::num::NonZeroUsize::new(3).unwrap();
+    let hashed = FileSumsBuilder::APT.hash_files(&paths, workers);
+    assert_eq!(hashed.len(), paths.len());
+    for (path, sums) in paths.iter().zip(hashed) {
+        match std::fs::read(path) {
+            Ok(data) => assert_eq!(sums.unwrap(), FileSums::new(&data[..]).unwrap()),
+            Err(_) => assert!(sums.is_err()),
+        }
+    }
+    assert!(
+        FileSumsBuilder::APT
+            .hash_files::<&Path>(&[], workers)
+            .is_empty()
+    );
+}
@@ -0,0 +1,334 @@
+//! End-to-end checks of a godsvagn setup, so first-time setup problems show up all at once
+//! instead of one opaque error at a time.
+
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use config::{Config, ServerConfig};
+use pgp::{
+    composed::{ArmorOptions, CleartextSignedMessage, Deserializable, SignedSecretKey},
+    types::Password,
+};
+use tempfile::TempDir;
+
+#[derive(argh::FromArgs)]
+#[argh(
+    subcommand,
+    name = "doctor",
+    description = "Check that the config, key, and directories work together"
+)]
+pub struct DoctorArgs {
+    #[argh(option, short = 'c')]
+    /// config file for godsvagn
+    config: PathBuf,
+    #[argh(switch)]
+    /// skip regenerating the repository from the deb directory
+    skip_regenerate: bool,
+    #[argh(option)]
+    /// also run `apt-get update` against the regenerated repository in a container of this
+    /// image, like `debian:stable`
+    apt_image: Option<String>,
+    #[argh(option, default = "String::from(\"docker\")")]
+    /// what runs that container, like `podman` (default docker)
+    container_runtime: String,
+}
+
+#[derive(Default)]
+pub(crate) struct Checklist {
+    pub(crate) failed: bool,
+}
+
+impl Checklist {
+    pub(crate) fn check<T>(&mut self, name: &str, result: Result<T, Box<dyn Error>>) -> Option<T> {
+        match result {
+            Ok(v) => {
+                println!("[ok]   {name}");
+                Some(v)
+            }
+            Err(e) => {
+                println!("[fail] {name}: {e}");
+                self.failed = true;
+                None
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        if self.failed {
+            Err("some checks failed".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub fn doctor(args: DoctorArgs) -> Result<(), Box<dyn Error>> {
+    let mut checks = Checklist::default();
+
+    let Some(config) = checks.check(
+        "config parses",
+        Config::load(&args.config).map_err(Into::into),
+    ) else {
+        return checks.finish();
+    };
+    for name in &config.unknown_overrides {
+        println!("[warn] ignoring {name}, which doesn't override any setting");
+    }
+    let Some(server) = checks.check(
+        "config has a [server] section",
+        config.server().cloned().map_err(Into::into),
+    ) else {
+        return checks.finish();
+    };
+
+    let key = checks.check("signing key loads", load_key(&server.keyfile));
+    if let Some(key) = &key {
+        checks.check("signing key can sign", test_sign(key));
+    }
+
+    checks.check("deb directory is writable", writable(&server.deb_directory));
+    let repo_parent = repo_parent(&server);
+    checks.check("repo directory's parent is writable", writable(repo_parent));
+
+    if let Some(key) = &key
+        && !args.skip_regenerate
+    {
+        let repo = checks.check(
+            "test regeneration round-trips",
+            regenerate(&args.config, &config, &server, key),
+        );
+        if let (Some((_work_dir, repo)), Some(image)) = (&repo, &args.apt_image) {
+            checks.check(
+                &format!("apt in {image} can update from it"),
+                apt_update(&args.container_runtime, image, &config, repo, key),
+            );
+        }
+    }
+
+    checks.finish()
+}
+
+pub(crate) fn repo_parent(server: &ServerConfig) -> &Path {
+    match server.repo_directory.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+fn load_key(keyfile: &Path) -> Result<SignedSecretKey, Box<dyn Error>> {
+    let key = SignedSecretKey::from_armor_file(keyfile)
+        .map_err(|e| format!("could not read {}: {e}", keyfile.display()))?
+        .0;
+    key.verify()?;
+    Ok(key)
+}
+
+fn test_sign(key: &SignedSecretKey) -> Result<(), Box<dyn Error>> {
+    let message = CleartextSignedMessage::sign(
+        rand::thread_rng(),
+        "godsvagn doctor\n",
+        &key.primary_key,
+        &Password::empty(),
+    )?;
+    message.verify(&key.signed_public_key())?;
+    Ok(())
+}
+
+pub(crate) fn writable(dir: &Path) -> Result<(), Box<dyn Error>> {
+    tempfile::tempfile_in(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    Ok(())
+}
+
+/// The regenerated repository, in a directory that's removed once it's dropped
+fn regenerate(
+    config_path: &Path,
+    config: &Config,
+    server: &ServerConfig,
+    key: &SignedSecretKey,
+) -> Result<(TempDir, PathBuf), Box<dyn Error>> {
+    let work_dir = tempfile::tempdir()?;
+    let output_dir = work_dir.path().join("repo");
+    match &server.repogen_command {
+        Some(command) => run_repogen(command, config_path, server, &output_dir)?,
+        None => {
+            let keyfiles: Vec<PathBuf> = server.keyfiles().map(Path::to_owned).collect();
+            let run = godsvagn_repogen::Run {
+                output_dir: &output_dir,
+                input_dir: &server.deb_directory,
+                keyfiles: &keyfiles,
+                unsigned: false,
+                overwrite: false,
+            };
+            let published = godsvagn_repogen::generate(Config::load(config_path)?, &run)?;
+            godsvagn_repogen::all_published(&published)?;
+        }
+    }
+
+    for path in in_release_paths(config, &output_dir) {
+        let name = path.strip_prefix(&output_dir).unwrap_or(&path).display();
+        let in_release =
+            std::fs::read_to_string(&path).map_err(|e| format!("could not read {name}: {e}"))?;
+        let (message, _headers) = CleartextSignedMessage::from_string(&in_release)?;
+        message
+            .verify(&key.signed_public_key())
+            .map_err(|e| format!("{name} does not verify against the signing key: {e}"))?;
+    }
+    Ok((work_dir, output_dir))
+}
+
+/// Where the InRelease of every suite in a repository is. A single suite has its own at the
+/// top of the repository, and `[[suites]]` each have theirs in `dists/<suite>/`.
+pub(crate) fn in_release_paths(config: &Config, repo: &Path) -> Vec<PathBuf> {
+    if config.suites.is_empty() {
+        return vec![repo.join("InRelease")];
+    }
+    config
+        .suites
+        .iter()
+        .map(|suite| repo.join("dists").join(&suite.suite).join("InRelease"))
+        .collect()
+}
+
+/// `apt-get update` in a throwaway container, with nothing but the regenerated repository
+/// in its sources, which checks it the way clients will read it, signatures and all
+fn apt_update(
+    runtime: &str,
+    image: &str,
+    config: &Config,
+    repo: &Path,
+    key: &SignedSecretKey,
+) -> Result<(), Box<dyn Error>> {
+    let keyring = repo.with_file_name("keyring.asc");
+    let public = key
+        .signed_public_key()
+        .to_armored_string(ArmorOptions::default())?;
+    std::fs::write(&keyring, public)?;
+
+    let sources = AptSources::new(config, repo)?;
+    let script = format!(
+        "rm -f /etc/apt/sources.list /etc/apt/sources.list.d/* && \
+         printf '%s' {} > /etc/apt/sources.list.d/godsvagn.sources && \
+         apt-get update -o APT::Update::Error-Mode=any",
+        shell_quote(&sources.stanzas)
+    );
+    let mut cmd = std::process::Command::new(runtime);
+    cmd.args(["run", "--rm", "--network", "none", "--mount"])
+        .arg(mount(&keyring, "/etc/apt/keyrings/godsvagn.asc"));
+    for (source, target) in &sources.mounts {
+        cmd.arg("--mount").arg(mount(source, target));
+    }
+    let output = cmd
+        .args([image, "sh", "-c", &script])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("could not run {runtime}: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("apt-get update failed: {}", stderr.trim()).into());
+    }
+    Ok(())
+}
+
+/// `--mount` rather than `--volume`, which can't tell a `:` in a path from its separator
+fn mount(source: &Path, target: &str) -> String {
+    format!(
+        "type=bind,source={},target={target},readonly",
+        source.display()
+    )
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// How a container sees a regenerated repository: where to mount it, and the deb822 sources
+/// adding every suite in it from `file:/srv/repo`
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct AptSources {
+    pub(crate) mounts: Vec<(PathBuf, String)>,
+    pub(crate) stanzas: String,
+}
+
+impl AptSources {
+    pub(crate) fn new(config: &Config, repo: &Path) -> Result<Self, Box<dyn Error>> {
+        let stanza = |suite: &str, release: &Path| -> Result<String, Box<dyn Error>> {
+            let mut stanza = format!("Types: deb\nURIs: file:/srv/repo\nSuites: {suite}\n");
+            if let Some(components) = release_field(release, "Components")? {
+                stanza.push_str(&format!("Components: {components}\n"));
+            }
+            stanza.push_str("Signed-By: /etc/apt/keyrings/godsvagn.asc\n");
+            Ok(stanza)
+        };
+        let whole = vec![(repo.to_owned(), "/srv/repo".to_owned())];
+        if config.release.flat {
+            return Ok(Self {
+                mounts: whole,
+                stanzas: stanza("./", &repo.join("Release"))?,
+            });
+        }
+        if config.suites.is_empty() {
+            // a single suite's Release files are at the top of the repository, next to the
+            // pool, so they go where apt looks for the suite
+            let suite = &config.release.suite;
+            return Ok(Self {
+                mounts: vec![
+                    (repo.to_owned(), format!("/srv/repo/dists/{suite}")),
+                    (repo.join("pool"), "/srv/repo/pool".to_owned()),
+                ],
+                stanzas: stanza(suite, &repo.join("Release"))?,
+            });
+        }
+        let stanzas = config
+            .suites
+            .iter()
+            .map(|suite| {
+                let release = repo.join("dists").join(&suite.suite).join("Release");
+                stanza(&suite.suite, &release)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            mounts: whole,
+            stanzas: stanzas.join("\n"),
+        })
+    }
+}
+
+/// The value of `name` in the Release file at `path`
+fn release_field(path: &Path, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let release = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+    Ok(release.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key == name).then(|| value.trim().to_owned())
+    }))
+}
+
+/// The server's `repogen_command`, run the way the server runs it
+fn run_repogen(
+    command: &str,
+    config_path: &Path,
+    server: &ServerConfig,
+    output_dir: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let mut cmd = std::process::Command::new(command);
+    cmd.arg("--config")
+        .arg(config_path)
+        .arg("--output-dir")
+        .arg(output_dir)
+        .arg("--input-dir")
+        .arg(&server.deb_directory);
+    for keyfile in server.keyfiles() {
+        cmd.arg("--keyfile").arg(keyfile);
+    }
+    let status = cmd
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| format!("could not run {command}: {e}"))?;
+    if !status.success() {
+        return Err(format!("{command} exited with {status}").into());
+    }
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use std::{fs::OpenOptions, io::Write, path::PathBuf, str::FromStr, time::Duration};
+
+use base16ct::HexDisplay;
+use doctor::DoctorArgs;
+use pgp::{
+    composed::{ArmorOptions, Deserializable, KeyType, SecretKeyParamsBuilder, SignedSecretKey},
+    types::{KeyDetails, PublicKeyTrait},
+};
+
+mod doctor;
+#[cfg(test)]
+mod tests;
+
+#[derive(argh::FromArgs)]
+#[argh(description = "Administrative tools for godsvagn repositories")]
+struct Args {
+    #[argh(subcommand)]
+    command: Command,
+}
+
+#[derive(argh::FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Keygen(KeygenArgs),
+    InspectKey(InspectKeyArgs),
+    Doctor(DoctorArgs),
+}
+
+#[derive(argh::FromArgs)]
+#[argh(
+    subcommand,
+    name = "keygen",
+    description = "Generate an OpenPGP key suitable for signing a repository"
+)]
+struct KeygenArgs {
+    #[argh(option, short = 'u')]
+    /// user id to put on the key, like `My Repo <repo@example.com>`
+    user_id: String,
+    #[argh(option, short = 's')]
+    /// where to write the armored secret key
+    secret_out: PathBuf,
+    #[argh(option, short = 'p')]
+    /// where to write the armored public key
+    public_out: PathBuf,
+    #[argh(option, short = 'a', default = "KeyAlgorithm::Ed25519")]
+    /// key algorithm, either `ed25519` or `rsa4096` (default ed25519)
+    algorithm: KeyAlgorithm,
+    #[argh(option, default = "730")]
+    /// days until the key expires, 0 for never (default 730)
+    expiry_days: u64,
+}
+
+#[derive(argh::FromArgs)]
+#[argh(
+    subcommand,
+    name = "inspect-key",
+    description = "Print fingerprints and expiry for a secret keyfile"
+)]
+struct InspectKeyArgs {
+    #[argh(option, short = 'k')]
+    /// key to inspect
+    keyfile: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum KeyAlgorithm {
+    Ed25519,
+    Rsa4096,
+}
+
+impl KeyAlgorithm {
+    fn key_type(self) -> KeyType {
+        match self {
+            // apt's gpgv and sqv both understand v4 EdDSA keys, v6 Ed25519 is still too new
+            Self::Ed25519 => KeyType::Ed25519Legacy,
+            Self::Rsa4096 => KeyType::Rsa(4096),
+        }
+    }
+}
+
+impl FromStr for KeyAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ed25519" | "eddsa" => Ok(Self::Ed25519),
+            "rsa" | "rsa4096" => Ok(Self::Rsa4096),
+            _ => Err(format!("unknown key algorithm `{s}`")),
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Args = argh::from_env();
+    match args.command {
+        Command::Keygen(args) => keygen(args),
+        Command::InspectKey(args) => inspect_key(args),
+        Command::Doctor(args) => doctor::doctor(args),
+    }
+}
+
+fn keygen(args: KeygenArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let expiration = match args.expiry_days {
+        0 => None,
+        days => Some(Duration::from_secs(days * 24 * 60 * 60)),
+    };
+
+    // indexgen signs with the primary key, so it has to carry the signing flag itself
+    // instead of delegating to a subkey
+    let params = SecretKeyParamsBuilder::default()
+        .key_type(args.algorithm.key_type())
+        .can_certify(true)
+        .can_sign(true)
+        .primary_user_id(args.user_id)
+        .expiration(expiration)
+        .build()?;
+    let key = params.generate(rand::thread_rng())?;
+    key.verify()?;
+
+    let secret = key.to_armored_string(ArmorOptions::default())?;
+    let public = key
+        .signed_public_key()
+        .to_armored_string(ArmorOptions::default())?;
+
+    write_new_file(&args.secret_out, secret.as_bytes(), true)?;
+    write_new_file(&args.public_out, public.as_bytes(), false)?;
+
+    println!(
+        "fingerprint: {:X}",
+        HexDisplay(key.fingerprint().as_bytes())
+    );
+    Ok(())
+}
+
+fn inspect_key(args: InspectKeyArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let key = SignedSecretKey::from_armor_file(&args.keyfile)?.0;
+    key.verify().map_err(|e| {
+        format!(
+            "key {} failed self-verification: {e}",
+            args.keyfile.display()
+        )
+    })?;
+
+    println!(
+        "fingerprint: {:X}",
+        HexDisplay(key.fingerprint().as_bytes())
+    );
+    println!("key id: {:X}", HexDisplay(key.key_id().as_ref()));
+    println!("algorithm: {:?}", key.algorithm());
+    println!("created: {}", key.created_at());
+    match key.signed_public_key().expires_at() {
+        Some(expiry) => println!("expires: {expiry}"),
+        None => println!("expires: never"),
+    }
+    for subkey in &key.secret_subkeys {
+        println!(
+            "subkey: {:X} ({:?})",
+            HexDisplay(subkey.key.fingerprint().as_bytes()),
+            subkey.key.algorithm()
+        );
+    }
+    Ok(())
+}
+
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn write_new_file(
+    path: &std::path::Path,
+    data: &[u8],
+    secret: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if secret {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("Could not create {}: {e}", path.display()))?;
+    file.write_all(data)?;
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use config::Config;
+
+use crate::doctor::{AptSources, Checklist, in_release_paths, repo_parent, writable};
+
+const EXAMPLE: &str = include_str!("../../../config.toml");
+
+fn config(extra: &str) -> Config {
+    Config::from_toml_with_env(&format!("{EXAMPLE}\n{extra}"), []).unwrap()
+}
+
+/// A repository with a Release file at `release`, listing `components`
+fn repo_with_release(release: &str, components: &str) -> tempfile::TempDir {
+    let repo = tempfile::tempdir().unwrap();
+    let path = repo.path().join(release);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(
+        path,
+        format!("Origin: godsvagn\nComponents: {components}\n"),
+    )
+    .unwrap();
+    repo
+}
+
+#[test]
+fn checklist_fails_if_any_check_does() {
+    let mut checks = Checklist::default();
+    assert_eq!(checks.check("passes", Ok(1)), Some(1));
+    assert!(!checks.failed);
+    assert_eq!(checks.check::<()>("fails", Err("nope".into())), None);
+    assert!(checks.failed);
+}
+
+#[test]
+fn writable_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    writable(dir.path()).unwrap();
+    let err = writable(&dir.path().join("missing")).unwrap_err();
+    assert!(err.to_string().contains("missing"));
+}
+
+#[test]
+fn repo_parent_of_relative_directory() {
+    let config = config("");
+    let mut server = config.server().unwrap().clone();
+    assert_eq!(repo_parent(&server), Path::new("."));
+    server.repo_directory = "/srv/godsvagn/repo".into();
+    assert_eq!(repo_parent(&server), Path::new("/srv/godsvagn"));
+}
+
+#[test]
+fn apt_sources_single_suite() {
+    let repo = repo_with_release("Release", "main contrib");
+    let sources = AptSources::new(&config(""), repo.path()).unwrap();
+    assert_eq!(
+        sources.mounts,
+        [
+            (repo.path().to_owned(), "/srv/repo/dists/testing".to_owned()),
+            (repo.path().join("pool"), "/srv/repo/pool".to_owned()),
+        ]
+    );
+    assert_eq!(
+        sources.stanzas,
+        "Types: deb\nURIs: file:/srv/repo\nSuites: testing\nComponents: main contrib\n\
+         Signed-By: /etc/apt/keyrings/godsvagn.asc\n"
+    );
+}
+
+#[test]
+fn apt_sources_suites() {
+    let repo = repo_with_release("dists/stable/Release", "main");
+    std::fs::create_dir_all(repo.path().join("dists/unstable")).unwrap();
+    std::fs::write(
+        repo.path().join("dists/unstable/Release"),
+        "Components: main non-free\n",
+    )
+    .unwrap();
+    let config = config(
+        "[[suites]]\nsuite = \"stable\"\ncodename = \"a\"\n\
+         [[suites]]\nsuite = \"unstable\"\ncodename = \"b\"\n",
+    );
+    let sources = AptSources::new(&config, repo.path()).unwrap();
+    assert_eq!(
+        sources.mounts,
+        [(repo.path().to_owned(), "/srv/repo".to_owned())]
+    );
+    let suites: Vec<&str> = sources
+        .stanzas
+        .lines()
+        .filter(|line| line.starts_with("Suites:") || line.starts_with("Components:"))
+        .collect();
+    assert_eq!(
+        suites,
+        [
+            "Suites: stable",
+            "Components: main",
+            "Suites: unstable",
+            "Components: main non-free"
+        ]
+    );
+}
+
+#[test]
+fn apt_sources_without_release() {
+    let repo = tempfile::tempdir().unwrap();
+    let err = AptSources::new(&config(""), repo.path()).unwrap_err();
+    assert!(err.to_string().contains("Release"));
+}
+
+#[test]
+fn in_release_of_every_suite() {
+    let repo = Path::new("/srv/repo");
+    assert_eq!(
+        in_release_paths(&config(""), repo),
+        [repo.join("InRelease")]
+    );
+    let config = config(
+        "[[suites]]\nsuite = \"stable\"\ncodename = \"a\"\n\
+         [[suites]]\nsuite = \"unstable\"\ncodename = \"b\"\n",
+    );
+    assert_eq!(
+        in_release_paths(&config, repo),
+        [
+            repo.join("dists/stable/InRelease"),
+            repo.join("dists/unstable/InRelease")
+        ]
+    );
+}
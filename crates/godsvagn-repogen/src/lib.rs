@@ -0,0 +1,598 @@
+//! Generating a repository out of a directory full of debs, for the godsvagn-repogen binary
+//! and for the server, which can do it in-process instead of running the binary
+
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    path::{Path, PathBuf},
+};
+
+use config::{CompressionLevelsConfig, CompressionPreset, Config, IndexCompression};
+use filemeta::{FileMeta, FileSumsBuilder, HashCache};
+use indexgen::{
+    CompressionLevels, CompressionSet, FileToUpload, GenerateOptions, KeyringPackageOptions,
+    MetalinkOptions, OutputStyle, PdiffHistory, PdiffOptions, PgpSigner, ReleaseMetadata,
+    SignatureHash, Signer, SourcesOptions, Suite,
+};
+use indexmap::IndexMap;
+use package::{KnownFields, Package, PackageMeta};
+use parsedeb::{DuplicatePolicy, FieldChecks, PackageKind, RequiredFields};
+use pgp::composed::{Deserializable, SignedSecretKey};
+
+mod publish;
+
+pub use publish::{PublishError, Published, all_published};
+
+/// Everything about a run that isn't in the config file
+#[derive(Debug, Clone, Copy)]
+pub struct Run<'a> {
+    /// where to generate the repository
+    pub output_dir: &'a Path,
+    /// where to get the debs to generate it from
+    pub input_dir: &'a Path,
+    /// keys to sign the repository with, several while rotating keys
+    pub keyfiles: &'a [PathBuf],
+    /// leave the repository unsigned, for local testing or to sign Release in a separate step
+    pub unsigned: bool,
+    /// delete `output_dir` first if it exists, instead of failing
+    pub overwrite: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    PackageRead(#[from] PackageReadError),
+    #[error(transparent)]
+    Generate(#[from] indexgen::GenerateError),
+    #[error(transparent)]
+    Config(#[from] config::Error),
+    #[error(transparent)]
+    Publish(#[from] PublishError),
+    #[error("could not load signing key: {0}")]
+    Key(#[from] pgp::errors::Error),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("output dir exists, specify --overwrite to delete it")]
+    OutputExists,
+    #[error("reproducible repositories need SOURCE_DATE_EPOCH to be set")]
+    NoSourceDateEpoch,
+    #[error("SOURCE_DATE_EPOCH is not a number of seconds: {0}")]
+    InvalidSourceDateEpoch(String),
+    #[error("invalid date: {0}")]
+    Time(#[from] jiff::Error),
+}
+
+impl Error {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::PackageRead(e) => e.code(),
+            Self::Generate(e) => e.code(),
+            Self::Config(e) => e.code(),
+            Self::Publish(e) => e.code(),
+            Self::Key(_) => "GV5006",
+            Self::Io(_) => "GV5011",
+            Self::OutputExists => "GV5012",
+            Self::NoSourceDateEpoch => "GV5013",
+            Self::InvalidSourceDateEpoch(_) | Self::Time(_) => "GV5014",
+        }
+    }
+}
+
+/// Generate a repository out of `run.input_dir` into `run.output_dir`, and publish it to every
+/// destination in `config`. Failing to publish doesn't fail the generation, so check what's
+/// returned with [`all_published`].
+pub fn generate(config: Config, run: &Run) -> Result<Vec<Published>, Error> {
+    let keys = run
+        .keyfiles
+        .iter()
+        .map(|keyfile| Ok(SignedSecretKey::from_armor_file(keyfile)?.0.primary_key))
+        .collect::<Result<Vec<_>, pgp::errors::Error>>()?;
+
+    if run.overwrite {
+        match std::fs::remove_dir_all(run.output_dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == IoErrorKind::NotFound => {}
+            Err(e) => {
+                let path = run.output_dir.display();
+                return Err(
+                    IoError::new(e.kind(), format!("Could not remove folder {path}")).into(),
+                );
+            }
+        }
+    } else if std::fs::exists(run.output_dir)? {
+        return Err(Error::OutputExists);
+    }
+
+    let parse_options = parsedeb::ParseOptions {
+        compute_installed_size: config.release.compute_installed_size,
+        lenient: config.release.lenient_control,
+        duplicate_policy: if config.release.lenient_control {
+            DuplicatePolicy::AllowIdentical
+        } else {
+            DuplicatePolicy::Reject
+        },
+        // warnings are for uploaders, repogen only needs to agree on what is rejected
+        field_checks: match config.release.field_checks {
+            config::FieldChecks::Reject => FieldChecks::Reject,
+            config::FieldChecks::Off | config::FieldChecks::Warn => FieldChecks::Off,
+        },
+        reject_essential: config.release.reject_essential,
+        ..Default::default()
+    };
+    let components = config.release.components.clone();
+    let data = DataWanted {
+        appstream: config.release.dep11,
+        changelog: config.release.changelogs.is_some(),
+    };
+    let hash_cache = match &config.release.hash_cache {
+        Some(path) => Some(HashCache::load(path)?),
+        None => None,
+    };
+    let read_input = |input_dir: &Path| -> Result<Vec<Package>, Error> {
+        let mut packages = Vec::new();
+        get_packages(
+            input_dir,
+            &parse_options,
+            data,
+            hash_cache.as_ref(),
+            &components,
+            None,
+            &mut packages,
+        )?;
+        for (start_path, package) in &packages {
+            let end_path = run.output_dir.join(&*package.meta.file.path);
+            std::fs::create_dir_all(end_path.parent().unwrap_or(run.output_dir))?;
+            std::fs::copy(start_path, &end_path).map_err(|e| {
+                let (start, end) = (start_path.display(), end_path.display());
+                IoError::new(e.kind(), format!("Could not copy {start} to {end}"))
+            })?;
+        }
+        Ok(packages.into_iter().map(|v| v.1).collect())
+    };
+
+    // how reproducible builds pass the time everything should say it was made
+    let now = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => {
+            let epoch = epoch
+                .parse()
+                .map_err(|_| Error::InvalidSourceDateEpoch(epoch))?;
+            jiff::Timestamp::from_second(epoch)?
+        }
+        Err(_) if config.release.reproducible => return Err(Error::NoSourceDateEpoch),
+        Err(_) => jiff::Timestamp::now(),
+    };
+
+    // snapshots are taken once a day, and kept as they were first published
+    let snapshot = config.snapshots.as_ref().and_then(|snapshots| {
+        let id = now.strftime("%Y-%m-%d").to_string();
+        (!snapshots.directory.join(&id).exists()).then_some(id)
+    });
+
+    let destinations = config.publish;
+    let rc = config.release;
+    let release_meta = ReleaseMetadata {
+        origin: rc.origin,
+        label: rc.label,
+        suite: rc.suite,
+        codename: rc.codename,
+        version: rc.version,
+        description: rc.description,
+        date: jiff::fmt::rfc2822::to_string(&now.in_tz("UTC")?)?,
+        not_automatic: rc.not_automatic,
+        but_automatic_upgrades: rc.but_automatic_upgrades,
+        signed_by: rc.signed_by,
+        changelogs: rc
+            .changelogs
+            .as_ref()
+            .map(|url| format!("{}/@CHANGEPATH@_changelog", url.trim_end_matches('/'))),
+        snapshots: config.snapshots.as_ref().and_then(|s| s.url.clone()),
+        extra_headers: rc.extra_headers.into_iter().collect(),
+    };
+
+    // Debian's naming, which sorts in the order versions were made
+    let pdiff_name = now.strftime("%Y-%m-%d-%H%M.%S").to_string();
+    let options = GenerateOptions {
+        style: if rc.apt_ftparchive_compat {
+            OutputStyle::AptFtparchive
+        } else {
+            OutputStyle::Native
+        },
+        metalink: config.metalink.map(|m| MetalinkOptions {
+            mirrors: m.mirrors,
+            min_size: m.min_size,
+        }),
+        translations: rc.translations,
+        separate_arch_all: rc.separate_arch_all,
+        compression: CompressionSet {
+            gzip: rc.compression.contains(&IndexCompression::Gzip),
+            xz: rc.compression.contains(&IndexCompression::Xz),
+            zstd: rc.compression.contains(&IndexCompression::Zstd),
+            bzip2: rc.compression.contains(&IndexCompression::Bzip2),
+            levels: compression_levels(&config.compression_levels),
+        },
+        pdiff: match &config.pdiff {
+            Some(pdiff) => Some(PdiffOptions {
+                name: pdiff_name.clone(),
+                history: PdiffHistory::load(&pdiff.state_directory)?,
+            }),
+            None => None,
+        },
+        dep11: rc.dep11,
+        changelogs: rc.changelogs.is_some(),
+        strict_multi_arch: rc.strict_multi_arch,
+        manifest: rc.manifest,
+        sources: rc.sources.map(|uri| SourcesOptions {
+            uri,
+            signed_by: rc.sources_signed_by,
+        }),
+        snapshot: snapshot.clone(),
+        keyring_package: config.keyring_package.map(|k| KeyringPackageOptions {
+            name: k
+                .name
+                .unwrap_or_else(|| keyring_package_name(&release_meta.origin)),
+            version: k.version,
+            maintainer: k.maintainer,
+        }),
+    };
+    let signer = PgpSigner {
+        keys,
+        hash: match rc.signature_hash {
+            config::SignatureHash::Default => SignatureHash::KeyDefault,
+            config::SignatureHash::Sha256 => SignatureHash::Sha256,
+            config::SignatureHash::Sha384 => SignatureHash::Sha384,
+            config::SignatureHash::Sha512 => SignatureHash::Sha512,
+        },
+        created: Some(now.into()),
+    };
+    let signer = (!run.unsigned).then_some(&signer as &dyn Signer);
+
+    // the Packages indexes, which are kept for diffing against next time
+    let mut to_diff = Vec::new();
+    let mut sink = |path: &str, data: &[u8]| -> Result<(), IoError> {
+        let create_file_at = run.output_dir.join(path);
+        let parent_dir = create_file_at.parent().unwrap_or(run.output_dir);
+        std::fs::create_dir_all(parent_dir).map_err(|e| {
+            let path = parent_dir.display();
+            IoError::new(e.kind(), format!("Could not create directory {path}"))
+        })?;
+        std::fs::write(&create_file_at, data).map_err(|e| {
+            let path = create_file_at.display();
+            IoError::new(e.kind(), format!("Unable to create file {path}"))
+        })?;
+        let in_snapshot = snapshot.as_ref().is_some_and(|id| {
+            path.starts_with(&format!("snapshots/{id}/"))
+                || path.contains(&format!("/snapshots/{id}/"))
+        });
+        // each snapshot is kept in a directory named after it
+        if let (Some(snapshots), Some(id), true) = (&config.snapshots, &snapshot, in_snapshot) {
+            let kept = snapshots.directory.join(id).join(path);
+            std::fs::create_dir_all(kept.parent().unwrap_or(&snapshots.directory))?;
+            std::fs::write(kept, data)?;
+        } else if config.pdiff.is_some() && PdiffHistory::tracks(path) {
+            to_diff.push(FileToUpload {
+                destination_path: path.into(),
+                data: data.into(),
+            });
+        }
+        Ok(())
+    };
+
+    if rc.flat {
+        let packages = read_input(run.input_dir)?;
+        indexgen::generate_flat_into(&release_meta, signer, &packages, &options, &mut sink)?;
+    } else if config.suites.is_empty() {
+        let packages = read_input(run.input_dir)?;
+        indexgen::generate_files_into(&release_meta, signer, &packages, &options, &mut sink)?;
+    } else {
+        // each suite's debs are in a directory of the input named after it
+        let mut suites = Vec::with_capacity(config.suites.len());
+        for suite in config.suites {
+            let packages = read_input(&run.input_dir.join(&suite.suite))?;
+            let metadata = ReleaseMetadata {
+                suite: suite.suite,
+                codename: suite.codename,
+                version: suite.version,
+                description: suite.description,
+                not_automatic: suite.not_automatic,
+                but_automatic_upgrades: suite.but_automatic_upgrades,
+                ..release_meta.clone()
+            };
+            suites.push((metadata, packages));
+        }
+        let suites: Vec<Suite> = suites
+            .iter()
+            .map(|(metadata, packages)| Suite {
+                metadata: metadata.clone(),
+                packages,
+            })
+            .collect();
+        indexgen::generate_suites_into(&suites, signer, &options, &mut sink)?;
+    }
+
+    if let Some(pdiff) = &config.pdiff {
+        PdiffHistory::save(&pdiff.state_directory, &pdiff_name, &to_diff, pdiff.keep)?;
+    }
+    if let Some(hash_cache) = &hash_cache {
+        hash_cache.save()?;
+    }
+    if let Some(snapshots) = &config.snapshots {
+        keep_snapshots(&snapshots.directory, run.output_dir, snapshot.is_some())?;
+    }
+
+    Ok(publish::publish_all(run.output_dir, &destinations))
+}
+
+/// `<origin>-archive-keyring`, with the origin made into something a package name can start with
+/// Where in the snapshots directory the pool files of every snapshot are kept, next to the
+/// snapshots named after the day they were taken
+const SNAPSHOT_POOL: &str = "pool";
+
+/// Put every snapshot back into the repository, along with the pool files they list, which
+/// may have been removed from the input since. When one was `taken` in this run, the pool
+/// files it lists are kept first. Files are hard linked where they can be, so this stays cheap
+/// however many snapshots there are.
+fn keep_snapshots(directory: &Path, output_dir: &Path, taken: bool) -> Result<(), Error> {
+    let pool = output_dir.join("pool");
+    if taken {
+        link_tree(&pool, &directory.join(SNAPSHOT_POOL))?;
+    }
+    if !directory.exists() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        if entry.file_name() == SNAPSHOT_POOL {
+            link_tree(&entry.path(), &pool)?;
+        } else {
+            link_tree(&entry.path(), output_dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Hard link every file under `source` into `destination` that isn't there yet, copying
+/// instead when they're on different filesystems
+fn link_tree(source: &Path, destination: &Path) -> Result<(), Error> {
+    if !source.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(destination)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let target = destination.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_tree(&entry.path(), &target)?;
+        } else if !target.exists() && std::fs::hard_link(entry.path(), &target).is_err() {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn keyring_package_name(origin: &str) -> String {
+    let origin: String = origin
+        .chars()
+        .map(|c| c.to_ascii_lowercase())
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '+' | '.' | '-' => c,
+            _ => '-',
+        })
+        .collect();
+    format!("{origin}-archive-keyring")
+}
+
+fn compression_levels(config: &CompressionLevelsConfig) -> CompressionLevels {
+    let preset = match config.preset {
+        CompressionPreset::Best => CompressionLevels::BEST,
+        CompressionPreset::Fast => CompressionLevels::FAST,
+    };
+    CompressionLevels {
+        gzip: config.gzip.unwrap_or(preset.gzip),
+        xz: config.xz.unwrap_or(preset.xz),
+        zstd: config.zstd.unwrap_or(preset.zstd),
+        bzip2: config.bzip2.unwrap_or(preset.bzip2),
+    }
+}
+
+/// Read every deb under `dir`. Debs under a top-level directory named after one of `components`
+/// go in that component, and the rest go in the first. The files `data` asks for are read out
+/// of each too. Debs `hash_cache` has the digests of aren't hashed again.
+fn get_packages(
+    dir: &Path,
+    options: &parsedeb::ParseOptions,
+    data: DataWanted,
+    hash_cache: Option<&HashCache>,
+    components: &[String],
+    component: Option<&str>,
+    write_into: &mut Vec<(PathBuf, Package)>,
+) -> Result<(), PackageReadError> {
+    let dir = match std::fs::read_dir(dir) {
+        Err(e) if e.kind() == IoErrorKind::NotFound => {
+            return Err(PackageReadError::Io(IoError::new(
+                e.kind(),
+                format!("Could not list directory {}: not found", dir.display()),
+            )));
+        }
+        Err(e) if e.kind() == IoErrorKind::NotADirectory => {
+            return Err(PackageReadError::Io(IoError::new(
+                e.kind(),
+                format!(
+                    "Could not list directory {}: not a directory",
+                    dir.display()
+                ),
+            )));
+        }
+        Err(e) => return Err(e.into()),
+        Ok(v) => v,
+    };
+    for entry in dir {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        // skip .DS_Store and friends, which desktop file managers drop everywhere
+        if entry.file_name().as_encoded_bytes().starts_with(b".") {
+            continue;
+        }
+        if file_type.is_dir() {
+            let name = entry.file_name();
+            let component = component.or_else(|| {
+                components
+                    .iter()
+                    .map(String::as_str)
+                    .find(|c| name.to_str() == Some(c))
+            });
+            get_packages(
+                &path, options, data, hash_cache, components, component, write_into,
+            )?;
+        } else if file_type.is_file() {
+            let component = component.unwrap_or(&components[0]);
+            let package = read_package(&path, options, data, hash_cache, component)?;
+            write_into.push((path, package));
+        } else {
+            return Err(PackageReadError::UnsupportedFileKind);
+        }
+    }
+    Ok(())
+}
+
+/// Files to read out of the data tarball of every package, for indexes that need more than
+/// its control file
+#[derive(Debug, Clone, Copy)]
+struct DataWanted {
+    /// AppStream files, for DEP-11
+    appstream: bool,
+    changelog: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PackageReadError {
+    #[error("unsupported file type")]
+    UnsupportedFileKind,
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    PackageRead(#[from] parsedeb::Error),
+    #[error("non-utf-8 path encountered")]
+    InvalidPath,
+    #[error("Files more than 4 gb are only supported on 64 bit platforms")]
+    FileTooBig,
+}
+
+impl PackageReadError {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnsupportedFileKind => "GV5001",
+            Self::Io(_) => "GV5002",
+            Self::PackageRead(e) => e.code(),
+            Self::InvalidPath => "GV5003",
+            Self::FileTooBig => "GV5004",
+        }
+    }
+}
+
+fn read_package(
+    p: &Path,
+    options: &parsedeb::ParseOptions,
+    data: DataWanted,
+    hash_cache: Option<&HashCache>,
+    component: &str,
+) -> Result<Package, PackageReadError> {
+    let metadata = std::fs::metadata(p)?;
+    // checked up front, since 32 bit platforms couldn't map it anyway
+    let size = metadata
+        .len()
+        .try_into()
+        .map_err(|_| PackageReadError::FileTooBig)?;
+    // SAFETY: nothing rewrites debs in place, the server only ever creates new ones
+    let deb = unsafe { parsedeb::map_deb(p)? };
+    let named_kind = p.file_name().and_then(|name| name.to_str());
+    let named_kind = named_kind.and_then(PackageKind::from_file_name);
+    let options = &parsedeb::ParseOptions {
+        kind: named_kind.unwrap_or_default(),
+        ..*options
+    };
+    let mut fields: IndexMap<Box<str>, Box<str>> = if options.compute_installed_size {
+        parsedeb::deb_to_control_with(&deb[..], options)?.0
+    } else {
+        let controlfile = parsedeb::control_from_slice(&deb, options)?;
+        parsedeb::get_control_with(&controlfile, options)?
+            .into_iter()
+            .map(parsedeb::pack)
+            .collect()
+    };
+
+    let (appstream, changelog) = if data.appstream || data.changelog {
+        let name = parsedeb::ControlFields::field(&fields, "Package").unwrap_or_default();
+        let is_changelog = |path: &str| data.changelog && indexgen::is_changelog_file(name, path);
+        let files = parsedeb::deb_to_data_files(&deb[..], &options.limits, |path| {
+            (data.appstream && indexgen::is_appstream_file(path)) || is_changelog(path)
+        })?;
+        let (changelogs, appstream): (Vec<_>, Vec<_>) =
+            files.into_iter().partition(|file| is_changelog(&file.path));
+        // changelog.gz is the upstream one when there's a changelog.Debian.gz too
+        let changelog = changelogs
+            .into_iter()
+            .max_by_key(|file| file.path.ends_with("/changelog.Debian.gz"))
+            .map(|file| file.contents);
+        (appstream, changelog)
+    } else {
+        (Vec::new(), None)
+    };
+
+    // so Description-md5 is taken over what the index will contain
+    parsedeb::normalize_description(&mut fields);
+    let digests = FileSumsBuilder::APT;
+    let sums = match hash_cache.and_then(|cache| cache.get(p, &metadata, &digests)) {
+        Some(sums) => sums,
+        None => {
+            let sums = digests.hash_slice(&deb);
+            if let Some(cache) = hash_cache {
+                cache.insert(p, &metadata, sums);
+            }
+            sums
+        }
+    };
+
+    let file_meta = FileMeta {
+        path: p.to_str().ok_or(PackageReadError::InvalidPath)?.into(),
+        size,
+        sums,
+    };
+
+    let description_md5 = package::description_md5(&fields);
+
+    let meta = PackageMeta {
+        file: file_meta,
+        description_md5,
+    };
+
+    // a udeb that says so is one whatever its file is called
+    let kind = match parsedeb::ControlFields::field(&fields, "Package-Type") {
+        Some("udeb") => PackageKind::Udeb,
+        _ => options.kind,
+    };
+    let required = RequiredFields::from_map_for(&fields, kind)?;
+    let path = package::pool_path(&fields, component, kind)?.into_boxed_str();
+    let RequiredFields {
+        package: name,
+        architecture,
+        version,
+        ..
+    } = required;
+    let package = Package {
+        meta: PackageMeta {
+            file: FileMeta { path, ..meta.file },
+            ..meta
+        },
+        name,
+        architecture,
+        version,
+        component: component.into(),
+        known: KnownFields::from_fields(&fields)?,
+        fields,
+        appstream,
+        changelog,
+    };
+    Ok(package)
+}
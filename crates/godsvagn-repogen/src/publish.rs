@@ -0,0 +1,325 @@
+//! Push a generated repository to every configured destination.
+//!
+//! Each destination is synced in phases, so clients never see a Release file that refers to
+//! indexes or packages which haven't arrived yet: first the pool, then the indexes, then the
+//! Release files along with removal of anything stale. OCI registries get the whole
+//! repository in one atomic manifest push instead.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use config::PublishDestination;
+
+/// Files that must be replaced after everything they describe, at the top of the repository
+/// or in `dists/<suite>/`
+const RELEASE_FILES: [&str; 3] = ["InRelease", "Release", "Release.gpg"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+enum Phase {
+    Pool,
+    Indexes,
+    Release,
+}
+
+impl Phase {
+    const ALL: [Self; 3] = [Self::Pool, Self::Indexes, Self::Release];
+
+    /// The phase a file of the repository goes up in, by its path relative to the top of it
+    fn of(path: &Path) -> Self {
+        if path.starts_with("pool") {
+            Self::Pool
+        } else if path
+            .file_name()
+            .is_some_and(|name| RELEASE_FILES.iter().any(|r| name == *r))
+        {
+            Self::Release
+        } else {
+            Self::Indexes
+        }
+    }
+}
+
+/// How publishing to one destination went
+#[derive(Debug)]
+pub struct Published {
+    /// the directory, rsync target, OCI reference or S3 URL published to
+    pub destination: String,
+    pub result: Result<(), PublishError>,
+}
+
+/// Publish `source` to every destination, continuing past failures, and report how each one
+/// went, for the caller to print or log.
+pub fn publish_all(source: &Path, destinations: &[PublishDestination]) -> Vec<Published> {
+    destinations
+        .iter()
+        .map(|destination| Published {
+            destination: destination_name(destination),
+            result: publish(source, destination),
+        })
+        .collect()
+}
+
+/// An error naming every destination publishing to failed, if any did
+pub fn all_published(published: &[Published]) -> Result<(), PublishError> {
+    let failures: Vec<String> = published
+        .iter()
+        .filter(|published| published.result.is_err())
+        .map(|published| published.destination.clone())
+        .collect();
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(PublishError::Failed(failures))
+    }
+}
+
+fn destination_name(destination: &PublishDestination) -> String {
+    match destination {
+        PublishDestination::Directory { path } => path.display().to_string(),
+        PublishDestination::Rsync { target } => target.clone(),
+        PublishDestination::Oci { reference } => reference.clone(),
+        PublishDestination::S3 { bucket, prefix, .. } => s3_url(bucket, prefix.as_deref()),
+    }
+}
+
+fn publish(source: &Path, destination: &PublishDestination) -> Result<(), PublishError> {
+    for phase in Phase::ALL {
+        match destination {
+            PublishDestination::Directory { path } => sync_directory(source, path, phase)?,
+            PublishDestination::Rsync { target } => sync_rsync(source, target, phase)?,
+            PublishDestination::Oci { reference } => sync_oci(source, reference, phase)?,
+            PublishDestination::S3 {
+                bucket,
+                prefix,
+                endpoint_url,
+            } => sync_s3(
+                source,
+                &s3_url(bucket, prefix.as_deref()),
+                endpoint_url.as_deref(),
+                phase,
+            )?,
+        }
+    }
+    Ok(())
+}
+
+fn sync_directory(source: &Path, destination: &Path, phase: Phase) -> Result<(), PublishError> {
+    match phase {
+        Phase::Pool => copy_tree(&source.join("pool"), &destination.join("pool")),
+        Phase::Indexes => copy_phase(source, destination, Phase::Indexes),
+        Phase::Release => {
+            copy_phase(source, destination, Phase::Release)?;
+            prune(source, destination)
+        }
+    }
+}
+
+/// Copy the files of `source` that go up in `phase`
+fn copy_phase(source: &Path, destination: &Path, phase: Phase) -> Result<(), PublishError> {
+    let mut files = Vec::new();
+    list_files(source, Path::new(""), &mut files)?;
+    for file in files.iter().filter(|file| Phase::of(file) == phase) {
+        let target = destination.join(file);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(source.join(file), target)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn copy_tree(source: &Path, destination: &Path) -> Result<(), PublishError> {
+    if !source.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(destination)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let target = destination.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_tree(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove everything in `destination` that doesn't exist in `source`
+fn prune(source: &Path, destination: &Path) -> Result<(), PublishError> {
+    for entry in std::fs::read_dir(destination)? {
+        let entry = entry?;
+        let counterpart = source.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+        if !counterpart.exists() {
+            if is_dir {
+                std::fs::remove_dir_all(entry.path())?;
+            } else {
+                std::fs::remove_file(entry.path())?;
+            }
+        } else if is_dir {
+            prune(&counterpart, &entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn sync_rsync(source: &Path, target: &str, phase: Phase) -> Result<(), PublishError> {
+    let target = target.trim_end_matches('/');
+    let mut cmd = Command::new("rsync");
+    cmd.arg("--archive");
+    match phase {
+        Phase::Pool => {
+            cmd.arg(dir_arg(&source.join("pool")))
+                .arg(format!("{target}/pool/"));
+        }
+        Phase::Indexes => {
+            // unanchored, so they match in every directory
+            for name in RELEASE_FILES {
+                cmd.arg(format!("--exclude={name}"));
+            }
+            cmd.arg(dir_arg(source)).arg(format!("{target}/"));
+        }
+        Phase::Release => {
+            cmd.arg("--delete-after")
+                .arg(dir_arg(source))
+                .arg(format!("{target}/"));
+        }
+    }
+    run(cmd)
+}
+
+fn sync_s3(
+    source: &Path,
+    url: &str,
+    endpoint_url: Option<&str>,
+    phase: Phase,
+) -> Result<(), PublishError> {
+    let mut cmd = Command::new("aws");
+    if let Some(endpoint_url) = endpoint_url {
+        cmd.arg("--endpoint-url").arg(endpoint_url);
+    }
+    cmd.arg("s3").arg("sync");
+    match phase {
+        Phase::Pool => {
+            cmd.arg(source.join("pool")).arg(format!("{url}/pool"));
+        }
+        Phase::Indexes => {
+            // `*` matches across `/`, so this is every directory below the top too
+            for name in RELEASE_FILES {
+                cmd.arg("--exclude").arg(name);
+                cmd.arg("--exclude").arg(format!("*/{name}"));
+            }
+            cmd.arg(source).arg(url);
+        }
+        Phase::Release => {
+            cmd.arg("--delete").arg(source).arg(url);
+        }
+    }
+    run(cmd)
+}
+
+/// A manifest push is atomic, so the whole repository goes up as one artifact once the
+/// earlier phases would have finished. Every file becomes a layer titled with its path,
+/// which is how the server finds files again.
+fn sync_oci(source: &Path, reference: &str, phase: Phase) -> Result<(), PublishError> {
+    if phase != Phase::Release {
+        return Ok(());
+    }
+    let mut files = Vec::new();
+    list_files(source, Path::new(""), &mut files)?;
+    // ordered like the other destinations, so blobs for the pool go up first
+    files.sort_by_key(|path| (Phase::of(path) as u8, path.clone()));
+
+    let mut cmd = Command::new("oras");
+    cmd.current_dir(source)
+        .arg("push")
+        .arg("--artifact-type")
+        .arg(OCI_ARTIFACT_TYPE)
+        .arg(reference);
+    for file in files {
+        let media_type = if file.extension().is_some_and(|ext| ext == "deb") {
+            OCI_DEB_MEDIA_TYPE
+        } else {
+            "application/octet-stream"
+        };
+        // oras wants forward slashes in titles on every platform
+        let path = file
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        cmd.arg(format!("{path}:{media_type}"));
+    }
+    run(cmd)
+}
+
+const OCI_ARTIFACT_TYPE: &str = "application/vnd.godsvagn.repository.v1";
+const OCI_DEB_MEDIA_TYPE: &str = "application/vnd.debian.binary-package";
+
+fn list_files(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<(), PublishError> {
+    for entry in std::fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            list_files(root, &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn s3_url(bucket: &str, prefix: Option<&str>) -> String {
+    match prefix.map(|p| p.trim_matches('/')) {
+        Some(prefix) if !prefix.is_empty() => format!("s3://{bucket}/{prefix}"),
+        _ => format!("s3://{bucket}"),
+    }
+}
+
+/// rsync copies the contents of a directory only when the source has a trailing slash
+fn dir_arg(path: &Path) -> PathBuf {
+    let mut arg = path.as_os_str().to_owned();
+    arg.push("/");
+    arg.into()
+}
+
+fn run(mut cmd: Command) -> Result<(), PublishError> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let status = cmd
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| PublishError::Spawn(program.clone(), e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(PublishError::CommandFailed(program, status))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PublishError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not run {0}: {1}")]
+    Spawn(String, std::io::Error),
+    #[error("{0} exited with {1}")]
+    CommandFailed(String, std::process::ExitStatus),
+    #[error("publishing failed for {}", .0.join(", "))]
+    Failed(Vec<String>),
+}
+
+impl PublishError {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "GV5007",
+            Self::Spawn(..) => "GV5008",
+            Self::CommandFailed(..) => "GV5009",
+            Self::Failed(_) => "GV5010",
+        }
+    }
+}
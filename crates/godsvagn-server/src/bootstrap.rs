@@ -0,0 +1,136 @@
+//! Public endpoints that hand clients everything they need to trust and add the repository.
+//! They're generated from the live keyfile and config on every request, so they can't drift
+//! from what repogen actually signs with.
+
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+use config::ReleaseConfig;
+use pgp::{
+    composed::{ArmorOptions, Deserializable, SignedPublicKey, SignedSecretKey},
+    ser::Serialize,
+};
+
+use crate::{AppState, Error};
+
+const KEY_CONTENT_TYPE: &str = "application/pgp-keys";
+
+/// Every key Release is signed with, so clients trust the repository across a key rotation
+fn public_keys(state: &AppState) -> Result<Vec<SignedPublicKey>, Error> {
+    state
+        .config
+        .keyfiles()
+        .map(|keyfile| {
+            let key = SignedSecretKey::from_armor_file(keyfile)?.0;
+            Ok(key.signed_public_key())
+        })
+        .collect()
+}
+
+pub async fn key_asc(State(state): State<AppState>) -> Result<Response, Error> {
+    let mut armored = String::new();
+    for key in public_keys(&state)? {
+        armored.push_str(&key.to_armored_string(ArmorOptions::default())?);
+    }
+    Ok(([(CONTENT_TYPE, KEY_CONTENT_TYPE)], armored).into_response())
+}
+
+pub async fn key_gpg(State(state): State<AppState>) -> Result<Response, Error> {
+    let mut binary = Vec::new();
+    for key in public_keys(&state)? {
+        binary.extend(key.to_bytes()?);
+    }
+    Ok(([(CONTENT_TYPE, KEY_CONTENT_TYPE)], binary).into_response())
+}
+
+pub async fn install_sh(State(state): State<AppState>) -> Result<Response, Error> {
+    let repo_url = state
+        .config
+        .repo_url
+        .as_deref()
+        .ok_or(Error::NoRepoUrl)?
+        .trim_end_matches('/');
+    let name = file_name_safe(&state.release.origin);
+    // where the `<origin>.sources` repogen publishes expects it, without a keyring package
+    let keyring = match &state.release.sources_signed_by {
+        Some(path) => path.clone(),
+        None => format!("/usr/share/keyrings/{name}-archive-keyring.pgp"),
+    };
+    let script = format!(
+        r#"#!/bin/sh
+# generated by godsvagn, adds the {name} repository to apt
+set -eu
+
+mkdir -p {keyring_dir}
+curl -fsSL {key_url} -o {keyring_quoted}
+
+cat > /etc/apt/sources.list.d/{name}.sources <<'SOURCES'
+{stanzas}SOURCES
+
+apt-get update
+"#,
+        // the keyring published inside the repo is the one its Release files are signed with
+        key_url = shell_quote(&format!("{repo_url}/deriv-archive-keyring.pgp")),
+        keyring_quoted = shell_quote(&keyring),
+        keyring_dir = shell_quote(match keyring.rsplit_once('/') {
+            Some(("", _)) => "/",
+            Some((dir, _)) => dir,
+            None => ".",
+        }),
+        stanzas = stanzas(&state.release, &state.suites, repo_url, &keyring),
+    );
+    Ok(([(CONTENT_TYPE, "text/plain; charset=utf-8")], script).into_response())
+}
+
+/// A stanza for each suite, like the `<origin>.sources` repogen publishes, with only the first
+/// enabled
+fn stanzas(release: &ReleaseConfig, suites: &[String], repo_url: &str, keyring: &str) -> String {
+    // flat repositories are added by their directory, and have no components
+    if release.flat {
+        return format!("Types: deb\nURIs: {repo_url}\nSuites: ./\nSigned-By: {keyring}\n");
+    }
+    let suites = if suites.is_empty() {
+        std::slice::from_ref(&release.suite)
+    } else {
+        suites
+    };
+    let components = release.components.join(" ");
+    let stanzas: Vec<String> = suites
+        .iter()
+        .enumerate()
+        .map(|(i, suite)| {
+            let enabled = if i > 0 { "Enabled: no\n" } else { "" };
+            format!(
+                "Types: deb\nURIs: {repo_url}\nSuites: {suite}\nComponents: {components}\n\
+                 Signed-By: {keyring}\n{enabled}"
+            )
+        })
+        .collect();
+    stanzas.join("\n")
+}
+
+/// Origins are freeform, but end up in file names
+fn file_name_safe(origin: &str) -> String {
+    let name: String = origin
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let name = name.trim_matches('-');
+    if name.is_empty() {
+        "godsvagn".to_owned()
+    } else {
+        name.to_owned()
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
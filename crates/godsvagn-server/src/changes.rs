@@ -0,0 +1,252 @@
+//! Uploads in the shape dput's http method produces: every file is PUT to
+//! `/incoming/<name>`, and the signed .changes file describing them comes last.
+//! Once the .changes arrives, its signature and every listed checksum are checked,
+//! and then all of its debs and udebs are stored, or none of them are.
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind as IoErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    body::Body,
+    extract::{Path as UrlPath, State},
+};
+use base16ct::HexDisplay;
+use config::ServerConfig;
+use filemeta::{DigestKind, FileSums};
+use futures_util::StreamExt;
+use parsedeb::{ListedFile, PackageKind, ParseOptions, RequiredFields};
+use pgp::composed::CleartextSignedMessage;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    AppState, Error, check_deb_signature, deb_storage_path, load_keyring, read_required_fields,
+};
+
+/// How long the hash of a file no .changes has listed is kept for
+const UPLOAD_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A file PUT to `/incoming/`, hashed as it was written. The hash is only trusted while the
+/// file is still the one that was hashed, so an upload of the same name for another .changes
+/// can't stand in for it.
+pub struct Uploaded {
+    sha256: [u8; 32],
+    size: u64,
+    modified: SystemTime,
+}
+
+impl Uploaded {
+    fn sha256_of(&self, metadata: &std::fs::Metadata) -> Option<[u8; 32]> {
+        let unchanged =
+            metadata.len() == self.size && metadata.modified().ok() == Some(self.modified);
+        unchanged.then_some(self.sha256)
+    }
+}
+
+pub async fn put_incoming(
+    State(state): State<AppState>,
+    UrlPath(name): UrlPath<String>,
+    body: Body,
+) -> Result<(), Error> {
+    let incoming = state
+        .config
+        .incoming_directory
+        .clone()
+        .ok_or(Error::ChangesDisabled)?;
+    if !valid_file_name(&name) {
+        return Err(Error::InvalidFileName);
+    }
+    tokio::fs::create_dir_all(&incoming).await?;
+
+    // partial uploads are dotfiles, so they can never be mistaken for a finished one
+    let partial = incoming.join(format!(".{name}.part"));
+    let mut file = tokio::fs::File::create(&partial).await?;
+    // hashed as it's written, so the .changes listing it doesn't have to read it all again
+    let (mut to_hash, hashed) = tokio::io::duplex(64 * 1024);
+    let write = async move {
+        let mut body_stream = body.into_data_stream();
+        while let Some(d) = body_stream.next().await.transpose()? {
+            file.write_all(&d).await?;
+            to_hash.write_all(&d).await?;
+        }
+        file.flush().await?;
+        Ok::<_, Error>(())
+    };
+    let sha256 = FileSums::builder().with(DigestKind::Sha256);
+    let hash = sha256.hash_async(tokio::io::BufReader::new(hashed));
+    let (written, sums) = tokio::join!(write, hash);
+    written?;
+    let path = incoming.join(&name);
+    tokio::fs::rename(&partial, &path).await?;
+
+    if !name.ends_with(".changes") {
+        if let Some(sha256) = sums?.sha256 {
+            let metadata = tokio::fs::metadata(&path).await?;
+            let uploaded = Uploaded {
+                sha256,
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            };
+            let mut all = state.uploaded.lock().await;
+            // files no .changes ever listed would otherwise be remembered forever
+            all.retain(|_, file| file.modified.elapsed().is_ok_and(|age| age < UPLOAD_TTL));
+            all.insert(name, uploaded);
+        }
+        return Ok(());
+    }
+    let guard = state.file_ops_pending.lock().await;
+    let config = state.config.clone();
+    let options = state.parse_options;
+    let all_uploaded = state.uploaded.clone();
+    tokio::task::spawn_blocking(move || {
+        // only this .changes' files, so the hashes of other uploads in progress are kept
+        let uploaded: HashMap<String, Uploaded> = {
+            let mut all = all_uploaded.blocking_lock();
+            listed_names(&path)?
+                .into_iter()
+                .filter_map(|name| all.remove_entry(&name))
+                .collect()
+        };
+        process_changes(&path, &config, &options, &uploaded, |_| Ok(()))
+    })
+    .await??;
+    drop(guard);
+    Ok(())
+}
+
+/// Names come straight from the url, so anything that could escape the incoming directory is refused
+pub fn valid_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.' | '_' | '~'))
+}
+
+/// Verify and store everything a .changes file lists, after running each deb and udeb past
+/// `check`. Every listed file and the .changes itself are removed once stored. Files in
+/// `uploaded` were hashed as they were uploaded, and aren't read again to check them.
+pub fn process_changes(
+    changes: &Path,
+    config: &ServerConfig,
+    options: &ParseOptions,
+    uploaded: &HashMap<String, Uploaded>,
+    check: impl Fn(&RequiredFields) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let keyring = config
+        .uploader_keyring
+        .as_deref()
+        .ok_or(Error::ChangesDisabled)?;
+    let incoming = changes.parent().ok_or(Error::NoParent)?;
+    let text = std::fs::read_to_string(changes)?;
+    let (message, _headers) = CleartextSignedMessage::from_string(&text)?;
+
+    let keys = load_keyring(keyring)?;
+    if !keys.iter().any(|key| message.verify(key).is_ok()) {
+        return Err(Error::UntrustedChanges);
+    }
+
+    let listed = listed_files(&message.signed_text())?;
+
+    let mut to_store = Vec::new();
+    for file in &listed {
+        let path = incoming.join(&*file.name);
+        let metadata =
+            std::fs::metadata(&path).map_err(|_| Error::MissingUpload(file.name.to_string()))?;
+        let sha256 = match uploaded
+            .get(&*file.name)
+            .and_then(|uploaded| uploaded.sha256_of(&metadata))
+        {
+            Some(sha256) => sha256,
+            None => filemeta::sha256(BufReader::new(File::open(&path)?))?,
+        };
+        if metadata.len() != file.size || format!("{:x}", HexDisplay(&sha256)) != *file.checksum {
+            return Err(Error::ChecksumMismatch(file.name.to_string()));
+        }
+        if let Some(kind) = PackageKind::from_file_name(&file.name) {
+            let options = ParseOptions { kind, ..*options };
+            let fields = read_required_fields(File::open(&path)?, &options)?;
+            check(&fields)?;
+            check_deb_signature(config, BufReader::new(File::open(&path)?))?;
+            let destination = deb_storage_path(&fields, kind, &config.deb_directory);
+            if destination.exists() {
+                return Err(Error::AlreadyExists);
+            }
+            to_store.push((path, destination));
+        }
+    }
+
+    store_all(&to_store)?;
+
+    for file in &listed {
+        std::fs::remove_file(incoming.join(&*file.name))?;
+    }
+    std::fs::remove_file(changes)?;
+    Ok(())
+}
+
+/// Names of the files a .changes file lists, without checking its signature
+pub fn listed_names(changes: &Path) -> Result<Vec<String>, Error> {
+    let text = std::fs::read_to_string(changes)?;
+    let listed = listed_files(&text)?;
+    Ok(listed.into_iter().map(|file| file.name.into()).collect())
+}
+
+/// Parse a .changes file, refusing any listed name that could escape the incoming directory
+fn listed_files(changes: &str) -> Result<Vec<ListedFile>, Error> {
+    let files = parsedeb::parse_changes(changes)
+        .map_err(Error::InvalidChanges)?
+        .files;
+    if !files.iter().all(|file| valid_file_name(&file.name)) {
+        return Err(Error::InvalidFileName);
+    }
+    Ok(files)
+}
+
+/// Copy every package into storage, removing the ones already copied if any of them fails
+fn store_all(files: &[(PathBuf, PathBuf)]) -> Result<(), Error> {
+    let mut stored: Vec<&Path> = Vec::with_capacity(files.len());
+    for (source, destination) in files {
+        if let Err(e) = store(source, destination) {
+            for path in stored {
+                if let Err(e) = std::fs::remove_file(path) {
+                    let path = path.display();
+                    tracing::error!(%path, error = ?e, "failed to roll back a stored deb");
+                }
+            }
+            return Err(e);
+        }
+        stored.push(destination);
+    }
+    Ok(())
+}
+
+pub fn store(source: &Path, destination: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(destination.parent().ok_or(Error::NoParent)?)?;
+    let outfile = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(destination)
+        .map_err(|e| {
+            if matches!(e.kind(), IoErrorKind::AlreadyExists) {
+                Error::AlreadyExists
+            } else {
+                Error::Io(e)
+            }
+        })?;
+    let copied = File::open(source).and_then(|source| {
+        let mut writer = BufWriter::new(outfile);
+        std::io::copy(&mut BufReader::new(source), &mut writer)?;
+        writer.flush()
+    });
+    if let Err(e) = copied {
+        // a half-written deb would otherwise block the retry with AlreadyExists
+        let _ = std::fs::remove_file(destination);
+        return Err(e.into());
+    }
+    Ok(())
+}
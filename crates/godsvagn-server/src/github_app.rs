@@ -0,0 +1,124 @@
+//! GitHub App installation tokens. They're opaque, so the only way to check one is to ask
+//! GitHub which repositories it can access. The answer is kept for a few minutes, so a burst of
+//! uploads with one token doesn't ask again for each.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use reqwest::{StatusCode, header::LINK};
+
+use crate::{AppState, Error, Principal};
+
+const FIRST_PAGE: &str = "https://api.github.com/installation/repositories?per_page=100";
+/// How long what a token can access is remembered. Installation tokens last an hour, and
+/// repositories removed from an installation stop being trusted after at most this long.
+const TTL: Duration = Duration::from_secs(5 * 60);
+/// Installations can have any number of repositories, but not this many pages of them
+const MAX_PAGES: usize = 50;
+
+/// The configured repositories each recently seen token can access, by the token's sha256
+#[derive(Default)]
+pub struct Installations {
+    cached: Mutex<Cache>,
+}
+
+type Cache = HashMap<[u8; 32], (Instant, Vec<String>)>;
+
+#[derive(serde::Deserialize)]
+struct InstallationRepositories {
+    repositories: Vec<InstallationRepository>,
+}
+
+#[derive(serde::Deserialize)]
+struct InstallationRepository {
+    full_name: String,
+}
+
+pub async fn principal(state: &AppState, token: &str) -> Result<Principal, Error> {
+    let app_config = state
+        .config
+        .github_app
+        .as_ref()
+        .ok_or(Error::GithubAppDisabled)?;
+    let key = filemeta::sha256(token.as_bytes())?;
+    let cached = state.installations.lock().get(&key).cloned();
+    let repositories = match cached {
+        Some((fetched, repositories)) if fetched.elapsed() < TTL => repositories,
+        _ => {
+            let repositories: Vec<String> = accessible(state, token)
+                .await?
+                .into_iter()
+                .filter(|name| {
+                    app_config
+                        .repositories
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(name))
+                })
+                .collect();
+            state.installations.insert(key, repositories.clone());
+            repositories
+        }
+    };
+    if repositories.is_empty() {
+        return Err(Error::UnauthorizedInstallation);
+    }
+    Ok(Principal::GithubApp { repositories })
+}
+
+/// Every repository the token can access, following GitHub's pagination
+async fn accessible(state: &AppState, token: &str) -> Result<Vec<String>, Error> {
+    let mut repositories = Vec::new();
+    let mut url = Some(FIRST_PAGE.to_owned());
+    for _ in 0..MAX_PAGES {
+        let Some(page) = url.take() else {
+            break;
+        };
+        let response = state
+            .http
+            .get(page)
+            .bearer_auth(token)
+            .header("accept", "application/vnd.github+json")
+            .header("user-agent", "godsvagn")
+            .header("x-github-api-version", "2022-11-28")
+            .send()
+            .await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(Error::InvalidGithubToken);
+        }
+        let response = response.error_for_status()?;
+        url = response
+            .headers()
+            .get(LINK)
+            .and_then(|link| link.to_str().ok())
+            .and_then(next_page);
+        let page: InstallationRepositories = response.json().await?;
+        repositories.extend(page.repositories.into_iter().map(|r| r.full_name));
+    }
+    Ok(repositories)
+}
+
+/// The `rel="next"` url of a `Link` header, if it's GitHub's API, which the token can be sent to
+pub(crate) fn next_page(link: &str) -> Option<String> {
+    link.split(',').find_map(|link| {
+        let mut parts = link.split(';').map(str::trim);
+        let url = parts.next()?.strip_prefix('<')?.strip_suffix('>')?;
+        let next = parts.any(|param| param == r#"rel="next""#);
+        (next && url.starts_with("https://api.github.com/")).then(|| url.to_owned())
+    })
+}
+
+impl Installations {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Cache> {
+        self.cached.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Forgets every token that's expired while it's at it, so the map can't keep growing
+    fn insert(&self, key: [u8; 32], repositories: Vec<String>) {
+        let mut cached = self.lock();
+        cached.retain(|_, (fetched, _)| fetched.elapsed() < TTL);
+        cached.insert(key, (Instant::now(), repositories));
+    }
+}
@@ -0,0 +1,230 @@
+//! reprepro-style processincoming: builders that can only write files drop debs or
+//! .changes sets into a directory, which is scanned periodically. Accepted packages
+//! are moved into the deb directory and trigger a regeneration, rejects are moved to
+//! a quarantine directory, and every decision is appended to a log.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{BufReader, Write},
+    path::Path,
+    time::Duration,
+};
+
+use config::{IncomingConfig, ServerConfig};
+use parsedeb::{PackageKind, ParseOptions, RequiredFields};
+use regex::Regex;
+
+use crate::{
+    AppState, Error, changes, check_deb_signature, deb_storage_path, read_required_fields,
+};
+
+/// What an incoming package has to look like to be accepted
+struct Policy {
+    architectures: Option<Vec<String>>,
+    package_pattern: Option<Regex>,
+}
+
+impl Policy {
+    fn new(config: &IncomingConfig) -> Result<Self, Error> {
+        let package_pattern = config
+            .package_pattern
+            .as_deref()
+            .map(|pattern| Regex::new(&format!("^(?:{pattern})$")))
+            .transpose()?;
+        Ok(Self {
+            architectures: config.architectures.clone(),
+            package_pattern,
+        })
+    }
+
+    fn check(&self, fields: &RequiredFields) -> Result<(), Error> {
+        if let Some(architectures) = &self.architectures
+            && !architectures.iter().any(|a| **a == *fields.architecture)
+        {
+            return Err(Error::PolicyRejected(format!(
+                "architecture {} is not allowed",
+                fields.architecture
+            )));
+        }
+        if let Some(pattern) = &self.package_pattern
+            && !pattern.is_match(&fields.package)
+        {
+            return Err(Error::PolicyRejected(format!(
+                "package name {} is not allowed",
+                fields.package
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(state: AppState) -> Result<(), Error> {
+    let config = state
+        .config
+        .process_incoming
+        .clone()
+        .ok_or(Error::IncomingDisabled)?;
+    // compile the policy up front so a bad pattern stops startup instead of every scan
+    Policy::new(&config)?;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.scan_interval_secs));
+    loop {
+        interval.tick().await;
+        let guard = state.file_ops_pending.lock().await;
+        let server_config = state.config.clone();
+        let options = state.parse_options;
+        let accepted = tokio::task::spawn_blocking(move || scan(&server_config, &options)).await;
+        match accepted {
+            Ok(Ok(0)) => {}
+            Ok(Ok(_)) => {
+                state.jobs.enqueue();
+            }
+            Ok(Err(e)) => tracing::error!(error = ?e, "failed to process incoming"),
+            Err(e) => tracing::error!(error = ?e, "incoming processing panicked"),
+        }
+        drop(guard);
+    }
+}
+
+/// Process everything in the incoming directory once, returning how many uploads were accepted
+fn scan(server: &ServerConfig, options: &ParseOptions) -> Result<usize, Error> {
+    let config = server
+        .process_incoming
+        .as_ref()
+        .ok_or(Error::IncomingDisabled)?;
+    let policy = Policy::new(config)?;
+    let settle = Duration::from_secs(config.settle_secs);
+    let incomplete_timeout = Duration::from_secs(config.incomplete_timeout_secs);
+
+    std::fs::create_dir_all(&config.directory)?;
+    // and how long ago each was last modified
+    let mut settled: Vec<(String, Duration)> = Vec::new();
+    for entry in std::fs::read_dir(&config.directory)? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+            continue;
+        };
+        // dotfiles are uploads still in progress, and recently touched files might be too
+        if name.starts_with('.') || !entry.file_type()?.is_file() {
+            continue;
+        }
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age >= settle {
+            settled.push((name, age));
+        }
+    }
+
+    let mut accepted = 0;
+    let mut claimed = HashSet::new();
+    for (name, age) in settled
+        .iter()
+        .filter(|(name, _)| name.ends_with(".changes"))
+    {
+        let changes_path = config.directory.join(name);
+        let listed = match changes::listed_names(&changes_path) {
+            Ok(listed) => listed,
+            Err(e) => {
+                reject(config, name, &[], &e);
+                continue;
+            }
+        };
+        claimed.extend(listed.iter().cloned());
+        let uploaded = HashMap::new();
+        let result =
+            changes::process_changes(&changes_path, server, options, &uploaded, |fields| {
+                policy.check(fields)
+            });
+        match result {
+            Ok(()) => {
+                log(config, &format!("accepted {name}"));
+                accepted += 1;
+            }
+            // the rest of the set hasn't been written yet, try again next scan
+            Err(Error::MissingUpload(_)) if *age < incomplete_timeout => {}
+            Err(e) => reject(config, name, &listed, &e),
+        }
+    }
+
+    for (name, _) in &settled {
+        if name.ends_with(".changes") || claimed.contains(name) {
+            continue;
+        }
+        let result = match PackageKind::from_file_name(name) {
+            Some(kind) => {
+                let options = ParseOptions { kind, ..*options };
+                accept_deb(&config.directory.join(name), server, &options, &policy)
+            }
+            None => Err(Error::InvalidFileName),
+        };
+        match result {
+            Ok(()) => {
+                log(config, &format!("accepted {name}"));
+                accepted += 1;
+            }
+            Err(e) => reject(config, name, &[], &e),
+        }
+    }
+    Ok(accepted)
+}
+
+fn accept_deb(
+    path: &Path,
+    server: &ServerConfig,
+    options: &ParseOptions,
+    policy: &Policy,
+) -> Result<(), Error> {
+    let fields = read_required_fields(File::open(path)?, options)?;
+    policy.check(&fields)?;
+    check_deb_signature(server, BufReader::new(File::open(path)?))?;
+    let destination = deb_storage_path(&fields, options.kind, &server.deb_directory);
+    changes::store(path, &destination)?;
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Move an upload, and any files it listed, into quarantine. Failing to doesn't stop the
+/// scan, and whatever's left is rejected again next time.
+fn reject(config: &IncomingConfig, name: &str, listed: &[String], error: &Error) {
+    log(config, &format!("rejected {name}: {error}"));
+    if let Err(e) = quarantine(config, name, listed) {
+        tracing::error!(name, error = ?e, "failed to quarantine a rejected upload");
+    }
+}
+
+fn quarantine(config: &IncomingConfig, name: &str, listed: &[String]) -> Result<(), Error> {
+    std::fs::create_dir_all(&config.quarantine_directory)?;
+    for file in std::iter::once(name).chain(listed.iter().map(String::as_str)) {
+        let source = config.directory.join(file);
+        if source.exists() {
+            move_file(&source, &config.quarantine_directory.join(file))?;
+        }
+    }
+    Ok(())
+}
+
+/// Rename, falling back to copying for quarantine directories on another filesystem
+fn move_file(source: &Path, destination: &Path) -> Result<(), Error> {
+    if std::fs::rename(source, destination).is_err() {
+        std::fs::copy(source, destination)?;
+        std::fs::remove_file(source)?;
+    }
+    Ok(())
+}
+
+/// Append a decision to the log file, prefixed with an RFC 3339 timestamp. The decision has
+/// already been made, so failing to record it is only reported.
+fn log(config: &IncomingConfig, message: &str) {
+    tracing::info!("incoming: {message}");
+    let Some(log_file) = &config.log_file else {
+        return;
+    };
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .and_then(|mut log| writeln!(log, "{} {message}", jiff::Timestamp::now()));
+    if let Err(e) = written {
+        let path = log_file.display();
+        tracing::error!(%path, error = ?e, "failed to write the incoming log");
+    }
+}
@@ -0,0 +1,182 @@
+//! Regenerations run in the background, one at a time, so a request asking for one doesn't
+//! have to stay open until it's done. Each gets a job that can be looked up to see how it went.
+
+use std::{collections::VecDeque, sync::Mutex, time::Instant};
+
+use axum::{
+    Json,
+    extract::{Path as UrlPath, State},
+    response::IntoResponse,
+};
+use reqwest::StatusCode;
+use tokio::sync::Notify;
+use tracing::Instrument;
+
+use crate::{AppState, Error};
+
+/// How many jobs are remembered. Once there are more, the oldest finished ones are forgotten.
+const KEEP: usize = 100;
+
+#[derive(Debug, Default)]
+pub struct Jobs {
+    jobs: Mutex<JobList>,
+    queued: Notify,
+}
+
+#[derive(Debug, Default)]
+struct JobList {
+    next_id: u64,
+    /// oldest first
+    jobs: VecDeque<Job>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Job {
+    id: u64,
+    state: JobState,
+    /// RFC 3339, like every other time here
+    queued: String,
+    started: Option<String>,
+    finished: Option<String>,
+    /// everything repogen wrote to stderr
+    stderr: String,
+    /// why it failed, if it did
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl Jobs {
+    /// Queue a regeneration, returning its job ID. A regeneration that's still queued picks up
+    /// every change made before it starts, so if there is one, that's returned instead.
+    pub fn enqueue(&self) -> u64 {
+        let mut list = self.lock();
+        if let Some(job) = list.jobs.iter().find(|job| job.state == JobState::Queued) {
+            return job.id;
+        }
+        let id = list.next_id;
+        list.next_id += 1;
+        list.jobs.push_back(Job {
+            id,
+            state: JobState::Queued,
+            queued: now(),
+            started: None,
+            finished: None,
+            stderr: String::new(),
+            error: None,
+        });
+        while list.jobs.len() > KEEP {
+            let Some(finished) = list
+                .jobs
+                .iter()
+                .position(|job| matches!(job.state, JobState::Succeeded | JobState::Failed))
+            else {
+                break;
+            };
+            list.jobs.remove(finished);
+        }
+        drop(list);
+        self.queued.notify_one();
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.lock().jobs.iter().find(|job| job.id == id).cloned()
+    }
+
+    /// Newest first
+    pub fn all(&self) -> Vec<Job> {
+        self.lock().jobs.iter().rev().cloned().collect()
+    }
+
+    /// Mark the oldest queued job as running and return its ID, if there is one
+    fn start_next(&self) -> Option<u64> {
+        let mut list = self.lock();
+        let job = list
+            .jobs
+            .iter_mut()
+            .find(|job| job.state == JobState::Queued)?;
+        job.state = JobState::Running;
+        job.started = Some(now());
+        Some(job.id)
+    }
+
+    fn finish(&self, id: u64, stderr: &[u8], result: Result<(), Error>) {
+        let mut list = self.lock();
+        let Some(job) = list.jobs.iter_mut().find(|job| job.id == id) else {
+            return;
+        };
+        job.state = match result {
+            Ok(()) => JobState::Succeeded,
+            Err(_) => JobState::Failed,
+        };
+        job.finished = Some(now());
+        job.stderr = String::from_utf8_lossy(stderr).into_owned();
+        job.error = result.err().map(|e| e.to_string());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, JobList> {
+        // nothing panics while holding it with a job half changed
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn now() -> String {
+    jiff::Timestamp::now().to_string()
+}
+
+/// Run queued regenerations forever, each while holding `file_ops_pending`
+pub async fn run(state: AppState) {
+    loop {
+        let Some(id) = state.jobs.start_next() else {
+            state.jobs.queued.notified().await;
+            continue;
+        };
+        let span = tracing::info_span!("regeneration", job = id);
+        run_one(&state, id).instrument(span).await;
+    }
+}
+
+async fn run_one(state: &AppState, id: u64) {
+    let guard = state.file_ops_pending.lock().await;
+    let mut stderr = Vec::new();
+    let started = Instant::now();
+    let result = crate::regenerate_locked(state, &mut stderr).await;
+    drop(guard);
+    let took = started.elapsed();
+    state.metrics.regeneration(result.is_ok(), took);
+    match &result {
+        Ok(()) => tracing::info!(seconds = took.as_secs_f64(), "regeneration succeeded"),
+        Err(e) => tracing::error!(seconds = took.as_secs_f64(), error = ?e, "regeneration failed"),
+    }
+    state.jobs.finish(id, &stderr, result);
+}
+
+#[derive(serde::Serialize)]
+struct Queued {
+    job: u64,
+}
+
+/// `POST /regenerate`, which answers as soon as the regeneration is queued
+pub async fn regenerate(State(state): State<AppState>) -> impl IntoResponse {
+    let job = state.jobs.enqueue();
+    (StatusCode::ACCEPTED, Json(Queued { job }))
+}
+
+pub async fn list(State(state): State<AppState>) -> Json<Vec<Job>> {
+    Json(state.jobs.all())
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<u64>,
+) -> Result<Json<Job>, Error> {
+    state.jobs.get(id).map(Json).ok_or(Error::NotFound)
+}
@@ -0,0 +1,102 @@
+//! GitHub's OIDC signing keys. GitHub rotates them now and then, so they're fetched again every
+//! so often, and right away when a token is signed by a key that isn't known yet.
+
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+
+use crate::Error;
+
+const JWKS_URL: &str = "https://token.actions.githubusercontent.com/.well-known/jwks";
+/// How often the keys are fetched again in the background
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Tokens with a made-up key ID shouldn't make every request fetch the keys, so an unknown
+/// key only fetches them if they're at least this old
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct Jwks {
+    http: reqwest::Client,
+    cached: RwLock<Cached>,
+    /// held while fetching, so requests with the same new key wait for one fetch
+    fetching: tokio::sync::Mutex<()>,
+}
+
+#[derive(Clone)]
+struct Cached {
+    keys: Arc<JwkSet>,
+    fetched: Instant,
+}
+
+impl Jwks {
+    pub async fn fetch(http: reqwest::Client) -> Result<Self, Error> {
+        let keys = fetch(&http).await?;
+        Ok(Self {
+            http,
+            cached: RwLock::new(Cached {
+                keys: Arc::new(keys),
+                fetched: Instant::now(),
+            }),
+            fetching: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// The key with ID `kid`, fetching the keys again first if it isn't one of them
+    pub async fn find(&self, kid: &str) -> Result<Jwk, Error> {
+        let cached = self.cached();
+        if let Some(jwk) = cached.keys.find(kid) {
+            return Ok(jwk.clone());
+        }
+        if cached.fetched.elapsed() < MIN_REFETCH_INTERVAL {
+            return Err(Error::UnknownJwk);
+        }
+        tracing::info!(kid, "fetching keys again for an unknown key ID");
+        let keys = self.refresh(MIN_REFETCH_INTERVAL).await?;
+        keys.find(kid).cloned().ok_or(Error::UnknownJwk)
+    }
+
+    /// Fetch the keys again every [`REFRESH_INTERVAL`], keeping the ones there are if that fails
+    pub async fn refresh_forever(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(REFRESH_INTERVAL).await;
+            if let Err(e) = self.refresh(Duration::ZERO).await {
+                tracing::warn!(error = ?e, "could not refresh OIDC signing keys");
+            }
+        }
+    }
+
+    /// Fetch the keys, unless they were fetched less than `fresh_for` ago, like by another
+    /// request while this one was waiting to
+    async fn refresh(&self, fresh_for: Duration) -> Result<Arc<JwkSet>, Error> {
+        let _fetching = self.fetching.lock().await;
+        let cached = self.cached();
+        if cached.fetched.elapsed() < fresh_for {
+            return Ok(cached.keys);
+        }
+        let keys = Arc::new(fetch(&self.http).await?);
+        *self.cached.write().unwrap_or_else(|e| e.into_inner()) = Cached {
+            keys: keys.clone(),
+            fetched: Instant::now(),
+        };
+        Ok(keys)
+    }
+
+    fn cached(&self) -> Cached {
+        self.cached
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+async fn fetch(http: &reqwest::Client) -> Result<JwkSet, Error> {
+    Ok(http
+        .get(JWKS_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
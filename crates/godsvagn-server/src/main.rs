@@ -1,51 +1,56 @@
 use std::{
     collections::HashMap,
-    fs::{File, OpenOptions},
-    io::{BufReader, BufWriter, ErrorKind as IoErrorKind, Seek, Write},
-    net::SocketAddr,
+    io::ErrorKind as IoErrorKind,
     path::{Path, PathBuf},
     process::Stdio,
     sync::Arc,
 };
 
 use axum::{
-    Router,
+    Json, Router,
     body::Body,
     extract::{Query, Request, State},
+    http::{HeaderMap, header::AUTHORIZATION},
     middleware::Next,
     response::{IntoResponse, Response},
-    routing::post,
+    routing::{get, post, put},
 };
-use bytes::Bytes;
+use base64::Engine;
+use config::{Bind, Config, LogFormat, ReleaseConfig, ServeUser, ServerConfig};
 use futures_util::StreamExt;
-use jsonwebtoken::{DecodingKey, Validation, jwk::JwkSet};
-use parsedeb::RequiredFields;
-use rand::{Rng, distr::Alphabetic};
+use jsonwebtoken::{DecodingKey, Validation};
+use metrics::UploadResult;
+use parsedeb::{
+    DuplicatePolicy, FieldChecks, PackageKind, PackageTriplet, ParseOptions, RequiredFields,
+    Warning,
+};
+use pgp::composed::{Deserializable, SignedPublicKey};
 use reqwest::StatusCode;
+use subtle::ConstantTimeEq;
 use tokio::{
+    io::{AsyncSeekExt, AsyncWriteExt},
     net::TcpListener,
-    sync::{Mutex, mpsc::Receiver as MpscReceiver},
+    sync::Mutex,
 };
+use tower_http::{
+    LatencyUnit,
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing_subscriber::EnvFilter;
 
-#[derive(serde::Deserialize, Debug)]
-pub struct Config {
-    pub server: ServerConfig,
-}
-
-#[derive(serde::Deserialize, Debug)]
-pub struct ServerConfig {
-    bind: SocketAddr,
-    deb_directory: PathBuf,
-    repo_directory: PathBuf,
-    audiences: Box<[String]>,
-    keyfile: PathBuf,
-    #[serde(default = "default_repogen")]
-    repogen_command: String,
-}
-
-fn default_repogen() -> String {
-    "godsvagn-repogen".to_owned()
-}
+mod bootstrap;
+mod changes;
+mod github_app;
+mod incoming;
+mod jobs;
+mod jwks;
+mod metrics;
+mod oci;
+mod packages;
+mod repo;
+#[cfg(test)]
+mod tests;
+mod tls;
 
 #[derive(argh::FromArgs)]
 #[argh(description = "Generate a valid debian repository from a directory full of .deb files")]
@@ -58,47 +63,226 @@ struct Args {
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Args = argh::from_env();
-    let config = std::fs::read_to_string(&args.config)?;
-    let config: Config = toml::from_str(&config)?;
+    let config = Config::load(&args.config)?;
+    let server_config = config.server()?.clone();
+
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_new(&server_config.log_level)?)
+        .with_writer(std::io::stderr);
+    match server_config.log_format {
+        LogFormat::Pretty => logs.init(),
+        LogFormat::Json => logs.json().init(),
+    }
+    for name in &config.unknown_overrides {
+        tracing::warn!(
+            name,
+            "ignoring environment variable that doesn't override any setting"
+        );
+    }
 
     let http = reqwest::ClientBuilder::new()
         .redirect(reqwest::redirect::Policy::none())
         .build()?;
 
-    let jwks: JwkSet = http
-        .get("https://token.actions.githubusercontent.com/.well-known/jwks")
-        .send()
-        .await?
-        .json()
-        .await?;
+    let jwks = Arc::new(jwks::Jwks::fetch(http.clone()).await?);
+    tokio::spawn(jwks.clone().refresh_forever());
 
-    let listener = TcpListener::bind(&config.server.bind).await?;
+    let listener = bind(&server_config).await?;
+
+    let oci = server_config
+        .oci
+        .as_ref()
+        .map(oci::OciSource::new)
+        .transpose()?
+        .map(Arc::new);
 
     let state = AppState {
-        jwks: Arc::new(jwks),
+        http,
+        oci,
+        jwks,
+        installations: Arc::default(),
         file_ops_pending: Arc::new(Mutex::new(())),
-        config: Arc::new(config),
+        jobs: Arc::default(),
+        metrics: Arc::default(),
+        uploaded: Arc::default(),
+        listed: Arc::default(),
+        config: Arc::new(server_config),
+        parse_options: parse_options(&config.release),
+        release: Arc::new(config.release),
+        suites: config.suites.into_iter().map(|s| s.suite).collect(),
         config_path: args.config.into(),
     };
 
+    tokio::spawn(jobs::run(state.clone()));
+    let file_ops_pending = state.file_ops_pending.clone();
+
+    if state.config.process_incoming.is_some() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = incoming::run(state).await {
+                tracing::error!(error = ?e, "incoming processing stopped");
+            }
+        });
+    }
+
     let app = Router::new()
         .route("/upload", post(upload))
-        .route("/regenerate", post(regenerate))
+        .route("/regenerate", post(jobs::regenerate))
+        .route("/jobs", get(jobs::list))
+        .route("/jobs/{id}", get(jobs::get))
+        .route("/packages", get(packages::list))
+        .route(
+            "/packages/{name}/{version}/{arch}",
+            get(packages::detail).delete(packages::delete),
+        )
         .layer(axum::middleware::from_fn_with_state(
             state.clone(),
             claim_validator,
         ))
+        .route(
+            "/incoming/{name}",
+            put(changes::put_incoming).layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                incoming_validator,
+            )),
+        )
+        .route("/key.asc", get(bootstrap::key_asc))
+        .route("/key.gpg", get(bootstrap::key_gpg))
+        .route("/install.sh", get(bootstrap::install_sh))
+        .route("/metrics", get(metrics::serve))
+        .route(
+            "/apt/{*path}",
+            match (&state.config.serve, &state.oci) {
+                (Some(_), _) => get(repo::serve),
+                (None, Some(_)) => get(oci::serve),
+                (None, None) => get(|| async { Error::NotFound }),
+            },
+        )
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|request: &Request| {
+                    tracing::info_span!(
+                        "request",
+                        method = %request.method(),
+                        path = request.uri().path(),
+                        subject = tracing::field::Empty,
+                    )
+                })
+                .on_request(())
+                .on_response(
+                    DefaultOnResponse::new()
+                        .level(tracing::Level::INFO)
+                        .latency_unit(LatencyUnit::Millis),
+                ),
+        )
         .with_state(state);
 
-    axum::serve(listener, app).await?;
+    // service managers stop the server with SIGTERM, which only unix has
+    #[cfg(unix)]
+    let mut terminate = {
+        use tokio::signal::unix::{SignalKind, signal};
+        signal(SignalKind::terminate())?
+    };
+    let shutdown = async move {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+        tracing::info!("shutting down once every request is done");
+    };
+    match listener {
+        Bound::Tcp(listener) => {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await?
+        }
+        Bound::Tls(listener) => {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await?
+        }
+        #[cfg(unix)]
+        Bound::Unix(listener, path) => {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await?;
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    // every request, and so every upload, is done by now. Regenerations and incoming
+    // processing run outside of requests, but only while holding this, so once it's free
+    // nothing is left half done, and holding it until exiting keeps them from starting again.
+    let _guard = file_ops_pending.lock().await;
+    tracing::info!("stopped");
     Ok(())
 }
 
+enum Bound {
+    Tcp(TcpListener),
+    Tls(tls::TlsListener),
+    /// removed again once the server stops
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+/// Listen where the config says to
+async fn bind(config: &ServerConfig) -> Result<Bound, Error> {
+    match (&config.bind, &config.tls) {
+        (Bind::Tcp(address), None) => Ok(Bound::Tcp(TcpListener::bind(address).await?)),
+        (Bind::Tcp(address), Some(tls)) => {
+            let listener = TcpListener::bind(address).await?;
+            Ok(Bound::Tls(tls::listen(listener, tls).await?))
+        }
+        #[cfg(unix)]
+        (Bind::Unix(path), _) => bind_unix(path, config.socket_mode),
+        #[cfg(not(unix))]
+        (Bind::Unix(_), _) => unreachable!("config validation turns down unix sockets off unix"),
+    }
+}
+
+/// A socket left behind by a server that didn't stop cleanly is replaced
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: Option<u32>) -> Result<Bound, Error> {
+    use std::{
+        fs::Permissions,
+        os::unix::fs::{FileTypeExt, PermissionsExt},
+    };
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        _ => {}
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    Ok(Bound::Unix(listener, path.to_owned()))
+}
+
 #[derive(Clone)]
 struct AppState {
+    http: reqwest::Client,
+    oci: Option<Arc<oci::OciSource>>,
     file_ops_pending: Arc<Mutex<()>>,
-    jwks: Arc<JwkSet>,
-    config: Arc<Config>,
+    /// regenerations, which run one at a time in the background
+    jobs: Arc<jobs::Jobs>,
+    metrics: Arc<metrics::Metrics>,
+    /// files PUT to `/incoming/` that no .changes has listed yet, by name
+    uploaded: Arc<Mutex<HashMap<String, changes::Uploaded>>>,
+    /// what `GET /packages` last read from `deb_directory`
+    listed: Arc<packages::Listed>,
+    jwks: Arc<jwks::Jwks>,
+    /// who recently seen GitHub App tokens were issued to
+    installations: Arc<github_app::Installations>,
+    config: Arc<ServerConfig>,
+    release: Arc<ReleaseConfig>,
+    /// the names of the `[[suites]]` repogen publishes under `dists/`, if it isn't just one
+    suites: Arc<[String]>,
+    /// how uploads are parsed, matching what repogen will accept
+    parse_options: ParseOptions,
     config_path: Arc<Path>,
 }
 
@@ -115,59 +299,212 @@ struct Claims {
     more: HashMap<String, String>,
 }
 
+/// Who a request was authenticated as, which its span records as the subject
+#[derive(Debug)]
+enum Principal {
+    Oidc(Claims),
+    /// a GitHub App installation token, with the configured repositories it can access
+    GithubApp {
+        repositories: Vec<String>,
+    },
+    /// one of `incoming_users`, by username
+    Uploader(String),
+}
+
 async fn claim_validator(
     State(state): State<AppState>,
-    mut request: Request,
+    request: Request,
     next: Next,
 ) -> Result<Response, Error> {
-    let jwt = request
-        .headers()
+    let principal = token_principal(&state, request.headers()).await;
+    record_principal(&state, principal)?;
+    Ok(next.run(request).await)
+}
+
+/// [`claim_validator`] for `/incoming/`, which also takes the basic auth logins dput sends
+/// instead of tokens, asking for one when a request has neither
+async fn incoming_validator(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let headers = request.headers();
+    let users = &state.config.incoming_users;
+    let has_token = headers.contains_key("github-token") || headers.contains_key("openid-token");
+    let principal = if has_token || users.is_empty() {
+        token_principal(&state, headers).await
+    } else {
+        basic_auth(users, headers)
+            .map(|username| Principal::Uploader(username.to_owned()))
+            .ok_or(Error::IncomingUnauthorized)
+    };
+    record_principal(&state, principal)?;
+    Ok(next.run(request).await)
+}
+
+async fn token_principal(state: &AppState, headers: &HeaderMap) -> Result<Principal, Error> {
+    match headers.get("github-token") {
+        Some(token) => github_app::principal(state, token.to_str()?.trim()).await,
+        None => oidc_principal(state, headers).await,
+    }
+}
+
+/// Count a failed authentication, or record who a request is from in its span
+fn record_principal(state: &AppState, principal: Result<Principal, Error>) -> Result<(), Error> {
+    let principal = principal.inspect_err(|_| state.metrics.auth_failure())?;
+    let span = tracing::Span::current();
+    match &principal {
+        Principal::Oidc(claims) => span.record("subject", claims.sub.as_str()),
+        Principal::GithubApp { repositories } => {
+            span.record("subject", repositories.join(",").as_str())
+        }
+        Principal::Uploader(username) => span.record("subject", username.as_str()),
+    };
+    Ok(())
+}
+
+/// The username of whichever of `users` a request logs in as with basic auth. Every user is
+/// compared in constant time, so how long it takes doesn't give away how much of a guess was
+/// right.
+fn basic_auth<'a>(users: &'a [ServeUser], headers: &HeaderMap) -> Option<&'a str> {
+    let credentials = headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let credentials = base64::engine::general_purpose::STANDARD
+        .decode(credentials)
+        .ok()?;
+    let (username, password) = std::str::from_utf8(&credentials).ok()?.split_once(':')?;
+    users.iter().fold(None, |found, user| {
+        let matches = user.username.as_bytes().ct_eq(username.as_bytes())
+            & user.password.as_bytes().ct_eq(password.as_bytes());
+        if bool::from(matches) {
+            Some(user.username.as_str())
+        } else {
+            found
+        }
+    })
+}
+
+async fn oidc_principal(state: &AppState, headers: &HeaderMap) -> Result<Principal, Error> {
+    let jwt = headers
         .get("openid-token")
         .ok_or(Error::MissingHeader)?
         .to_str()?
         .trim();
     let header = jsonwebtoken::decode_header(jwt)?;
-    let relevant_jwk = state
-        .jwks
-        .find(&header.kid.ok_or(Error::NoKeyId)?)
-        .ok_or(Error::UnknownJwk)?;
-    let key = DecodingKey::from_jwk(relevant_jwk)?;
+    let relevant_jwk = state.jwks.find(&header.kid.ok_or(Error::NoKeyId)?).await?;
+    let key = DecodingKey::from_jwk(&relevant_jwk)?;
     let mut validator = Validation::new(jsonwebtoken::Algorithm::RS256);
-    validator.set_audience(&state.config.server.audiences);
+    validator.set_audience(&state.config.audiences);
     validator.set_issuer(&["https://token.actions.githubusercontent.com"]);
 
     let claims: Claims = jsonwebtoken::decode(jwt, &key, &validator)?.claims;
-    request.extensions_mut().insert(claims);
-    Ok(next.run(request).await)
+    Ok(Principal::Oidc(claims))
 }
 
-async fn regenerate(State(state): State<AppState>) -> Result<(), Error> {
-    let guard = state.file_ops_pending.lock().await;
-    let output_tmp = tempfile::tempdir()?;
-    let mut cmd = tokio::process::Command::new(state.config.server.repogen_command.as_str());
+/// Regenerate and publish the repository, keeping what `repogen_command` writes to stderr, if
+/// it's set, in `stderr`. Callers must hold `file_ops_pending`.
+async fn regenerate_locked(state: &AppState, stderr: &mut Vec<u8>) -> Result<(), Error> {
+    let repo_directory = &state.config.repo_directory;
+    let work_dir = tempfile::Builder::new()
+        .prefix(".godsvagn-new-")
+        .tempdir_in(parent_dir(repo_directory))?;
+    let output_dir = work_dir.path().join("repo");
+    match &state.config.repogen_command {
+        Some(command) => regenerate_subprocess(state, command, &output_dir, stderr).await?,
+        None => {
+            let config_path = state.config_path.clone();
+            let server = state.config.clone();
+            let output_dir = output_dir.clone();
+            let published = tokio::task::spawn_blocking(move || {
+                // read again, like repogen would, so changes apply without a restart
+                let config = Config::load(&config_path).map_err(godsvagn_repogen::Error::from)?;
+                let keyfiles: Vec<PathBuf> = server.keyfiles().map(Path::to_owned).collect();
+                let run = godsvagn_repogen::Run {
+                    output_dir: &output_dir,
+                    input_dir: &server.deb_directory,
+                    keyfiles: &keyfiles,
+                    unsigned: false,
+                    overwrite: false,
+                };
+                godsvagn_repogen::generate(config, &run)
+            })
+            .await??;
+            for published in &published {
+                let destination = &published.destination;
+                match &published.result {
+                    Ok(()) => tracing::info!(%destination, "published repository"),
+                    Err(e) => {
+                        tracing::error!(%destination, error = ?e, "could not publish repository");
+                    }
+                }
+            }
+            godsvagn_repogen::all_published(&published).map_err(godsvagn_repogen::Error::from)?;
+        }
+    }
+    publish_directory(&output_dir, repo_directory)
+}
+
+/// Run `command` like godsvagn-repogen to generate the repository into `output_dir`
+async fn regenerate_subprocess(
+    state: &AppState,
+    command: &str,
+    output_dir: &Path,
+    stderr: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let mut cmd = tokio::process::Command::new(command);
     cmd.arg("--config").arg(state.config_path.as_os_str());
-    cmd.arg("--output-dir").arg(output_tmp.path());
-    cmd.arg("--input-dir")
-        .arg(&state.config.server.deb_directory);
-    cmd.arg("--keyfile").arg(&state.config.server.keyfile);
+    cmd.arg("--output-dir").arg(output_dir);
+    cmd.arg("--input-dir").arg(&state.config.deb_directory);
+    for keyfile in state.config.keyfiles() {
+        cmd.arg("--keyfile").arg(keyfile);
+    }
     cmd.stdin(Stdio::null())
         .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
-    let output = cmd.spawn()?.wait().await?;
-    if output.success() {
-        let dir_suffix: String = rand::rng()
-            .sample_iter(Alphabetic)
-            .take(16)
-            .map(char::from)
-            .collect();
-        let to_delete = std::env::temp_dir().join(dir_suffix);
-        std::fs::rename(&state.config.server.repo_directory, &to_delete)?;
-        std::fs::rename(output_tmp, &state.config.server.repo_directory)?;
-        std::fs::remove_dir_all(&to_delete)?;
-    } else {
+        .stderr(Stdio::piped());
+    let output = cmd.output().await?;
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        tracing::info!(command, "{line}");
+    }
+    stderr.extend_from_slice(&output.stderr);
+    if !output.status.success() {
         return Err(Error::GenerateFailed);
     }
-    drop(guard);
+    Ok(())
+}
+
+/// `Path::parent` returns an empty path for bare relative names
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Swap a freshly generated repository into place. The old tree is moved aside next to the
+/// destination first, so no rename crosses filesystems or targets an existing directory,
+/// neither of which works everywhere.
+fn publish_directory(new: &Path, destination: &Path) -> Result<(), Error> {
+    let old_dir = tempfile::Builder::new()
+        .prefix(".godsvagn-old-")
+        .tempdir_in(parent_dir(destination))?;
+    let old = old_dir.path().join("repo");
+    match std::fs::rename(destination, &old) {
+        Ok(()) => {}
+        Err(e) if e.kind() == IoErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    if let Err(e) = std::fs::rename(new, destination) {
+        // put the previous repository back rather than leaving nothing published
+        if old.exists() {
+            std::fs::rename(&old, destination)?;
+        }
+        return Err(e.into());
+    }
+    // dropping old_dir deletes the previous repository
+    drop(old_dir);
     Ok(())
 }
 
@@ -181,68 +518,81 @@ fn falsey() -> bool {
     false
 }
 
+#[tracing::instrument(skip_all, fields(ignore_exists))]
 async fn upload(
     State(state): State<AppState>,
     Query(UploadQuery { ignore_exists }): Query<UploadQuery>,
     body: Body,
-) -> Result<(), Error> {
-    let (output_tx, output) = tokio::sync::oneshot::channel();
-    // this block drops the body sender to prevent a deadlock of the background task
-    // waiting for more data, while the "no more data" signal sent by dropping bytes_tx will
-    // never be sent because it would be waiting on output.await
-    {
-        let (bytes_tx, bytes_rx) = tokio::sync::mpsc::channel(50);
-        let deb_dir = state.config.server.deb_directory.clone();
-        std::thread::spawn(move || {
-            let o = deb_channel_to_storage(bytes_rx, &deb_dir);
-            if let Err(e) = output_tx.send(o) {
-                eprintln!("Failed to send output to parent thread: {e:?}");
-            }
-        });
-        let mut body_stream = body.into_data_stream();
-        while let Some(d) = body_stream.next().await.transpose()? {
-            bytes_tx.send(d).await.map_err(Error::InvalidSend)?;
-        }
-    }
-    match output.await.map_err(|_| Error::BackgroundCrashed)? {
-        Err(Error::AlreadyExists) if ignore_exists => Ok(()),
-        v => v,
+) -> Result<Json<UploadResponse>, Error> {
+    let mut tmp = tokio::fs::File::from_std(tempfile::tempfile()?);
+    let mut body_stream = body.into_data_stream();
+    let mut size = 0;
+    while let Some(d) = body_stream.next().await.transpose()? {
+        tmp.write_all(&d).await?;
+        size += d.len() as u64;
     }
+    tmp.rewind().await?;
+    let stored = move_deb_to_storage(tmp, state.config.clone(), state.parse_options).await;
+    let result = match &stored {
+        Ok(_) => UploadResult::Accepted,
+        Err(Error::AlreadyExists) if ignore_exists => UploadResult::Duplicate,
+        Err(_) => UploadResult::Rejected,
+    };
+    state.metrics.upload(result, size);
+    tracing::info!(size, ?result, "upload finished");
+    let warnings = match stored {
+        Err(Error::AlreadyExists) if ignore_exists => Vec::new(),
+        v => v?,
+    };
+    let warnings = warnings
+        .iter()
+        .map(|warning| WarningBody {
+            code: warning.code(),
+            warning: warning.to_string(),
+        })
+        .collect();
+    Ok(Json(UploadResponse { warnings }))
 }
 
-fn deb_channel_to_storage(
-    mut bytes_rx: MpscReceiver<Bytes>,
-    deb_directory: &Path,
-) -> Result<(), Error> {
-    let mut tmp = tempfile::tempfile()?;
-    while let Some(val) = bytes_rx.blocking_recv() {
-        tmp.write_all(&val)?;
-    }
-    tmp.rewind()?;
-    find_location_and_move_deb_to_storage(tmp, deb_directory)?;
-    Ok(())
+/// Problems with an accepted upload, for CI to show without failing
+#[derive(serde::Serialize)]
+struct UploadResponse {
+    warnings: Vec<WarningBody>,
 }
 
-fn find_location_and_move_deb_to_storage(
-    mut work_file: File,
-    deb_directory: &Path,
-) -> Result<(), Error> {
-    let (values, _raw) = parsedeb::deb_to_control(&work_file)?;
-    let RequiredFields {
-        package: name,
-        architecture,
-        version,
-        ..
-    } = RequiredFields::from_map(&values).ok_or(Error::MissingField)?;
-
-    let outfile_path = deb_directory.join(format!(
-        "{architecture}/{name}_{version}_{architecture}.deb"
-    ));
-    std::fs::create_dir_all(outfile_path.parent().ok_or(Error::NoParent)?)?;
-    let outfile = OpenOptions::new()
+#[derive(serde::Serialize)]
+struct WarningBody {
+    code: &'static str,
+    warning: String,
+}
+
+async fn move_deb_to_storage(
+    mut work_file: tokio::fs::File,
+    config: Arc<ServerConfig>,
+    options: ParseOptions,
+) -> Result<Vec<Warning>, Error> {
+    let (values, _raw) = parsedeb::deb_to_control_async_with(&mut work_file, &options).await?;
+    let warnings = parsedeb::control_warnings_with(&values, &options);
+    let fields = RequiredFields::from_map(&values)?;
+    tracing::info!(
+        package = %fields.package,
+        version = %fields.version,
+        architecture = %fields.architecture,
+        "storing upload"
+    );
+    if config.deb_signature_keyring.is_some() {
+        work_file.rewind().await?;
+        let deb = std::io::BufReader::new(work_file.try_clone().await?.into_std().await);
+        let config = config.clone();
+        tokio::task::spawn_blocking(move || check_deb_signature(&config, deb)).await??;
+    }
+    let outfile_path = deb_storage_path(&fields, PackageKind::Deb, &config.deb_directory);
+    tokio::fs::create_dir_all(outfile_path.parent().ok_or(Error::NoParent)?).await?;
+    let mut outfile = tokio::fs::OpenOptions::new()
         .write(true)
         .create_new(true)
         .open(outfile_path)
+        .await
         .map_err(|e| {
             if matches!(e.kind(), IoErrorKind::AlreadyExists) {
                 Error::AlreadyExists
@@ -251,11 +601,84 @@ fn find_location_and_move_deb_to_storage(
             }
         })?;
 
-    work_file.rewind()?;
-    std::io::copy(&mut BufReader::new(work_file), &mut BufWriter::new(outfile))?;
+    work_file.rewind().await?;
+    tokio::io::copy(&mut work_file, &mut outfile).await?;
+    outfile.flush().await?;
+    Ok(warnings)
+}
+
+/// Require a trusted debsigs signature, if the server is configured to
+fn check_deb_signature(
+    config: &ServerConfig,
+    deb: impl std::io::Read + std::io::Seek,
+) -> Result<(), Error> {
+    let Some(keyring) = &config.deb_signature_keyring else {
+        return Ok(());
+    };
+    for signer in parsedeb::verify_deb_signatures(deb, &load_keyring(keyring)?)? {
+        tracing::info!(
+            role = %signer.role,
+            fingerprint = %signer.fingerprint,
+            "deb signature verified"
+        );
+    }
     Ok(())
 }
 
+/// Every key in a file of armored public keys
+fn load_keyring(path: &Path) -> Result<Vec<SignedPublicKey>, Error> {
+    let (keys, _headers) = SignedPublicKey::from_armor_many(std::fs::File::open(path)?)?;
+    Ok(keys.collect::<Result<Vec<_>, _>>()?)
+}
+
+/// The same checks repogen is configured with, see [`ReleaseConfig::lenient_control`],
+/// [`ReleaseConfig::field_checks`] and [`ReleaseConfig::reject_essential`]
+fn parse_options(release: &ReleaseConfig) -> ParseOptions {
+    ParseOptions {
+        lenient: release.lenient_control,
+        duplicate_policy: if release.lenient_control {
+            DuplicatePolicy::AllowIdentical
+        } else {
+            DuplicatePolicy::Reject
+        },
+        field_checks: match release.field_checks {
+            config::FieldChecks::Off => FieldChecks::Off,
+            config::FieldChecks::Warn => FieldChecks::Warn,
+            config::FieldChecks::Reject => FieldChecks::Reject,
+        },
+        reject_essential: release.reject_essential,
+        ..Default::default()
+    }
+}
+
+fn read_required_fields(
+    deb: impl std::io::Read,
+    options: &ParseOptions,
+) -> Result<RequiredFields, Error> {
+    let (values, _raw) = parsedeb::deb_to_control_with(deb, options)?;
+    Ok(RequiredFields::from_map_for(&values, options.kind)?)
+}
+
+/// Where in the deb directory a package belongs, ending in `.udeb` for udebs. The fields were
+/// validated when they were parsed, so this can't point outside of it.
+fn deb_storage_path(fields: &RequiredFields, kind: PackageKind, deb_directory: &Path) -> PathBuf {
+    triplet_storage_path(&PackageTriplet::from_fields(fields), kind, deb_directory)
+}
+
+/// [`deb_storage_path`] for a package that's only known by name, version and architecture.
+/// An epoch's `:` is written `%3a`, like apt's archive cache does, since Windows and some
+/// sync tools can't have it in a file name, and leaving the epoch out would give `1:1.0` and
+/// `1.0` the same one.
+fn triplet_storage_path(
+    triplet: &PackageTriplet,
+    kind: PackageKind,
+    deb_directory: &Path,
+) -> PathBuf {
+    deb_directory
+        .join(triplet.architecture().as_str())
+        .join(triplet.file_name_for(kind).replace(':', "%3a"))
+}
+
 #[derive(Debug, thiserror::Error)]
 enum Error {
     #[error("invalid jwt")]
@@ -264,35 +687,159 @@ enum Error {
     UnknownJwk,
     #[error("missing header")]
     MissingHeader,
-    #[error("missing controlfile field")]
-    MissingField,
-    #[error("background task crashed")]
-    BackgroundCrashed,
     #[error("regenerate failed")]
     GenerateFailed,
     #[error("already exists")]
     AlreadyExists,
     #[error("tried to take parent of root- this should be impossible")]
     NoParent,
+    #[error("github app tokens are not enabled")]
+    GithubAppDisabled,
+    #[error("invalid github token")]
+    InvalidGithubToken,
+    #[error("github token cannot access any allowed repository")]
+    UnauthorizedInstallation,
+    #[error("github api error")]
+    GithubApi(#[from] reqwest::Error),
     #[error("invalid jwt")]
     Jwt(#[from] jsonwebtoken::errors::Error),
     #[error("invalid header")]
     HeaderIsInvalidStr(#[from] reqwest::header::ToStrError),
     #[error("i/o error")]
     Io(#[from] std::io::Error),
-    #[error("i/o channel error")]
-    InvalidSend(tokio::sync::mpsc::error::SendError<Bytes>),
     #[error("body error")]
     Axum(#[from] axum::Error),
     #[error("invalid deb file: {0}")]
     DebParse(#[from] parsedeb::Error),
     #[error("task panicked")]
     TaskPanic(#[from] tokio::task::JoinError),
+    #[error("server.repo_url is not configured")]
+    NoRepoUrl,
+    #[error("could not load signing key")]
+    Key(#[from] pgp::errors::Error),
+    #[error(".changes uploads are not enabled")]
+    ChangesDisabled,
+    #[error("invalid file name")]
+    InvalidFileName,
+    #[error(".changes file is not signed by a trusted uploader")]
+    UntrustedChanges,
+    #[error("invalid .changes file: {0}")]
+    InvalidChanges(parsedeb::Error),
+    #[error("{0} is listed in the .changes file but was not uploaded")]
+    MissingUpload(String),
+    #[error("{0} does not match the checksum in the .changes file")]
+    ChecksumMismatch(String),
+    #[error("incoming processing is not enabled")]
+    IncomingDisabled,
+    #[error("rejected by policy: {0}")]
+    PolicyRejected(String),
+    #[error("invalid package pattern")]
+    Regex(#[from] regex::Error),
+    #[error("serving from an OCI registry is not enabled")]
+    OciDisabled,
+    #[error("server.oci.reference must look like registry/repository:tag")]
+    InvalidOciReference,
+    #[error("could not authenticate to the OCI registry")]
+    OciAuth,
+    #[error("not found")]
+    NotFound,
+    #[error("log in to download the repository")]
+    RepoUnauthorized,
+    #[error("log in to upload")]
+    IncomingUnauthorized,
+    #[error("requested range is outside of the {0} byte file")]
+    RangeNotSatisfiable(u64),
+    #[error("invalid package: {0}")]
+    InvalidPackage(parsedeb::Error),
+    #[error("regenerate failed: {0}")]
+    Repogen(#[from] godsvagn_repogen::Error),
+    #[error("invalid TLS certificate or key: {0}")]
+    Tls(#[from] tokio_rustls::rustls::Error),
+    #[error("could not read TLS certificate or key: {0}")]
+    TlsPem(#[from] tokio_rustls::rustls::pki_types::pem::Error),
+}
+
+impl Error {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NoKeyId => "GV3001",
+            Self::UnknownJwk => "GV3002",
+            Self::MissingHeader => "GV3003",
+            Self::GenerateFailed => "GV3006",
+            Self::AlreadyExists => "GV3007",
+            Self::NoParent => "GV3008",
+            Self::GithubAppDisabled => "GV3009",
+            Self::InvalidGithubToken => "GV3010",
+            Self::UnauthorizedInstallation => "GV3011",
+            Self::GithubApi(_) => "GV3012",
+            Self::Jwt(_) => "GV3013",
+            Self::HeaderIsInvalidStr(_) => "GV3014",
+            Self::Io(_) => "GV3015",
+            Self::Axum(_) => "GV3017",
+            Self::DebParse(e) => e.code(),
+            Self::TaskPanic(_) => "GV3018",
+            Self::NoRepoUrl => "GV3019",
+            Self::Key(_) => "GV3020",
+            Self::ChangesDisabled => "GV3021",
+            Self::InvalidFileName => "GV3022",
+            Self::UntrustedChanges => "GV3023",
+            Self::InvalidChanges(_) => "GV3024",
+            Self::MissingUpload(_) => "GV3025",
+            Self::ChecksumMismatch(_) => "GV3026",
+            Self::IncomingDisabled => "GV3027",
+            Self::PolicyRejected(_) => "GV3028",
+            Self::Regex(_) => "GV3029",
+            Self::OciDisabled => "GV3030",
+            Self::InvalidOciReference => "GV3031",
+            Self::OciAuth => "GV3032",
+            Self::NotFound => "GV3033",
+            Self::RepoUnauthorized => "GV3034",
+            Self::RangeNotSatisfiable(_) => "GV3035",
+            Self::InvalidPackage(_) => "GV3036",
+            Self::Repogen(e) => e.code(),
+            Self::Tls(_) => "GV3037",
+            Self::TlsPem(_) => "GV3038",
+            Self::IncomingUnauthorized => "GV3039",
+        }
+    }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        eprintln!("{self:?}");
-        (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+        tracing::warn!(code = self.code(), error = ?self, "request failed");
+        let body = ErrorBody {
+            code: self.code(),
+            error: self.to_string(),
+        };
+        let status = match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::RepoUnauthorized | Self::IncomingUnauthorized => StatusCode::UNAUTHORIZED,
+            Self::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let mut response = (status, Json(body)).into_response();
+        let headers = response.headers_mut();
+        match self {
+            Self::RepoUnauthorized | Self::IncomingUnauthorized => {
+                headers.insert(
+                    axum::http::header::WWW_AUTHENTICATE,
+                    axum::http::HeaderValue::from_static("Basic realm=\"godsvagn\""),
+                );
+            }
+            Self::RangeNotSatisfiable(len) => {
+                if let Ok(range) = axum::http::HeaderValue::try_from(format!("bytes */{len}")) {
+                    headers.insert(axum::http::header::CONTENT_RANGE, range);
+                }
+            }
+            _ => {}
+        }
+        response
     }
 }
+
+#[derive(serde::Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    error: String,
+}
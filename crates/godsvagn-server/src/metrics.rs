@@ -0,0 +1,269 @@
+//! Counters for `/metrics`, in the Prometheus text format. There are few enough of them that
+//! they're kept by hand rather than pulling in a metrics library.
+
+use std::{
+    fmt::Write as _,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use axum::{extract::State, http::header::CONTENT_TYPE, response::IntoResponse};
+
+use crate::{AppState, Error};
+
+/// Upper bounds of the upload size buckets, in bytes, from 64 KiB to 1 GiB
+const SIZE_BUCKETS: [f64; 6] = [
+    65_536.0,
+    1_048_576.0,
+    16_777_216.0,
+    67_108_864.0,
+    268_435_456.0,
+    1_073_741_824.0,
+];
+/// Upper bounds of the regeneration duration buckets, in seconds
+const DURATION_BUCKETS: [f64; 7] = [1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0];
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    uploads_accepted: AtomicU64,
+    uploads_duplicate: AtomicU64,
+    uploads_rejected: AtomicU64,
+    upload_bytes: Histogram<6>,
+    auth_failures: AtomicU64,
+    regenerations_succeeded: AtomicU64,
+    regenerations_failed: AtomicU64,
+    regeneration_seconds: Histogram<7>,
+    /// seconds since the epoch, or 0 if there hasn't been one since the server started
+    last_regeneration: AtomicU64,
+}
+
+/// What happened to an upload to `/upload`
+#[derive(Debug, Clone, Copy)]
+pub enum UploadResult {
+    Accepted,
+    /// already stored, and the upload asked for that to be ignored
+    Duplicate,
+    Rejected,
+}
+
+impl Metrics {
+    pub fn upload(&self, result: UploadResult, bytes: u64) {
+        let counter = match result {
+            UploadResult::Accepted => &self.uploads_accepted,
+            UploadResult::Duplicate => &self.uploads_duplicate,
+            UploadResult::Rejected => &self.uploads_rejected,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.upload_bytes.observe(&SIZE_BUCKETS, bytes as f64);
+    }
+
+    pub fn auth_failure(&self) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn regeneration(&self, succeeded: bool, took: Duration) {
+        self.regeneration_seconds
+            .observe(&DURATION_BUCKETS, took.as_secs_f64());
+        if succeeded {
+            self.regenerations_succeeded.fetch_add(1, Ordering::Relaxed);
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            self.last_regeneration
+                .store(now.as_secs(), Ordering::Relaxed);
+        } else {
+            self.regenerations_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn render(&self, pool: PoolSize) -> String {
+        let mut out = String::new();
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        header(
+            &mut out,
+            "godsvagn_uploads_total",
+            "counter",
+            "debs uploaded to /upload, by what happened to them",
+        );
+        for (result, counter) in [
+            ("accepted", &self.uploads_accepted),
+            ("duplicate", &self.uploads_duplicate),
+            ("rejected", &self.uploads_rejected),
+        ] {
+            let _ = writeln!(
+                out,
+                "godsvagn_uploads_total{{result=\"{result}\"}} {}",
+                get(counter)
+            );
+        }
+        header(
+            &mut out,
+            "godsvagn_upload_bytes",
+            "histogram",
+            "size of debs uploaded to /upload",
+        );
+        self.upload_bytes
+            .render(&mut out, "godsvagn_upload_bytes", &SIZE_BUCKETS);
+        header(
+            &mut out,
+            "godsvagn_auth_failures_total",
+            "counter",
+            "requests refused for a missing or invalid token",
+        );
+        let _ = writeln!(
+            out,
+            "godsvagn_auth_failures_total {}",
+            get(&self.auth_failures)
+        );
+        header(
+            &mut out,
+            "godsvagn_regenerations_total",
+            "counter",
+            "regenerations of the repository, by outcome",
+        );
+        for (outcome, counter) in [
+            ("succeeded", &self.regenerations_succeeded),
+            ("failed", &self.regenerations_failed),
+        ] {
+            let _ = writeln!(
+                out,
+                "godsvagn_regenerations_total{{outcome=\"{outcome}\"}} {}",
+                get(counter)
+            );
+        }
+        header(
+            &mut out,
+            "godsvagn_regeneration_seconds",
+            "histogram",
+            "how long regenerating the repository took",
+        );
+        self.regeneration_seconds.render(
+            &mut out,
+            "godsvagn_regeneration_seconds",
+            &DURATION_BUCKETS,
+        );
+        header(
+            &mut out,
+            "godsvagn_last_successful_regeneration_timestamp_seconds",
+            "gauge",
+            "when the repository was last regenerated, or 0 if it hasn't been since the server started",
+        );
+        let _ = writeln!(
+            out,
+            "godsvagn_last_successful_regeneration_timestamp_seconds {}",
+            get(&self.last_regeneration)
+        );
+        header(
+            &mut out,
+            "godsvagn_pool_files",
+            "gauge",
+            "files in the published pool",
+        );
+        let _ = writeln!(out, "godsvagn_pool_files {}", pool.files);
+        header(
+            &mut out,
+            "godsvagn_pool_bytes",
+            "gauge",
+            "size of the published pool",
+        );
+        let _ = writeln!(out, "godsvagn_pool_bytes {}", pool.bytes);
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Counts of observations at or under each bucket's bound, which are passed in every time so
+/// they can be constants
+#[derive(Debug)]
+struct Histogram<const N: usize> {
+    buckets: [AtomicU64; N],
+    count: AtomicU64,
+    /// an f64, since there's no atomic float
+    sum: AtomicU64,
+}
+
+impl<const N: usize> Default for Histogram<N> {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+}
+
+impl<const N: usize> Histogram<N> {
+    fn observe(&self, bounds: &[f64; N], value: f64) {
+        for (bucket, bound) in self.buckets.iter().zip(bounds) {
+            if value <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    fn render(&self, out: &mut String, name: &str, bounds: &[f64; N]) {
+        let count = self.count.load(Ordering::Relaxed);
+        for (bucket, bound) in self.buckets.iter().zip(bounds) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{le=\"{bound}\"}} {}",
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let sum = f64::from_bits(self.sum.load(Ordering::Relaxed));
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PoolSize {
+    files: u64,
+    bytes: u64,
+}
+
+/// Everything under `pool/` of the published repository, counted when it's scraped so it's
+/// right however the pool changed
+fn pool_size(dir: &Path, size: &mut PoolSize) -> Result<(), std::io::Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            pool_size(&entry.path(), size)?;
+        } else if file_type.is_file() {
+            size.files += 1;
+            size.bytes += entry.metadata()?.len();
+        }
+    }
+    Ok(())
+}
+
+pub async fn serve(State(state): State<AppState>) -> Result<impl IntoResponse, Error> {
+    let pool = state.config.repo_directory.join("pool");
+    let pool = tokio::task::spawn_blocking(move || {
+        let mut size = PoolSize::default();
+        pool_size(&pool, &mut size).map(|()| size)
+    })
+    .await??;
+    Ok((
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        state.metrics.render(pool),
+    ))
+}
@@ -0,0 +1,294 @@
+//! Serve the apt-facing paths of a repository that repogen pushed to an OCI registry.
+//! The registry is only a mirror repogen publishes to, and debs are still stored in the deb
+//! directory. The repository is one artifact, with a layer per file titled with its path, so
+//! a request is a manifest lookup followed by streaming the matching blob.
+//!
+//! The manifest is kept for a little while, and then only fetched again if its digest
+//! changed. Blobs are named by their digest, so it's their ETag, and single byte ranges of
+//! them are honored like they are when serving from disk.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{Path as UrlPath, State},
+    http::{
+        HeaderMap, HeaderName,
+        header::{
+            ACCEPT, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+            IF_NONE_MATCH, RANGE, WWW_AUTHENTICATE,
+        },
+    },
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use config::OciConfig;
+use futures_util::TryStreamExt;
+use reqwest::StatusCode;
+use tokio::{io::AsyncReadExt, sync::Mutex};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::{
+    AppState, Error,
+    repo::{byte_range, if_range_matches},
+};
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+/// How long the manifest is used before checking that it's still the latest. Clients see a
+/// new publish at most this late.
+const MANIFEST_TTL: Duration = Duration::from_secs(30);
+
+pub struct OciSource {
+    /// unlike the main client, this one follows redirects, which registries use for blobs
+    http: reqwest::Client,
+    registry: String,
+    repository: String,
+    tag: String,
+    credentials: Option<(String, String)>,
+    token: Mutex<Option<String>>,
+    manifest: Mutex<Option<CachedManifest>>,
+}
+
+#[derive(Clone)]
+struct CachedManifest {
+    manifest: Arc<Manifest>,
+    /// what the registry said the manifest's ETag or digest was, to ask if it changed with
+    etag: Option<String>,
+    checked: Instant,
+}
+
+#[derive(serde::Deserialize)]
+struct Manifest {
+    layers: Vec<Layer>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Layer {
+    media_type: String,
+    digest: String,
+    size: u64,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+impl OciSource {
+    pub fn new(config: &OciConfig) -> Result<Self, Error> {
+        // registry/repository/path:tag, where the registry is everything before the first slash
+        let (registry, rest) = config
+            .reference
+            .split_once('/')
+            .ok_or(Error::InvalidOciReference)?;
+        let (repository, tag) = match rest.rsplit_once(':') {
+            Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+            _ => (rest, "latest"),
+        };
+        if registry.is_empty() || repository.is_empty() || tag.is_empty() {
+            return Err(Error::InvalidOciReference);
+        }
+        let credentials = config.username.clone().zip(config.password.clone());
+        Ok(Self {
+            http: reqwest::Client::new(),
+            registry: registry.to_owned(),
+            repository: repository.to_owned(),
+            tag: tag.to_owned(),
+            credentials,
+            token: Mutex::new(None),
+            manifest: Mutex::new(None),
+        })
+    }
+
+    /// The tag's manifest, from the last time it was fetched if that's recent or it hasn't
+    /// changed since
+    async fn manifest(&self) -> Result<Arc<Manifest>, Error> {
+        let cached = self.manifest.lock().await.clone();
+        if let Some(cached) = &cached
+            && cached.checked.elapsed() < MANIFEST_TTL
+        {
+            return Ok(cached.manifest.clone());
+        }
+        let mut headers = vec![(ACCEPT, MANIFEST_MEDIA_TYPE.to_owned())];
+        if let Some(etag) = cached.as_ref().and_then(|cached| cached.etag.clone()) {
+            headers.push((IF_NONE_MATCH, etag));
+        }
+        let response = self
+            .get(&format!("manifests/{}", self.tag), &headers)
+            .await?;
+        let fresh = match cached {
+            Some(cached) if response.status() == StatusCode::NOT_MODIFIED => CachedManifest {
+                checked: Instant::now(),
+                ..cached
+            },
+            _ => {
+                let response = response.error_for_status()?;
+                let header = |name: &str| {
+                    let value = response.headers().get(name)?.to_str().ok()?;
+                    Some(value.to_owned())
+                };
+                let etag = header("etag")
+                    .or_else(|| header("docker-content-digest").map(|d| format!("\"{d}\"")));
+                CachedManifest {
+                    manifest: Arc::new(response.json().await?),
+                    etag,
+                    checked: Instant::now(),
+                }
+            }
+        };
+        let manifest = fresh.manifest.clone();
+        *self.manifest.lock().await = Some(fresh);
+        Ok(manifest)
+    }
+
+    /// GET something under the repository, authenticating if the registry asks for it
+    async fn get(
+        &self,
+        path: &str,
+        headers: &[(HeaderName, String)],
+    ) -> Result<reqwest::Response, Error> {
+        let url = format!("https://{}/v2/{}/{path}", self.registry, self.repository);
+        let response = self.send(&url, headers).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let challenge = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .ok_or(Error::OciAuth)?
+            .to_str()?
+            .to_owned();
+        *self.token.lock().await = Some(self.fetch_token(&challenge).await?);
+        let response = self.send(&url, headers).await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(Error::OciAuth);
+        }
+        Ok(response)
+    }
+
+    async fn send(
+        &self,
+        url: &str,
+        headers: &[(HeaderName, String)],
+    ) -> Result<reqwest::Response, Error> {
+        let mut request = self.http.get(url);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        if let Some(token) = self.token.lock().await.as_deref() {
+            request = request.bearer_auth(token);
+        }
+        Ok(request.send().await?)
+    }
+
+    /// Exchange a `WWW-Authenticate: Bearer realm=...` challenge for a token
+    async fn fetch_token(&self, challenge: &str) -> Result<String, Error> {
+        let params = challenge
+            .strip_prefix("Bearer ")
+            .map(challenge_params)
+            .ok_or(Error::OciAuth)?;
+        let realm = params.get("realm").ok_or(Error::OciAuth)?;
+        let query: Vec<(&str, &str)> = ["service", "scope"]
+            .into_iter()
+            .filter_map(|key| Some((key, params.get(key)?.as_str())))
+            .collect();
+        let mut request = self.http.get(realm).query(&query);
+        if let Some((username, password)) = &self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        let response: TokenResponse = request.send().await?.error_for_status()?.json().await?;
+        response
+            .token
+            .or(response.access_token)
+            .ok_or(Error::OciAuth)
+    }
+}
+
+/// Parse `key="value",key="value"`, where values may themselves contain commas
+fn challenge_params(challenge: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = challenge.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        params.insert(key.to_ascii_lowercase(), value.to_owned());
+        rest = after;
+    }
+    params
+}
+
+pub async fn serve(
+    State(state): State<AppState>,
+    UrlPath(path): UrlPath<String>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let oci = state.oci.as_ref().ok_or(Error::OciDisabled)?;
+    let manifest = oci.manifest().await?;
+    let layer = manifest
+        .layers
+        .iter()
+        .find(|layer| layer.annotations.get(TITLE_ANNOTATION) == Some(&path))
+        .ok_or(Error::NotFound)?;
+
+    let len = layer.size;
+    let etag = format!("\"{}\"", layer.digest);
+    let mut response_headers = vec![
+        (CONTENT_TYPE, layer.media_type.clone()),
+        (ACCEPT_RANGES, "bytes".to_owned()),
+        (ETAG, etag.clone()),
+    ];
+    let range = headers
+        .get(RANGE)
+        .and_then(|range| range.to_str().ok())
+        .filter(|_| if_range_matches(&headers, Some(&etag), None));
+    let blob_path = format!("blobs/{}", layer.digest);
+    let Some(range) = range.and_then(|range| byte_range(range, len).transpose()) else {
+        let blob = oci
+            .get(&blob_path, &[(ACCEPT, "*/*".to_owned())])
+            .await?
+            .error_for_status()?;
+        response_headers.push((CONTENT_LENGTH, len.to_string()));
+        let body = Body::from_stream(blob.bytes_stream());
+        return Ok((AppendHeaders(response_headers), body).into_response());
+    };
+    let range = range.map_err(|()| Error::RangeNotSatisfiable(len))?;
+    let (start, end) = (*range.start(), *range.end());
+    let blob = oci
+        .get(
+            &blob_path,
+            &[
+                (ACCEPT, "*/*".to_owned()),
+                (RANGE, format!("bytes={start}-{end}")),
+            ],
+        )
+        .await?
+        .error_for_status()?;
+    // registries don't have to honor ranges, and then the rest of the blob is skipped here
+    let skip = match blob.status() {
+        StatusCode::PARTIAL_CONTENT => 0,
+        _ => start,
+    };
+    let mut reader = StreamReader::new(blob.bytes_stream().map_err(std::io::Error::other));
+    tokio::io::copy(&mut (&mut reader).take(skip), &mut tokio::io::sink()).await?;
+    response_headers.push((CONTENT_LENGTH, (end - start + 1).to_string()));
+    response_headers.push((CONTENT_RANGE, format!("bytes {start}-{end}/{len}")));
+    let body = Body::from_stream(ReaderStream::new(reader.take(end - start + 1)));
+    Ok((
+        StatusCode::PARTIAL_CONTENT,
+        AppendHeaders(response_headers),
+        body,
+    )
+        .into_response())
+}
@@ -0,0 +1,312 @@
+//! Managing the debs in `deb_directory` over HTTP, so fixing a bad upload or checking what's
+//! been uploaded doesn't take a shell on the server
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use axum::{
+    Json,
+    extract::{Path as UrlPath, Query, State},
+};
+use base16ct::HexDisplay;
+use config::ReleaseConfig;
+use filemeta::{FileSums, FileSumsBuilder, HashCache};
+use indexmap::IndexMap;
+use parsedeb::{
+    Description, OptionalFields, PackageKind, PackageTriplet, ParseOptions, RequiredFields,
+};
+
+use crate::{AppState, Error, WarningBody, read_required_fields, triplet_storage_path};
+
+/// How many packages a page has when the query doesn't say
+const DEFAULT_LIMIT: usize = 100;
+/// The most packages a page can have, since every one of them might have to be hashed
+const MAX_LIMIT: usize = 1000;
+
+/// The fields of every deb the last listing read, by path, so listing again only has to parse
+/// what was uploaded since. A deb is only trusted to be the same while its size and mtime are.
+#[derive(Default)]
+pub struct Listed {
+    fields: Mutex<HashMap<PathBuf, (u64, SystemTime, RequiredFields)>>,
+}
+
+impl Listed {
+    fn get(&self, path: &Path, size: u64, modified: SystemTime) -> Option<RequiredFields> {
+        let listed = self.lock();
+        let (listed_size, listed_modified, fields) = listed.get(path)?;
+        (*listed_size == size && *listed_modified == modified).then(|| fields.clone())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<PathBuf, (u64, SystemTime, RequiredFields)>> {
+        self.fields.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListQuery {
+    name: Option<String>,
+    arch: Option<String>,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+/// A page of `GET /packages`
+#[derive(serde::Serialize)]
+pub struct ListResponse {
+    /// how many packages match, over every page
+    total: usize,
+    offset: usize,
+    packages: Vec<PackageSummary>,
+}
+
+#[derive(serde::Serialize)]
+pub struct PackageSummary {
+    name: String,
+    version: String,
+    architecture: String,
+    size: u64,
+    /// lowercase hex, by the name of each digest, like `sha256`
+    hashes: BTreeMap<&'static str, String>,
+    /// when the deb was last written to `deb_directory`, in RFC 3339
+    uploaded: Option<String>,
+}
+
+/// Every deb in `deb_directory` matching the query, sorted by name, then newest version
+/// first, then architecture, so pages stay put while nothing is uploaded
+pub async fn list(
+    State(state): State<AppState>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<ListResponse>, Error> {
+    let deb_directory = state.config.deb_directory.clone();
+    let release = state.release.clone();
+    let options = state.parse_options;
+    let listed = state.listed.clone();
+    let response = tokio::task::spawn_blocking(move || {
+        list_blocking(&deb_directory, &release, &options, &listed, query)
+    })
+    .await??;
+    Ok(Json(response))
+}
+
+fn list_blocking(
+    deb_directory: &Path,
+    release: &ReleaseConfig,
+    options: &ParseOptions,
+    listed: &Listed,
+    query: ListQuery,
+) -> Result<ListResponse, Error> {
+    let mut paths = Vec::new();
+    find_debs(deb_directory, &mut paths)?;
+    let mut found: Vec<(RequiredFields, PathBuf)> = Vec::new();
+    let mut relisted = HashMap::with_capacity(paths.len());
+    for path in paths {
+        let metadata = std::fs::metadata(&path)?;
+        let (size, modified) = (metadata.len(), metadata.modified()?);
+        let fields = match listed.get(&path, size, modified) {
+            Some(fields) => fields,
+            None => match read_required_fields(File::open(&path)?, options) {
+                Ok(fields) => fields,
+                Err(e) => {
+                    let path = path.display();
+                    tracing::warn!(%path, error = ?e, "could not read a deb to list it");
+                    continue;
+                }
+            },
+        };
+        relisted.insert(path.clone(), (size, modified, fields.clone()));
+        let matches = query
+            .name
+            .as_ref()
+            .is_none_or(|name| fields.package.as_str() == name)
+            && (query.arch.as_ref()).is_none_or(|arch| fields.architecture.as_str() == arch);
+        if matches {
+            found.push((fields, path));
+        }
+    }
+    found.sort_by(|(a, _), (b, _)| {
+        a.package
+            .as_str()
+            .cmp(b.package.as_str())
+            .then_with(|| b.version.parsed().cmp(a.version.parsed()))
+            .then_with(|| a.architecture.as_str().cmp(b.architecture.as_str()))
+    });
+    // debs that are gone are forgotten along with the rest
+    *listed.lock() = relisted;
+
+    let hash_cache = load_hash_cache(release)?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let packages = found
+        .iter()
+        .skip(query.offset)
+        .take(limit)
+        .map(|(fields, path)| summarize(fields, path, hash_cache.as_ref()))
+        .collect::<Result<_, Error>>()?;
+    Ok(ListResponse {
+        total: found.len(),
+        offset: query.offset,
+        packages,
+    })
+}
+
+/// `GET /packages/{name}/{version}/{arch}`, everything in the control file of a package
+#[derive(serde::Serialize)]
+pub struct PackageDetail {
+    #[serde(flatten)]
+    summary: PackageSummary,
+    /// every field, in the order the control file has them, with values trimmed
+    control: IndexMap<Box<str>, Box<str>>,
+    description: Option<Description>,
+    /// the fields that are parsed further, the same way repogen does
+    parsed: OptionalFields,
+    /// what an upload of the package would be warned about now
+    warnings: Vec<WarningBody>,
+}
+
+pub async fn detail(
+    State(state): State<AppState>,
+    UrlPath((name, version, arch)): UrlPath<(String, String, String)>,
+) -> Result<Json<PackageDetail>, Error> {
+    let triplet = PackageTriplet::new(&name, &version, &arch).map_err(Error::InvalidPackage)?;
+    let path = triplet_storage_path(&triplet, PackageKind::Deb, &state.config.deb_directory);
+    let release = state.release.clone();
+    let options = state.parse_options;
+    let detail =
+        tokio::task::spawn_blocking(move || detail_blocking(&path, &release, &options)).await??;
+    Ok(Json(detail))
+}
+
+fn detail_blocking(
+    path: &Path,
+    release: &ReleaseConfig,
+    options: &ParseOptions,
+) -> Result<PackageDetail, Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+    let (mut control, _raw) = parsedeb::deb_to_control_with(BufReader::new(file), options)?;
+    let fields = RequiredFields::from_map(&control)?;
+    let parsed = OptionalFields::from_map(&control)?;
+    let warnings = parsedeb::control_warnings_with(&control, options)
+        .iter()
+        .map(|warning| WarningBody {
+            code: warning.code(),
+            warning: warning.to_string(),
+        })
+        .collect();
+    let description = control
+        .get("Description")
+        .map(|description| Description::parse(description));
+    for value in control.values_mut() {
+        *value = value.trim().into();
+    }
+    Ok(PackageDetail {
+        summary: summarize(&fields, path, load_hash_cache(release)?.as_ref())?,
+        control,
+        description,
+        parsed,
+        warnings,
+    })
+}
+
+/// repogen keeps the cache up to date, so only what was uploaded since the last regeneration
+/// has to be hashed. It's only ever saved by repogen.
+fn load_hash_cache(release: &ReleaseConfig) -> Result<Option<HashCache>, Error> {
+    match &release.hash_cache {
+        Some(path) => Ok(Some(HashCache::load(path)?)),
+        None => Ok(None),
+    }
+}
+
+fn summarize(
+    fields: &RequiredFields,
+    path: &Path,
+    hash_cache: Option<&HashCache>,
+) -> Result<PackageSummary, Error> {
+    let metadata = std::fs::metadata(path)?;
+    let sums = match hash_cache.and_then(|cache| cache.get(path, &metadata, &FileSumsBuilder::APT))
+    {
+        Some(sums) => sums,
+        None => FileSums::new(BufReader::new(File::open(path)?))?,
+    };
+    let uploaded = (metadata.modified().ok())
+        .and_then(|modified| jiff::Timestamp::try_from(modified).ok())
+        .map(|modified| modified.to_string());
+    Ok(PackageSummary {
+        name: fields.package.to_string(),
+        version: fields.version.to_string(),
+        architecture: fields.architecture.to_string(),
+        size: metadata.len(),
+        hashes: hex_sums(&sums),
+        uploaded,
+    })
+}
+
+fn hex_sums(sums: &FileSums) -> BTreeMap<&'static str, String> {
+    sums.iter()
+        .map(|(kind, digest)| (kind.name(), format!("{:x}", HexDisplay(digest))))
+        .collect()
+}
+
+/// Every file under `dir`, the same way repogen finds them, skipping hidden ones
+fn find_debs(dir: &Path, write_into: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_name().as_encoded_bytes().starts_with(b".") {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            find_debs(&entry.path(), write_into)?;
+        } else if file_type.is_file() {
+            write_into.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// What `DELETE /packages/{name}/{version}/{arch}` did
+#[derive(serde::Serialize)]
+pub struct DeleteResponse {
+    package: String,
+    version: String,
+    architecture: String,
+    /// the regeneration that removes the package from the repository. It stays published
+    /// until that's done.
+    job: u64,
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    UrlPath((name, version, arch)): UrlPath<(String, String, String)>,
+) -> Result<Json<DeleteResponse>, Error> {
+    let triplet = PackageTriplet::new(&name, &version, &arch).map_err(Error::InvalidPackage)?;
+    let path = triplet_storage_path(&triplet, PackageKind::Deb, &state.config.deb_directory);
+    let guard = state.file_ops_pending.lock().await;
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound),
+        Err(e) => return Err(e.into()),
+    }
+    drop(guard);
+    tracing::info!(path = %path.display(), "deleted package");
+    Ok(Json(DeleteResponse {
+        package: triplet.package().to_string(),
+        version: triplet.version().to_string(),
+        architecture: triplet.architecture().to_string(),
+        job: state.jobs.enqueue(),
+    }))
+}
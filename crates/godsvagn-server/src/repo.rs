@@ -0,0 +1,219 @@
+//! Serve the published repository straight out of `repo_directory` under `/apt/`, so the
+//! server is all a small deployment needs. Only files are served, never directory listings,
+//! and nothing hidden, like the temporary trees a regeneration swaps in.
+//!
+//! Single byte ranges are honored, so apt and download accelerators can resume a big package
+//! instead of fetching it again from the start.
+
+use std::{
+    io::SeekFrom,
+    ops::RangeInclusive,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use axum::{
+    body::Body,
+    extract::{Path as UrlPath, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{
+            ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE,
+            LAST_MODIFIED, RANGE,
+        },
+    },
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use config::ServeConfig;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::{AppState, Error, basic_auth};
+
+pub async fn serve(
+    State(state): State<AppState>,
+    UrlPath(path): UrlPath<String>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let serve = state.config.serve.as_ref().ok_or(Error::NotFound)?;
+    if !authorized(serve, &headers) {
+        return Err(Error::RepoUnauthorized);
+    }
+    let path = repo_path(&state.config.repo_directory, &path).ok_or(Error::NotFound)?;
+    let mut file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Err(Error::NotFound);
+    }
+    let len = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = modified.map(|modified| etag(len, modified));
+    let mut response_headers = vec![
+        (CONTENT_TYPE, content_type(&path).to_owned()),
+        (ACCEPT_RANGES, "bytes".to_owned()),
+    ];
+    if let Some(modified) = modified {
+        response_headers.push((LAST_MODIFIED, httpdate::fmt_http_date(modified)));
+    }
+    if let Some(etag) = &etag {
+        response_headers.push((ETAG, etag.clone()));
+    }
+
+    let range = headers
+        .get(RANGE)
+        .and_then(|range| range.to_str().ok())
+        .filter(|_| if_range_matches(&headers, etag.as_deref(), modified));
+    let Some(range) = range.and_then(|range| byte_range(range, len).transpose()) else {
+        response_headers.push((CONTENT_LENGTH, len.to_string()));
+        let body = Body::from_stream(ReaderStream::new(file));
+        return Ok((AppendHeaders(response_headers), body).into_response());
+    };
+    let range = range.map_err(|()| Error::RangeNotSatisfiable(len))?;
+    let (start, end) = (*range.start(), *range.end());
+    file.seek(SeekFrom::Start(start)).await?;
+    response_headers.push((CONTENT_LENGTH, (end - start + 1).to_string()));
+    response_headers.push((CONTENT_RANGE, format!("bytes {start}-{end}/{len}")));
+    let body = Body::from_stream(ReaderStream::new(file.take(end - start + 1)));
+    Ok((
+        StatusCode::PARTIAL_CONTENT,
+        AppendHeaders(response_headers),
+        body,
+    )
+        .into_response())
+}
+
+/// Strong enough to resume with, since regenerating only ever replaces files, which changes
+/// their modification time
+fn etag(len: u64, modified: SystemTime) -> String {
+    let nanos = modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since| since.as_nanos())
+        .unwrap_or_default();
+    format!("\"{len:x}-{nanos:x}\"")
+}
+
+/// Whether a range request can be answered with part of the file as it is now. Without
+/// `If-Range` it always can, otherwise only if the file is still the one the client has the
+/// rest of, going by its ETag or its modification date.
+pub fn if_range_matches(
+    headers: &HeaderMap,
+    etag: Option<&str>,
+    modified: Option<SystemTime>,
+) -> bool {
+    let Some(if_range) = headers.get(IF_RANGE) else {
+        return true;
+    };
+    let Ok(if_range) = if_range.to_str() else {
+        return false;
+    };
+    if if_range.starts_with('"') {
+        return etag == Some(if_range);
+    }
+    // dates only have a precision of seconds, so the file has to be at least that old to
+    // be sure it didn't change within the same second
+    match (httpdate::parse_http_date(if_range), modified) {
+        (Ok(date), Some(modified)) => {
+            httpdate::fmt_http_date(modified) == httpdate::fmt_http_date(date)
+                && SystemTime::now()
+                    .duration_since(modified)
+                    .is_ok_and(|age| age.as_secs() >= 1)
+        }
+        _ => false,
+    }
+}
+
+/// The bytes a `Range` header asks for out of `len`, as `None` if it's one that's ignored and
+/// the whole file is sent, like several ranges at once or other units, and as an error if it
+/// starts past the end of the file
+pub fn byte_range(range: &str, len: u64) -> Result<Option<RangeInclusive<u64>>, ()> {
+    let Some(range) = range.strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    let Some((start, end)) = range.trim().split_once('-') else {
+        return Ok(None);
+    };
+    if range.contains(',') {
+        return Ok(None);
+    }
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let Ok(suffix) = suffix.parse::<u64>() else {
+                return Ok(None);
+            };
+            if suffix == 0 || len == 0 {
+                return Err(());
+            }
+            len.saturating_sub(suffix)..=len - 1
+        }
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return Ok(None);
+            };
+            let end = match end {
+                "" => u64::MAX,
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return Ok(None),
+                },
+            };
+            if start >= len {
+                return Err(());
+            }
+            start..=end.min(len - 1)
+        }
+    };
+    Ok(Some(range))
+}
+
+/// Anyone can download when no users are configured, otherwise apt has to log in as one of
+/// them with basic auth, like it does with credentials from `/etc/apt/auth.conf.d/`
+pub(crate) fn authorized(serve: &ServeConfig, headers: &HeaderMap) -> bool {
+    serve.users.is_empty() || basic_auth(&serve.users, headers).is_some()
+}
+
+/// Where a url path is in the repository, if it names something that could be served. Every
+/// part of it has to be a plain name, so it can't leave the repository or reach hidden files,
+/// on any platform: no drive prefixes, roots or `:`, which Windows reads as a drive or a
+/// stream, and no `\`, which it reads as a separator.
+pub(crate) fn repo_path(repo_directory: &Path, path: &str) -> Option<PathBuf> {
+    let mut full = repo_directory.to_owned();
+    for part in path.split('/') {
+        let mut components = Path::new(part).components();
+        let plain = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        );
+        if !plain || part.starts_with('.') || part.contains(['\\', ':']) {
+            return None;
+        }
+        full.push(part);
+    }
+    Some(full)
+}
+
+/// What apt and browsers should take each file of a repository for. Indexes without an
+/// extension are text.
+fn content_type(path: &Path) -> &'static str {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("deb" | "udeb") => "application/vnd.debian.binary-package",
+        Some("gz") => "application/gzip",
+        Some("xz") => "application/x-xz",
+        Some("zst") => "application/zstd",
+        Some("bz2") => "application/x-bzip2",
+        Some("gpg") if name == "Release.gpg" => "application/pgp-signature",
+        Some("gpg" | "pgp" | "asc") => "application/pgp-keys",
+        Some("json") => "application/json",
+        Some("meta4") => "application/metalink4+xml",
+        Some("sources" | "dsc" | "changes" | "txt") => "text/plain; charset=utf-8",
+        Some(_) => "application/octet-stream",
+        None => "text/plain; charset=utf-8",
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use axum::http::{
+    HeaderMap, HeaderValue,
+    header::{AUTHORIZATION, IF_RANGE},
+};
+use base64::Engine;
+use config::{ServeConfig, ServeUser};
+
+use crate::{
+    github_app::next_page,
+    repo::{authorized, byte_range, if_range_matches, repo_path},
+};
+
+fn headers(name: axum::http::HeaderName, value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(name, HeaderValue::from_str(value).unwrap());
+    headers
+}
+
+#[test]
+fn byte_ranges() {
+    assert_eq!(byte_range("bytes=0-99", 1000), Ok(Some(0..=99)));
+    assert_eq!(byte_range("bytes=900-5000", 1000), Ok(Some(900..=999)));
+    // open-ended
+    assert_eq!(byte_range("bytes=500-", 1000), Ok(Some(500..=999)));
+    // suffixes, even ones longer than the file
+    assert_eq!(byte_range("bytes=-100", 1000), Ok(Some(900..=999)));
+    assert_eq!(byte_range("bytes=-2000", 1000), Ok(Some(0..=999)));
+
+    // unsatisfiable
+    assert_eq!(byte_range("bytes=1000-", 1000), Err(()));
+    assert_eq!(byte_range("bytes=-0", 1000), Err(()));
+    assert_eq!(byte_range("bytes=-10", 0), Err(()));
+
+    // ignored, so the whole file is sent
+    assert_eq!(byte_range("bytes=0-1,5-6", 1000), Ok(None));
+    assert_eq!(byte_range("items=0-1", 1000), Ok(None));
+    assert_eq!(byte_range("bytes=5-1", 1000), Ok(None));
+    assert_eq!(byte_range("bytes=a-b", 1000), Ok(None));
+}
+
+#[test]
+fn if_range() {
+    let etag = "\"3e8-1\"";
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let date = httpdate::fmt_http_date(modified);
+
+    assert!(if_range_matches(&HeaderMap::new(), None, None));
+    assert!(if_range_matches(
+        &headers(IF_RANGE, etag),
+        Some(etag),
+        Some(modified)
+    ));
+    assert!(!if_range_matches(
+        &headers(IF_RANGE, "\"other\""),
+        Some(etag),
+        Some(modified)
+    ));
+    assert!(!if_range_matches(&headers(IF_RANGE, etag), None, None));
+
+    assert!(if_range_matches(
+        &headers(IF_RANGE, &date),
+        Some(etag),
+        Some(modified)
+    ));
+    let later = httpdate::fmt_http_date(modified + Duration::from_secs(1));
+    assert!(!if_range_matches(
+        &headers(IF_RANGE, &later),
+        Some(etag),
+        Some(modified)
+    ));
+    assert!(!if_range_matches(
+        &headers(IF_RANGE, &date),
+        Some(etag),
+        None
+    ));
+    // a file modified this second might change again within it
+    let now = SystemTime::now();
+    assert!(!if_range_matches(
+        &headers(IF_RANGE, &httpdate::fmt_http_date(now)),
+        Some(etag),
+        Some(now)
+    ));
+}
+
+#[test]
+fn basic_auth_logins() {
+    let login = |credentials: &str| {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+        headers(AUTHORIZATION, &format!("Basic {encoded}"))
+    };
+    let anyone = ServeConfig { users: Vec::new() };
+    assert!(authorized(&anyone, &HeaderMap::new()));
+
+    let serve = ServeConfig {
+        users: vec![
+            ServeUser {
+                username: "apt".to_owned(),
+                password: "hunter2".to_owned(),
+            },
+            ServeUser {
+                username: "mirror".to_owned(),
+                password: "correct horse".to_owned(),
+            },
+        ],
+    };
+    assert!(authorized(&serve, &login("apt:hunter2")));
+    assert!(authorized(&serve, &login("mirror:correct horse")));
+
+    assert!(!authorized(&serve, &HeaderMap::new()));
+    assert!(!authorized(&serve, &login("apt:hunter3")));
+    assert!(!authorized(&serve, &login("apt:correct horse")));
+    assert!(!authorized(&serve, &login("nobody:hunter2")));
+    assert!(!authorized(&serve, &login("apt")));
+    assert!(!authorized(&serve, &headers(AUTHORIZATION, "Basic !!!")));
+    assert!(!authorized(
+        &serve,
+        &headers(AUTHORIZATION, "Bearer hunter2")
+    ));
+}
+
+#[test]
+fn repo_paths() {
+    let repo = Path::new("/srv/repo");
+    assert_eq!(
+        repo_path(repo, "dists/stable/InRelease"),
+        Some(repo.join("dists").join("stable").join("InRelease"))
+    );
+    for bad in [
+        "",
+        "dists//InRelease",
+        "../etc/passwd",
+        "dists/../../etc",
+        ".hidden",
+        "dists/.tmp/Release",
+        "pool\\..\\..\\etc",
+        "c:",
+        "pool/hello_1:1.0_all.deb",
+    ] {
+        assert_eq!(repo_path(repo, bad), None, "{bad}");
+    }
+}
+
+#[test]
+fn github_link_header() {
+    let link = "<https://api.github.com/installation/repositories?per_page=100&page=2>; \
+                rel=\"next\", <https://api.github.com/installation/repositories?per_page=100&page=5>; \
+                rel=\"last\"";
+    assert_eq!(
+        next_page(link).as_deref(),
+        Some("https://api.github.com/installation/repositories?per_page=100&page=2")
+    );
+    let last = "<https://api.github.com/installation/repositories?page=1>; rel=\"prev\"";
+    assert_eq!(next_page(last), None);
+    // the token is sent along, so it can't be pointed anywhere else
+    assert_eq!(next_page("<https://example.com/steal>; rel=\"next\""), None);
+}
@@ -0,0 +1,123 @@
+//! HTTPS without a reverse proxy in front. Handshakes happen in their own tasks, so a slow
+//! client can't hold up everyone else's connections, and on unix the certificate is read again
+//! on SIGHUP so renewing it doesn't take a restart. Elsewhere it's only read at startup.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use config::TlsConfig;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        self,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+    server::TlsStream,
+};
+
+use crate::Error;
+
+/// How long a client gets to finish its handshake before it's dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections whose handshake is done, for [`axum::serve`]
+pub struct TlsListener {
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Addr = SocketAddr;
+    type Io = TlsStream<TcpStream>;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // the task accepting connections never stops while this is around
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// Accept TLS connections on `listener` with the certificate and key in `config`, reading
+/// them again every time the server gets SIGHUP on unix
+pub async fn listen(listener: TcpListener, config: &TlsConfig) -> Result<TlsListener, Error> {
+    let local_addr = listener.local_addr()?;
+    let acceptor = Arc::new(RwLock::new(load(config).await?));
+    #[cfg(unix)]
+    reload_on_hangup(config.clone(), acceptor.clone())?;
+
+    let (send, connections) = mpsc::channel(64);
+    tokio::spawn(async move {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // like axum does, since this is mostly running out of file descriptors
+                    tracing::error!(error = ?e, "could not accept a connection");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            let acceptor = acceptor.read().unwrap_or_else(|e| e.into_inner()).clone();
+            let send = send.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = send.send((stream, addr)).await;
+                    }
+                    Ok(Err(e)) => tracing::debug!(%addr, error = ?e, "TLS handshake failed"),
+                    Err(_) => tracing::debug!(%addr, "TLS handshake timed out"),
+                }
+            });
+        }
+    });
+    Ok(TlsListener {
+        connections,
+        local_addr,
+    })
+}
+
+#[cfg(unix)]
+fn reload_on_hangup(config: TlsConfig, acceptor: Arc<RwLock<TlsAcceptor>>) -> Result<(), Error> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match load(&config).await {
+                Ok(reloaded) => {
+                    *acceptor.write().unwrap_or_else(|e| e.into_inner()) = reloaded;
+                    tracing::info!("reloaded TLS certificate");
+                }
+                // the old certificate is still better than none
+                Err(e) => tracing::error!(error = ?e, "could not reload TLS certificate"),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn load(config: &TlsConfig) -> Result<TlsAcceptor, Error> {
+    let certificates = tokio::fs::read(&config.certificate).await?;
+    let certificates =
+        CertificateDer::pem_slice_iter(&certificates).collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_slice(&tokio::fs::read(&config.key).await?)?;
+    let mut server = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)?;
+    // axum only speaks HTTP/1.1
+    server.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(server)))
+}
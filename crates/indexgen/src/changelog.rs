@@ -0,0 +1,64 @@
+//! Changelogs for `apt changelog`, which fetches them from the URL template in Release's
+//! `Changelogs` field with `@CHANGEPATH@` replaced by a path like `main/h/hello/hello_1.0-1`.
+//! They're published under `changelogs/` at the top of the repository, the way Debian's
+//! metadata.ftp-master.debian.org lays them out.
+
+use std::{collections::BTreeMap, io::Read};
+
+use package::Package;
+use parsedeb::SourcePackage;
+
+use crate::{GenerateError, Sink, write_to};
+
+/// Whether `path` is where `package` installs its changelog: `changelog.Debian.gz`, or just
+/// `changelog.gz` in native packages
+pub fn is_changelog_file(package: &str, path: &str) -> bool {
+    path.strip_prefix("/usr/share/doc/")
+        .and_then(|rest| rest.strip_prefix(package))
+        .is_some_and(|name| matches!(name, "/changelog.Debian.gz" | "/changelog.gz"))
+}
+
+/// The changelog of every source package, gathered as packages are added. Binaries built from
+/// the same source share a changelog, which is taken from the first one that has it.
+#[derive(Debug, Default)]
+pub(crate) struct Changelogs {
+    /// by change path, with the pool file each came from
+    changelogs: BTreeMap<String, (Box<str>, Box<[u8]>)>,
+}
+
+impl Changelogs {
+    pub(crate) fn add(&mut self, package: &Package) -> Result<(), GenerateError> {
+        if let Some(changelog) = &package.changelog {
+            self.changelogs
+                .entry(change_path(package)?)
+                .or_insert_with(|| (package.meta.file.path.clone(), changelog.clone()));
+        }
+        Ok(())
+    }
+
+    /// Write every changelog to `sink`, decompressed
+    pub(crate) fn write(self, sink: &mut dyn Sink) -> Result<(), GenerateError> {
+        for (change_path, (path, changelog)) in self.changelogs {
+            let mut text = Vec::with_capacity(changelog.len() * 4);
+            flate2::read::GzDecoder::new(&changelog[..])
+                .read_to_end(&mut text)
+                .map_err(|e| GenerateError::Changelog(path, e))?;
+            write_to(sink, &format!("changelogs/{change_path}_changelog"), &text)?;
+        }
+        Ok(())
+    }
+}
+
+/// What apt puts in place of `@CHANGEPATH@` for a package: its source's pool directory without
+/// `pool/`, then the source name and version, which never has an epoch in it
+fn change_path(package: &Package) -> Result<String, GenerateError> {
+    let source = SourcePackage::of(package).map_err(|e| {
+        let e = std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        GenerateError::Changelog(package.meta.file.path.clone(), e)
+    })?;
+    let directory = source.pool_directory(&package.component);
+    let directory = directory.strip_prefix("pool/").unwrap_or(&directory);
+    let version = source.version.to_string();
+    let version = version.split_once(':').map_or(&*version, |(_, rest)| rest);
+    Ok(format!("{directory}{}_{version}", source.name))
+}
@@ -0,0 +1,172 @@
+//! The compressed copies of each index that are published next to the uncompressed one
+
+use std::{
+    io::Write,
+    num::NonZero,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use flate2::{Compression, GzBuilder};
+
+/// Which compressed copies of each index to publish. apt fetches whichever it prefers of those
+/// listed in Release, so these only change what clients can choose from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CompressionSet {
+    pub gzip: bool,
+    pub xz: bool,
+    /// apt 2.x reads these, and they decompress far faster than xz on slow clients
+    pub zstd: bool,
+    /// for old tooling that predates xz
+    pub bzip2: bool,
+    pub levels: CompressionLevels,
+}
+
+/// How hard each format is compressed. Higher levels make smaller indexes, and on big
+/// repositories take up most of the time spent generating them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CompressionLevels {
+    /// 0 to 9
+    pub gzip: u32,
+    /// 0 to 9
+    pub xz: u32,
+    /// 1 to 22
+    pub zstd: i32,
+    /// 1 to 9
+    pub bzip2: u32,
+}
+
+impl CompressionLevels {
+    /// The smallest indexes each format can make
+    pub const BEST: Self = Self {
+        gzip: 9,
+        xz: 9,
+        zstd: 19,
+        bzip2: 9,
+    };
+    /// Indexes a little bigger than [`BEST`](Self::BEST), in a fraction of the time
+    pub const FAST: Self = Self {
+        gzip: 1,
+        xz: 1,
+        zstd: 3,
+        bzip2: 1,
+    };
+}
+
+impl Default for CompressionLevels {
+    fn default() -> Self {
+        Self::BEST
+    }
+}
+
+impl Default for CompressionSet {
+    fn default() -> Self {
+        Self {
+            gzip: true,
+            xz: true,
+            zstd: false,
+            bzip2: false,
+            levels: CompressionLevels::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Format {
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+impl CompressionSet {
+    pub(crate) fn formats(&self) -> impl Iterator<Item = Format> {
+        [
+            (self.gzip, Format::Gzip),
+            (self.xz, Format::Xz),
+            (self.zstd, Format::Zstd),
+            (self.bzip2, Format::Bzip2),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, format)| enabled.then_some(format))
+    }
+}
+
+impl Format {
+    pub(crate) const ALL: [Self; 4] = [Self::Gzip, Self::Xz, Self::Zstd, Self::Bzip2];
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Self::Gzip => "gz",
+            Self::Xz => "xz",
+            Self::Zstd => "zst",
+            Self::Bzip2 => "bz2",
+        }
+    }
+
+    pub(crate) fn compress(
+        self,
+        data: &[u8],
+        levels: &CompressionLevels,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            Self::Gzip => {
+                let mut gz = Vec::new();
+                // no timestamp, and the OS byte saying "unknown", so the same index always
+                // compresses the same
+                let header = GzBuilder::new().mtime(0).operating_system(255);
+                let mut writer = header.write(&mut gz, Compression::new(levels.gzip));
+                writer.write_all(data)?;
+                writer.finish()?;
+                Ok(gz)
+            }
+            Self::Xz => liblzma::encode_all(data, levels.xz),
+            Self::Zstd => zstd::encode_all(data, levels.zstd),
+            Self::Bzip2 => {
+                let level = bzip2::Compression::new(levels.bzip2);
+                let mut writer = bzip2::write::BzEncoder::new(Vec::new(), level);
+                writer.write_all(data)?;
+                writer.finish()
+            }
+        }
+    }
+}
+
+/// Compress each of `jobs`, spread over as many threads as there are cores, since the indexes
+/// of a repository with many architectures take a while one after another. Results are in the
+/// same order as `jobs`.
+pub(crate) fn compress_parallel(
+    jobs: &[(&[u8], Format)],
+    levels: &CompressionLevels,
+) -> Vec<Result<Vec<u8>, std::io::Error>> {
+    let threads = std::thread::available_parallelism()
+        .map_or(1, NonZero::get)
+        .min(jobs.len());
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<_> = jobs.iter().map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let job = next.fetch_add(1, Ordering::Relaxed);
+                        let Some((data, format)) = jobs.get(job) else {
+                            return done;
+                        };
+                        done.push((job, format.compress(data, levels)));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            let done = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            for (job, result) in done {
+                results[job] = Some(result);
+            }
+        }
+    });
+    // every job was taken by a worker, and every worker was joined
+    results.into_iter().flatten().collect()
+}
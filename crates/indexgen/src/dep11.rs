@@ -0,0 +1,627 @@
+//! DEP-11, the AppStream metadata software centers like GNOME Software and KDE Discover list
+//! apps from. `<component>/dep11/Components-<arch>.yml` describes every app in the component,
+//! built from the metainfo files and desktop entries its packages install, and
+//! `icons-<size>.tar.gz` holds their icons.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use package::Package;
+use parsedeb::{Architecture, DataFile, PackageName};
+
+use crate::{
+    GenerateError, IndexFile, PackageIndexFile,
+    compress::{CompressionLevels, Format},
+};
+
+/// Sizes of the icons published in the icon tarballs, from the hicolor theme
+const ICON_SIZES: [u32; 2] = [64, 128];
+/// Metainfo is small, anything nested deeper than this isn't
+const MAX_DEPTH: usize = 32;
+
+/// Whether repogen needs to read a file a package installs to describe it in DEP-11
+pub fn is_appstream_file(path: &str) -> bool {
+    let in_dir = |dir: &str, extension: &str| {
+        path.strip_prefix(dir)
+            .is_some_and(|name| !name.contains('/') && name.ends_with(extension))
+    };
+    in_dir("/usr/share/metainfo/", ".xml")
+        || in_dir("/usr/share/appdata/", ".xml")
+        || in_dir("/usr/share/applications/", ".desktop")
+        || ICON_SIZES.iter().any(|size| {
+            in_dir(
+                &format!("/usr/share/icons/hicolor/{size}x{size}/apps/"),
+                ".png",
+            )
+        })
+}
+
+/// The Components files of one component, one for each of `architectures`, and its icon
+/// tarballs. Apps from packages for `all` are in every Components file.
+pub(crate) fn generate_dep11<'a>(
+    suite: &str,
+    component: &str,
+    architectures: &[&str],
+    apps: impl Iterator<Item = &'a App>,
+    levels: &CompressionLevels,
+) -> Result<(Vec<IndexFile>, Vec<PackageIndexFile>), GenerateError> {
+    let mut apps: Vec<&App> = apps.collect();
+    apps.sort_by(|a, b| (&a.package, &a.id).cmp(&(&b.package, &b.id)));
+
+    // apt only fetches the Components file of its own architecture
+    let real: Vec<&str> = architectures
+        .iter()
+        .copied()
+        .filter(|arch| *arch != "all")
+        .collect();
+    let architectures = if real.is_empty() { &["all"][..] } else { &real };
+
+    let mut indexes = Vec::new();
+    for arch in architectures {
+        let mut o = String::with_capacity(1024);
+        writeln!(o, "---\nFile: DEP-11\nVersion: '0.16'")?;
+        writeln!(
+            o,
+            "Origin: {}",
+            yaml_string(&format!("{suite}-{component}"))
+        )?;
+        for app in &apps {
+            let package_arch = app.architecture.as_str();
+            if package_arch == *arch || package_arch == "all" {
+                app.write_yaml(&mut o)?;
+            }
+        }
+        indexes.push(IndexFile {
+            base_path: format!("{component}/dep11/Components-{arch}.yml"),
+            contents: o.into_boxed_str(),
+        });
+    }
+
+    let mut tarballs = Vec::new();
+    for size in ICON_SIZES {
+        let path = format!("{component}/dep11/icons-{size}x{size}.tar.gz");
+        let icons: Vec<(String, &[u8])> = apps
+            .iter()
+            .filter_map(|app| Some((app.cached_icon_name()?, app.icon(size)?)))
+            .collect();
+        if icons.is_empty() {
+            continue;
+        }
+        let tarball = icon_tarball(&icons, levels)
+            .map_err(|e| GenerateError::Compression("gz", path.clone(), e))?;
+        tarballs.push(PackageIndexFile {
+            path: path.into(),
+            data: tarball.into_boxed_slice(),
+        });
+    }
+    Ok((indexes, tarballs))
+}
+
+fn icon_tarball(icons: &[(String, &[u8])], levels: &CompressionLevels) -> std::io::Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, data) in icons {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_cksum();
+        builder.append_data(&mut header, name, *data)?;
+    }
+    Format::Gzip.compress(&builder.into_inner()?, levels)
+}
+
+/// One AppStream component, from a metainfo file, with everything it needs from its package
+pub(crate) struct App {
+    package: PackageName,
+    architecture: Architecture,
+    id: String,
+    kind: String,
+    /// by locale, with `C` for the untranslated text
+    names: BTreeMap<String, String>,
+    summaries: BTreeMap<String, String>,
+    description: Option<String>,
+    project_license: Option<String>,
+    homepage: Option<String>,
+    desktop_ids: Vec<String>,
+    categories: Vec<String>,
+    stock_icon: Option<String>,
+    /// the stock icon at each of [`ICON_SIZES`] the package has it in
+    icons: Vec<(u32, Box<[u8]>)>,
+}
+
+impl App {
+    /// Every valid component `package` ships metainfo for
+    pub(crate) fn all_in(package: &Package) -> Vec<Self> {
+        let metainfo = package
+            .appstream
+            .iter()
+            .filter(|f| f.path.ends_with(".xml"));
+        metainfo
+            .filter_map(|file| {
+                let root = parse_document(std::str::from_utf8(&file.contents).ok()?)?;
+                Self::new(package, &root)
+            })
+            .collect()
+    }
+
+    fn new(package: &Package, root: &Element) -> Option<Self> {
+        if !matches!(&*root.name, "component" | "application") {
+            return None;
+        }
+        let kind = match root.attribute("type") {
+            None => "generic",
+            Some("desktop") => "desktop-application",
+            Some(kind) => kind,
+        };
+        let id = root.child("id")?.text();
+        let localized = |name: &str| -> BTreeMap<String, String> {
+            root.elements()
+                .filter(|e| e.name == name)
+                .map(|e| (e.attribute("xml:lang").unwrap_or("C").to_owned(), e.text()))
+                .filter(|(_, text)| !text.is_empty())
+                .collect()
+        };
+        let names = localized("name");
+        let summaries = localized("summary");
+        if id.is_empty() || !names.contains_key("C") || !summaries.contains_key("C") {
+            return None;
+        }
+
+        let mut desktop_ids: Vec<String> = root
+            .elements()
+            .filter(|e| e.name == "launchable" && e.attribute("type") == Some("desktop-id"))
+            .map(Element::text)
+            .collect();
+        // older metainfo names desktop applications after their desktop entry instead
+        if desktop_ids.is_empty() && kind == "desktop-application" && id.ends_with(".desktop") {
+            desktop_ids.push(id.clone());
+        }
+        let desktop_entry = desktop_ids.iter().find_map(|desktop_id| {
+            let path = format!("/usr/share/applications/{desktop_id}");
+            let file = package.appstream.iter().find(|f| *f.path == path)?;
+            Some(DesktopEntry::parse(
+                std::str::from_utf8(&file.contents).ok()?,
+            ))
+        });
+
+        let mut categories: Vec<String> = root
+            .child("categories")
+            .into_iter()
+            .flat_map(Element::elements)
+            .filter(|e| e.name == "category")
+            .map(Element::text)
+            .collect();
+        if categories.is_empty()
+            && let Some(entry) = &desktop_entry
+        {
+            categories.clone_from(&entry.categories);
+        }
+        let stock_icon = root
+            .elements()
+            .find(|e| e.name == "icon" && e.attribute("type") == Some("stock"))
+            .map(Element::text)
+            .or_else(|| desktop_entry.and_then(|entry| entry.icon));
+        let icons = match &stock_icon {
+            Some(icon) => ICON_SIZES
+                .into_iter()
+                .filter_map(|size| Some((size, icon_file(package, icon, size)?.into())))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Some(Self {
+            package: package.name.clone(),
+            architecture: package.architecture.clone(),
+            id,
+            kind: kind.to_owned(),
+            names,
+            summaries,
+            description: root
+                .elements()
+                .find(|e| e.name == "description" && e.attribute("xml:lang").is_none())
+                .map(Element::inner_xml),
+            project_license: root.child("project_license").map(Element::text),
+            homepage: root
+                .elements()
+                .find(|e| e.name == "url" && e.attribute("type") == Some("homepage"))
+                .map(Element::text),
+            desktop_ids,
+            categories,
+            stock_icon,
+            icons,
+        })
+    }
+
+    /// The name of the icon in the icon tarballs, which is unique across packages
+    fn cached_icon_name(&self) -> Option<String> {
+        let icon = self.stock_icon.as_deref()?;
+        Some(format!("{}_{icon}.png", self.package))
+    }
+
+    /// The stock icon at `size` pixels square
+    fn icon(&self, size: u32) -> Option<&[u8]> {
+        let (_, data) = self
+            .icons
+            .iter()
+            .find(|(icon_size, _)| *icon_size == size)?;
+        Some(data)
+    }
+
+    fn write_yaml(&self, o: &mut String) -> Result<(), std::fmt::Error> {
+        writeln!(o, "---")?;
+        writeln!(o, "Type: {}", self.kind)?;
+        writeln!(o, "ID: {}", yaml_string(&self.id))?;
+        writeln!(o, "Package: {}", yaml_string(&self.package))?;
+        for (field, localized) in [("Name", &self.names), ("Summary", &self.summaries)] {
+            writeln!(o, "{field}:")?;
+            for (locale, text) in localized {
+                writeln!(o, "  {}: {}", yaml_key(locale), yaml_string(text))?;
+            }
+        }
+        if let Some(description) = &self.description {
+            writeln!(o, "Description:\n  C: {}", yaml_string(description))?;
+        }
+        if let Some(license) = &self.project_license {
+            writeln!(o, "ProjectLicense: {}", yaml_string(license))?;
+        }
+        if let Some(homepage) = &self.homepage {
+            writeln!(o, "Url:\n  homepage: {}", yaml_string(homepage))?;
+        }
+        if !self.desktop_ids.is_empty() {
+            writeln!(o, "Launchable:\n  desktop-id:")?;
+            for desktop_id in &self.desktop_ids {
+                writeln!(o, "  - {}", yaml_string(desktop_id))?;
+            }
+        }
+        if !self.categories.is_empty() {
+            writeln!(o, "Categories:")?;
+            for category in &self.categories {
+                writeln!(o, "- {}", yaml_string(category))?;
+            }
+        }
+        if let Some(icon) = &self.stock_icon {
+            writeln!(o, "Icon:\n  stock: {}", yaml_string(icon))?;
+            let sizes: Vec<u32> = ICON_SIZES
+                .into_iter()
+                .filter(|size| self.icon(*size).is_some())
+                .collect();
+            if let (false, Some(name)) = (sizes.is_empty(), self.cached_icon_name()) {
+                writeln!(o, "  cached:")?;
+                for size in sizes {
+                    writeln!(o, "  - name: {}", yaml_string(&name))?;
+                    writeln!(o, "    width: {size}\n    height: {size}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The icon named `icon` that `package` installs at `size` pixels square
+fn icon_file<'a>(package: &'a Package, icon: &str, size: u32) -> Option<&'a [u8]> {
+    let path = format!("/usr/share/icons/hicolor/{size}x{size}/apps/{icon}.png");
+    let file: &DataFile = package.appstream.iter().find(|f| *f.path == path)?;
+    (png_size(&file.contents)? == (size, size)).then_some(&*file.contents)
+}
+
+/// The keys of a desktop entry DEP-11 falls back on
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+struct DesktopEntry {
+    icon: Option<String>,
+    categories: Vec<String>,
+}
+
+impl DesktopEntry {
+    fn parse(contents: &str) -> Self {
+        let mut entry = Self::default();
+        let mut in_main_group = false;
+        for line in contents.lines().map(str::trim) {
+            if line.starts_with('[') {
+                in_main_group = line == "[Desktop Entry]";
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=').filter(|_| in_main_group) else {
+                continue;
+            };
+            match key.trim() {
+                // icons given by path aren't in the theme, so can't be found by name
+                "Icon" if !value.contains('/') => {
+                    entry.icon = Some(value.trim().trim_end_matches(".png").to_owned());
+                }
+                "Categories" => {
+                    entry.categories = value
+                        .split(';')
+                        .map(str::trim)
+                        .filter(|c| !c.is_empty())
+                        .map(str::to_owned)
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+        entry
+    }
+}
+
+/// The width and height of a PNG, from its header
+fn png_size(data: &[u8]) -> Option<(u32, u32)> {
+    if data.get(..8)? != b"\x89PNG\r\n\x1a\n" || data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
+
+/// A mapping key, quoted unless it's a plain locale like `pt_BR` or `sr@latin`
+fn yaml_key(key: &str) -> String {
+    let plain = key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '@' | '.'));
+    if plain && !key.is_empty() {
+        key.to_owned()
+    } else {
+        yaml_string(key)
+    }
+}
+
+/// A YAML double-quoted scalar, which can hold any text
+fn yaml_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04X}", u32::from(c));
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+/// Just enough XML for metainfo files: elements, attributes, text, CDATA and the predefined
+/// and numeric entities. Comments, processing instructions and doctypes are skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| &**value)
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.elements().find(|e| e.name == name)
+    }
+
+    /// All the text inside, with whitespace collapsed like AppStream does
+    fn text(&self) -> String {
+        let mut text = String::new();
+        self.collect_text(&mut text);
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    fn collect_text(&self, into: &mut String) {
+        for child in &self.children {
+            match child {
+                Node::Element(element) => element.collect_text(into),
+                Node::Text(text) => into.push_str(text),
+            }
+        }
+    }
+
+    /// The markup inside, like a description's paragraphs and lists, leaving out translations.
+    /// Whitespace is collapsed, and only kept around text and inline markup.
+    fn inner_xml(&self) -> String {
+        let holds_text = matches!(&*self.name, "p" | "li" | "em" | "code");
+        let mut xml = String::new();
+        for child in &self.children {
+            match child {
+                Node::Element(element) if element.attribute("xml:lang").is_none() => {
+                    xml.push('<');
+                    xml.push_str(&element.name);
+                    xml.push('>');
+                    xml.push_str(&element.inner_xml());
+                    xml.push_str("</");
+                    xml.push_str(&element.name);
+                    xml.push('>');
+                }
+                Node::Element(_) => {}
+                Node::Text(_) if !holds_text => {}
+                Node::Text(text) => {
+                    let mut collapsed = String::with_capacity(text.len());
+                    for c in text.chars() {
+                        if !c.is_whitespace() {
+                            collapsed.push(c);
+                        } else if !collapsed.ends_with(' ') {
+                            collapsed.push(' ');
+                        }
+                    }
+                    xml.push_str(&escape(&collapsed));
+                }
+            }
+        }
+        if matches!(&*self.name, "p" | "li") {
+            xml.trim().to_owned()
+        } else {
+            xml
+        }
+    }
+}
+
+fn parse_document(input: &str) -> Option<Element> {
+    let mut parser = Parser {
+        rest: input.trim_start_matches('\u{feff}'),
+    };
+    parser.skip_prolog()?;
+    parser.element(0)
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn skip_prolog(&mut self) -> Option<()> {
+        loop {
+            self.rest = self.rest.trim_start();
+            if self.rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest.starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Some(());
+            }
+        }
+    }
+
+    fn skip_past(&mut self, end: &str) -> Option<()> {
+        let at = self.rest.find(end)?;
+        self.rest = &self.rest[at + end.len()..];
+        Some(())
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let end = self
+            .rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '='))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return None;
+        }
+        let (name, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(name.to_owned())
+    }
+
+    fn element(&mut self, depth: usize) -> Option<Element> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        self.rest = self.rest.strip_prefix('<')?;
+        let name = self.name()?;
+        let mut attributes = Vec::new();
+        loop {
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix("/>") {
+                self.rest = rest;
+                return Some(Element {
+                    name,
+                    attributes,
+                    children: Vec::new(),
+                });
+            }
+            if let Some(rest) = self.rest.strip_prefix('>') {
+                self.rest = rest;
+                break;
+            }
+            let key = self.name()?;
+            self.rest = self.rest.trim_start().strip_prefix('=')?.trim_start();
+            let quote = self
+                .rest
+                .chars()
+                .next()
+                .filter(|c| matches!(c, '"' | '\''))?;
+            let (value, rest) = self.rest[1..].split_once(quote)?;
+            attributes.push((key, unescape(value)));
+            self.rest = rest;
+        }
+
+        let mut children = Vec::new();
+        loop {
+            if let Some(rest) = self.rest.strip_prefix("</") {
+                self.rest = rest.strip_prefix(&*name)?.trim_start().strip_prefix('>')?;
+                return Some(Element {
+                    name,
+                    attributes,
+                    children,
+                });
+            } else if self.rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if let Some(rest) = self.rest.strip_prefix("<![CDATA[") {
+                let (text, rest) = rest.split_once("]]>")?;
+                children.push(Node::Text(text.to_owned()));
+                self.rest = rest;
+            } else if self.rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest.starts_with('<') {
+                children.push(Node::Element(self.element(depth + 1)?));
+            } else {
+                let end = self.rest.find('<')?;
+                children.push(Node::Text(unescape(&self.rest[..end])));
+                self.rest = &self.rest[end..];
+            }
+        }
+    }
+}
+
+/// Replace entity references, leaving any that aren't predefined or numeric as they are
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(at) = rest.find('&') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let replacement = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "lt" => '<',
+                "gt" => '>',
+                "amp" => '&',
+                "quot" => '"',
+                "apos" => '\'',
+                entity => {
+                    let code = match entity.strip_prefix("#x") {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end))
+        });
+        match replacement {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,155 @@
+//! A package installing the repository's keyring into `/usr/share/keyrings/`, like Debian's
+//! `debian-archive-keyring`. Users who install it once get new keys by upgrading it, instead of
+//! fetching `deriv-archive-keyring.pgp` by hand each time the key changes.
+
+use filemeta::{DigestKind, FileMeta, FileSums};
+use package::{KnownFields, Package, PackageMeta};
+use parsedeb::{PackageKind, RequiredFields};
+
+use crate::{
+    GenerateError,
+    compress::{CompressionLevels, Format},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyringPackageOptions {
+    /// like `example-archive-keyring`
+    pub name: String,
+    /// has to go up whenever the keys change, or apt won't upgrade to the new ones
+    pub version: String,
+    pub maintainer: String,
+}
+
+/// The keyring package of the repository whose Release files `origin` says they're from, and
+/// the .deb itself. Like Debian's, it goes in `main`.
+pub(crate) fn keyring_package(
+    options: &KeyringPackageOptions,
+    origin: &str,
+    keyring: &[u8],
+    levels: &CompressionLevels,
+) -> Result<(Package, Vec<u8>), GenerateError> {
+    let name = &options.name;
+    let keyring_path = format!("./usr/share/keyrings/{name}.pgp");
+    let control = format!(
+        "Package: {name}\n\
+         Version: {}\n\
+         Architecture: all\n\
+         Maintainer: {}\n\
+         Installed-Size: {}\n\
+         Multi-Arch: foreign\n\
+         Section: misc\n\
+         Priority: optional\n\
+         Description: OpenPGP keys of the {origin} archive\n \
+         The keys that sign the Release files of the {origin} archive, in\n \
+         /usr/share/keyrings/{name}.pgp for apt sources to refer to with Signed-By.\n",
+        options.version,
+        options.maintainer,
+        keyring.len().div_ceil(1024),
+    );
+    let md5 = FileSums::builder().with(DigestKind::Md5).hash(keyring);
+    let md5sums: String = (md5.map_err(hash_error)?.md5.iter())
+        .map(|md5| format!("{:x}  {}\n", base16ct::HexDisplay(md5), &keyring_path[2..]))
+        .collect();
+
+    let control_tar = tarball(
+        &["./"],
+        &[
+            ("./control", control.as_bytes()),
+            ("./md5sums", md5sums.as_bytes()),
+        ],
+        levels,
+    )?;
+    let data_tar = tarball(
+        &["./", "./usr/", "./usr/share/", "./usr/share/keyrings/"],
+        &[(&keyring_path, keyring)],
+        levels,
+    )?;
+    let deb = ar_archive(&[
+        ("debian-binary", b"2.0\n"),
+        ("control.tar.gz", &control_tar),
+        ("data.tar.gz", &data_tar),
+    ]);
+
+    // read back like any other deb, which also checks the name and version are valid
+    let (mut fields, _) = parsedeb::deb_to_control(&deb[..])?;
+    parsedeb::normalize_description(&mut fields);
+    let required = RequiredFields::from_map(&fields)?;
+    let path = package::pool_path(&fields, "main", PackageKind::Deb)?;
+    let file = FileMeta::new(path.into(), &deb).map_err(hash_error)?;
+    let RequiredFields {
+        package: name,
+        architecture,
+        version,
+        ..
+    } = required;
+    let package = Package {
+        meta: PackageMeta {
+            file,
+            description_md5: package::description_md5(&fields),
+        },
+        name,
+        architecture,
+        version,
+        component: "main".into(),
+        known: KnownFields::from_fields(&fields)?,
+        fields,
+        appstream: Vec::new(),
+        changelog: None,
+    };
+    Ok((package, deb))
+}
+
+fn hash_error(e: std::io::Error) -> GenerateError {
+    GenerateError::HashFile("keyring package".into(), e)
+}
+
+/// A gzipped tarball of `dirs` and `files`, owned by root and dated 0 so it never changes
+fn tarball(
+    dirs: &[&str],
+    files: &[(&str, &[u8])],
+    levels: &CompressionLevels,
+) -> Result<Vec<u8>, GenerateError> {
+    let build = || -> std::io::Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for dir in dirs {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            header.set_mode(0o755);
+            header.set_mtime(0);
+            header.set_cksum();
+            builder.append_data(&mut header, dir, std::io::empty())?;
+        }
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(0);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data)?;
+        }
+        Format::Gzip.compress(&builder.into_inner()?, levels)
+    };
+    build().map_err(|e| GenerateError::Compression("gz", "keyring package".into(), e))
+}
+
+/// An ar archive the way dpkg-deb writes them
+fn ar_archive(members: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive = b"!<arch>\n".to_vec();
+    for (name, contents) in members {
+        let header = format!(
+            "{name:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            0,
+            0,
+            0,
+            100644,
+            contents.len()
+        );
+        archive.extend_from_slice(header.as_bytes());
+        archive.extend_from_slice(contents);
+        if contents.len() % 2 == 1 {
+            archive.push(b'\n');
+        }
+    }
+    archive
+}
@@ -1,167 +1,642 @@
 use std::{
-    collections::{HashMap, hash_map::Entry},
+    borrow::{Borrow, Cow},
+    collections::HashSet,
+    convert::Infallible,
     fmt::Write,
-    io::Write as _,
 };
 
 use base16ct::HexDisplay;
-use filemeta::FileMeta;
-use flate2::{Compression, GzBuilder};
+use filemeta::{DigestKind, FileMeta};
 use package::Package;
-use pgp::{
-    composed::{ArmorOptions, CleartextSignedMessage},
-    packet::SecretKey,
-    ser::Serialize,
-    types::Password,
-};
 
-const ARMOR_OPTS: ArmorOptions = ArmorOptions {
-    headers: None,
-    include_checksum: true,
-};
+mod changelog;
+mod compress;
+mod dep11;
+mod keyring;
+mod manifest;
+mod metalink;
+mod pdiff;
+mod sign;
+mod sources;
+mod suite;
+#[cfg(test)]
+mod tests;
+mod translation;
+
+pub use changelog::is_changelog_file;
+use compress::Format;
+pub use compress::{CompressionLevels, CompressionSet};
+pub use dep11::is_appstream_file;
+pub use keyring::KeyringPackageOptions;
+pub use manifest::{Manifest, ManifestEntry, ManifestSink};
+pub use metalink::MetalinkOptions;
+pub use pdiff::{PdiffHistory, PdiffOptions};
+pub use sign::{PgpSigner, SignatureHash, SignedRelease, Signer};
+pub use sources::SourcesOptions;
+use suite::SuiteIndexes;
 
+/// Which conventions the generated indexes follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OutputStyle {
+    #[default]
+    Native,
+    /// Match apt-ftparchive's package field order, Release field set and checksum layout,
+    /// so mirrors moving over from it don't see client-visible churn
+    AptFtparchive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct GenerateOptions {
+    pub style: OutputStyle,
+    /// Publish a `.meta4` file next to each large pool file
+    pub metalink: Option<MetalinkOptions>,
+    /// Move long descriptions out of the Packages indexes into `<component>/i18n/Translation-en`,
+    /// leaving only the synopsis behind
+    pub translations: bool,
+    pub compression: CompressionSet,
+    /// Publish `Packages.diff/` patches from earlier versions of each Packages index
+    pub pdiff: Option<PdiffOptions>,
+    /// Publish DEP-11 AppStream metadata and icons for the apps among the packages, from the
+    /// [`appstream`](Package::appstream) files read out of them
+    pub dep11: bool,
+    /// List `Architecture: all` packages only in the `binary-all` indexes, instead of in the
+    /// index of every other architecture too. Clients that don't fetch `binary-all` can't see
+    /// them then.
+    pub separate_arch_all: bool,
+    /// Publish the [`changelog`](Package::changelog) of each source package under
+    /// `changelogs/`, for [`ReleaseMetadata::changelogs`] to point apt at. Flat repositories
+    /// don't get them, since the paths apt asks for start with the component.
+    pub changelogs: bool,
+    /// Refuse to publish a suite where a `Multi-Arch: same` package's newest version isn't the
+    /// same on every architecture it's built for, since apt can only keep it installed for
+    /// several of them at one version. Off, uploads for each architecture can't arrive one at a
+    /// time.
+    pub strict_multi_arch: bool,
+    /// Publish a package installing the keyring, and put it in `main` of every suite. Needs a
+    /// signer, since without one there's no keyring.
+    pub keyring_package: Option<KeyringPackageOptions>,
+    /// Also publish each suite as it is now under `snapshots/<id>/` in its directory, like
+    /// `dists/<suite>/snapshots/<id>/`, for clients to pin to with `Suites: <suite>/snapshots/<id>`
+    /// while the suite itself moves on.
+    /// Snapshots are never written again, so keeping earlier ones, and the pool files they
+    /// refer to, is up to the caller. Flat repositories don't get snapshots.
+    pub snapshot: Option<String>,
+    /// Finish with `manifest.json` at the top of the repository, listing every other file
+    /// written with its size, checksums and compression, so deployment tooling can sync only
+    /// what changed. [`ManifestSink`] gets the same list without writing it.
+    pub manifest: bool,
+    /// Publish `<origin>.sources`, a deb822 file adding the repository to apt with Signed-By
+    /// pointing at its keyring, for users to copy into `/etc/apt/sources.list.d/`
+    pub sources: Option<SourcesOptions>,
+}
+
+/// One suite of a repository that has several, like `stable` or `testing`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suite<'a> {
+    /// `suite` names the directory under `dists/` the suite is published in
+    pub metadata: ReleaseMetadata,
+    pub packages: &'a [Package],
+}
+
+/// A repository with a single suite, whose Release files sit at the top of it.
+/// Without a `signer` only Release is written, leaving InRelease, Release.gpg and the keyring to
+/// whatever signs it later.
 pub fn generate_files(
     release_config: &ReleaseMetadata,
-    key: &SecretKey,
+    signer: Option<&dyn Signer>,
     packages: &[Package],
+    options: &GenerateOptions,
 ) -> Result<Vec<FileToUpload>, GenerateError> {
-    let indexes: Vec<PackageIndexFile> = generate_index_files(packages)?
-        .into_iter()
-        .flat_map(result_flat_mapper)
-        .collect::<Result<_, _>>()?;
+    let mut to_upload = Vec::new();
+    generate_files_into(release_config, signer, packages, options, &mut to_upload)?;
+    Ok(to_upload)
+}
 
-    let mut package_meta = Vec::new();
-    let mut architectures = Vec::new();
-    for PackageIndexFile { path, arch, data } in &indexes {
-        let meta = match FileMeta::new(path.clone(), data) {
-            Ok(v) => v,
-            Err(e) => return Err(GenerateError::HashFile(path.clone(), e)),
-        };
-        package_meta.push(meta);
-        architectures.push(&**arch);
-    }
-
-    let release = generate_release(release_config, &package_meta, &architectures)?;
-    let sig = CleartextSignedMessage::sign(rand::thread_rng(), &release, key, &Password::empty())?;
-
-    let indexes_base = [
-        FileToUpload {
-            destination_path: "InRelease".into(),
-            data: sig.to_armored_bytes(ARMOR_OPTS)?.into(),
-        },
-        FileToUpload {
-            destination_path: "Release".into(),
-            data: release.as_bytes().into(),
-        },
-        FileToUpload {
-            destination_path: "Release.gpg".into(),
-            data: sig
-                .signatures()
-                .first()
-                .ok_or(GenerateError::NoSignatures)?
-                .to_armored_bytes(ARMOR_OPTS)?
-                .into(),
-        },
-        FileToUpload {
-            destination_path: "deriv-archive-keyring.pgp".into(),
-            data: key.public_key().to_bytes()?.into(),
-        },
-    ];
+/// [`generate_files`], writing each file to `sink` as soon as it's made
+pub fn generate_files_into(
+    release_config: &ReleaseMetadata,
+    signer: Option<&dyn Signer>,
+    packages: &[Package],
+    options: &GenerateOptions,
+    sink: &mut dyn Sink,
+) -> Result<(), GenerateError> {
+    let packages = packages.iter().map(Ok::<_, Infallible>);
+    generate_single(release_config, signer, packages, options, sink)
+}
 
-    let to_upload = indexes
-        .into_iter()
-        .map(|v| FileToUpload {
-            destination_path: v.path,
-            data: v.data,
-        })
-        .chain(indexes_base)
-        .collect();
+/// [`generate_files_into`], taking packages one at a time as `packages` yields them, like rows
+/// of a database query, so they never all have to be in memory. Only what goes in the indexes
+/// is kept of each. The first error `packages` yields stops generation.
+pub fn generate_files_streamed<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+    release_config: &ReleaseMetadata,
+    signer: Option<&dyn Signer>,
+    packages: impl IntoIterator<Item = Result<Package, E>>,
+    options: &GenerateOptions,
+    sink: &mut dyn Sink,
+) -> Result<(), GenerateError> {
+    generate_single(release_config, signer, packages, options, sink)
+}
+
+fn generate_single<P: Borrow<Package>, E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+    release_config: &ReleaseMetadata,
+    signer: Option<&dyn Signer>,
+    packages: impl IntoIterator<Item = Result<P, E>>,
+    options: &GenerateOptions,
+    sink: &mut dyn Sink,
+) -> Result<(), GenerateError> {
+    with_manifest(options, sink, |sink| {
+        single(release_config, signer, packages, options, sink)
+    })
+}
+
+fn single<P: Borrow<Package>, E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+    release_config: &ReleaseMetadata,
+    signer: Option<&dyn Signer>,
+    packages: impl IntoIterator<Item = Result<P, E>>,
+    options: &GenerateOptions,
+    sink: &mut dyn Sink,
+) -> Result<(), GenerateError> {
+    let keyring = keyring_package(&release_config.origin, signer, options, sink)?;
+    let mut indexes = SuiteIndexes::new(options);
+    let mut shared = Shared::default();
+    for package in packages {
+        let package = package.map_err(|e| GenerateError::PackageSource(e.into()))?;
+        indexes.add(package.borrow())?;
+        shared.add(package.borrow(), options, options.changelogs)?;
+    }
+    if let Some(keyring) = &keyring {
+        indexes.add(keyring)?;
+    }
+    let (origin, suite) = (&release_config.origin, &release_config.suite);
+    shared.sources.add(origin, suite, &indexes.components());
+    generate_suite(release_config, indexes, signer, options, "", sink)?;
+    shared.write(signer, options, sink)
+}
+
+/// A flat repository, whose single Packages index sits at the top of it next to Release, for
+/// `deb https://host/repo ./` lines. Every package goes in that index whatever its component
+/// and architecture, and keeps its long description, since flat repositories have no
+/// Translation-en. Nor do they have DEP-11 metadata, which apt only fetches per component.
+pub fn generate_flat(
+    release_config: &ReleaseMetadata,
+    signer: Option<&dyn Signer>,
+    packages: &[Package],
+    options: &GenerateOptions,
+) -> Result<Vec<FileToUpload>, GenerateError> {
+    let mut to_upload = Vec::new();
+    generate_flat_into(release_config, signer, packages, options, &mut to_upload)?;
     Ok(to_upload)
 }
 
-fn gzip(a: &[u8]) -> Result<Vec<u8>, std::io::Error> {
-    let mut gz = Vec::new();
-    let mut writer = GzBuilder::new().write(&mut gz, Compression::best());
-    writer.write_all(a)?;
-    writer.finish()?;
-    Ok(gz)
+/// [`generate_flat`], writing each file to `sink` as soon as it's made
+pub fn generate_flat_into(
+    release_config: &ReleaseMetadata,
+    signer: Option<&dyn Signer>,
+    packages: &[Package],
+    options: &GenerateOptions,
+    sink: &mut dyn Sink,
+) -> Result<(), GenerateError> {
+    with_manifest(options, sink, |sink| {
+        flat(release_config, signer, packages, options, sink)
+    })
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct PackageIndexFile {
-    arch: Box<str>,
-    path: Box<str>,
-    data: Box<[u8]>,
+fn flat(
+    release_config: &ReleaseMetadata,
+    signer: Option<&dyn Signer>,
+    packages: &[Package],
+    options: &GenerateOptions,
+    sink: &mut dyn Sink,
+) -> Result<(), GenerateError> {
+    let write_into_packages = match options.style {
+        OutputStyle::Native => Package::write_into_packages,
+        OutputStyle::AptFtparchive => Package::write_into_packages_apt_ftparchive,
+    };
+    let keyring = keyring_package(&release_config.origin, signer, options, sink)?;
+    let mut contents = String::with_capacity(1024);
+    for package in ordered(packages, keyring.as_ref()) {
+        write_into_packages(package, &mut contents)?;
+        contents.push_str("\n\n");
+    }
+    let mut architectures: Vec<&str> = packages.iter().map(|p| p.architecture.as_str()).collect();
+    architectures.extend(keyring.as_ref().map(|p| p.architecture.as_str()));
+    architectures.sort_unstable();
+    architectures.dedup();
+
+    let index = IndexFile {
+        base_path: "Packages".to_owned(),
+        contents: contents.into_boxed_str(),
+    };
+    let releases = hashed_indexes(
+        vec![index],
+        (&architectures, &[]),
+        Vec::new(),
+        options,
+        &[(release_config, "")],
+        sink,
+    )?;
+    for release in releases {
+        release_files(release, signer, "", sink)?;
+    }
+    let mut shared = Shared::default();
+    for package in packages {
+        shared.add(package, options, false)?;
+    }
+    shared.sources.add(&release_config.origin, "./", &[]);
+    shared.write(signer, options, sink)
 }
 
-fn result_flat_mapper(
-    IndexFileWithArch { arch, contents }: IndexFileWithArch,
-) -> Box<[Result<PackageIndexFile, GenerateError>]> {
-    let base_path = format!("main/binary-{arch}/Packages");
-    let gz = match gzip(contents.as_bytes()) {
-        Ok(v) => v,
-        Err(e) => return Box::new([Err(GenerateError::Compression("gz", base_path, e))]),
+/// A repository with the standard `dists/<suite>/` layout, whose suites share one pool
+pub fn generate_suites(
+    suites: &[Suite],
+    signer: Option<&dyn Signer>,
+    options: &GenerateOptions,
+) -> Result<Vec<FileToUpload>, GenerateError> {
+    let mut to_upload = Vec::new();
+    generate_suites_into(suites, signer, options, &mut to_upload)?;
+    Ok(to_upload)
+}
+
+/// [`generate_suites`], writing each file to `sink` as soon as it's made
+pub fn generate_suites_into(
+    suites: &[Suite],
+    signer: Option<&dyn Signer>,
+    options: &GenerateOptions,
+    sink: &mut dyn Sink,
+) -> Result<(), GenerateError> {
+    with_manifest(options, sink, |sink| {
+        suites_into(suites, signer, options, sink)
+    })
+}
+
+fn suites_into(
+    suites: &[Suite],
+    signer: Option<&dyn Signer>,
+    options: &GenerateOptions,
+    sink: &mut dyn Sink,
+) -> Result<(), GenerateError> {
+    let mut seen = HashSet::new();
+    if let Some(repeated) = suites
+        .iter()
+        .find(|suite| !seen.insert(&suite.metadata.suite))
+    {
+        return Err(GenerateError::DuplicateSuite(
+            repeated.metadata.suite.clone(),
+        ));
+    }
+
+    let keyring = match suites.first() {
+        Some(suite) => keyring_package(&suite.metadata.origin, signer, options, sink)?,
+        None => None,
     };
-    let xz = match liblzma::encode_all(contents.as_bytes(), 9) {
-        Ok(v) => v,
-        Err(e) => return Box::new([Err(GenerateError::Compression("gz", base_path, e))]),
+    let mut shared = Shared::default();
+    for suite in suites {
+        let mut indexes = SuiteIndexes::new(options);
+        for package in suite.packages.iter().chain(&keyring) {
+            indexes.add(package)?;
+        }
+        for package in suite.packages {
+            shared.add(package, options, options.changelogs)?;
+        }
+        let (origin, suite_name) = (&suite.metadata.origin, &suite.metadata.suite);
+        shared
+            .sources
+            .add(origin, suite_name, &indexes.components());
+        let dir = format!("dists/{}/", suite.metadata.suite);
+        generate_suite(&suite.metadata, indexes, signer, options, &dir, sink)?;
+    }
+    shared.write(signer, options, sink)
+}
+
+/// Where generated files are written as they're made, so only a few indexes have to be in
+/// memory at once however big the repository is. Every index is written before the Release
+/// file listing it. `Vec<FileToUpload>` collects them all, and closures taking the path and
+/// contents of each file are sinks too.
+pub trait Sink {
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), std::io::Error>;
+}
+
+impl Sink for Vec<FileToUpload> {
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), std::io::Error> {
+        self.push(FileToUpload {
+            destination_path: path.into(),
+            data: data.into(),
+        });
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&str, &[u8]) -> Result<(), std::io::Error>> Sink for F {
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), std::io::Error> {
+        self(path, data)
+    }
+}
+
+/// Run `generate` on `sink`, then write `manifest.json` listing everything it wrote if
+/// [`GenerateOptions::manifest`] asks for it
+fn with_manifest(
+    options: &GenerateOptions,
+    sink: &mut dyn Sink,
+    generate: impl FnOnce(&mut dyn Sink) -> Result<(), GenerateError>,
+) -> Result<(), GenerateError> {
+    if !options.manifest {
+        return generate(sink);
+    }
+    let mut recording = ManifestSink::new(sink);
+    generate(&mut recording)?;
+    let (sink, manifest) = recording.into_parts();
+    write_to(
+        sink,
+        manifest::MANIFEST_PATH,
+        manifest.to_json()?.as_bytes(),
+    )
+}
+
+fn write_to(sink: &mut dyn Sink, path: &str, data: &[u8]) -> Result<(), GenerateError> {
+    sink.write_file(path, data)
+        .map_err(|e| GenerateError::Sink(path.into(), e))
+}
+
+/// Files that go at the top of the repository however many suites it has, gathered from the
+/// packages of every suite as they're added
+#[derive(Debug, Default)]
+struct Shared {
+    metalinks: metalink::Metalinks,
+    changelogs: changelog::Changelogs,
+    sources: sources::Sources,
+}
+
+impl Shared {
+    /// `changelogs` is whether to publish changelogs, which flat repositories can't
+    fn add(
+        &mut self,
+        package: &Package,
+        options: &GenerateOptions,
+        changelogs: bool,
+    ) -> Result<(), GenerateError> {
+        if let Some(metalink_options) = &options.metalink {
+            self.metalinks.add(metalink_options, package)?;
+        }
+        if changelogs {
+            self.changelogs.add(package)?;
+        }
+        Ok(())
+    }
+
+    fn write(
+        self,
+        signer: Option<&dyn Signer>,
+        options: &GenerateOptions,
+        sink: &mut dyn Sink,
+    ) -> Result<(), GenerateError> {
+        self.changelogs.write(sink)?;
+        if let Some(sources_options) = &options.sources {
+            // like keyring_package, there's only a keyring package if there's a keyring
+            let keyring_package = options
+                .keyring_package
+                .as_ref()
+                .filter(|_| signer.is_some());
+            let keyring_package = keyring_package.map(|k| k.name.as_str());
+            self.sources.write(sources_options, keyring_package, sink)?;
+        }
+        if let Some(signer) = signer {
+            write_to(sink, "deriv-archive-keyring.pgp", &signer.keyring()?)?;
+        }
+        for metalink in self.metalinks.files {
+            write_to(sink, &metalink.destination_path, &metalink.data)?;
+        }
+        Ok(())
+    }
+}
+
+/// The keyring package, if [`GenerateOptions::keyring_package`] asks for one and there's a
+/// keyring to put in it. The .deb goes straight into the pool.
+fn keyring_package(
+    origin: &str,
+    signer: Option<&dyn Signer>,
+    options: &GenerateOptions,
+    sink: &mut dyn Sink,
+) -> Result<Option<Package>, GenerateError> {
+    let (Some(keyring_options), Some(signer)) = (&options.keyring_package, signer) else {
+        return Ok(None);
     };
-    Box::new([
-        Ok(PackageIndexFile {
-            path: format!("{base_path}.gz").into(),
-            arch: arch.clone(),
-            data: gz.into_boxed_slice(),
-        }),
-        Ok(PackageIndexFile {
-            path: format!("{base_path}.xz").into(),
-            arch: arch.clone(),
-            data: xz.into_boxed_slice(),
-        }),
-        Ok(PackageIndexFile {
-            path: base_path.into(),
-            arch,
-            data: contents.into_boxed_bytes(),
-        }),
-    ])
+    let levels = &options.compression.levels;
+    let (package, deb) =
+        keyring::keyring_package(keyring_options, origin, &signer.keyring()?, levels)?;
+    write_to(sink, &package.meta.file.path, &deb)?;
+    Ok(Some(package))
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct IndexFileWithArch {
-    arch: Box<str>,
-    contents: Box<str>,
+/// The indexes and Release files of one suite, with `dir` in front of their paths
+fn generate_suite(
+    release_config: &ReleaseMetadata,
+    indexes: SuiteIndexes,
+    signer: Option<&dyn Signer>,
+    options: &GenerateOptions,
+    dir: &str,
+    sink: &mut dyn Sink,
+) -> Result<(), GenerateError> {
+    let snapshot = options.snapshot.as_ref().map(|id| {
+        let metadata = ReleaseMetadata {
+            suite: format!("{}/snapshots/{id}", release_config.suite),
+            ..release_config.clone()
+        };
+        (metadata, format!("{dir}snapshots/{id}/"))
+    });
+    let mut targets = vec![(release_config, dir)];
+    targets.extend(
+        snapshot
+            .as_ref()
+            .map(|(metadata, dir)| (metadata, dir.as_str())),
+    );
+
+    let releases = indexes.write(&targets, sink)?;
+    for (release, (_, dir)) in releases.into_iter().zip(targets) {
+        release_files(release, signer, dir, sink)?;
+    }
+    Ok(())
 }
 
-fn generate_index_files(packages: &[Package]) -> Result<Vec<IndexFileWithArch>, GenerateError> {
-    let mut aggregator = HashMap::with_capacity(8);
+/// Release, along with InRelease and Release.gpg if there's a `signer`
+fn release_files(
+    release: String,
+    signer: Option<&dyn Signer>,
+    dir: &str,
+    sink: &mut dyn Sink,
+) -> Result<(), GenerateError> {
+    let signed = signer.map(|signer| signer.sign(&release)).transpose()?;
+    let (inline, detached) = signed.map(|s| (s.inline, s.detached)).unzip();
 
-    for package in packages {
-        match aggregator.entry(package.architecture.clone()) {
-            Entry::Occupied(mut v) => {
-                package.write_into_packages(v.get_mut())?;
-                v.get_mut().push_str("\n\n");
+    if let Some(inline) = inline {
+        write_to(sink, &format!("{dir}InRelease"), &inline)?;
+    }
+    write_to(sink, &format!("{dir}Release"), release.as_bytes())?;
+    if let Some(detached) = detached {
+        write_to(sink, &format!("{dir}Release.gpg"), &detached)?;
+    }
+    Ok(())
+}
+
+/// Compress `uncompressed` and write every copy of it to `sink` along with the `prebuilt`
+/// files, which are published as they are, and return the Release file listing them all.
+/// Indexes are compressed a few at a time, so the compressed copies of only those few are held
+/// at once.
+///
+/// Each of `targets` gets the indexes in its directory, and a Release file from its metadata.
+/// The first is the suite itself, and the others are snapshots of it.
+fn hashed_indexes(
+    uncompressed: Vec<IndexFile>,
+    (architectures, components): (&[&str], &[&str]),
+    prebuilt: Vec<PackageIndexFile>,
+    options: &GenerateOptions,
+    targets: &[(&ReleaseMetadata, &str)],
+    sink: &mut dyn Sink,
+) -> Result<Vec<String>, GenerateError> {
+    let dir = targets[0].1;
+    let write_everywhere = |path: &str, data: &[u8], sink: &mut dyn Sink| {
+        for (_, dir) in targets {
+            write_to(sink, &format!("{dir}{path}"), data)?;
+        }
+        Ok::<_, GenerateError>(())
+    };
+    let mut package_meta = Vec::new();
+    let mut publish = |index: PackageIndexFile, sink: &mut dyn Sink| {
+        let meta = FileMeta::new(index.path.clone(), &index.data)
+            .map_err(|e| GenerateError::HashFile(index.path.clone(), e))?;
+        package_meta.push(meta);
+        write_everywhere(&index.path, &index.data, sink)
+    };
+
+    // enough indexes to keep every core busy compressing
+    let formats = options.compression.formats().count().max(1);
+    let batch_size =
+        std::thread::available_parallelism().map_or(1, |threads| threads.get().div_ceil(formats));
+    let mut diff_indexes = Vec::new();
+    let mut uncompressed = uncompressed.into_iter().peekable();
+    while uncompressed.peek().is_some() {
+        let batch = uncompressed.by_ref().take(batch_size).collect();
+        for index in compress_indexes(batch, &options.compression)? {
+            if let Some(pdiff_options) = &options.pdiff
+                && pdiff::is_diffable(&index.path)
+            {
+                let (diff_index, patches) = pdiff::generate_pdiffs(
+                    pdiff_options,
+                    &index.path,
+                    &format!("{dir}{}", index.path),
+                    &index.data,
+                    &options.compression.levels,
+                )?;
+                // patches aren't listed in Release, apt checks them against the diff index
+                for patch in patches {
+                    write_everywhere(&patch.destination_path, &patch.data, sink)?;
+                }
+                diff_indexes.push(PackageIndexFile {
+                    path: diff_index.destination_path,
+                    data: diff_index.data,
+                });
             }
-            Entry::Vacant(v) => {
-                let mut s = String::with_capacity(1024);
-                package.write_into_packages(&mut s)?;
-                s.push_str("\n\n");
-                v.insert(s);
+            publish(index, sink)?;
+        }
+    }
+    for file in prebuilt.into_iter().chain(diff_indexes) {
+        publish(file, sink)?;
+    }
+
+    let mut releases = Vec::with_capacity(targets.len());
+    for (release_config, _) in targets {
+        releases.push(match options.style {
+            OutputStyle::Native => {
+                generate_release(release_config, &package_meta, architectures, components)?
             }
+            OutputStyle::AptFtparchive => generate_release_apt_ftparchive(
+                release_config,
+                &package_meta,
+                architectures,
+                components,
+            )?,
+        });
+    }
+    Ok(releases)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PackageIndexFile {
+    path: Box<str>,
+    data: Box<[u8]>,
+}
+
+/// An index before compression, which is published at `base_path` and next to it in each
+/// format of a [`CompressionSet`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct IndexFile {
+    base_path: String,
+    contents: Box<str>,
+}
+
+/// Every index along with its compressed copies, in the order they were given
+fn compress_indexes(
+    indexes: Vec<IndexFile>,
+    compression: &CompressionSet,
+) -> Result<Vec<PackageIndexFile>, GenerateError> {
+    let formats: Vec<Format> = compression.formats().collect();
+    let jobs: Vec<(&[u8], Format)> = indexes
+        .iter()
+        .flat_map(|index| formats.iter().map(|f| (index.contents.as_bytes(), *f)))
+        .collect();
+    let mut compressed = compress::compress_parallel(&jobs, &compression.levels).into_iter();
+
+    let mut out = Vec::with_capacity(jobs.len() + indexes.len());
+    for IndexFile {
+        base_path,
+        contents,
+    } in indexes
+    {
+        for (format, data) in formats.iter().zip(compressed.by_ref()) {
+            let extension = format.extension();
+            let data =
+                data.map_err(|e| GenerateError::Compression(extension, base_path.clone(), e))?;
+            out.push(PackageIndexFile {
+                path: format!("{base_path}.{extension}").into(),
+                data: data.into_boxed_slice(),
+            });
         }
+        out.push(PackageIndexFile {
+            path: base_path.into(),
+            data: contents.into_boxed_bytes(),
+        });
     }
+    Ok(out)
+}
 
-    Ok(aggregator
-        .into_iter()
-        .map(|(arch, d)| IndexFileWithArch {
-            arch,
-            contents: d.into_boxed_str(),
-        })
-        .collect())
+/// `packages` and then `extra` in the order they go in indexes, see [`IndexOrder`](package::IndexOrder)
+fn ordered<'a>(packages: &'a [Package], extra: Option<&'a Package>) -> Vec<&'a Package> {
+    let mut ordered: Vec<&Package> = packages.iter().chain(extra).collect();
+    ordered.sort_by_cached_key(|package| package.index_order());
+    ordered
+}
+
+/// Debian's components, in the order it lists them. Any others go after these.
+const KNOWN_COMPONENTS: [&str; 4] = ["main", "contrib", "non-free", "non-free-firmware"];
+
+/// Every component of `packages`, or just `main` for an empty repository
+fn components<'a>(packages: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut components: Vec<&str> = packages.into_iter().collect();
+    components.sort_unstable_by_key(|component| {
+        let known = KNOWN_COMPONENTS.iter().position(|known| known == component);
+        (known.unwrap_or(KNOWN_COMPONENTS.len()), *component)
+    });
+    components.dedup();
+    if components.is_empty() {
+        components.push("main");
+    }
+    components
 }
 
 fn generate_release(
     meta: &ReleaseMetadata,
     files: &[FileMeta],
     arches: &[&str],
+    components: &[&str],
 ) -> Result<String, std::fmt::Error> {
     let mut o = String::with_capacity(1024);
     writeln!(o, "Origin: {}", meta.origin)?;
@@ -170,43 +645,108 @@ fn generate_release(
     writeln!(o, "Version: {}", meta.version)?;
     writeln!(o, "Codename: {}", meta.codename)?;
     writeln!(o, "Date: {}", meta.date)?;
+    for (key, value) in meta.optional_fields() {
+        if !value.is_empty() {
+            writeln!(o, "{key}: {value}")?;
+        }
+    }
     writeln!(o, "Architectures: {}", arches.join(" "))?;
-    writeln!(o, "Components: main")?;
+    // flat repositories have none
+    if !components.is_empty() {
+        writeln!(o, "Components: {}", components.join(" "))?;
+    }
+    if !meta.description.is_empty() {
+        writeln!(o, "Description: {}", meta.description)?;
+    }
     writeln!(o, "Acquire-By-Hash: no")?;
-    writeln!(o, "Changelogs: no")?;
-    writeln!(o, "Snapshots: no")?;
-
-    writeln!(o, "MD5Sum:")?;
-    for file in files {
-        writeln!(
-            o,
-            " {:x} {} {}",
-            HexDisplay(&file.sums.md5),
-            file.size,
-            file.path
-        )?;
-    }
-
-    writeln!(o, "SHA1:")?;
-    for file in files {
-        writeln!(
-            o,
-            " {:x} {} {}",
-            HexDisplay(&file.sums.sha1),
-            file.size,
-            file.path
-        )?;
-    }
-
-    writeln!(o, "SHA256:")?;
-    for file in files {
-        writeln!(
-            o,
-            " {:x} {} {}",
-            HexDisplay(&file.sums.sha256),
-            file.size,
-            file.path
-        )?;
+    // flat repositories have no changelogs, see GenerateOptions::changelogs
+    let changelogs = meta
+        .changelogs
+        .as_deref()
+        .filter(|_| !components.is_empty());
+    writeln!(o, "Changelogs: {}", changelogs.unwrap_or("no"))?;
+    writeln!(
+        o,
+        "Snapshots: {}",
+        meta.snapshots.as_deref().unwrap_or("no")
+    )?;
+    for (key, value) in &meta.extra_headers {
+        writeln!(o, "{key}: {value}")?;
+    }
+
+    for (name, digests) in checksum_sections(files.iter()) {
+        writeln!(o, "{name}:")?;
+        for (file, digest) in digests {
+            writeln!(o, " {:x} {} {}", HexDisplay(digest), file.size, file.path)?;
+        }
+    }
+    Ok(o)
+}
+
+/// The checksum sections of Release, each with every file and its digest. A section has to
+/// list every file, so one is left out if any file is missing its digest.
+fn checksum_sections<'a>(
+    files: impl Iterator<Item = &'a FileMeta> + Clone,
+) -> impl Iterator<Item = (&'static str, Vec<(&'a FileMeta, &'a [u8])>)> {
+    [
+        ("MD5Sum", DigestKind::Md5),
+        ("SHA1", DigestKind::Sha1),
+        ("SHA256", DigestKind::Sha256),
+        ("SHA512", DigestKind::Sha512),
+    ]
+    .into_iter()
+    .filter_map(move |(name, kind)| {
+        let digests = files.clone().map(|file| Some((file, file.sums.get(kind)?)));
+        Some((name, digests.collect::<Option<Vec<_>>>()?))
+    })
+}
+
+/// The Release layout written by apt-ftparchive: empty fields are skipped, files are sorted
+/// by path and sizes are right-aligned to 16 columns.
+fn generate_release_apt_ftparchive(
+    meta: &ReleaseMetadata,
+    files: &[FileMeta],
+    arches: &[&str],
+    components: &[&str],
+) -> Result<String, std::fmt::Error> {
+    let mut o = String::with_capacity(1024);
+    let architectures = arches.join(" ");
+    let components = components.join(" ");
+    let [not_automatic, but_automatic_upgrades, signed_by] = meta.optional_fields();
+    let fields: [(&str, &str); 12] = [
+        ("Origin", &meta.origin),
+        ("Label", &meta.label),
+        ("Suite", &meta.suite),
+        ("Version", &meta.version),
+        ("Codename", &meta.codename),
+        ("Date", &meta.date),
+        (not_automatic.0, &not_automatic.1),
+        (but_automatic_upgrades.0, &but_automatic_upgrades.1),
+        (signed_by.0, &signed_by.1),
+        ("Architectures", &architectures),
+        ("Components", &components),
+        ("Description", &meta.description),
+    ];
+    let extra = meta.extra_headers.iter().map(|(k, v)| (&**k, &**v));
+    for (key, value) in fields.into_iter().chain(extra) {
+        if !value.is_empty() {
+            writeln!(o, "{key}: {value}")?;
+        }
+    }
+
+    let mut files: Vec<&FileMeta> = files.iter().collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    for (name, digests) in checksum_sections(files.into_iter()) {
+        writeln!(o, "{name}:")?;
+        for (file, digest) in digests {
+            writeln!(
+                o,
+                " {:x} {:>16} {}",
+                HexDisplay(digest),
+                file.size,
+                file.path
+            )?;
+        }
     }
     Ok(o)
 }
@@ -217,7 +757,7 @@ pub struct FileToUpload {
     pub data: Box<[u8]>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 
 // these fields are pretty much all freeform, though see https://wiki.debian.org/DebianRepository/Format
 pub struct ReleaseMetadata {
@@ -229,6 +769,32 @@ pub struct ReleaseMetadata {
     pub description: String,
     /// this one isn't freeform
     pub date: String,
+    /// apt won't upgrade to packages from this suite unless asked to, like Debian's experimental
+    pub not_automatic: bool,
+    /// along with `not_automatic`, apt still upgrades packages that came from this suite
+    pub but_automatic_upgrades: bool,
+    /// fingerprints of the only keys apt should accept this suite's signatures from
+    pub signed_by: Vec<String>,
+    /// where `apt changelog` fetches changelogs from, as a URL with `@CHANGEPATH@` in place of
+    /// the path of one. Release says there are none without it.
+    pub changelogs: Option<String>,
+    /// where apt's `--snapshot` finds snapshots of the repository, as a URL with `@SNAPSHOTID@`
+    /// in place of the snapshot. Release says there are none without it.
+    pub snapshots: Option<String>,
+    /// any other fields, written as they are and in this order after all of the above
+    pub extra_headers: Vec<(String, String)>,
+}
+
+impl ReleaseMetadata {
+    /// The optional fields that go after Date, as they're written to Release
+    fn optional_fields(&self) -> [(&'static str, Cow<'_, str>); 3] {
+        let yes = |set: bool| Cow::Borrowed(if set { "yes" } else { "" });
+        [
+            ("NotAutomatic", yes(self.not_automatic)),
+            ("ButAutomaticUpgrades", yes(self.but_automatic_upgrades)),
+            ("Signed-By", Cow::Owned(self.signed_by.join(", "))),
+        ]
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -243,4 +809,46 @@ pub enum GenerateError {
     HashFile(Box<str>, std::io::Error),
     #[error("no signatures created- this is a bug")]
     NoSignatures,
+    #[error("no keys to sign Release with")]
+    NoKeys,
+    #[error("suite {0} is listed more than once")]
+    DuplicateSuite(String),
+    #[error("cannot sign Release files with a {0} key")]
+    UnsupportedKey(String),
+    /// For [`Signer`]s that don't sign with pgp in process
+    #[error("external signer failed: {0}")]
+    ExternalSigner(Box<dyn std::error::Error + Send + Sync>),
+    #[error("could not write {0}: {1}")]
+    Sink(Box<str>, std::io::Error),
+    #[error("could not build the keyring package: {0}")]
+    KeyringPackage(#[from] parsedeb::Error),
+    #[error("could not read the changelog of {0}: {1}")]
+    Changelog(Box<str>, std::io::Error),
+    /// For errors from the packages given to [`generate_files_streamed`]
+    #[error("could not get a package: {0}")]
+    PackageSource(Box<dyn std::error::Error + Send + Sync>),
+    #[error("{package} is Multi-Arch: same, but at different versions: {versions}")]
+    MultiArchVersions { package: String, versions: String },
+}
+
+impl GenerateError {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Format(_) => "GV2001",
+            Self::Signing(_) => "GV2002",
+            Self::Compression(..) => "GV2003",
+            Self::HashFile(..) => "GV2004",
+            Self::NoSignatures => "GV2005",
+            Self::DuplicateSuite(_) => "GV2006",
+            Self::UnsupportedKey(_) => "GV2007",
+            Self::NoKeys => "GV2008",
+            Self::ExternalSigner(_) => "GV2009",
+            Self::Sink(..) => "GV2010",
+            Self::KeyringPackage(_) => "GV2011",
+            Self::Changelog(..) => "GV2012",
+            Self::PackageSource(_) => "GV2013",
+            Self::MultiArchVersions { .. } => "GV2014",
+        }
+    }
 }
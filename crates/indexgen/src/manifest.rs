@@ -0,0 +1,113 @@
+//! `manifest.json`, listing every file of a generated repository with its size and checksums,
+//! for deployment tooling that syncs only what changed to a CDN instead of all of it
+
+use std::fmt::Write;
+
+use base16ct::HexDisplay;
+use filemeta::FileMeta;
+
+use crate::{Sink, compress::Format};
+
+/// Where the manifest goes, at the top of the repository
+pub(crate) const MANIFEST_PATH: &str = "manifest.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ManifestEntry {
+    pub file: FileMeta,
+    /// the extension of the format the file is compressed with, like `xz`, for the compressed
+    /// copies of indexes and anything else that's compressed as a whole
+    pub compression: Option<&'static str>,
+}
+
+/// Every file written to a [`ManifestSink`], in the order they were written
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Like `{"files":[{"path":"Release","size":1234,"compression":null,"md5":"…",…}]}`, with
+    /// one file per line
+    pub fn to_json(&self) -> Result<String, std::fmt::Error> {
+        let mut o = String::with_capacity(256 * self.files.len() + 16);
+        o.push_str("{\"files\":[");
+        for (i, entry) in self.files.iter().enumerate() {
+            let file = &entry.file;
+            if i > 0 {
+                o.push(',');
+            }
+            write!(
+                o,
+                "\n{{\"path\":{},\"size\":{},\"compression\":",
+                json_string(&file.path),
+                file.size
+            )?;
+            match entry.compression {
+                Some(format) => o.push_str(&json_string(format)),
+                None => o.push_str("null"),
+            }
+            for (kind, digest) in file.sums.iter() {
+                write!(o, ",\"{}\":\"{:x}\"", kind.name(), HexDisplay(digest))?;
+            }
+            o.push('}');
+        }
+        o.push_str("\n]}\n");
+        Ok(o)
+    }
+}
+
+/// A [`Sink`] that writes every file to another sink and records it in a [`Manifest`]
+pub struct ManifestSink<'a> {
+    sink: &'a mut dyn Sink,
+    manifest: Manifest,
+}
+
+impl<'a> ManifestSink<'a> {
+    pub fn new(sink: &'a mut dyn Sink) -> Self {
+        Self {
+            sink,
+            manifest: Manifest::default(),
+        }
+    }
+
+    /// The sink files were written to, and the manifest of them
+    pub fn into_parts(self) -> (&'a mut dyn Sink, Manifest) {
+        (self.sink, self.manifest)
+    }
+}
+
+impl Sink for ManifestSink<'_> {
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), std::io::Error> {
+        self.sink.write_file(path, data)?;
+        let compression = Format::ALL
+            .into_iter()
+            .map(Format::extension)
+            .find(|extension| {
+                path.rsplit_once('.')
+                    .is_some_and(|(_, ext)| ext == *extension)
+            });
+        self.manifest.files.push(ManifestEntry {
+            file: FileMeta::new(path.into(), data)?,
+            compression,
+        });
+        Ok(())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
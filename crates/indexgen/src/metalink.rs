@@ -0,0 +1,99 @@
+//! Metalink 4 (RFC 5854) descriptions for pool files, so download accelerators can fetch
+//! large debs from several mirrors at once and verify them against known checksums.
+
+use std::{collections::HashSet, fmt::Write};
+
+use base16ct::HexDisplay;
+use filemeta::DigestKind;
+use package::Package;
+
+use crate::FileToUpload;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MetalinkOptions {
+    /// Base URLs of every copy of the repository, in order of preference
+    pub mirrors: Vec<String>,
+    /// Packages smaller than this many bytes don't get a metalink
+    pub min_size: usize,
+}
+
+/// The metalinks of pool files, made as packages are added. Suites share a pool, so a package
+/// in several of them gets one metalink.
+#[derive(Debug, Default)]
+pub(crate) struct Metalinks {
+    seen: HashSet<Box<str>>,
+    pub(crate) files: Vec<FileToUpload>,
+}
+
+impl Metalinks {
+    pub(crate) fn add(
+        &mut self,
+        options: &MetalinkOptions,
+        package: &Package,
+    ) -> Result<(), std::fmt::Error> {
+        let file = &package.meta.file;
+        if file.size < options.min_size || !self.seen.insert(file.path.clone()) {
+            return Ok(());
+        }
+        self.files.push(FileToUpload {
+            destination_path: format!("{}.meta4", file.path).into(),
+            data: metalink(options, package)?.into_bytes().into(),
+        });
+        Ok(())
+    }
+}
+
+fn metalink(options: &MetalinkOptions, package: &Package) -> Result<String, std::fmt::Error> {
+    let file = &package.meta.file;
+    let name = file.path.rsplit('/').next().unwrap_or(&file.path);
+
+    let mut o = String::with_capacity(512);
+    writeln!(o, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(o, r#"<metalink xmlns="urn:ietf:params:xml:ns:metalink">"#)?;
+    writeln!(o, r#"  <file name="{}">"#, XmlEscaped(name))?;
+    writeln!(o, "    <size>{}</size>", file.size)?;
+    // by their names in the IANA hash function registry, strongest first
+    for (name, kind) in [
+        ("sha-256", DigestKind::Sha256),
+        ("sha-1", DigestKind::Sha1),
+        ("md5", DigestKind::Md5),
+    ] {
+        if let Some(digest) = file.sums.get(kind) {
+            writeln!(
+                o,
+                r#"    <hash type="{name}">{:x}</hash>"#,
+                HexDisplay(digest)
+            )?;
+        }
+    }
+    for (i, mirror) in options.mirrors.iter().enumerate() {
+        let url = format!("{}/{}", mirror.trim_end_matches('/'), file.path);
+        writeln!(
+            o,
+            r#"    <url priority="{}">{}</url>"#,
+            i + 1,
+            XmlEscaped(&url)
+        )?;
+    }
+    writeln!(o, "  </file>")?;
+    writeln!(o, "</metalink>")?;
+    Ok(o)
+}
+
+struct XmlEscaped<'a>(&'a str);
+
+impl std::fmt::Display for XmlEscaped<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for char in self.0.chars() {
+            match char {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&apos;")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+//! `Packages.diff/`, which lets apt update a Packages index it already has by downloading a small
+//! ed-style patch instead of the whole thing. Patches are "merged", each one going straight from
+//! an earlier version of the index to the current one, so clients only ever fetch one.
+//!
+//! Diffing needs the earlier versions themselves, so they're kept in a [`PdiffHistory`] between
+//! runs.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use base16ct::HexDisplay;
+
+use crate::{
+    FileToUpload, GenerateError,
+    compress::{CompressionLevels, Format},
+};
+
+/// Patches changing more lines than this aren't worth it over fetching the whole index, and
+/// would take a while to find
+const MAX_EDITS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PdiffOptions {
+    /// What the version being generated is called in later runs' indexes. Debian uses the UTC
+    /// time, like `2024-05-01-1200.00`, and names have to sort in the order they were made.
+    pub name: String,
+    pub history: PdiffHistory,
+}
+
+/// Earlier versions of each Packages index, keyed by their path in the repository, oldest first
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct PdiffHistory {
+    versions: BTreeMap<String, Vec<IndexVersion>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct IndexVersion {
+    name: String,
+    contents: Box<[u8]>,
+}
+
+impl PdiffHistory {
+    /// Read back the versions kept in `dir` by [`save`](Self::save), which are stored as
+    /// `<dir>/<index path>/<name>`. A missing directory is an empty history.
+    pub fn load(dir: &Path) -> Result<Self, std::io::Error> {
+        let mut history = Self::default();
+        if dir.exists() {
+            history.load_from(dir, &mut PathBuf::new())?;
+        }
+        for versions in history.versions.values_mut() {
+            versions.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        }
+        Ok(history)
+    }
+
+    fn load_from(&mut self, dir: &Path, relative: &mut PathBuf) -> Result<(), std::io::Error> {
+        for entry in std::fs::read_dir(dir.join(&*relative))? {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            relative.push(&name);
+            if entry.file_type()?.is_dir() {
+                self.load_from(dir, relative)?;
+            } else if let Some(index) = relative.parent().and_then(Path::to_str) {
+                let contents = std::fs::read(entry.path())?.into_boxed_slice();
+                self.versions
+                    .entry(index.replace(std::path::MAIN_SEPARATOR, "/"))
+                    .or_default()
+                    .push(IndexVersion { name, contents });
+            }
+            relative.pop();
+        }
+        Ok(())
+    }
+
+    /// Keep the Packages indexes among `files` in `dir` as version `name`, dropping all but the
+    /// newest `keep` versions of each. Indexes that didn't change since the last version aren't
+    /// stored again.
+    pub fn save(
+        dir: &Path,
+        name: &str,
+        files: &[FileToUpload],
+        keep: usize,
+    ) -> Result<(), std::io::Error> {
+        let history = Self::load(dir)?;
+        for file in files.iter().filter(|f| is_diffable(&f.destination_path)) {
+            let index_dir = dir.join(&*file.destination_path);
+            let versions = history.versions(&file.destination_path);
+            if versions
+                .last()
+                .is_none_or(|last| last.contents != file.data)
+            {
+                std::fs::create_dir_all(&index_dir)?;
+                std::fs::write(index_dir.join(name), &file.data)?;
+            }
+            let mut names: Vec<&str> = versions.iter().map(|v| &*v.name).collect();
+            names.push(name);
+            names.sort_unstable();
+            names.dedup();
+            for old in &names[..names.len().saturating_sub(keep)] {
+                std::fs::remove_file(index_dir.join(old))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether [`save`](Self::save) keeps versions of the file at `path`
+    pub fn tracks(path: &str) -> bool {
+        is_diffable(path)
+    }
+
+    fn versions(&self, index: &str) -> &[IndexVersion] {
+        self.versions.get(index).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Only the Packages indexes get diffs, like Debian's
+pub(crate) fn is_diffable(path: &str) -> bool {
+    path == "Packages" || path.ends_with("/Packages")
+}
+
+/// The diff index of the Packages index at `path`, to be listed in Release, and the patches
+/// it points to. `history_path` is where the index is in the whole repository.
+pub(crate) fn generate_pdiffs(
+    options: &PdiffOptions,
+    path: &str,
+    history_path: &str,
+    current: &[u8],
+    levels: &CompressionLevels,
+) -> Result<(FileToUpload, Vec<FileToUpload>), GenerateError> {
+    let hash = |data: &[u8]| {
+        filemeta::sha256(data).map_err(|e| GenerateError::HashFile(history_path.into(), e))
+    };
+    let current_sha256 = hash(current)?;
+    let mut history = String::new();
+    let mut patches = String::new();
+    let mut downloads = String::new();
+    let mut to_upload = Vec::new();
+
+    let current_lines: Vec<&[u8]> = current.split_inclusive(|b| *b == b'\n').collect();
+    for version in options.history.versions(history_path) {
+        if version.name == options.name || *version.contents == *current {
+            continue;
+        }
+        let old_lines: Vec<&[u8]> = version.contents.split_inclusive(|b| *b == b'\n').collect();
+        let Some(hunks) = diff(&old_lines, &current_lines) else {
+            continue;
+        };
+        let patch = ed_script(&hunks, &current_lines);
+        let gz = Format::Gzip
+            .compress(&patch, levels)
+            .map_err(|e| GenerateError::Compression("gz", history_path.to_owned(), e))?;
+        let name = &version.name;
+        writeln!(
+            history,
+            " {:x} {} {name}",
+            HexDisplay(&hash(&version.contents)?),
+            version.contents.len()
+        )?;
+        writeln!(
+            patches,
+            " {:x} {} {name}",
+            HexDisplay(&hash(&patch)?),
+            patch.len()
+        )?;
+        writeln!(
+            downloads,
+            " {:x} {} {name}.gz",
+            HexDisplay(&hash(&gz)?),
+            gz.len()
+        )?;
+        to_upload.push(FileToUpload {
+            destination_path: format!("{path}.diff/{name}.gz").into(),
+            data: gz.into_boxed_slice(),
+        });
+    }
+
+    let mut index = format!(
+        "SHA256-Current: {:x} {}\n",
+        HexDisplay(&current_sha256),
+        current.len()
+    );
+    for (field, entries) in [
+        ("SHA256-History", history),
+        ("SHA256-Patches", patches),
+        ("SHA256-Download", downloads),
+    ] {
+        index.push_str(field);
+        index.push_str(":\n");
+        index.push_str(&entries);
+    }
+    index.push_str("X-Patch-Precedence: merged\n");
+    let index = FileToUpload {
+        destination_path: format!("{path}.diff/Index").into(),
+        data: index.into_bytes().into_boxed_slice(),
+    };
+    Ok((index, to_upload))
+}
+
+/// Lines `old_start..old_end` of the old file are replaced by `new_start..new_end` of the new one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Hunk {
+    old_start: usize,
+    old_end: usize,
+    new_start: usize,
+    new_end: usize,
+}
+
+/// The hunks turning `old` into `new`, found with Myers' algorithm, or `None` if that takes more
+/// than [`MAX_EDITS`] inserted and deleted lines
+fn diff(old: &[&[u8]], new: &[&[u8]]) -> Option<Vec<Hunk>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (MAX_EDITS as isize).min(n + m);
+    // the furthest x reached on each diagonal k = x - y, offset so k = -max is index 0
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let offset = max + 1;
+    let mut trace = Vec::new();
+    for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, offset, n, m));
+            }
+        }
+    }
+    None
+}
+
+/// Walk back through the furthest points of each round of [`diff`] to find the edits it made
+fn backtrack(trace: &[Vec<isize>], offset: isize, n: isize, m: isize) -> Vec<Hunk> {
+    let (mut x, mut y) = (n, m);
+    // which lines of each file are kept, from the end
+    let mut kept = Vec::new();
+    for (d, v) in trace.iter().enumerate().skip(1).rev() {
+        let d = d as isize;
+        let k = x - y;
+        let previous_k =
+            if k == -d || (k != d && v[(k - 1 + offset) as usize] < v[(k + 1 + offset) as usize]) {
+                k + 1
+            } else {
+                k - 1
+            };
+        let previous_x = v[(previous_k + offset) as usize];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            kept.push((x as usize, y as usize));
+        }
+        (x, y) = (previous_x, previous_y);
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        kept.push((x as usize, y as usize));
+    }
+    kept.reverse();
+
+    let mut hunks = Vec::new();
+    let (mut old_at, mut new_at) = (0, 0);
+    for (old_kept, new_kept) in kept.into_iter().chain([(n as usize, m as usize)]) {
+        if old_kept > old_at || new_kept > new_at {
+            hunks.push(Hunk {
+                old_start: old_at,
+                old_end: old_kept,
+                new_start: new_at,
+                new_end: new_kept,
+            });
+        }
+        (old_at, new_at) = (old_kept + 1, new_kept + 1);
+    }
+    hunks
+}
+
+/// `hunks` as the ed commands `diff --ed` writes, last first so earlier line numbers stay right
+fn ed_script(hunks: &[Hunk], new: &[&[u8]]) -> Vec<u8> {
+    let mut script = Vec::new();
+    for hunk in hunks.iter().rev() {
+        // ed counts lines from 1, and appends after the line it's given
+        let (first, last) = (hunk.old_start + 1, hunk.old_end);
+        let range = if first == last {
+            first.to_string()
+        } else {
+            format!("{first},{last}")
+        };
+        let command = match (
+            hunk.old_start == hunk.old_end,
+            hunk.new_start == hunk.new_end,
+        ) {
+            (true, _) => format!("{}a\n", hunk.old_start),
+            (false, true) => format!("{range}d\n"),
+            (false, false) => format!("{range}c\n"),
+        };
+        script.extend_from_slice(command.as_bytes());
+        if hunk.new_start != hunk.new_end {
+            for line in &new[hunk.new_start..hunk.new_end] {
+                script.extend_from_slice(line);
+                if !line.ends_with(b"\n") {
+                    script.push(b'\n');
+                }
+            }
+            script.extend_from_slice(b".\n");
+        }
+    }
+    script
+}
@@ -0,0 +1,174 @@
+//! Signing Release files. apt's gpgv only trusts signatures with a strong enough hash, so the hash
+//! is picked here instead of being left to whatever the key prefers.
+
+use std::time::SystemTime;
+
+use chrono::{DateTime, SubsecRound, Utc};
+use pgp::{
+    composed::{ArmorOptions, CleartextSignedMessage},
+    crypto::{hash::HashAlgorithm, public_key::PublicKeyAlgorithm},
+    packet::{SecretKey, SignatureConfig, SignatureType, Subpacket, SubpacketData},
+    ser::Serialize,
+    types::{KeyDetails, KeyVersion, Password, SecretKeyTrait},
+};
+
+use crate::GenerateError;
+
+const ARMOR_OPTS: ArmorOptions = ArmorOptions {
+    headers: None,
+    include_checksum: true,
+};
+
+/// Signs Release files. Implement this to sign with a key that isn't on the repository host,
+/// like one held by gpg-agent, an HSM or a cloud KMS. [`PgpSigner`] signs with keys in memory.
+pub trait Signer {
+    /// Sign the text of a Release file
+    fn sign(&self, release: &str) -> Result<SignedRelease, GenerateError>;
+    /// The binary keyring clients verify the signatures with, published as
+    /// `deriv-archive-keyring.pgp`
+    fn keyring(&self) -> Result<Vec<u8>, GenerateError>;
+}
+
+/// The signed forms of a Release file
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SignedRelease {
+    /// The armored inline-signed message, published as InRelease
+    pub inline: Vec<u8>,
+    /// An armored detached signature, published as Release.gpg
+    pub detached: Vec<u8>,
+}
+
+/// Signs with secret keys held in memory. Every one of `keys` signs, and all of them go in the
+/// keyring.
+#[derive(Debug, Clone)]
+pub struct PgpSigner {
+    pub keys: Vec<SecretKey>,
+    pub hash: SignatureHash,
+    /// When the signatures say they were made, or now. RSA and Ed25519 keys make the same
+    /// signature each time from the same time and text, so fixing this makes signed Release
+    /// files reproducible with them.
+    pub created: Option<SystemTime>,
+}
+
+impl Signer for PgpSigner {
+    fn sign(&self, release: &str) -> Result<SignedRelease, GenerateError> {
+        let message = sign_release(release, &self.keys, self.hash, self.created)?;
+        let inline = message.to_armored_string(ARMOR_OPTS)?;
+        // the signature block of InRelease holds every signature, and is a detached signature
+        // of Release on its own
+        let detached = inline
+            .find("-----BEGIN PGP SIGNATURE-----")
+            .map(|start| inline.as_bytes()[start..].to_vec())
+            .ok_or(GenerateError::NoSignatures)?;
+        Ok(SignedRelease {
+            inline: inline.into_bytes(),
+            detached,
+        })
+    }
+
+    fn keyring(&self) -> Result<Vec<u8>, GenerateError> {
+        // a binary keyring is just its keys one after another
+        let mut keyring = Vec::new();
+        for key in &self.keys {
+            keyring.extend(key.public_key().to_bytes()?);
+        }
+        Ok(keyring)
+    }
+}
+
+/// The hash Release signatures are made over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SignatureHash {
+    /// Whatever the key prefers, as long as apt accepts it, otherwise SHA-256
+    #[default]
+    KeyDefault,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl SignatureHash {
+    fn algorithm(self, key: &SecretKey) -> HashAlgorithm {
+        match self {
+            Self::KeyDefault => match key.hash_alg() {
+                strong @ (HashAlgorithm::Sha256
+                | HashAlgorithm::Sha384
+                | HashAlgorithm::Sha512
+                | HashAlgorithm::Sha3_256
+                | HashAlgorithm::Sha3_512) => strong,
+                // apt rejects signatures over SHA-1 and older
+                _ => HashAlgorithm::Sha256,
+            },
+            Self::Sha256 => HashAlgorithm::Sha256,
+            Self::Sha384 => HashAlgorithm::Sha384,
+            Self::Sha512 => HashAlgorithm::Sha512,
+        }
+    }
+}
+
+/// Sign `text` as an inline-signed message carrying a signature from each of `keys`, whose
+/// signatures also go into Release.gpg. apt accepts a Release if any one of them is from a key
+/// it trusts, so clients keep working while the repository moves from one key to another.
+fn sign_release(
+    text: &str,
+    keys: &[SecretKey],
+    hash: SignatureHash,
+    created: Option<SystemTime>,
+) -> Result<CleartextSignedMessage, GenerateError> {
+    if keys.is_empty() {
+        return Err(GenerateError::NoKeys);
+    }
+    let created: DateTime<Utc> = created.unwrap_or_else(SystemTime::now).into();
+    let configs = keys
+        .iter()
+        .map(|key| Ok((key, signature_config(key, hash, created)?)))
+        .collect::<Result<Vec<_>, GenerateError>>()?;
+    Ok(CleartextSignedMessage::new_many(text, |normalized| {
+        configs
+            .into_iter()
+            .map(|(key, config)| config.sign(key, &Password::empty(), normalized.as_bytes()))
+            .collect()
+    })?)
+}
+
+/// How `key` signs. RSA, ECDSA and Ed25519 keys are supported, both the legacy and the
+/// RFC 9580 kind.
+fn signature_config(
+    key: &SecretKey,
+    hash: SignatureHash,
+    created: DateTime<Utc>,
+) -> Result<SignatureConfig, GenerateError> {
+    let algorithm = key.algorithm();
+    if !matches!(
+        algorithm,
+        PublicKeyAlgorithm::RSA
+            | PublicKeyAlgorithm::ECDSA
+            | PublicKeyAlgorithm::EdDSALegacy
+            | PublicKeyAlgorithm::Ed25519
+    ) {
+        return Err(GenerateError::UnsupportedKey(format!("{algorithm:?}")));
+    }
+    let hash = hash.algorithm(key);
+
+    let mut config = match key.version() {
+        KeyVersion::V4 => SignatureConfig::v4(SignatureType::Text, algorithm, hash),
+        KeyVersion::V6 => SignatureConfig::v6(
+            &mut rand::thread_rng(),
+            SignatureType::Text,
+            algorithm,
+            hash,
+        )?,
+        version => return Err(GenerateError::UnsupportedKey(format!("{version:?} key"))),
+    };
+    config.hashed_subpackets = vec![
+        Subpacket::regular(SubpacketData::IssuerFingerprint(key.fingerprint()))?,
+        Subpacket::regular(SubpacketData::SignatureCreationTime(
+            created.trunc_subsecs(0),
+        ))?,
+    ];
+    // v6 signatures only identify their key by fingerprint
+    if key.version() == KeyVersion::V4 {
+        config.unhashed_subpackets = vec![Subpacket::regular(SubpacketData::Issuer(key.key_id()))?];
+    }
+    Ok(config)
+}
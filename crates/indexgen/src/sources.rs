@@ -0,0 +1,85 @@
+//! A deb822 `.sources` file adding the repository to apt, published at the top of it as
+//! `<origin>.sources`. Copied into `/etc/apt/sources.list.d/` next to the keyring, it's all a
+//! client needs, so operators can hand users one file instead of instructions.
+
+use crate::{GenerateError, Sink, write_to};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourcesOptions {
+    /// the url clients reach the top of the repository at
+    pub uri: String,
+    /// where clients keep the keyring. Without it that's wherever the keyring package installs
+    /// it, or `/usr/share/keyrings/<origin>-archive-keyring.pgp` for users to save
+    /// `deriv-archive-keyring.pgp` to when there's no keyring package.
+    pub signed_by: Option<String>,
+}
+
+/// The suites to list, gathered as they're generated. Only the first one is enabled, and the
+/// rest are there for users to switch to.
+#[derive(Debug, Default)]
+pub(crate) struct Sources {
+    origin: Option<String>,
+    /// the suite and space-separated components of each, `./` and none for flat repositories
+    suites: Vec<(String, String)>,
+}
+
+impl Sources {
+    pub(crate) fn add(&mut self, origin: &str, suite: &str, components: &[&str]) {
+        self.origin.get_or_insert_with(|| origin.to_owned());
+        self.suites.push((suite.to_owned(), components.join(" ")));
+    }
+
+    /// `keyring_package` is the name of the keyring package, if one was published
+    pub(crate) fn write(
+        self,
+        options: &SourcesOptions,
+        keyring_package: Option<&str>,
+        sink: &mut dyn Sink,
+    ) -> Result<(), GenerateError> {
+        let Some(origin) = self.origin else {
+            return Ok(());
+        };
+        let name = file_name(&origin);
+        let signed_by = match (&options.signed_by, keyring_package) {
+            (Some(path), _) => path.clone(),
+            (None, Some(package)) => format!("/usr/share/keyrings/{package}.pgp"),
+            (None, None) => format!("/usr/share/keyrings/{name}-archive-keyring.pgp"),
+        };
+        let mut stanzas = Vec::with_capacity(self.suites.len());
+        for (i, (suite, components)) in self.suites.iter().enumerate() {
+            let mut stanza = format!("Types: deb\nURIs: {}\nSuites: {suite}\n", options.uri);
+            if !components.is_empty() {
+                stanza.push_str(&format!("Components: {components}\n"));
+            }
+            stanza.push_str(&format!("Signed-By: {signed_by}\n"));
+            if i > 0 {
+                stanza.push_str("Enabled: no\n");
+            }
+            stanzas.push(stanza);
+        }
+        write_to(
+            sink,
+            &format!("{name}.sources"),
+            stanzas.join("\n").as_bytes(),
+        )
+    }
+}
+
+/// Origins are freeform, but apt only reads files in `sources.list.d/` named with letters,
+/// digits, `_`, `-` and `.`
+fn file_name(origin: &str) -> String {
+    let name: String = origin
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    match name.trim_matches('-') {
+        "" => "godsvagn".to_owned(),
+        name => name.to_owned(),
+    }
+}
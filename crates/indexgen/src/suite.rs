@@ -0,0 +1,212 @@
+//! The indexes of one suite, built up a package at a time. Only what goes in them is kept, so
+//! packages can come straight from wherever they're stored instead of all being in memory.
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use package::{IndexOrder, MultiArch, Package};
+use parsedeb::{Architecture, PackageKind, PackageName, Version};
+
+use crate::{
+    GenerateError, GenerateOptions, IndexFile, OutputStyle, ReleaseMetadata, Sink, components,
+    dep11, hashed_indexes, translation,
+};
+
+/// What one package puts in the indexes of its suite
+struct Indexed {
+    component: Box<str>,
+    architecture: Architecture,
+    udeb: bool,
+    order: IndexOrder,
+    stanza: Box<str>,
+    translation: Option<translation::Entry>,
+    apps: Vec<dep11::App>,
+}
+
+pub(crate) struct SuiteIndexes<'a> {
+    options: &'a GenerateOptions,
+    packages: Vec<Indexed>,
+    /// the newest version on each architecture of every `Multi-Arch: same` package in each
+    /// component, when [`strict_multi_arch`](GenerateOptions::strict_multi_arch)
+    multi_arch_same: BTreeMap<(Box<str>, PackageName), BTreeMap<Architecture, Version>>,
+}
+
+impl<'a> SuiteIndexes<'a> {
+    pub(crate) fn new(options: &'a GenerateOptions) -> Self {
+        Self {
+            options,
+            packages: Vec::new(),
+            multi_arch_same: BTreeMap::new(),
+        }
+    }
+
+    /// Put `package` in the indexes, where its [`IndexOrder`] puts it among the packages added
+    /// before it
+    pub(crate) fn add(&mut self, package: &Package) -> Result<(), GenerateError> {
+        let options = self.options;
+        // debian-installer doesn't read Translation-en, so udebs keep their descriptions
+        let udeb = package.kind() == PackageKind::Udeb;
+        let split = options.translations && !udeb;
+        let apt_ftparchive = options.style == OutputStyle::AptFtparchive;
+        let shortened = if split {
+            translation::synopsis_only(package, apt_ftparchive)
+        } else {
+            Cow::Borrowed(package)
+        };
+        let mut stanza = String::with_capacity(1024);
+        if apt_ftparchive {
+            shortened.write_into_packages_apt_ftparchive(&mut stanza)?;
+        } else {
+            shortened.write_into_packages(&mut stanza)?;
+        }
+        stanza.push_str("\n\n");
+
+        if options.strict_multi_arch && package.known.multi_arch == Some(MultiArch::Same) {
+            let key = (package.component.clone(), package.name.clone());
+            let newest = self.multi_arch_same.entry(key).or_default();
+            match newest.get_mut(&package.architecture) {
+                Some(newest) if newest.parsed() >= package.version.parsed() => {}
+                Some(newest) => *newest = package.version.clone(),
+                None => {
+                    newest.insert(package.architecture.clone(), package.version.clone());
+                }
+            }
+        }
+
+        self.packages.push(Indexed {
+            component: package.component.clone(),
+            architecture: package.architecture.clone(),
+            udeb,
+            order: package.index_order(),
+            stanza: stanza.into_boxed_str(),
+            translation: if split {
+                translation::entry(package)
+            } else {
+                None
+            },
+            apps: if options.dep11 {
+                dep11::App::all_in(package)
+            } else {
+                Vec::new()
+            },
+        });
+        Ok(())
+    }
+
+    /// Write the indexes to `sink` in the directory of each of `targets`, and return the
+    /// unsigned Release file of each
+    pub(crate) fn write(
+        mut self,
+        targets: &[(&ReleaseMetadata, &str)],
+        sink: &mut dyn Sink,
+    ) -> Result<Vec<String>, GenerateError> {
+        let options = self.options;
+        self.check_multi_arch()?;
+        self.packages.sort_by(|a, b| a.order.cmp(&b.order));
+        let components = self.components();
+        let index_files = self.index_files();
+        let mut architectures: Vec<&str> = index_files.keys().map(|(_, arch)| *arch).collect();
+        architectures.sort_unstable();
+        architectures.dedup();
+
+        let mut uncompressed: Vec<IndexFile> = index_files
+            .into_iter()
+            .map(|((component, arch), contents)| IndexFile {
+                base_path: format!("{component}/binary-{arch}/Packages"),
+                contents: contents.into_boxed_str(),
+            })
+            .collect();
+        if options.translations {
+            for component in &components {
+                let in_component = self.packages.iter().filter(|p| *p.component == **component);
+                let entries = in_component.filter_map(|p| p.translation.as_ref());
+                uncompressed.push(IndexFile {
+                    base_path: translation::translation_path(component),
+                    contents: translation::generate_translation(entries)?.into_boxed_str(),
+                });
+            }
+        }
+        let mut prebuilt = Vec::new();
+        if options.dep11 {
+            for component in &components {
+                let in_component = self.packages.iter().filter(|p| *p.component == **component);
+                let (metadata, icons) = dep11::generate_dep11(
+                    &targets[0].0.suite,
+                    component,
+                    &architectures,
+                    in_component.flat_map(|p| &p.apps),
+                    &options.compression.levels,
+                )?;
+                uncompressed.extend(metadata);
+                prebuilt.extend(icons);
+            }
+        }
+        hashed_indexes(
+            uncompressed,
+            (&architectures, &components),
+            prebuilt,
+            options,
+            targets,
+            sink,
+        )
+    }
+
+    /// Every component with packages in the suite
+    pub(crate) fn components(&self) -> Vec<&str> {
+        components(self.packages.iter().map(|p| &*p.component))
+    }
+
+    fn check_multi_arch(&self) -> Result<(), GenerateError> {
+        for ((_, package), newest) in &self.multi_arch_same {
+            let mut versions = newest.values().map(|v| v.parsed());
+            let first = versions.next();
+            if versions.all(|version| Some(version) == first) {
+                continue;
+            }
+            let versions: Vec<String> = newest
+                .iter()
+                .map(|(arch, version)| format!("{version} on {arch}"))
+                .collect();
+            return Err(GenerateError::MultiArchVersions {
+                package: package.to_string(),
+                versions: versions.join(", "),
+            });
+        }
+        Ok(())
+    }
+
+    /// The contents of each Packages index, by the directory it goes in under the suite and its
+    /// architecture. `Architecture: all` packages are in `binary-all` and, unless
+    /// [`separate_arch_all`](GenerateOptions::separate_arch_all), the index of every other
+    /// architecture with packages in the suite. udebs go in indexes of their own, in the
+    /// `debian-installer` directory of their component.
+    fn index_files(&self) -> BTreeMap<(Cow<'_, str>, &str), String> {
+        let mut other_architectures: Vec<&str> = self
+            .packages
+            .iter()
+            .map(|p| p.architecture.as_str())
+            .filter(|arch| *arch != "all")
+            .collect();
+        other_architectures.sort_unstable();
+        other_architectures.dedup();
+
+        let mut indexes: BTreeMap<(Cow<str>, &str), String> = BTreeMap::new();
+        for package in &self.packages {
+            let component = if package.udeb {
+                Cow::Owned(format!("{}/debian-installer", package.component))
+            } else {
+                Cow::Borrowed(&*package.component)
+            };
+            let mut architectures = vec![package.architecture.as_str()];
+            if package.architecture.as_str() == "all" && !self.options.separate_arch_all {
+                architectures.extend(&other_architectures);
+            }
+            for arch in architectures {
+                indexes
+                    .entry((component.clone(), arch))
+                    .or_default()
+                    .push_str(&package.stanza);
+            }
+        }
+        indexes
+    }
+}
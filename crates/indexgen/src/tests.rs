@@ -0,0 +1,1069 @@
+use super::*;
+
+fn release_metadata() -> ReleaseMetadata {
+    ReleaseMetadata {
+        origin: "godsvagn".to_owned(),
+        label: "godsvagn".to_owned(),
+        suite: "testing".to_owned(),
+        codename: "salolampi".to_owned(),
+        version: "0.1".to_owned(),
+        description: String::new(),
+        date: "Sat, 05 Jul 2025 12:00:00 UTC".to_owned(),
+        ..Default::default()
+    }
+}
+
+fn described_package(name: &str, description: &str) -> Package {
+    let mut package = Package {
+        meta: package::PackageMeta {
+            file: FileMeta::new(format!("pool/main/{name}_1.0_all.deb").into(), b"").unwrap(),
+            description_md5: [0; 16],
+        },
+        name: name.parse().unwrap(),
+        architecture: "all".parse().unwrap(),
+        version: "1.0".parse().unwrap(),
+        component: "main".into(),
+        appstream: Vec::new(),
+        changelog: None,
+        fields: Default::default(),
+        known: Default::default(),
+    };
+    let fields = &mut package.fields;
+    fields.insert("Package".into(), format!(" {name}\n").into());
+    fields.insert("Description".into(), description.into());
+    package.meta.description_md5 = package::description_md5(&package.fields);
+    package
+}
+
+/// The indexes and the golden file are what apt-ftparchive makes of package's fixture deb, see
+/// `package/src/testfiles/regenerate.sh`
+#[test]
+fn apt_ftparchive_release() {
+    let files = [
+        (
+            "main/binary-amd64/Packages.xz",
+            &include_bytes!("testfiles/Packages.xz")[..],
+        ),
+        (
+            "main/binary-amd64/Packages",
+            include_bytes!("testfiles/Packages"),
+        ),
+        (
+            "main/binary-amd64/Packages.gz",
+            include_bytes!("testfiles/Packages.gz"),
+        ),
+    ]
+    .map(|(path, data)| FileMeta::new(path.into(), data).unwrap());
+    let out = generate_release_apt_ftparchive(&release_metadata(), &files, &["amd64"], &["main"])
+        .unwrap();
+    assert_eq!(include_str!("testfiles/Release.apt-ftparchive"), out);
+}
+
+#[test]
+fn metalink() {
+    let file = FileMeta::new("pool/main/big_1.0_amd64.deb".into(), b"hello").unwrap();
+    let package = Package {
+        meta: package::PackageMeta {
+            file,
+            description_md5: [0; 16],
+        },
+        name: "big".parse().unwrap(),
+        architecture: "amd64".parse().unwrap(),
+        version: "1.0".parse().unwrap(),
+        component: "main".into(),
+        appstream: Vec::new(),
+        changelog: None,
+        fields: Default::default(),
+        known: Default::default(),
+    };
+    let options = MetalinkOptions {
+        mirrors: vec![
+            "https://a.example/".to_owned(),
+            "https://b.example/r&d/".to_owned(),
+        ],
+        min_size: 5,
+    };
+    let mut metalinks = metalink::Metalinks::default();
+    metalinks.add(&options, &package).unwrap();
+    let out = metalinks.files;
+    assert_eq!(out.len(), 1);
+    assert_eq!(
+        &*out[0].destination_path,
+        "pool/main/big_1.0_amd64.deb.meta4"
+    );
+    assert_eq!(
+        include_bytes!("testfiles/big_1.0_amd64.deb.meta4"),
+        &*out[0].data
+    );
+
+    let too_small = MetalinkOptions {
+        min_size: 6,
+        ..options
+    };
+    let mut metalinks = metalink::Metalinks::default();
+    metalinks.add(&too_small, &package).unwrap();
+    assert!(metalinks.files.is_empty());
+}
+
+#[test]
+fn translation() {
+    let long = " a tool\n that does things.\n .\n Many things.\n";
+    let packages = [
+        described_package("zed", long),
+        described_package("abc", long),
+        described_package("short", " just a synopsis\n"),
+    ];
+
+    let entries: Vec<_> = packages.iter().filter_map(translation::entry).collect();
+    let out = translation::generate_translation(entries.iter()).unwrap();
+    assert_eq!(
+        out,
+        "Package: abc\n\
+         Description-md5: 5ba3f046fce9d45fb261533252ecaa45\n\
+         Description-en: a tool\n that does things.\n .\n Many things.\n\n\
+         Package: short\n\
+         Description-md5: 529b1aa66fd85ec3b39f0293a9caf67b\n\
+         Description-en: just a synopsis\n\n"
+    );
+
+    let index = |style| {
+        let options = GenerateOptions {
+            style,
+            translations: true,
+            ..Default::default()
+        };
+        let files = generate_files(&release_metadata(), None, &packages[..1], &options).unwrap();
+        let file = files
+            .iter()
+            .find(|f| &*f.destination_path == "main/binary-all/Packages");
+        String::from_utf8(file.unwrap().data.to_vec()).unwrap()
+    };
+    assert!(index(OutputStyle::Native).contains("Description: a tool\nFilename:"));
+    assert!(
+        index(OutputStyle::AptFtparchive)
+            .contains("Description: a tool\nDescription-md5: 5ba3f046fce9d45fb261533252ecaa45\n")
+    );
+}
+
+#[test]
+fn components() {
+    let mut packages = ["aa", "bb", "cc", "dd"].map(|name| described_package(name, " x\n"));
+    packages[0].component = "non-free".into();
+    packages[1].component = "extra".into();
+    packages[2].component = "main".into();
+    packages[3].component = "non-free".into();
+    let names = packages.iter().map(|p| &*p.component);
+    assert_eq!(super::components(names), ["main", "non-free", "extra"]);
+    assert_eq!(super::components([]), ["main"]);
+
+    let options = GenerateOptions::default();
+    let files = generate_files(&release_metadata(), None, &packages, &options).unwrap();
+    let indexes: Vec<&str> = files
+        .iter()
+        .map(|f| &*f.destination_path)
+        .filter(|path| path.ends_with("/Packages"))
+        .collect();
+    assert_eq!(
+        indexes,
+        [
+            "extra/binary-all/Packages",
+            "main/binary-all/Packages",
+            "non-free/binary-all/Packages"
+        ]
+    );
+    let non_free = files
+        .iter()
+        .find(|f| &*f.destination_path == "non-free/binary-all/Packages")
+        .unwrap();
+    let non_free = std::str::from_utf8(&non_free.data).unwrap();
+    assert!(non_free.contains("Package: aa\n"));
+    assert!(non_free.contains("Package: dd\n"));
+}
+
+#[test]
+fn suites() {
+    let testing = [
+        described_package("shared", " x\n"),
+        described_package("newer", " y\n"),
+    ];
+    let metadata = ReleaseMetadata {
+        suite: "testing".to_owned(),
+        ..release_metadata()
+    };
+    let options = GenerateOptions::default();
+    let mut files = Vec::new();
+    let targets = [(&metadata, "dists/testing/")];
+    let mut indexes = SuiteIndexes::new(&options);
+    for package in &testing {
+        indexes.add(package).unwrap();
+    }
+    let release = indexes.write(&targets, &mut files).unwrap();
+    let release = &release[0];
+    let paths: Vec<&str> = files.iter().map(|f| &*f.destination_path).collect();
+    assert_eq!(
+        paths,
+        [
+            "dists/testing/main/binary-all/Packages.gz",
+            "dists/testing/main/binary-all/Packages.xz",
+            "dists/testing/main/binary-all/Packages",
+        ]
+    );
+    // Release lists files relative to the suite's directory
+    assert!(release.contains("Suite: testing\n"));
+    assert!(release.contains(" main/binary-all/Packages\n"));
+
+    // the pool is shared, so a package in both suites only gets one metalink
+    let options = MetalinkOptions {
+        mirrors: vec!["https://a.example/".to_owned()],
+        min_size: 0,
+    };
+    let stable = [described_package("shared", " x\n")];
+    let mut metalinks = metalink::Metalinks::default();
+    for package in stable.iter().chain(&testing) {
+        metalinks.add(&options, package).unwrap();
+    }
+    assert_eq!(metalinks.files.len(), 2);
+}
+
+#[test]
+fn compression() {
+    let index = || IndexFile {
+        base_path: "main/binary-all/Packages".to_owned(),
+        contents: "Package: hello\n\n".into(),
+    };
+    let paths = |files: &[PackageIndexFile]| -> Vec<String> {
+        files.iter().map(|f| f.path.to_string()).collect()
+    };
+
+    let files = compress_indexes(vec![index()], &CompressionSet::default()).unwrap();
+    assert_eq!(
+        paths(&files),
+        [
+            "main/binary-all/Packages.gz",
+            "main/binary-all/Packages.xz",
+            "main/binary-all/Packages",
+        ]
+    );
+
+    let all = CompressionSet {
+        zstd: true,
+        bzip2: true,
+        ..Default::default()
+    };
+    let files = compress_indexes(vec![index()], &all).unwrap();
+    assert_eq!(files.len(), 5);
+    let zst = files.iter().find(|f| f.path.ends_with(".zst")).unwrap();
+    assert_eq!(
+        &*zstd::decode_all(&*zst.data).unwrap(),
+        b"Package: hello\n\n"
+    );
+    let bz2 = files.iter().find(|f| f.path.ends_with(".bz2")).unwrap();
+    let mut decoded = String::new();
+    std::io::Read::read_to_string(&mut bzip2::read::BzDecoder::new(&*bz2.data), &mut decoded)
+        .unwrap();
+    assert_eq!(decoded, "Package: hello\n\n");
+
+    let none = CompressionSet {
+        gzip: false,
+        xz: false,
+        zstd: false,
+        bzip2: false,
+        ..Default::default()
+    };
+    let files = compress_indexes(vec![index()], &none).unwrap();
+    assert_eq!(paths(&files), ["main/binary-all/Packages"]);
+
+    let fast = CompressionSet {
+        zstd: true,
+        bzip2: true,
+        levels: CompressionLevels::FAST,
+        ..Default::default()
+    };
+    let files = compress_indexes(vec![index()], &fast).unwrap();
+    let xz = files.iter().find(|f| f.path.ends_with(".xz")).unwrap();
+    assert_eq!(
+        &*liblzma::decode_all(&*xz.data).unwrap(),
+        b"Package: hello\n\n"
+    );
+
+    // compressed in parallel, but kept in order
+    let many: Vec<IndexFile> = (0..20)
+        .map(|i| IndexFile {
+            base_path: format!("main/binary-arch{i}/Packages"),
+            contents: format!("Package: p{i}\n\n").into(),
+        })
+        .collect();
+    let files = compress_indexes(many, &fast).unwrap();
+    assert_eq!(files.len(), 100);
+    for (i, group) in files.chunks(5).enumerate() {
+        assert!(group.iter().all(|f| f.path.contains(&format!("arch{i}/"))));
+        let mut decoded = String::new();
+        let mut gz = flate2::read::GzDecoder::new(&*group[0].data);
+        std::io::Read::read_to_string(&mut gz, &mut decoded).unwrap();
+        assert_eq!(decoded, format!("Package: p{i}\n\n"));
+    }
+}
+
+#[test]
+fn release_headers() {
+    let meta = ReleaseMetadata {
+        not_automatic: true,
+        but_automatic_upgrades: true,
+        signed_by: vec!["AAAA".to_owned(), "BBBB".to_owned()],
+        description: "Example packages".to_owned(),
+        extra_headers: vec![
+            ("X-Mirror-Policy".to_owned(), "weekly".to_owned()),
+            ("X-Contact".to_owned(), "admin@example.com".to_owned()),
+        ],
+        ..release_metadata()
+    };
+    let headers = "Date: Sat, 05 Jul 2025 12:00:00 UTC\n\
+                   NotAutomatic: yes\n\
+                   ButAutomaticUpgrades: yes\n\
+                   Signed-By: AAAA, BBBB\n\
+                   Architectures: amd64\n";
+
+    let native = generate_release(&meta, &[], &["amd64"], &["main"]).unwrap();
+    assert!(native.contains(headers), "{native}");
+    assert!(native.contains("Components: main\nDescription: Example packages\n"));
+    assert!(native.contains(
+        "Snapshots: no\nX-Mirror-Policy: weekly\nX-Contact: admin@example.com\nMD5Sum:\n"
+    ));
+
+    let apt = generate_release_apt_ftparchive(&meta, &[], &["amd64"], &["main"]).unwrap();
+    assert!(apt.contains(headers), "{apt}");
+    assert!(apt.contains(
+        "Description: Example packages\nX-Mirror-Policy: weekly\nX-Contact: admin@example.com\n"
+    ));
+
+    let plain = generate_release(&release_metadata(), &[], &["amd64"], &["main"]).unwrap();
+    assert!(!plain.contains("NotAutomatic"));
+    assert!(!plain.contains("Signed-By"));
+    assert!(!plain.contains("Description"));
+}
+
+#[test]
+fn no_keys() {
+    let packages = [described_package("hello", " x\n")];
+    let options = GenerateOptions::default();
+    let signer = PgpSigner {
+        keys: Vec::new(),
+        hash: SignatureHash::default(),
+        created: None,
+    };
+    let err = generate_files(&release_metadata(), Some(&signer), &packages, &options).unwrap_err();
+    assert!(matches!(err, GenerateError::NoKeys), "{err}");
+}
+
+/// Stands in for a key held somewhere else
+struct FakeSigner;
+
+impl Signer for FakeSigner {
+    fn sign(&self, release: &str) -> Result<SignedRelease, GenerateError> {
+        Ok(SignedRelease {
+            inline: format!("signed {}", release.len()).into_bytes(),
+            detached: b"detached".to_vec(),
+        })
+    }
+
+    fn keyring(&self) -> Result<Vec<u8>, GenerateError> {
+        Ok(b"keyring".to_vec())
+    }
+}
+
+#[test]
+fn external_signer() {
+    let packages = [described_package("hello", " x\n")];
+    let files = generate_files(
+        &release_metadata(),
+        Some(&FakeSigner),
+        &packages,
+        &GenerateOptions::default(),
+    )
+    .unwrap();
+    let file = |path: &str| {
+        let file = files.iter().find(|f| &*f.destination_path == path).unwrap();
+        String::from_utf8(file.data.to_vec()).unwrap()
+    };
+    let release = file("Release");
+    assert_eq!(file("InRelease"), format!("signed {}", release.len()));
+    assert_eq!(file("Release.gpg"), "detached");
+    assert_eq!(file("deriv-archive-keyring.pgp"), "keyring");
+}
+
+#[test]
+fn unsigned() {
+    let packages = [described_package("hello", " x\n")];
+    let options = GenerateOptions::default();
+    let files = generate_files(&release_metadata(), None, &packages, &options).unwrap();
+    let paths: Vec<&str> = files.iter().map(|f| &*f.destination_path).collect();
+    assert_eq!(
+        paths,
+        [
+            "main/binary-all/Packages.gz",
+            "main/binary-all/Packages.xz",
+            "main/binary-all/Packages",
+            "Release",
+        ]
+    );
+}
+
+#[test]
+fn sink() {
+    let packages = [described_package("hello", " x\n")];
+    let options = GenerateOptions::default();
+    let mut written = Vec::new();
+    let mut sink = |path: &str, data: &[u8]| {
+        written.push((path.to_owned(), data.len()));
+        Ok(())
+    };
+    generate_files_into(&release_metadata(), None, &packages, &options, &mut sink).unwrap();
+    let paths: Vec<&str> = written.iter().map(|(path, _)| &**path).collect();
+    assert_eq!(
+        paths,
+        [
+            "main/binary-all/Packages.gz",
+            "main/binary-all/Packages.xz",
+            "main/binary-all/Packages",
+            "Release"
+        ]
+    );
+
+    let mut full = |path: &str, _: &[u8]| {
+        if path.ends_with(".xz") {
+            Err(std::io::Error::from(std::io::ErrorKind::StorageFull))
+        } else {
+            Ok(())
+        }
+    };
+    let err =
+        generate_files_into(&release_metadata(), None, &packages, &options, &mut full).unwrap_err();
+    assert!(matches!(err, GenerateError::Sink(path, _) if &*path == "main/binary-all/Packages.xz"));
+}
+
+#[test]
+fn flat() {
+    let mut other = described_package("other", " y\n");
+    other.component = "contrib".into();
+    let packages = [described_package("hello", " x\n"), other];
+    let options = GenerateOptions {
+        translations: true,
+        ..Default::default()
+    };
+    let files = generate_flat(&release_metadata(), None, &packages, &options).unwrap();
+    let paths: Vec<&str> = files.iter().map(|f| &*f.destination_path).collect();
+    assert_eq!(paths, ["Packages.gz", "Packages.xz", "Packages", "Release"]);
+
+    let file = |path: &str| {
+        let file = files.iter().find(|f| &*f.destination_path == path).unwrap();
+        String::from_utf8(file.data.to_vec()).unwrap()
+    };
+    let index = file("Packages");
+    assert!(index.contains("Package: hello\n"));
+    assert!(index.contains("Package: other\n"));
+    let release = file("Release");
+    assert!(release.contains("Architectures: all\n"));
+    assert!(!release.contains("Components:"));
+    assert!(release.contains(" Packages\n"));
+}
+
+/// What apt does with a pdiff patch
+fn apply_ed_script(old: &[u8], script: &[u8]) -> Vec<u8> {
+    let mut lines: Vec<&[u8]> = old.split_inclusive(|b| *b == b'\n').collect();
+    let mut script = script.split_inclusive(|b| *b == b'\n');
+    while let Some(command) = script.next() {
+        let command = std::str::from_utf8(command).unwrap().trim_end();
+        let (range, action) = command.split_at(command.len() - 1);
+        let (first, last): (usize, usize) = match range.split_once(',') {
+            Some((first, last)) => (first.parse().unwrap(), last.parse().unwrap()),
+            None => (range.parse().unwrap(), range.parse().unwrap()),
+        };
+        let mut text = Vec::new();
+        if action != "d" {
+            text.extend(script.by_ref().take_while(|line| *line != b".\n"));
+        }
+        let replaced = if action == "a" {
+            first..first
+        } else {
+            first - 1..last
+        };
+        lines.splice(replaced, text);
+    }
+    lines.concat()
+}
+
+#[test]
+fn pdiff() {
+    let state = tempfile::tempdir().unwrap();
+    let generate = |name: &str, packages: &[Package]| {
+        let options = GenerateOptions {
+            pdiff: Some(PdiffOptions {
+                name: name.to_owned(),
+                history: PdiffHistory::load(state.path()).unwrap(),
+            }),
+            ..Default::default()
+        };
+        generate_files(&release_metadata(), None, packages, &options).unwrap()
+    };
+    let file = |files: &[FileToUpload], path: &str| {
+        files
+            .iter()
+            .find(|f| &*f.destination_path == path)
+            .map(|f| f.data.to_vec())
+    };
+
+    let first = [described_package("hello", " x\n")];
+    let files = generate("2024-01-01-0000.00", &first);
+    let index = file(&files, "main/binary-all/Packages.diff/Index").unwrap();
+    assert!(
+        String::from_utf8(index)
+            .unwrap()
+            .contains("SHA256-History:\nSHA256-Patches:\n")
+    );
+    PdiffHistory::save(state.path(), "2024-01-01-0000.00", &files, 2).unwrap();
+    let old = file(&files, "main/binary-all/Packages").unwrap();
+
+    let second = [
+        described_package("hello", " x\n"),
+        described_package("other", " y\n"),
+    ];
+    let files = generate("2024-01-01-0100.00", &second);
+    let index = String::from_utf8(file(&files, "main/binary-all/Packages.diff/Index").unwrap());
+    assert!(index.unwrap().contains(" 2024-01-01-0000.00.gz\n"));
+    let release = String::from_utf8(file(&files, "Release").unwrap()).unwrap();
+    assert!(release.contains(" main/binary-all/Packages.diff/Index\n"));
+    assert!(!release.contains(".diff/2024"));
+
+    let patch = file(
+        &files,
+        "main/binary-all/Packages.diff/2024-01-01-0000.00.gz",
+    )
+    .unwrap();
+    let mut script = Vec::new();
+    std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&*patch), &mut script).unwrap();
+    let new = file(&files, "main/binary-all/Packages").unwrap();
+    assert_eq!(apply_ed_script(&old, &script), new);
+
+    PdiffHistory::save(state.path(), "2024-01-01-0100.00", &files, 1).unwrap();
+    let kept = std::fs::read_dir(state.path().join("main/binary-all/Packages")).unwrap();
+    assert_eq!(kept.count(), 1);
+
+    // removing, changing and adding all at once
+    let third = [
+        described_package("other", " z\n"),
+        described_package("third", " y\n"),
+    ];
+    let files = generate("2024-01-01-0200.00", &third);
+    let patch = file(
+        &files,
+        "main/binary-all/Packages.diff/2024-01-01-0100.00.gz",
+    )
+    .unwrap();
+    let mut script = Vec::new();
+    std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&*patch), &mut script).unwrap();
+    let newest = file(&files, "main/binary-all/Packages").unwrap();
+    assert_eq!(apply_ed_script(&new, &script), newest);
+}
+
+#[test]
+fn dep11() {
+    // just the signature and header of a 64x64 PNG, which is all that's checked
+    let mut icon = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    icon.extend(64u32.to_be_bytes());
+    icon.extend(64u32.to_be_bytes());
+    let desktop_entry = "[Desktop Entry]\nName=Hello\nIcon=hello\nCategories=Utility;GTK;\n\
+        [Desktop Action New]\nIcon=other\n";
+    let mut hello = described_package("hello", " x\n");
+    hello.architecture = "amd64".parse().unwrap();
+    hello.appstream = [
+        (
+            "/usr/share/metainfo/org.example.Hello.metainfo.xml",
+            include_bytes!("testfiles/hello.metainfo.xml").to_vec(),
+        ),
+        (
+            "/usr/share/applications/org.example.Hello.desktop",
+            desktop_entry.as_bytes().to_vec(),
+        ),
+        (
+            "/usr/share/icons/hicolor/64x64/apps/hello.png",
+            icon.clone(),
+        ),
+        // the wrong size, so it's left out
+        ("/usr/share/icons/hicolor/128x128/apps/hello.png", icon),
+    ]
+    .map(|(path, contents)| parsedeb::DataFile {
+        path: path.into(),
+        contents: contents.into(),
+    })
+    .into();
+    let packages = [hello, described_package("plain", " y\n")];
+    let options = GenerateOptions {
+        dep11: true,
+        ..Default::default()
+    };
+    let files = generate_files(&release_metadata(), None, &packages, &options).unwrap();
+    let file = |path: &str| files.iter().find(|f| &*f.destination_path == path);
+
+    let components = file("main/dep11/Components-amd64.yml").unwrap();
+    assert_eq!(
+        include_str!("testfiles/Components-amd64.yml"),
+        std::str::from_utf8(&components.data).unwrap()
+    );
+    assert!(file("main/dep11/Components-amd64.yml.gz").is_some());
+    assert!(file("main/dep11/icons-128x128.tar.gz").is_none());
+
+    let icons = file("main/dep11/icons-64x64.tar.gz").unwrap();
+    let mut tarball = tar::Archive::new(flate2::read::GzDecoder::new(&icons.data[..]));
+    let names: Vec<String> = tarball
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap().path().unwrap().display().to_string())
+        .collect();
+    assert_eq!(names, ["hello_hello.png"]);
+    let release = file("Release").unwrap();
+    let release = std::str::from_utf8(&release.data).unwrap();
+    assert!(release.contains(" main/dep11/icons-64x64.tar.gz\n"));
+}
+
+#[test]
+fn reproducible() {
+    let mut amd64 = described_package("zzz", " z\n");
+    amd64.architecture = "amd64".parse().unwrap();
+    let mut newer = described_package("hello", " x\n");
+    newer.version = "1.0+b1".parse().unwrap();
+    newer.fields.insert("Version".into(), " 1.0+b1\n".into());
+    let packages = [
+        described_package("hello", " x\n"),
+        amd64,
+        described_package("abc", " y\n"),
+        newer,
+    ];
+    let mut reversed = packages.clone();
+    reversed.reverse();
+    let options = GenerateOptions {
+        translations: true,
+        ..Default::default()
+    };
+    let first = generate_files(&release_metadata(), None, &packages, &options).unwrap();
+    let second = generate_files(&release_metadata(), None, &reversed, &options).unwrap();
+    assert_eq!(first, second);
+
+    let index = first
+        .iter()
+        .find(|f| &*f.destination_path == "main/binary-all/Packages")
+        .unwrap();
+    let index = std::str::from_utf8(&index.data).unwrap();
+    assert!(index.find("Package: abc").unwrap() < index.find("Package: hello").unwrap());
+    // the newest version of a package comes first
+    let newer = index.find("Version: 1.0+b1").unwrap();
+    assert!(index[newer..].contains("Package: hello\n"));
+    let gz = first
+        .iter()
+        .find(|f| &*f.destination_path == "main/binary-all/Packages.gz")
+        .unwrap();
+    // no mtime, and no OS
+    assert_eq!(gz.data[4..8], [0; 4]);
+    assert_eq!(gz.data[9], 255);
+}
+
+#[test]
+fn keyring_package() {
+    let mut contrib = described_package("hello", " x\n");
+    contrib.component = "contrib".into();
+    let options = GenerateOptions {
+        keyring_package: Some(KeyringPackageOptions {
+            name: "godsvagn-archive-keyring".to_owned(),
+            version: "2".to_owned(),
+            maintainer: "Repo Admin <admin@example.com>".to_owned(),
+        }),
+        ..Default::default()
+    };
+    let files =
+        generate_files(&release_metadata(), Some(&FakeSigner), &[contrib], &options).unwrap();
+    let file = |path: &str| files.iter().find(|f| &*f.destination_path == path).unwrap();
+
+    let deb = file("pool/main/g/godsvagn-archive-keyring/godsvagn-archive-keyring_2_all.deb");
+    let (fields, _) = parsedeb::deb_to_control(&deb.data[..]).unwrap();
+    assert_eq!(
+        parsedeb::ControlFields::field(&fields, "Installed-Size"),
+        Some("1")
+    );
+    let data = parsedeb::deb_to_data_files(&deb.data[..], &Default::default(), |_| true).unwrap();
+    assert_eq!(data.len(), 1);
+    assert_eq!(
+        &*data[0].path,
+        "/usr/share/keyrings/godsvagn-archive-keyring.pgp"
+    );
+    assert_eq!(&*data[0].contents, b"keyring");
+
+    let index = std::str::from_utf8(&file("main/binary-all/Packages").data).unwrap();
+    assert!(index.contains("Package: godsvagn-archive-keyring\n"));
+    let release = std::str::from_utf8(&file("Release").data).unwrap();
+    assert!(release.contains("Components: main contrib\n"));
+
+    // without a signer there's no keyring to package
+    let files = generate_files(&release_metadata(), None, &[], &options).unwrap();
+    assert!(
+        !files
+            .iter()
+            .any(|f| f.destination_path.starts_with("pool/"))
+    );
+}
+
+#[test]
+fn snapshot() {
+    let suites = [Suite {
+        metadata: ReleaseMetadata {
+            suite: "stable".to_owned(),
+            snapshots: Some("https://example.com/snapshots/@SNAPSHOTID@/".to_owned()),
+            ..release_metadata()
+        },
+        packages: &[described_package("hello", " x\n")],
+    }];
+    let options = GenerateOptions {
+        snapshot: Some("2024-06-01".to_owned()),
+        ..Default::default()
+    };
+    let files = generate_suites(&suites, None, &options).unwrap();
+    let file = |path: &str| {
+        let file = files.iter().find(|f| &*f.destination_path == path).unwrap();
+        String::from_utf8(file.data.to_vec()).unwrap()
+    };
+    assert_eq!(
+        file("dists/stable/main/binary-all/Packages"),
+        file("dists/stable/snapshots/2024-06-01/main/binary-all/Packages")
+    );
+    let release = file("dists/stable/Release");
+    assert!(release.contains("Suite: stable\n"));
+    assert!(release.contains("Snapshots: https://example.com/snapshots/@SNAPSHOTID@/\n"));
+    let snapshot = file("dists/stable/snapshots/2024-06-01/Release");
+    assert!(snapshot.contains("Suite: stable/snapshots/2024-06-01\n"));
+    assert_eq!(
+        release.split_once("MD5Sum:").unwrap().1,
+        snapshot.split_once("MD5Sum:").unwrap().1
+    );
+}
+
+#[test]
+fn changelogs() {
+    let gzip = |text: &[u8]| {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        std::io::Write::write_all(&mut encoder, text).unwrap();
+        Some(encoder.finish().unwrap().into_boxed_slice())
+    };
+    let mut libhello = described_package("libhello1", " x\n");
+    libhello
+        .fields
+        .insert("Source".into(), "libhello (1:2.0-1)".into());
+    libhello.changelog = gzip(b"libhello (1:2.0-1) unstable; urgency=medium\n");
+    // from the same source, so it has to share the first one's changelog
+    let mut dev = described_package("libhello-dev", " x\n");
+    dev.fields
+        .insert("Source".into(), "libhello (1:2.0-1)".into());
+    dev.changelog = gzip(b"something else");
+    let mut hello = described_package("hello", " x\n");
+    hello.fields.insert("Version".into(), "1.0".into());
+    hello.component = "contrib".into();
+    hello.changelog = gzip(b"hello (1.0) unstable; urgency=low\n");
+
+    let metadata = ReleaseMetadata {
+        changelogs: Some("https://example.com/changelogs/@CHANGEPATH@_changelog".to_owned()),
+        ..release_metadata()
+    };
+    let options = GenerateOptions {
+        changelogs: true,
+        ..Default::default()
+    };
+    let packages = [libhello, dev, hello];
+    let files = generate_files(&metadata, None, &packages, &options).unwrap();
+    let file = |path: &str| {
+        let file = files.iter().find(|f| &*f.destination_path == path).unwrap();
+        String::from_utf8(file.data.to_vec()).unwrap()
+    };
+    assert_eq!(
+        file("changelogs/main/libh/libhello/libhello_2.0-1_changelog"),
+        "libhello (1:2.0-1) unstable; urgency=medium\n"
+    );
+    assert_eq!(
+        file("changelogs/contrib/h/hello/hello_1.0_changelog"),
+        "hello (1.0) unstable; urgency=low\n"
+    );
+    assert_eq!(
+        files
+            .iter()
+            .filter(|f| f.destination_path.starts_with("changelogs/"))
+            .count(),
+        2
+    );
+    assert!(
+        file("Release")
+            .contains("Changelogs: https://example.com/changelogs/@CHANGEPATH@_changelog\n")
+    );
+
+    // no components to put in the path
+    let files = generate_flat(&metadata, None, &packages, &options).unwrap();
+    assert!(
+        !files
+            .iter()
+            .any(|f| f.destination_path.starts_with("changelogs/"))
+    );
+    let release = files.iter().find(|f| &*f.destination_path == "Release");
+    assert!(
+        std::str::from_utf8(&release.unwrap().data)
+            .unwrap()
+            .contains("Changelogs: no\n")
+    );
+
+    assert!(is_changelog_file(
+        "hello",
+        "/usr/share/doc/hello/changelog.Debian.gz"
+    ));
+    assert!(is_changelog_file(
+        "hello",
+        "/usr/share/doc/hello/changelog.gz"
+    ));
+    assert!(!is_changelog_file(
+        "hello",
+        "/usr/share/doc/hello-doc/changelog.gz"
+    ));
+    assert!(!is_changelog_file(
+        "hello",
+        "/usr/share/doc/hello/NEWS.Debian.gz"
+    ));
+}
+
+#[test]
+fn debian_installer() {
+    let mut udeb = described_package("hello-udeb", " x\n long\n");
+    udeb.architecture = "amd64".parse().unwrap();
+    udeb.meta.file.path = "pool/main/h/hello/hello-udeb_1.0_amd64.udeb".into();
+    let mut deb = described_package("hello", " y\n long\n");
+    deb.architecture = "amd64".parse().unwrap();
+    let options = GenerateOptions {
+        translations: true,
+        ..Default::default()
+    };
+    let files = generate_files(&release_metadata(), None, &[udeb, deb], &options).unwrap();
+    let file = |path: &str| {
+        let file = files.iter().find(|f| &*f.destination_path == path).unwrap();
+        String::from_utf8(file.data.to_vec()).unwrap()
+    };
+    let installer = file("main/debian-installer/binary-amd64/Packages");
+    assert!(installer.contains("Package: hello-udeb\n"));
+    assert!(installer.contains("Description: x\n long\n"));
+    assert!(!installer.contains("Package: hello\n"));
+    let regular = file("main/binary-amd64/Packages");
+    assert!(!regular.contains("hello-udeb"));
+    assert!(!file("main/i18n/Translation-en").contains("hello-udeb"));
+    let release = file("Release");
+    assert!(release.contains(" main/debian-installer/binary-amd64/Packages\n"));
+    assert!(release.contains("Components: main\n"));
+}
+
+#[test]
+fn arch_all_everywhere() {
+    let mut amd64 = described_package("hello", " x\n");
+    amd64.architecture = "amd64".parse().unwrap();
+    let mut arm64 = described_package("hello-arm", " x\n");
+    arm64.architecture = "arm64".parse().unwrap();
+    let packages = [described_package("hello-doc", " y\n"), amd64, arm64];
+    let index = |options: &GenerateOptions, path: &str| {
+        let files = generate_files(&release_metadata(), None, &packages, options).unwrap();
+        let file = files.iter().find(|f| &*f.destination_path == path).unwrap();
+        String::from_utf8(file.data.to_vec()).unwrap()
+    };
+
+    let options = GenerateOptions::default();
+    for arch in ["all", "amd64", "arm64"] {
+        let index = index(&options, &format!("main/binary-{arch}/Packages"));
+        assert!(index.contains("Package: hello-doc\n"), "{arch}");
+    }
+    assert!(!index(&options, "main/binary-arm64/Packages").contains("Package: hello\n"));
+
+    let options = GenerateOptions {
+        separate_arch_all: true,
+        ..Default::default()
+    };
+    assert!(index(&options, "main/binary-all/Packages").contains("Package: hello-doc\n"));
+    assert!(!index(&options, "main/binary-amd64/Packages").contains("Package: hello-doc\n"));
+}
+
+#[test]
+fn strict_multi_arch() {
+    let library = |arch: &str, version: &str| {
+        let mut package = described_package("libhello1", " x\n");
+        package.architecture = arch.parse().unwrap();
+        package.version = version.parse().unwrap();
+        package.known.multi_arch = Some(package::MultiArch::Same);
+        package
+    };
+    let options = GenerateOptions {
+        strict_multi_arch: true,
+        ..Default::default()
+    };
+    // an older version on one architecture doesn't matter as long as the newest ones agree
+    let matching = [
+        library("amd64", "1.0"),
+        library("arm64", "1.1"),
+        library("amd64", "1.1"),
+    ];
+    generate_files(&release_metadata(), None, &matching, &options).unwrap();
+
+    let skewed = [library("amd64", "1.1"), library("arm64", "1.0")];
+    let err = generate_files(&release_metadata(), None, &skewed, &options).unwrap_err();
+    assert_eq!(err.code(), "GV2014");
+    assert!(err.to_string().contains("1.1 on amd64, 1.0 on arm64"));
+    generate_files(
+        &release_metadata(),
+        None,
+        &skewed,
+        &GenerateOptions::default(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn streamed() {
+    let mut amd64 = described_package("zzz", " z\n");
+    amd64.architecture = "amd64".parse().unwrap();
+    let packages = [described_package("hello", " x\n long\n"), amd64];
+    let options = GenerateOptions {
+        translations: true,
+        ..Default::default()
+    };
+    let from_slice = generate_files(&release_metadata(), None, &packages, &options).unwrap();
+    let mut streamed = Vec::new();
+    let rows = packages.clone().map(Ok::<_, std::io::Error>);
+    generate_files_streamed(&release_metadata(), None, rows, &options, &mut streamed).unwrap();
+    assert_eq!(from_slice, streamed);
+
+    let rows = [
+        Ok(packages[0].clone()),
+        Err(std::io::Error::other("cursor closed")),
+    ];
+    let err = generate_files_streamed(&release_metadata(), None, rows, &options, &mut Vec::new())
+        .unwrap_err();
+    assert!(matches!(err, GenerateError::PackageSource(_)), "{err}");
+    assert_eq!(err.code(), "GV2013");
+}
+
+#[test]
+fn manifest() {
+    let packages = [described_package("hello", " x\n")];
+    let options = GenerateOptions {
+        manifest: true,
+        ..Default::default()
+    };
+    let files = generate_files(&release_metadata(), None, &packages, &options).unwrap();
+    let (manifest, generated) = files.split_last().unwrap();
+    assert_eq!(&*manifest.destination_path, "manifest.json");
+    let json = std::str::from_utf8(&manifest.data).unwrap();
+    assert!(json.starts_with("{\"files\":[\n{\"path\":"), "{json}");
+    assert!(json.ends_with("\n]}\n"), "{json}");
+    assert_eq!(json.lines().count(), generated.len() + 2);
+    for file in generated {
+        let path = format!("{{\"path\":\"{}\",", file.destination_path);
+        assert!(json.contains(&path), "{path} missing from {json}");
+    }
+    assert!(json.contains("\"path\":\"main/binary-all/Packages.gz\","));
+    assert!(json.contains("\"compression\":\"gz\""));
+    assert!(json.contains("\"path\":\"Release\",\"size\":"));
+
+    let mut written = Vec::new();
+    let mut recording = ManifestSink::new(&mut written);
+    let options = GenerateOptions::default();
+    generate_files_into(
+        &release_metadata(),
+        None,
+        &packages,
+        &options,
+        &mut recording,
+    )
+    .unwrap();
+    let (_, manifest) = recording.into_parts();
+    assert_eq!(manifest.files.len(), written.len());
+    for (entry, file) in manifest.files.iter().zip(&written) {
+        assert_eq!(entry.file.path, file.destination_path);
+        assert_eq!(entry.file.size, file.data.len());
+        let extension = file.destination_path.rsplit_once('.').map(|(_, ext)| ext);
+        let compressed = matches!(extension, Some("gz" | "xz"));
+        assert_eq!(entry.compression, extension.filter(|_| compressed));
+    }
+}
+
+#[test]
+fn sources() {
+    let packages = [described_package("hello", " x\n")];
+    let options = GenerateOptions {
+        sources: Some(SourcesOptions {
+            uri: "https://deb.example.com/".to_owned(),
+            signed_by: None,
+        }),
+        ..Default::default()
+    };
+    let stable = ReleaseMetadata {
+        suite: "stable".to_owned(),
+        origin: "Example Org".to_owned(),
+        ..release_metadata()
+    };
+    let testing = ReleaseMetadata {
+        suite: "testing".to_owned(),
+        ..stable.clone()
+    };
+    let suites = [
+        Suite {
+            metadata: stable,
+            packages: &packages,
+        },
+        Suite {
+            metadata: testing,
+            packages: &packages,
+        },
+    ];
+    let files = generate_suites(&suites, None, &options).unwrap();
+    let sources = files
+        .iter()
+        .find(|f| &*f.destination_path == "example-org.sources")
+        .unwrap();
+    assert_eq!(
+        std::str::from_utf8(&sources.data).unwrap(),
+        "Types: deb\n\
+         URIs: https://deb.example.com/\n\
+         Suites: stable\n\
+         Components: main\n\
+         Signed-By: /usr/share/keyrings/example-org-archive-keyring.pgp\n\
+         \n\
+         Types: deb\n\
+         URIs: https://deb.example.com/\n\
+         Suites: testing\n\
+         Components: main\n\
+         Signed-By: /usr/share/keyrings/example-org-archive-keyring.pgp\n\
+         Enabled: no\n"
+    );
+
+    let options = GenerateOptions {
+        sources: Some(SourcesOptions {
+            uri: "https://deb.example.com/flat/".to_owned(),
+            signed_by: Some("/etc/apt/keyrings/example.pgp".to_owned()),
+        }),
+        ..Default::default()
+    };
+    let files = generate_flat(&release_metadata(), None, &packages, &options).unwrap();
+    let sources = files
+        .iter()
+        .find(|f| &*f.destination_path == "godsvagn.sources")
+        .unwrap();
+    assert_eq!(
+        std::str::from_utf8(&sources.data).unwrap(),
+        "Types: deb\n\
+         URIs: https://deb.example.com/flat/\n\
+         Suites: ./\n\
+         Signed-By: /etc/apt/keyrings/example.pgp\n"
+    );
+    let files = generate_files(&release_metadata(), None, &packages, &Default::default()).unwrap();
+    assert!(
+        !files
+            .iter()
+            .any(|f| f.destination_path.ends_with(".sources"))
+    );
+}
@@ -0,0 +1,78 @@
+//! `<component>/i18n/Translation-en`, which holds the long descriptions split out of the Packages
+//! indexes. apt looks entries up by Description-md5, so packages sharing a description share one.
+
+use std::{borrow::Cow, collections::HashSet, fmt::Write};
+
+use base16ct::HexDisplay;
+use package::Package;
+use parsedeb::{ControlFields, Description, PackageName};
+
+pub(crate) fn translation_path(component: &str) -> String {
+    format!("{component}/i18n/Translation-en")
+}
+
+/// What a package puts in Translation-en
+pub(crate) struct Entry {
+    name: PackageName,
+    description_md5: [u8; 16],
+    description: String,
+}
+
+/// The Translation-en entry of `package`, if it has a description
+pub(crate) fn entry(package: &Package) -> Option<Entry> {
+    let description = Description::parse(package.raw_field("Description")?);
+    Some(Entry {
+        name: package.name.clone(),
+        description_md5: package.meta.description_md5,
+        description: description.to_string(),
+    })
+}
+
+/// The Translation-en index for `entries`, sorted by package name like Debian's
+pub(crate) fn generate_translation<'a>(
+    entries: impl Iterator<Item = &'a Entry>,
+) -> Result<String, std::fmt::Error> {
+    let mut entries: Vec<&Entry> = entries.collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut seen = HashSet::new();
+    let mut o = String::with_capacity(1024);
+    for entry in entries {
+        if !seen.insert(entry.description_md5) {
+            continue;
+        }
+        writeln!(o, "Package: {}", entry.name)?;
+        writeln!(
+            o,
+            "Description-md5: {:x}",
+            HexDisplay(&entry.description_md5)
+        )?;
+        writeln!(o, "Description-en: {}", entry.description)?;
+        o.push('\n');
+    }
+    Ok(o)
+}
+
+/// `package` with its Description cut down to the synopsis, for a Packages index whose long
+/// descriptions are in Translation-en. `with_md5` adds Description-md5 as a field, for writers
+/// that don't emit it themselves.
+pub(crate) fn synopsis_only(package: &Package, with_md5: bool) -> Cow<'_, Package> {
+    let Some(raw) = package.raw_field("Description") else {
+        return Cow::Borrowed(package);
+    };
+    let description = Description::parse(raw);
+    if description.extended.is_empty() && !with_md5 {
+        return Cow::Borrowed(package);
+    }
+    let mut package = package.clone();
+    for (key, value) in &mut package.fields {
+        if key.eq_ignore_ascii_case("Description") {
+            *value = format!(" {}\n", description.synopsis).into();
+        }
+    }
+    if with_md5 {
+        let md5 = format!(" {:x}\n", HexDisplay(&package.meta.description_md5));
+        package.fields.insert("Description-md5".into(), md5.into());
+    }
+    Cow::Owned(package)
+}
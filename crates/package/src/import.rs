@@ -0,0 +1,198 @@
+//! Reading packages back out of a published repository, for importing or mirroring one, or for
+//! regenerating a repository without the original .debs at hand.
+
+use filemeta::{FileMeta, FileSums};
+use indexmap::IndexMap;
+use parsedeb::{
+    ControlFields, PackageKind, RequiredFields, parse_control, parse_stanzas, strip_signature,
+};
+
+use crate::{KnownFields, Package, PackageMeta, description_md5};
+
+/// Fields written from [`PackageMeta`] rather than the package's control file
+const META_FIELDS: [&str; 7] = [
+    "Filename",
+    "Size",
+    "Description-md5",
+    "MD5sum",
+    "SHA1",
+    "SHA256",
+    "SHA512",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("{0}")]
+    Parse(#[from] parsedeb::Error),
+    #[error("index stanza is missing `{0}`")]
+    MissingField(&'static str),
+    #[error("`{field}` has an invalid value `{value}`")]
+    InvalidField { field: &'static str, value: String },
+    #[error("`{0}` is not listed in the Release file")]
+    NotInRelease(String),
+    #[error("`{0}` does not match its Release checksum")]
+    ChecksumMismatch(String),
+}
+
+impl ImportError {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Parse(e) => e.code(),
+            Self::MissingField(_) => "GV6001",
+            Self::InvalidField { .. } => "GV6002",
+            Self::NotInRelease(_) => "GV6003",
+            Self::ChecksumMismatch(_) => "GV6004",
+        }
+    }
+}
+
+/// The packages in a `Packages` index, checked against the `SHA256` list of the `Release` or
+/// `InRelease` file it was published with. `path` is where the index sits relative to the
+/// Release file, like `main/binary-amd64/Packages`, whose first directory is taken as the
+/// packages' component. InRelease signatures are stripped, not verified.
+pub fn packages_from_index(
+    release: &str,
+    path: &str,
+    packages: &str,
+) -> Result<Vec<Package>, ImportError> {
+    check_release_sum(release, path, packages.as_bytes())?;
+    let component = path.split('/').next().unwrap_or_default();
+    parse_stanzas(packages)
+        .map_err(parsedeb::Error::from)?
+        .into_iter()
+        .map(|stanza| {
+            let fields = stanza.into_iter().map(parsedeb::pack).collect();
+            package_from_stanza(fields, component)
+        })
+        .collect()
+}
+
+impl Package {
+    /// Read back a stanza [`Package::write_into_packages`] wrote, or any other stanza of a
+    /// Packages index. The component is the directory under `pool/` the package is in, or
+    /// `main` if it isn't in one.
+    pub fn parse_stanza(stanza: &str) -> Result<Self, ImportError> {
+        // written without the line break that ends it, or with the blank line after it
+        let stanza = format!("{}\n", stanza.trim_end());
+        let fields: IndexMap<Box<str>, Box<str>> = parse_control(&stanza)
+            .map_err(parsedeb::Error::from)?
+            .into_iter()
+            .map(parsedeb::pack)
+            .collect();
+        let component = fields
+            .field("Filename")
+            .and_then(|path| path.strip_prefix("pool/"))
+            .and_then(|path| Some(path.split_once('/')?.0))
+            .unwrap_or("main")
+            .to_owned();
+        package_from_stanza(fields, &component)
+    }
+}
+
+fn check_release_sum(release: &str, path: &str, contents: &[u8]) -> Result<(), ImportError> {
+    let release = strip_signature(release)?;
+    let fields = parse_control(&release).map_err(parsedeb::Error::from)?;
+    let listed = fields
+        .field("SHA256")
+        .unwrap_or_default()
+        .lines()
+        .find_map(|line| {
+            let mut parts = line.split_whitespace();
+            let (hash, size, listed_path) = (parts.next()?, parts.next()?, parts.next()?);
+            (listed_path == path).then_some((hash, size))
+        })
+        .ok_or_else(|| ImportError::NotInRelease(path.to_owned()))?;
+
+    // reading from memory can't fail
+    let sha256 = filemeta::sha256(contents).map_err(parsedeb::Error::from)?;
+    let expected = decode_hex::<32>("SHA256", listed.0)?;
+    if listed.1.parse() != Ok(contents.len()) || expected != sha256 {
+        return Err(ImportError::ChecksumMismatch(path.to_owned()));
+    }
+    Ok(())
+}
+
+fn package_from_stanza(
+    mut fields: IndexMap<Box<str>, Box<str>>,
+    component: &str,
+) -> Result<Package, ImportError> {
+    let mut meta_field = |name: &'static str| {
+        let index = fields
+            .keys()
+            .position(|key| key.eq_ignore_ascii_case(name))?;
+        fields
+            .shift_remove_index(index)
+            .map(|(_, value)| value.trim().to_owned())
+    };
+    let mut meta = IndexMap::new();
+    for name in META_FIELDS {
+        if let Some(value) = meta_field(name) {
+            meta.insert(name, value);
+        }
+    }
+    let required = |name: &'static str| meta.get(name).ok_or(ImportError::MissingField(name));
+
+    let path: Box<str> = required("Filename")?.as_str().into();
+    let kind = PackageKind::from_file_name(&path).unwrap_or_default();
+    let size = required("Size")?;
+    let size = size.parse().map_err(|_| ImportError::InvalidField {
+        field: "Size",
+        value: size.clone(),
+    })?;
+    // only SHA256 is needed, since the others can be left out when they're generated
+    let sums = FileSums {
+        md5: optional_hex(&meta, "MD5sum")?,
+        sha1: optional_hex(&meta, "SHA1")?,
+        sha256: Some(decode_hex("SHA256", required("SHA256")?)?),
+        sha512: optional_hex(&meta, "SHA512")?,
+        blake2b: None,
+    };
+    // only written when long descriptions are split out, otherwise worked out the same way
+    // repogen does
+    let description_md5 = match meta.get("Description-md5") {
+        Some(md5) => decode_hex("Description-md5", md5)?,
+        None => description_md5(&fields),
+    };
+
+    let RequiredFields {
+        package,
+        version,
+        architecture,
+        ..
+    } = RequiredFields::from_map_for(&fields, kind)?;
+    Ok(Package {
+        meta: PackageMeta {
+            file: FileMeta { path, size, sums },
+            description_md5,
+        },
+        name: package,
+        architecture,
+        version,
+        component: component.into(),
+        known: KnownFields::from_fields(&fields)?,
+        fields,
+        appstream: Vec::new(),
+        changelog: None,
+    })
+}
+
+fn decode_hex<const N: usize>(field: &'static str, hex: &str) -> Result<[u8; N], ImportError> {
+    let mut out = [0; N];
+    match base16ct::mixed::decode(hex, &mut out) {
+        Ok(decoded) if decoded.len() == N => Ok(out),
+        _ => Err(ImportError::InvalidField {
+            field,
+            value: hex.to_owned(),
+        }),
+    }
+}
+
+fn optional_hex<const N: usize>(
+    meta: &IndexMap<&str, String>,
+    field: &'static str,
+) -> Result<Option<[u8; N]>, ImportError> {
+    meta.get(field)
+        .map(|hex| decode_hex(field, hex))
+        .transpose()
+}
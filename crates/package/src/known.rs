@@ -0,0 +1,188 @@
+//! The fields policy gives a meaning to that are worth checking and querying, parsed once when
+//! a package is read instead of every time something looks at them
+
+use std::{fmt::Display, str::FromStr};
+
+use parsedeb::{ControlFields, Relation, Warning};
+
+/// Section, Priority, Installed-Size, Homepage, Depends and Multi-Arch, each `None` or empty if
+/// the package doesn't have it. Sections can be any single word, since archives make up their own, but
+/// priorities have to be one of policy's. The package's `fields` still hold them as they were
+/// written, so indexes keep them where the package put them.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct KnownFields {
+    pub section: Option<Section>,
+    pub priority: Option<Priority>,
+    /// in KiB, as an estimate of what the package takes up once installed
+    pub installed_size: Option<u64>,
+    pub homepage: Option<Box<str>>,
+    pub depends: Vec<Relation>,
+    pub multi_arch: Option<MultiArch>,
+}
+
+impl KnownFields {
+    pub fn from_fields(fields: &impl ControlFields) -> Result<Self, parsedeb::Error> {
+        let invalid = parsedeb::Error::InvalidFieldValue;
+        let section = fields.field("Section").map(|section| {
+            section
+                .parse()
+                .map_err(|()| invalid(Warning::UnknownSection(section.into())))
+        });
+        let priority = fields.field("Priority").map(|priority| {
+            priority
+                .parse()
+                .map_err(|()| invalid(Warning::UnknownPriority(priority.into())))
+        });
+        let installed_size = fields.field("Installed-Size").map(|size| {
+            size.parse()
+                .map_err(|_| parsedeb::Error::InvalidInstalledSize(size.to_owned()))
+        });
+        let multi_arch = match fields.field("Multi-Arch") {
+            Some(value) => {
+                let multi_arch = value
+                    .parse()
+                    .map_err(|()| invalid(Warning::UnknownMultiArch(value.into())))?;
+                if multi_arch == MultiArch::Same && fields.field("Architecture") == Some("all") {
+                    return Err(invalid(Warning::MultiArchSameForAll));
+                }
+                Some(multi_arch)
+            }
+            None => None,
+        };
+        Ok(Self {
+            section: section.transpose()?,
+            priority: priority.transpose()?,
+            installed_size: installed_size.transpose()?,
+            homepage: fields.field("Homepage").map(Into::into),
+            depends: (fields.field("Depends").map(parsedeb::parse_relations))
+                .transpose()?
+                .unwrap_or_default(),
+            multi_arch,
+        })
+    }
+}
+
+/// A section like `libs`, or `contrib/libs` for one outside of `main`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Section {
+    /// the archive area in front of the section, if there is one
+    pub area: Option<Box<str>>,
+    pub name: Box<str>,
+}
+
+impl FromStr for Section {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        let valid = |part: &str| !part.is_empty() && !part.contains(char::is_whitespace);
+        match value.split_once('/') {
+            Some((area, name)) if valid(area) && valid(name) && !name.contains('/') => Ok(Self {
+                area: Some(area.into()),
+                name: name.into(),
+            }),
+            None if valid(value) => Ok(Self {
+                area: None,
+                name: value.into(),
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(area) = &self.area {
+            write!(f, "{area}/")?;
+        }
+        f.write_str(&self.name)
+    }
+}
+
+/// The priorities from policy §2.5, most important first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    Required,
+    Important,
+    Standard,
+    Optional,
+    /// deprecated in favour of [`Optional`](Self::Optional), but still common
+    Extra,
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Required => "required",
+            Self::Important => "important",
+            Self::Standard => "standard",
+            Self::Optional => "optional",
+            Self::Extra => "extra",
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        [
+            Self::Required,
+            Self::Important,
+            Self::Standard,
+            Self::Optional,
+            Self::Extra,
+        ]
+        .into_iter()
+        .find(|priority| priority.as_str() == value)
+        .ok_or(())
+    }
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How a package can be installed alongside packages of other architectures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MultiArch {
+    /// only satisfies dependencies of packages of its own architecture, like leaving the field
+    /// out
+    No,
+    /// can be installed for several architectures at once, as long as it's the same version on
+    /// each
+    Same,
+    /// satisfies dependencies of packages of any architecture
+    Foreign,
+    /// satisfies dependencies of any architecture that ask for it with `:any`
+    Allowed,
+}
+
+impl MultiArch {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::No => "no",
+            Self::Same => "same",
+            Self::Foreign => "foreign",
+            Self::Allowed => "allowed",
+        }
+    }
+}
+
+impl FromStr for MultiArch {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        [Self::No, Self::Same, Self::Foreign, Self::Allowed]
+            .into_iter()
+            .find(|multi_arch| multi_arch.as_str() == value)
+            .ok_or(())
+    }
+}
+
+impl Display for MultiArch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
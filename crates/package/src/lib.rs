@@ -1,5 +1,90 @@
-use filemeta::FileMeta;
+use std::{borrow::Cow, cmp::Reverse, fmt::Write};
+
+use filemeta::{DigestKind, FileMeta};
 use indexmap::IndexMap;
+use md5::{Digest, Md5};
+use parsedeb::{
+    Architecture, ControlFields, DebianVersion, Description, PackageKind, PackageName,
+    SourcePackage, Version,
+};
+
+mod import;
+mod known;
+mod retention;
+#[cfg(test)]
+mod tests;
+
+pub use import::{ImportError, packages_from_index};
+pub use known::{KnownFields, MultiArch, Priority, Section};
+pub use retention::{Retention, keep_latest};
+
+/// The digests Packages has fields for, which are all apt checks. Any others the file was
+/// hashed with are left out.
+const DIGEST_FIELDS: [(&str, DigestKind); 4] = [
+    ("MD5sum", DigestKind::Md5),
+    ("SHA1", DigestKind::Sha1),
+    ("SHA256", DigestKind::Sha256),
+    ("SHA512", DigestKind::Sha512),
+];
+
+/// The field order apt-ftparchive rewrites package stanzas into (apt's `TFRewritePackageOrder`).
+/// Fields not listed here keep their relative order and go after all of these.
+const APT_FTPARCHIVE_FIELD_ORDER: &[&str] = &[
+    "Package",
+    "Package-Type",
+    "Architecture",
+    "Subarchitecture",
+    "Version",
+    "Revision",
+    "Package-Revision",
+    "Package_Revision",
+    "Kernel-Version",
+    "Built-Using",
+    "Static-Built-Using",
+    "Built-For-Profiles",
+    "Auto-Built-Package",
+    "Multi-Arch",
+    "Status",
+    "Priority",
+    "Class",
+    "Essential",
+    "Protected",
+    "Installer-Menu-Item",
+    "Section",
+    "Source",
+    "Origin",
+    "Maintainer",
+    "Original-Maintainer",
+    "Bugs",
+    "Config-Version",
+    "Conffiles",
+    "Triggers-Awaited",
+    "Triggers-Pending",
+    "Installed-Size",
+    "Provides",
+    "Pre-Depends",
+    "Depends",
+    "Recommends",
+    "Recommended",
+    "Suggests",
+    "Optional",
+    "Conflicts",
+    "Breaks",
+    "Replaces",
+    "Enhances",
+    "Filename",
+    "MSDOS-Filename",
+    "Size",
+    "MD5sum",
+    "SHA1",
+    "SHA256",
+    "SHA512",
+    "Homepage",
+    "Description",
+    "Description-md5",
+    "Tag",
+    "Task",
+];
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PackageMeta {
@@ -11,36 +96,172 @@ impl PackageMeta {
     fn serialize(&self, f: &mut dyn std::fmt::Write) -> std::fmt::Result {
         writeln!(f, "Filename: {}", self.file.path)?;
         writeln!(f, "Size: {}", self.file.size)?;
-        writeln!(
+        write!(
             f,
             "Description-md5: {:x}",
             base16ct::HexDisplay(&self.description_md5)
         )?;
-        let sums = &self.file.sums;
-        writeln!(f, "MD5sum: {:x}", base16ct::HexDisplay(&sums.md5))?;
-        writeln!(f, "SHA1: {:x}", base16ct::HexDisplay(&sums.sha1))?;
-        write!(f, "SHA256: {:x}", base16ct::HexDisplay(&sums.sha256))?;
+        for (name, digest) in self.digest_fields() {
+            write!(f, "\n{name}: {:x}", base16ct::HexDisplay(digest))?;
+        }
         Ok(())
     }
+
+    /// The digests of the file that there are fields for in Packages, with the name of each
+    fn digest_fields(&self) -> impl Iterator<Item = (&'static str, &[u8])> {
+        DIGEST_FIELDS
+            .into_iter()
+            .filter_map(|(name, kind)| Some((name, self.file.sums.get(kind)?)))
+    }
+
+    /// apt-ftparchive only emits Description-md5 when splitting out long descriptions,
+    /// which it does not do by default
+    fn apt_ftparchive_fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("Filename", self.file.path.to_string()),
+            ("Size", self.file.size.to_string()),
+        ];
+        for (name, digest) in self.digest_fields() {
+            fields.push((name, format!("{:x}", base16ct::HexDisplay(digest))));
+        }
+        fields
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Package {
     pub meta: PackageMeta,
-    pub name: Box<str>,
-    pub architecture: Box<str>,
-    pub version: Box<str>,
+    pub name: PackageName,
+    pub architecture: Architecture,
+    pub version: Version,
+    /// The archive area the package is published in, like `main` or `contrib`
+    pub component: Box<str>,
     pub fields: IndexMap<Box<str>, Box<str>>,
+    /// The fields of `fields` with a meaning worth checking, parsed from it
+    pub known: KnownFields,
+    /// AppStream metadata, desktop entries and icons from the package's data tarball, for
+    /// DEP-11. Empty unless they were asked for when the deb was read.
+    pub appstream: Vec<parsedeb::DataFile>,
+    /// The gzipped changelog the package installs under `/usr/share/doc/`, if it was asked for
+    /// when the deb was read and the package has one
+    pub changelog: Option<Box<[u8]>>,
+}
+
+impl ControlFields for Package {
+    fn raw_field(&self, name: &str) -> Option<&str> {
+        self.fields.raw_field(name)
+    }
+}
+
+/// Where a package goes in an index: by name, newest version first, then by architecture and
+/// finally by pool path, so indexes come out the same whatever order the packages were read in
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexOrder {
+    name: PackageName,
+    version: Reverse<DebianVersion>,
+    architecture: Architecture,
+    path: Box<str>,
 }
 
 impl Package {
+    pub fn index_order(&self) -> IndexOrder {
+        IndexOrder {
+            name: self.name.clone(),
+            version: Reverse(self.version.parsed().clone()),
+            architecture: self.architecture.clone(),
+            path: self.meta.file.path.clone(),
+        }
+    }
+
+    /// Whether this is a regular package or a udeb, going by its file name in the pool
+    pub fn kind(&self) -> PackageKind {
+        PackageKind::from_file_name(&self.meta.file.path).unwrap_or_default()
+    }
+
     pub fn write_into_packages(&self, target: &mut String) -> std::fmt::Result {
-        for field in self.fields.iter() {
-            target.push_str(field.0);
+        for (key, value) in self.fields.iter() {
+            target.push_str(key);
             target.push_str(": ");
-            target.push_str(field.1.trim());
+            target.push_str(&index_value(key, value));
             target.push('\n');
         }
         self.meta.serialize(target)
     }
+
+    /// Same contract as [`Package::write_into_packages`], but with fields ordered and
+    /// selected the way apt-ftparchive writes them.
+    pub fn write_into_packages_apt_ftparchive(&self, target: &mut String) -> std::fmt::Result {
+        let meta_fields = self.meta.apt_ftparchive_fields();
+        let mut fields: Vec<(&str, Cow<str>)> = self
+            .fields
+            .iter()
+            .map(|(k, v)| (&**k, index_value(k, v)))
+            .chain(
+                meta_fields
+                    .iter()
+                    .map(|(k, v)| (*k, Cow::Borrowed(v.as_str()))),
+            )
+            .collect();
+        // stable sort, so unknown fields stay in their original order
+        fields.sort_by_key(|(key, _)| {
+            APT_FTPARCHIVE_FIELD_ORDER
+                .iter()
+                .position(|known| known.eq_ignore_ascii_case(key))
+                .unwrap_or(APT_FTPARCHIVE_FIELD_ORDER.len())
+        });
+
+        for (i, (key, value)) in fields.iter().enumerate() {
+            if i != 0 {
+                target.push('\n');
+            }
+            write!(target, "{key}: {value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The Description-md5 apt expects: the MD5 of the Description value as it appears in the
+/// index, starting after the space that follows the colon
+pub fn description_md5<K: AsRef<str>, V: AsRef<str>>(fields: &IndexMap<K, V>) -> [u8; 16] {
+    fields
+        .raw_field("Description")
+        .and_then(|v| v.get(1..))
+        .map(|v| Md5::new().chain_update(v).finalize())
+        .unwrap_or_else(|| Md5::new().finalize())
+        .into()
+}
+
+/// Where a package goes in the pool: the directory of the source it was built from in
+/// `component`, like `pool/main/h/hello/` or `pool/main/libf/libfoo/`, then
+/// `name_version_architecture.deb`, with the epoch left out of the version like dak does
+pub fn pool_path(
+    fields: &impl ControlFields,
+    component: &str,
+    kind: PackageKind,
+) -> Result<String, parsedeb::Error> {
+    let field = |name| {
+        fields
+            .field(name)
+            .ok_or(parsedeb::Error::MissingField(name))
+    };
+    let name: PackageName = field("Package")?.parse()?;
+    let version: Version = field("Version")?.parse()?;
+    let architecture: Architecture = field("Architecture")?.parse()?;
+    let version = version.as_str();
+    let version = version.split_once(':').map_or(version, |(_, rest)| rest);
+    Ok(format!(
+        "{}{name}_{version}_{architecture}.{}",
+        SourcePackage::of(fields)?.pool_directory(component),
+        kind.extension()
+    ))
+}
+
+/// A field value as it goes into an index: Description is refolded into its canonical form,
+/// anything else just loses its surrounding whitespace
+fn index_value<'a>(key: &str, value: &'a str) -> Cow<'a, str> {
+    if key.eq_ignore_ascii_case("Description") {
+        Cow::Owned(Description::parse(value).to_string())
+    } else {
+        Cow::Borrowed(value.trim())
+    }
 }
@@ -0,0 +1,61 @@
+//! Which old versions of packages to drop once newer ones are published, so every place that
+//! cleans up a repository agrees on what "the latest N" are
+
+use std::collections::HashMap;
+
+use crate::Package;
+
+/// The packages [`keep_latest`] split up, each in the order they were given in
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Retention<'a> {
+    pub keep: Vec<&'a Package>,
+    pub drop: Vec<&'a Package>,
+}
+
+/// Keep the `latest` newest versions of each package on each architecture, going by dpkg's
+/// version order, and drop the rest. Every package with one of those versions is kept, so the
+/// same version published in two components counts once. With `latest` 0, everything is dropped.
+pub fn keep_latest<'a>(
+    packages: impl IntoIterator<Item = &'a Package>,
+    latest: usize,
+) -> Retention<'a> {
+    let packages: Vec<&Package> = packages.into_iter().collect();
+    let mut groups: HashMap<_, Vec<usize>> = HashMap::new();
+    for (i, package) in packages.iter().enumerate() {
+        groups
+            .entry((&package.name, &package.architecture))
+            .or_default()
+            .push(i);
+    }
+
+    let mut kept = vec![false; packages.len()];
+    for mut group in groups.into_values() {
+        group.sort_by(|a, b| {
+            let version = |i: &usize| packages[*i].version.parsed();
+            version(b).cmp(version(a))
+        });
+        let mut versions = 0;
+        let mut previous = None;
+        for i in group {
+            let version = packages[i].version.parsed();
+            if previous != Some(version) {
+                versions += 1;
+                previous = Some(version);
+            }
+            if versions > latest {
+                break;
+            }
+            kept[i] = true;
+        }
+    }
+
+    let mut retention = Retention::default();
+    for (package, kept) in packages.into_iter().zip(kept) {
+        if kept {
+            retention.keep.push(package);
+        } else {
+            retention.drop.push(package);
+        }
+    }
+    retention
+}
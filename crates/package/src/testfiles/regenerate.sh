@@ -0,0 +1,79 @@
+#!/bin/sh
+# Regenerates the fixture deb and the golden files the apt-ftparchive compatibility tests in
+# package and indexgen compare against. Needs dpkg-deb, gzip, xz, and either apt-ftparchive
+# (apt-utils) or python3-apt. Without apt-ftparchive, Release is written in its layout by hand,
+# with the checksums of the real indexes, so regenerate it with apt-ftparchive when there is one.
+set -eu
+here=$(cd "$(dirname "$0")" && pwd)
+indexgen=$here/../../../indexgen/src/testfiles
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+
+# the fixture: a real deb, built reproducibly
+mkdir -p "$work/hello/DEBIAN" "$work/hello/usr/share/doc/hello"
+cp "$here/hello.control" "$work/hello/DEBIAN/control"
+printf 'hello, world\n' > "$work/hello/usr/share/doc/hello/README"
+SOURCE_DATE_EPOCH=1751716800 dpkg-deb --root-owner-group -Zxz --build "$work/hello" \
+    "$here/hello_2.10-3_amd64.deb" > /dev/null
+
+pool=pool/main/h/hello
+mkdir -p "$work/repo/$pool"
+cp "$here/hello_2.10-3_amd64.deb" "$work/repo/$pool/"
+cd "$work/repo"
+if command -v apt-ftparchive > /dev/null; then
+    apt-ftparchive packages pool > "$work/Packages"
+else
+    # the rewrite apt-ftparchive does itself, through libapt-pkg: the control file with the
+    # pool fields added, in apt's package field order
+    /usr/bin/python3 - "$pool/hello_2.10-3_amd64.deb" > "$work/Packages" <<'PY'
+import hashlib, os, subprocess, sys
+import apt_pkg
+
+path = sys.argv[1]
+data = open(path, "rb").read()
+control = subprocess.run(["dpkg-deb", "-f", path], check=True, capture_output=True).stdout
+rewrite = [apt_pkg.TagRewrite("Filename", path), apt_pkg.TagRewrite("Size", str(len(data)))]
+for name, digest in [("MD5sum", "md5"), ("SHA1", "sha1"), ("SHA256", "sha256"), ("SHA512", "sha512")]:
+    rewrite.append(apt_pkg.TagRewrite(name, hashlib.new(digest, data).hexdigest()))
+sys.stdout.flush()
+apt_pkg.TagSection(control.decode()).write(sys.stdout, apt_pkg.REWRITE_PACKAGE_ORDER, rewrite)
+print()
+PY
+fi
+# the stanza, without the blank line ending it
+sed '/^$/d' "$work/Packages" > "$here/hello.apt-ftparchive"
+
+dists=dists/testing/main/binary-amd64
+mkdir -p "$dists"
+cp "$work/Packages" "$dists/Packages"
+gzip -9n < "$dists/Packages" > "$dists/Packages.gz"
+xz -6 < "$dists/Packages" > "$dists/Packages.xz"
+cp "$dists/Packages" "$dists/Packages.gz" "$dists/Packages.xz" "$indexgen/"
+if command -v apt-ftparchive > /dev/null; then
+apt-ftparchive -o APT::FTPArchive::Release::Origin=godsvagn \
+    -o APT::FTPArchive::Release::Label=godsvagn \
+    -o APT::FTPArchive::Release::Suite=testing \
+    -o APT::FTPArchive::Release::Version=0.1 \
+    -o APT::FTPArchive::Release::Codename=salolampi \
+    -o APT::FTPArchive::Release::Architectures=amd64 \
+    -o APT::FTPArchive::Release::Components=main \
+    release dists/testing > "$work/Release"
+else
+    # apt-ftparchive's layout, with the checksums of the indexes above
+    {
+        printf 'Origin: godsvagn\nLabel: godsvagn\nSuite: testing\nVersion: 0.1\n'
+        printf 'Codename: salolampi\nDate: %s\n' "$(LC_ALL=C date -u -R)"
+        printf 'Architectures: amd64\nComponents: main\n'
+        cd dists/testing
+        for sum in MD5Sum:md5sum SHA1:sha1sum SHA256:sha256sum SHA512:sha512sum; do
+            echo "${sum%%:*}:"
+            for file in main/binary-amd64/Packages main/binary-amd64/Packages.gz \
+                main/binary-amd64/Packages.xz; do
+                printf ' %s %16s %s\n' "$(${sum#*:} < "$file" | cut -d' ' -f1)" \
+                    "$(wc -c < "$file")" "$file"
+            done
+        done
+    } > "$work/Release"
+fi
+# apt-ftparchive dates Release with when it ran, the test with a fixed date
+sed 's/^Date: .*/Date: Sat, 05 Jul 2025 12:00:00 UTC/' "$work/Release" > "$indexgen/Release.apt-ftparchive"
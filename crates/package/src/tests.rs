@@ -0,0 +1,218 @@
+use filemeta::FileSums;
+
+use super::*;
+
+fn hello() -> Package {
+    let fields = [
+        ("Package", " hello\n"),
+        ("Version", " 2.10-3\n"),
+        ("Architecture", " amd64\n"),
+        ("Maintainer", " Santiago Vila <sanvila@debian.org>\n"),
+        ("Installed-Size", " 280\n"),
+        ("Depends", " libc6 (>= 2.34)\n"),
+        ("X-Custom-Field", " kept last\n"),
+        ("Section", " devel\n"),
+        ("Priority", " optional\n"),
+        ("Homepage", " https://www.gnu.org/software/hello/\n"),
+        (
+            "Description",
+            " example package based on GNU hello\n The GNU hello program produces a familiar, friendly greeting.\n",
+        ),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.into(), v.into()))
+    .collect();
+    Package {
+        meta: PackageMeta {
+            file: FileMeta {
+                path: "pool/main/hello_2.10-3_amd64.deb".into(),
+                size: 5,
+                sums: FileSums::new(&b"hello"[..]).unwrap(),
+            },
+            description_md5: [0; 16],
+        },
+        name: "hello".parse().unwrap(),
+        architecture: "amd64".parse().unwrap(),
+        version: "2.10-3".parse().unwrap(),
+        component: "main".into(),
+        appstream: Vec::new(),
+        changelog: None,
+        known: KnownFields::from_fields(&fields).unwrap(),
+        fields,
+    }
+}
+
+/// The golden file is what apt-ftparchive writes for the fixture deb, see
+/// `testfiles/regenerate.sh`
+#[test]
+fn apt_ftparchive_order() {
+    let deb = include_bytes!("testfiles/hello_2.10-3_amd64.deb");
+    let mut package = hello();
+    (package.fields, _) = parsedeb::deb_to_control(&deb[..]).unwrap();
+    package.meta.file =
+        FileMeta::new("pool/main/h/hello/hello_2.10-3_amd64.deb".into(), deb).unwrap();
+    let mut out = String::new();
+    package
+        .write_into_packages_apt_ftparchive(&mut out)
+        .unwrap();
+    out.push('\n');
+    assert_eq!(include_str!("testfiles/hello.apt-ftparchive"), out);
+}
+
+#[test]
+fn description_refolded() {
+    let mut package = hello();
+    package.fields["Description"] = " greets you\n\tfirst\n\n .  \n second\n".into();
+    let mut out = String::new();
+    package.write_into_packages(&mut out).unwrap();
+    assert!(out.contains("Description: greets you\n first\n .\n .\n second\n"));
+}
+
+#[test]
+fn import_from_index() {
+    let mut package = hello();
+    package.meta.description_md5 = description_md5(&package.fields);
+    let mut index = String::new();
+    package.write_into_packages(&mut index).unwrap();
+    index.push_str("\n\n");
+
+    let sha256 = filemeta::sha256(index.as_bytes()).unwrap();
+    let release = format!(
+        "Origin: test\nSHA256:\n {:x} {} main/binary-amd64/Packages\n",
+        base16ct::HexDisplay(&sha256),
+        index.len()
+    );
+    let imported = packages_from_index(&release, "main/binary-amd64/Packages", &index).unwrap();
+    assert_eq!(imported.len(), 1);
+    let imported = &imported[0];
+    assert_eq!(imported.meta, package.meta);
+    assert_eq!(imported.name, package.name);
+    assert_eq!(imported.version, package.version);
+    assert!(imported.fields.keys().eq(package.fields.keys()));
+
+    // writing it out again gives the same index
+    let mut rewritten = String::new();
+    imported.write_into_packages(&mut rewritten).unwrap();
+    rewritten.push_str("\n\n");
+    assert_eq!(rewritten, index);
+
+    let err = packages_from_index(&release, "main/binary-arm64/Packages", &index).unwrap_err();
+    assert_eq!(err.code(), "GV6003");
+    let tampered = index.replace("friendly", "hostile!");
+    let err = packages_from_index(&release, "main/binary-amd64/Packages", &tampered).unwrap_err();
+    assert_eq!(err.code(), "GV6004");
+}
+
+#[test]
+fn retention() {
+    let version = |version: &str, architecture: &str, component: &str| {
+        let mut package = hello();
+        package.version = version.parse().unwrap();
+        package.architecture = architecture.parse().unwrap();
+        package.component = component.into();
+        package
+    };
+    let packages = [
+        version("2.10-3", "amd64", "main"),
+        version("2.10-10", "amd64", "main"),
+        version("2.9", "amd64", "main"),
+        version("1:1.0", "amd64", "main"),
+        version("2.10-10", "amd64", "contrib"),
+        version("2.9", "arm64", "main"),
+    ];
+    let retention = keep_latest(&packages, 2);
+    let kept: Vec<_> = retention
+        .keep
+        .iter()
+        .map(|p| (p.version.as_str(), p.architecture.as_str()))
+        .collect();
+    // the epoch makes 1:1.0 the newest, and 2.10-10 is in both components
+    assert_eq!(
+        kept,
+        [
+            ("2.10-10", "amd64"),
+            ("1:1.0", "amd64"),
+            ("2.10-10", "amd64"),
+            ("2.9", "arm64")
+        ]
+    );
+    assert_eq!(retention.drop.len(), 2);
+    assert!(keep_latest(&packages, 0).keep.is_empty());
+}
+
+#[test]
+fn pool_paths() {
+    let mut package = hello();
+    assert_eq!(
+        pool_path(&package, "main", PackageKind::Deb).unwrap(),
+        "pool/main/h/hello/hello_2.10-3_amd64.deb"
+    );
+    package.fields["Version"] = " 1:2.10-3+b1".into();
+    package
+        .fields
+        .insert("Source".into(), " libhello (1:2.10-3)".into());
+    assert_eq!(
+        pool_path(&package, "contrib", PackageKind::Udeb).unwrap(),
+        "pool/contrib/libh/libhello/hello_2.10-3+b1_amd64.udeb"
+    );
+    package.fields.shift_remove("Architecture");
+    assert!(pool_path(&package, "main", PackageKind::Deb).is_err());
+}
+
+#[test]
+fn known_fields() {
+    let known = hello().known;
+    assert_eq!(known.section.unwrap().to_string(), "devel");
+    assert_eq!(known.priority, Some(Priority::Optional));
+    assert_eq!(known.installed_size, Some(280));
+    assert_eq!(
+        known.homepage.as_deref(),
+        Some("https://www.gnu.org/software/hello/")
+    );
+    assert_eq!(&*known.depends[0].alternatives[0].name, "libc6");
+
+    let mut fields = hello().fields;
+    fields["Section"] = " contrib/libs\n".into();
+    let section = KnownFields::from_fields(&fields).unwrap().section.unwrap();
+    assert_eq!(section.area.as_deref(), Some("contrib"));
+    assert_eq!(&*section.name, "libs");
+
+    fields.insert("Multi-Arch".into(), " same\n".into());
+    let known = KnownFields::from_fields(&fields).unwrap();
+    assert_eq!(known.multi_arch, Some(MultiArch::Same));
+    fields["Architecture"] = " all\n".into();
+    let err = KnownFields::from_fields(&fields).unwrap_err();
+    assert_eq!(err.code(), "GV1408");
+
+    for (field, value, code) in [
+        ("Priority", " sometimes\n", "GV1405"),
+        ("Section", " a/b/c\n", "GV1406"),
+        ("Installed-Size", " big\n", "GV1113"),
+        ("Multi-Arch", " sometimes\n", "GV1407"),
+    ] {
+        let mut fields = hello().fields;
+        fields.insert(field.into(), value.into());
+        let err = KnownFields::from_fields(&fields).unwrap_err();
+        assert_eq!(err.code(), code);
+    }
+}
+
+#[test]
+fn parse_stanza() {
+    let mut package = hello();
+    package.meta.description_md5 = description_md5(&package.fields);
+    package.meta.file.sums.sha1 = None;
+    let mut stanza = String::new();
+    package.write_into_packages(&mut stanza).unwrap();
+
+    let parsed = Package::parse_stanza(&stanza).unwrap();
+    assert_eq!(parsed, package);
+
+    let flat = stanza.replace("pool/main/", "./");
+    let parsed = Package::parse_stanza(&flat).unwrap();
+    assert_eq!(&*parsed.component, "main");
+    assert_eq!(&*parsed.meta.file.path, "./hello_2.10-3_amd64.deb");
+
+    let err = Package::parse_stanza(&stanza.replace("SHA256", "X-SHA256")).unwrap_err();
+    assert_eq!(err.code(), "GV6001");
+}
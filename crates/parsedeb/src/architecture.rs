@@ -0,0 +1,115 @@
+//! Architecture names, as dpkg-architecture knows them. The tables mirror dpkg's
+//! cputable and tupletable, without the ABI and libc details we don't need.
+
+/// CPUs from dpkg's cputable. Each of these is also an architecture of its own on GNU/Linux.
+const CPUS: [&str; 37] = [
+    "alpha",
+    "amd64",
+    "arc",
+    "armeb",
+    "arm",
+    "arm64",
+    "avr32",
+    "hppa",
+    "loong64",
+    "i386",
+    "ia64",
+    "m32r",
+    "m68k",
+    "mips",
+    "mipsel",
+    "mipsr6",
+    "mipsr6el",
+    "mips64",
+    "mips64el",
+    "mips64r6",
+    "mips64r6el",
+    "nios2",
+    "or1k",
+    "powerpc",
+    "powerpcel",
+    "ppc64",
+    "ppc64el",
+    "riscv64",
+    "s390",
+    "s390x",
+    "sh3",
+    "sh3eb",
+    "sh4",
+    "sh4eb",
+    "sparc",
+    "sparc64",
+    "tilegx",
+];
+
+/// Prefixes that combine with any CPU, like `hurd-amd64`
+const OS_PREFIXES: [&str; 14] = [
+    "uclibc-linux",
+    "musl-linux",
+    "kfreebsd",
+    "knetbsd",
+    "kopensolaris",
+    "hurd",
+    "dragonflybsd",
+    "freebsd",
+    "openbsd",
+    "netbsd",
+    "darwin",
+    "aix",
+    "solaris",
+    "uclinux",
+];
+
+/// Architectures that name an ABI rather than following the `os-cpu` pattern
+const SPECIAL: [&str; 14] = [
+    "armel",
+    "armhf",
+    "arm64ilp32",
+    "mipsn32",
+    "mipsn32el",
+    "mipsn32r6",
+    "mipsn32r6el",
+    "powerpcspe",
+    "x32",
+    "uclibc-linux-armel",
+    "musl-linux-armhf",
+    "kfreebsd-armhf",
+    "uclinux-armel",
+    "mint-m68k",
+];
+
+/// Whether `name` is a concrete architecture dpkg knows, like `amd64` or `hurd-i386`
+pub fn is_known_architecture(name: &str) -> bool {
+    SPECIAL.contains(&name)
+        || CPUS.contains(&name)
+        || OS_PREFIXES.iter().any(|os| {
+            name.strip_prefix(os)
+                .and_then(|rest| rest.strip_prefix('-'))
+                .is_some_and(|cpu| CPUS.contains(&cpu))
+        })
+}
+
+/// `all`, `any`, a known architecture, or a wildcard like `linux-any` or `any-arm64`
+fn valid_architecture_term(term: &str) -> bool {
+    if matches!(term, "all" | "any") || is_known_architecture(term) {
+        return true;
+    }
+    if let Some(os) = term.strip_suffix("-any") {
+        return os == "linux" || OS_PREFIXES.contains(&os);
+    }
+    term.strip_prefix("any-")
+        .is_some_and(|cpu| CPUS.contains(&cpu))
+}
+
+/// Check the value of an `Architecture` field, which may be a space-separated list.
+/// Returns the first term that isn't a known architecture.
+pub(crate) fn check_architecture(value: &str) -> Result<(), &str> {
+    let mut terms = value.split_whitespace().peekable();
+    if terms.peek().is_none() {
+        return Err(value.trim());
+    }
+    match terms.find(|term| !valid_architecture_term(term)) {
+        Some(term) => Err(term),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,340 @@
+//! The ar container debs are wrapped in. Packages come from the network, so every header is
+//! checked and no size is trusted: input that ends early, or a member over the size limit, is
+//! an [`ArchiveError`] rather than a hang, a panic, or a huge allocation.
+
+use std::io::Read;
+
+use crate::{Error, Limit, Limits, limits};
+
+pub(crate) const AR_MAGIC: &[u8; 8] = b"!<arch>\n";
+pub(crate) const AR_HEADER_LEN: usize = 60;
+/// BSD long names are file names, so anything longer than this is garbage
+const MAX_LONG_NAME_LEN: u64 = 4096;
+/// `debian-binary` holds a version number and a newline, this leaves room for a lot of whitespace
+pub(crate) const MAX_FORMAT_LEN: u64 = 64;
+pub(crate) const FORMAT_MEMBER: &[u8] = b"debian-binary";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("not an ar archive")]
+    NotAr,
+    #[error("malformed ar member header")]
+    BadHeader,
+    #[error("malformed ar member size")]
+    BadSize,
+    #[error("malformed ar long name")]
+    BadLongName,
+    #[error("archive is truncated")]
+    Truncated,
+    #[error("archive has more than one `{0}` member")]
+    DuplicateMember(String),
+    #[error("archive does not start with a debian-binary member")]
+    MissingFormat,
+    #[error("unsupported deb format version `{0}`, only 2.0 is supported")]
+    UnsupportedFormat(String),
+}
+
+impl ArchiveError {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotAr => "GV1501",
+            Self::BadHeader => "GV1502",
+            Self::BadSize => "GV1503",
+            Self::BadLongName => "GV1504",
+            Self::Truncated => "GV1505",
+            Self::DuplicateMember(_) => "GV1506",
+            Self::MissingFormat => "GV1507",
+            Self::UnsupportedFormat(_) => "GV1508",
+        }
+    }
+}
+
+/// For member readers, which can only fail with an I/O error. [`Error`]'s `From` unwraps it again.
+impl From<ArchiveError> for std::io::Error {
+    fn from(e: ArchiveError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Check an ar member header, returning its identifier, with any GNU-style trailing slash
+/// removed, and the size of the member
+pub(crate) fn ar_header(header: &[u8]) -> Result<(&[u8], u64), ArchiveError> {
+    if header.len() != AR_HEADER_LEN || header[58..] != *b"`\n" {
+        return Err(ArchiveError::BadHeader);
+    }
+    let size = std::str::from_utf8(&header[48..58])
+        .ok()
+        .and_then(|size| size.trim().parse().ok())
+        .ok_or(ArchiveError::BadSize)?;
+    let identifier = header[..16].trim_ascii_end();
+    // GNU ar terminates names with a slash
+    let identifier = match identifier.strip_suffix(b"/") {
+        Some(name) if !name.is_empty() => name,
+        _ => identifier,
+    };
+    Ok((identifier, size))
+}
+
+/// The modification time in an ar member header, in seconds since the epoch.
+/// Reproducible builds write 0, and some tools leave it blank, which is read as 0 too.
+fn ar_mtime(header: &[u8]) -> Result<u64, ArchiveError> {
+    let mtime = std::str::from_utf8(&header[16..28])
+        .map_err(|_| ArchiveError::BadHeader)?
+        .trim();
+    if mtime.is_empty() {
+        return Ok(0);
+    }
+    mtime.parse().map_err(|_| ArchiveError::BadHeader)
+}
+
+/// The length of a BSD ar long name, which is stored at the start of the member's contents
+pub(crate) fn bsd_name_len(identifier: &[u8], size: u64) -> Result<Option<u64>, ArchiveError> {
+    let Some(len) = identifier.strip_prefix(b"#1/") else {
+        return Ok(None);
+    };
+    std::str::from_utf8(len)
+        .ok()
+        .and_then(|len| len.parse().ok())
+        .filter(|len| *len <= size && *len <= MAX_LONG_NAME_LEN)
+        .map(Some)
+        .ok_or(ArchiveError::BadLongName)
+}
+
+/// Check the contents of the `debian-binary` member. dpkg ignores anything after the first line.
+pub(crate) fn check_format(contents: &[u8]) -> Result<(), ArchiveError> {
+    let version = contents.split(|b| *b == b'\n').next().unwrap_or_default();
+    if version.trim_ascii() != b"2.0" {
+        return Err(ArchiveError::UnsupportedFormat(
+            String::from_utf8_lossy(version).trim().to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that apply to every member, whichever way the archive is being read
+pub(crate) struct MemberChecks {
+    limits: Limits,
+    members: usize,
+    /// size of the current member, from its header
+    size: u64,
+    seen: Vec<Vec<u8>>,
+}
+
+impl MemberChecks {
+    pub(crate) fn new(limits: &Limits) -> Self {
+        Self {
+            limits: *limits,
+            members: 0,
+            size: 0,
+            seen: Vec::new(),
+        }
+    }
+
+    /// Count a member and check its size. Call before reading anything past its header.
+    pub(crate) fn header(&mut self, size: u64) -> Result<(), Error> {
+        limits::count_member(&mut self.members, self.limits.max_members)?;
+        if size > self.limits.max_member_size {
+            return Err(Error::LimitExceeded(Limit::MemberSize));
+        }
+        self.size = size;
+        Ok(())
+    }
+
+    /// Reject a second copy of a member. Tarballs count as the same member whatever their
+    /// compression, so `control.tar.gz` repeats `control.tar.xz`.
+    /// The first member has to be `debian-binary`, whose contents go to [`check_format`].
+    pub(crate) fn identifier(&mut self, identifier: &[u8]) -> Result<(), ArchiveError> {
+        if self.members == 1 {
+            if identifier != FORMAT_MEMBER {
+                return Err(ArchiveError::MissingFormat);
+            }
+            if self.size > MAX_FORMAT_LEN {
+                return Err(ArchiveError::UnsupportedFormat("<too long>".into()));
+            }
+        }
+        let kind = ["control.tar", "data.tar"]
+            .into_iter()
+            .find(|stem| identifier.starts_with(stem.as_bytes()))
+            .map_or(identifier, str::as_bytes);
+        if self.seen.iter().any(|seen| seen == kind) {
+            return Err(ArchiveError::DuplicateMember(
+                String::from_utf8_lossy(kind).into_owned(),
+            ));
+        }
+        self.seen.push(kind.to_vec());
+        Ok(())
+    }
+}
+
+/// Reads the members of a deb's ar archive one after another, checking each header as it goes
+/// and streaming the contents. Members have to start with `debian-binary`, may not repeat, and
+/// are held to the member count and size [`Limits`].
+///
+/// ```no_run
+/// # fn main() -> Result<(), parsedeb::Error> {
+/// let deb = std::fs::File::open("hello_1.0-1_all.deb")?;
+/// let mut archive = parsedeb::ArReader::new(deb, &parsedeb::Limits::default())?;
+/// while let Some(member) = archive.next_member()? {
+///     println!("{} is {} bytes", member.name(), member.size());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct ArReader<R> {
+    inner: R,
+    checks: MemberChecks,
+    /// unread bytes of the current member
+    remaining: u64,
+    /// the contents of `debian-binary`, which are read up front to be checked
+    format: Option<std::io::Cursor<Vec<u8>>>,
+    /// whether the current member is followed by a padding byte, since members start on even offsets
+    padded: bool,
+}
+
+/// One member of an [`ArReader`], which reads its contents
+pub struct ArMember<'a, R> {
+    identifier: Vec<u8>,
+    size: u64,
+    mtime: u64,
+    archive: &'a mut ArReader<R>,
+}
+
+impl<R> ArMember<'_, R> {
+    /// The member's name, like `control.tar.xz`, with BSD and GNU long name encodings removed
+    pub fn identifier(&self) -> &[u8] {
+        &self.identifier
+    }
+
+    /// [`identifier`](Self::identifier) as text, replacing anything that isn't UTF-8
+    pub fn name(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.identifier)
+    }
+
+    /// The size of the contents in bytes, not counting a BSD long name stored before them
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// When the member was last modified, in seconds since the epoch
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+}
+
+impl<R: Read> ArReader<R> {
+    pub fn new(mut inner: R, limits: &Limits) -> Result<Self, Error> {
+        let mut magic = [0; AR_MAGIC.len()];
+        if read_full(&mut inner, &mut magic)? != magic.len() || magic != *AR_MAGIC {
+            return Err(ArchiveError::NotAr.into());
+        }
+        Ok(Self {
+            inner,
+            checks: MemberChecks::new(limits),
+            remaining: 0,
+            format: None,
+            padded: false,
+        })
+    }
+
+    /// The next member, after skipping whatever is left of the current one.
+    /// `None` once the archive has ended.
+    pub fn next_member(&mut self) -> Result<Option<ArMember<'_, R>>, Error> {
+        self.skip_rest()?;
+        let mut header = [0; AR_HEADER_LEN];
+        match read_full(&mut self.inner, &mut header)? {
+            0 => return Ok(None),
+            AR_HEADER_LEN => {}
+            _ => return Err(ArchiveError::Truncated.into()),
+        }
+        let (raw_identifier, mut size) = ar_header(&header)?;
+        let mtime = ar_mtime(&header)?;
+        self.checks.header(size)?;
+        self.padded = size % 2 == 1;
+
+        let identifier = match bsd_name_len(raw_identifier, size)? {
+            Some(len) => {
+                // bounded by MAX_LONG_NAME_LEN
+                let mut name = vec![0; len as usize];
+                if read_full(&mut self.inner, &mut name)? != name.len() {
+                    return Err(ArchiveError::Truncated.into());
+                }
+                size -= len;
+                name.retain(|b| *b != 0);
+                name
+            }
+            None => raw_identifier.to_vec(),
+        };
+        self.checks.identifier(&identifier)?;
+        self.remaining = size;
+        if identifier == FORMAT_MEMBER {
+            // bounded by MAX_FORMAT_LEN
+            let mut contents = vec![0; size as usize];
+            if read_full(&mut self.inner, &mut contents)? != contents.len() {
+                return Err(ArchiveError::Truncated.into());
+            }
+            check_format(&contents)?;
+            self.remaining = 0;
+            self.format = Some(std::io::Cursor::new(contents));
+        }
+        Ok(Some(ArMember {
+            identifier,
+            size,
+            mtime,
+            archive: self,
+        }))
+    }
+
+    fn skip_rest(&mut self) -> Result<(), Error> {
+        let to_skip = self.remaining + u64::from(self.padded);
+        let skipped = std::io::copy(&mut (&mut self.inner).take(to_skip), &mut std::io::sink())?;
+        // the padding after the last member is often left out, so only the member itself counts
+        if skipped < self.remaining {
+            return Err(ArchiveError::Truncated.into());
+        }
+        self.remaining = 0;
+        self.format = None;
+        self.padded = false;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ArMember<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.archive.read_member(buf)
+    }
+}
+
+impl<R: Read> ArReader<R> {
+    /// Read from the member [`next_member`](Self::next_member) last returned, for readers that
+    /// can't hold on to the [`ArMember`] borrowing the archive
+    pub(crate) fn read_member(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(format) = &mut self.format {
+            return format.read(buf);
+        }
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let max = usize::try_from(self.remaining).map_or(buf.len(), |left| left.min(buf.len()));
+        let read = self.inner.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(ArchiveError::Truncated.into());
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Fill as much of `buf` as the input has, returning how much that was
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
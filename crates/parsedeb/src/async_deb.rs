@@ -0,0 +1,106 @@
+//! Parsing from a tokio `AsyncRead`. Only the ar container is read asynchronously; the control
+//! tarball is small and is buffered and decoded in memory, while data.tar is never read at all.
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    Error, Limit, PackageMap, ParseOptions,
+    archive::{
+        AR_HEADER_LEN, AR_MAGIC, ArchiveError, FORMAT_MEMBER, MemberChecks, ar_header,
+        bsd_name_len, check_format,
+    },
+    clean_if_lenient, get_control_with, limits, pack, read_control, tar_member,
+};
+
+/// [`deb_to_control`](crate::deb_to_control), for async readers.
+/// Wrap a `Stream` of bytes in `tokio_util::io::StreamReader` to parse straight from a request body.
+pub async fn deb_to_control_async(
+    deb: impl AsyncRead + Unpin,
+) -> Result<(PackageMap, Box<str>), Error> {
+    deb_to_control_async_with(deb, &ParseOptions::default()).await
+}
+
+/// [`deb_to_control_async`], with non-default options.
+/// [`ParseOptions::compute_installed_size`] is ignored, since data.tar is never read.
+pub async fn deb_to_control_async_with(
+    mut deb: impl AsyncRead + Unpin,
+    options: &ParseOptions,
+) -> Result<(PackageMap, Box<str>), Error> {
+    let mut magic = [0; AR_MAGIC.len()];
+    deb.read_exact(&mut magic).await?;
+    if magic != *AR_MAGIC {
+        return Err(ArchiveError::NotAr.into());
+    }
+
+    let limits = options.limits;
+    let mut checks = MemberChecks::new(&limits);
+    loop {
+        let mut header = [0; AR_HEADER_LEN];
+        match deb.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Err(Error::NoControlBundle);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let (raw_identifier, mut size) = ar_header(&header)?;
+        checks.header(size)?;
+        // members start on even offsets
+        let padded = size % 2 == 1;
+
+        // BSD ar stores long names right after the header, as part of the member's size
+        let identifier = if let Some(len) = bsd_name_len(raw_identifier, size)? {
+            let mut name = Vec::new();
+            (&mut deb).take(len).read_to_end(&mut name).await?;
+            if name.len() as u64 != len {
+                return Err(ArchiveError::Truncated.into());
+            }
+            size -= len;
+            name.retain(|b| *b != 0);
+            name
+        } else {
+            raw_identifier.to_vec()
+        };
+        checks.identifier(&identifier)?;
+
+        let mut body = Vec::new();
+        if identifier == FORMAT_MEMBER {
+            // bounded by MAX_FORMAT_LEN
+            (&mut deb).take(size).read_to_end(&mut body).await?;
+            if body.len() as u64 != size {
+                return Err(ArchiveError::Truncated.into());
+            }
+            check_format(&body)?;
+        } else if identifier.starts_with(b"control.tar") {
+            // a compressed tarball over the decompressed limit is not worth buffering
+            if size > limits.max_control_size {
+                return Err(Error::LimitExceeded(Limit::ControlSize));
+            }
+            (&mut deb).take(size).read_to_end(&mut body).await?;
+            if body.len() as u64 != size {
+                return Err(ArchiveError::Truncated.into());
+            }
+            if let Some(tar_reader) = tar_member(&identifier, "control", &body[..])? {
+                let tarball = limits::read_limited(tar_reader, limits.max_control_size)?;
+                let tar_reader: Box<dyn std::io::Read> = Box::new(&tarball[..]);
+                let raw_controlfile = read_control(&mut tar::Archive::new(tar_reader), &limits)?;
+                let raw_controlfile = clean_if_lenient(raw_controlfile, options);
+                let package_map = get_control_with(&raw_controlfile, options)?
+                    .into_iter()
+                    .map(pack)
+                    .collect();
+                return Ok((package_map, raw_controlfile));
+            }
+        } else {
+            let skipped =
+                tokio::io::copy(&mut (&mut deb).take(size), &mut tokio::io::sink()).await?;
+            if skipped != size {
+                return Err(ArchiveError::Truncated.into());
+            }
+        }
+        if padded {
+            // left out after the last member by some tools, which the next header read catches
+            let _ = deb.read(&mut [0]).await?;
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! `.changes` files, which describe an upload: the source it came from, where it is
+//! going, and every file that is part of it.
+
+use std::borrow::Cow;
+
+use crate::{DebianVersion, Error, PackageMap, find_field, pack, parse_control};
+
+/// A parsed `.changes` file. Its signature, if any, is stripped but not verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changes {
+    /// the source package name, without the version binNMUs put after it
+    pub source: Box<str>,
+    pub version: DebianVersion,
+    pub distribution: Vec<Box<str>>,
+    pub architecture: Vec<Box<str>>,
+    /// from `Checksums-Sha256`, so every checksum is a sha256
+    pub files: Vec<ListedFile>,
+    /// every field, unmodified
+    pub fields: PackageMap,
+}
+
+/// One line of a file list field like `Checksums-Sha256` or `Files`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ListedFile {
+    /// lowercase hex
+    pub checksum: Box<str>,
+    pub size: u64,
+    pub name: Box<str>,
+}
+
+pub fn parse_changes(input: &str) -> Result<Changes, Error> {
+    let text = strip_signature(input)?;
+    let fields = parse_control(&text)?;
+    let get = |name: &'static str| find_field(&fields, name).ok_or(Error::MissingField(name));
+    let words = |value: &str| value.split_whitespace().map(Into::into).collect();
+
+    let source = get("Source")?
+        .split_whitespace()
+        .next()
+        .ok_or(Error::MissingField("Source"))?
+        .into();
+    Ok(Changes {
+        source,
+        version: get("Version")?.parse()?,
+        distribution: words(get("Distribution")?),
+        architecture: words(get("Architecture")?),
+        files: parse_file_list(get("Checksums-Sha256")?)?,
+        fields: fields.iter().map(|(k, v)| pack((*k, *v))).collect(),
+    })
+}
+
+/// Parse the `checksum size name` lines of a file list field.
+/// The `Files` field of a .changes file also has a section and priority before the name,
+/// which are skipped.
+pub fn parse_file_list(value: &str) -> Result<Vec<ListedFile>, Error> {
+    value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let (checksum, size, name) = match parts[..] {
+                [checksum, size, name] | [checksum, size, _, _, name] => (checksum, size, name),
+                _ => return Err(Error::InvalidFileEntry(line.to_owned())),
+            };
+            let size = size
+                .parse()
+                .map_err(|_| Error::InvalidFileEntry(line.to_owned()))?;
+            if !checksum.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(Error::InvalidFileEntry(line.to_owned()));
+            }
+            Ok(ListedFile {
+                checksum: checksum.to_ascii_lowercase().into(),
+                size,
+                name: name.into(),
+            })
+        })
+        .collect()
+}
+
+/// Remove OpenPGP cleartext signature framing, undoing dash-escaping, without checking
+/// the signature. Unsigned input is returned as is.
+pub fn strip_signature(input: &str) -> Result<Cow<'_, str>, Error> {
+    let Some(rest) = input
+        .trim_start()
+        .strip_prefix("-----BEGIN PGP SIGNED MESSAGE-----")
+    else {
+        return Ok(Cow::Borrowed(input));
+    };
+    // armor headers like `Hash:` end at the first blank line
+    let mut lines = rest
+        .lines()
+        .skip(1)
+        .skip_while(|line| !line.trim().is_empty());
+    lines.next();
+
+    let mut text = String::with_capacity(rest.len());
+    for line in lines {
+        if line.trim_end() == "-----BEGIN PGP SIGNATURE-----" {
+            return Ok(Cow::Owned(text));
+        }
+        text.push_str(line.strip_prefix("- ").unwrap_or(line));
+        text.push('\n');
+    }
+    Err(Error::UnterminatedSignature)
+}
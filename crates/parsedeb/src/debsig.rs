@@ -0,0 +1,148 @@
+//! debsigs-style signatures: `_gpg<role>` ar members, each a detached OpenPGP signature
+//! over `debian-binary`, the control tarball, and the data tarball, concatenated in order.
+//!
+//! The signatures come last, so the package is read once to find them, and then streamed
+//! again for each one that's checked, instead of holding a data tarball of any size in memory.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use pgp::{
+    composed::{Deserializable, SignedPublicKey, StandaloneSignature},
+    types::{KeyDetails, PublicKeyTrait},
+};
+
+use crate::{Error, Limits, archive::ArReader, limits};
+
+/// Who made a signature that verified
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Signer {
+    /// from the member name, like `origin` for `_gpgorigin`
+    pub role: Box<str>,
+    /// uppercase hex fingerprint of the primary key, even when a subkey made the signature
+    pub fingerprint: Box<str>,
+}
+
+/// Check every signature embedded in a .deb against `keyring`, returning who made them.
+/// Fails if there are no signatures, or if any of them isn't valid for any key in the keyring.
+/// The package is read from where `deb` is positioned, and is read again for every key a
+/// signature is checked against.
+pub fn verify_deb_signatures(
+    mut deb: impl Read + Seek,
+    keyring: &[SignedPublicKey],
+) -> Result<Vec<Signer>, Error> {
+    let start = deb.stream_position()?;
+    let limits = Limits::default();
+    let mut signatures = Vec::new();
+    let mut archive = ArReader::new(&mut deb, &limits)?;
+    while let Some(entry) = archive.next_member()? {
+        if let Some(role) = entry.identifier().strip_prefix(b"_gpg") {
+            let role = String::from_utf8_lossy(role).into_owned();
+            let contents = limits::read_limited(entry, limits.max_control_size)?;
+            signatures.push((role, contents));
+        }
+    }
+    if signatures.is_empty() {
+        return Err(Error::NotSigned);
+    }
+
+    signatures
+        .into_iter()
+        .map(|(role, contents)| {
+            let signature = parse_signature(&contents)?;
+            let fingerprint = keyring
+                .iter()
+                .find(|key| verifies(&signature, key, &mut deb, start))
+                .map(|key| fingerprint_hex(key.fingerprint().as_bytes()))
+                .ok_or_else(|| Error::UntrustedSignature(role.clone()))?;
+            Ok(Signer {
+                role: role.into(),
+                fingerprint: fingerprint.into(),
+            })
+        })
+        .collect()
+}
+
+/// `debian-binary`, the control tarball and the data tarball of an archive, one after another
+struct SignedParts<R> {
+    archive: ArReader<R>,
+    /// whether the member being read is one of them
+    signed: bool,
+}
+
+impl<R> SignedParts<R> {
+    fn new(archive: ArReader<R>) -> Self {
+        Self {
+            archive,
+            signed: false,
+        }
+    }
+}
+
+impl<R: Read> Read for SignedParts<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.signed {
+                let read = self.archive.read_member(buf)?;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+            }
+            let Some(member) = self.archive.next_member().map_err(std::io::Error::other)? else {
+                return Ok(0);
+            };
+            let identifier = member.identifier();
+            self.signed = identifier == b"debian-binary"
+                || identifier.starts_with(b"control.tar")
+                || identifier.starts_with(b"data.tar");
+        }
+    }
+}
+
+/// debsigs writes armored signatures, but binary ones are just as valid
+fn parse_signature(contents: &[u8]) -> Result<StandaloneSignature, Error> {
+    let signature = if contents.starts_with(b"-----BEGIN") {
+        StandaloneSignature::from_string(&String::from_utf8_lossy(contents))?.0
+    } else {
+        StandaloneSignature::from_bytes(contents)?
+    };
+    Ok(signature)
+}
+
+/// Whether `key` or one of its subkeys made `signature` over the package at `start` of `deb`
+fn verifies(
+    signature: &StandaloneSignature,
+    key: &SignedPublicKey,
+    deb: &mut (impl Read + Seek),
+    start: u64,
+) -> bool {
+    verifies_with(signature, key, deb, start)
+        || key
+            .public_subkeys
+            .iter()
+            .any(|subkey| verifies_with(signature, subkey, deb, start))
+}
+
+fn verifies_with(
+    signature: &StandaloneSignature,
+    key: &impl PublicKeyTrait,
+    deb: &mut (impl Read + Seek),
+    start: u64,
+) -> bool {
+    if deb.seek(SeekFrom::Start(start)).is_err() {
+        return false;
+    }
+    match ArReader::new(&mut *deb, &Limits::default()) {
+        Ok(archive) => signature
+            .signature
+            .verify(key, SignedParts::new(archive))
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn fingerprint_hex(fingerprint: &[u8]) -> String {
+    fingerprint
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect()
+}
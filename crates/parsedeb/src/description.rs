@@ -0,0 +1,48 @@
+//! deb822 folding for Description: a synopsis on the field's first line, then an extended
+//! description on continuation lines that each start with a space, where ` .` is a blank line.
+
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Description {
+    pub synopsis: Box<str>,
+    /// extended description lines without the space that folds them, and empty for blank lines.
+    /// Lines indented further, which frontends show verbatim, keep the rest of their indent.
+    pub extended: Vec<Box<str>>,
+}
+
+impl Description {
+    /// Unfold a field value as parsed, with its leading space and trailing newline
+    pub fn parse(raw: &str) -> Self {
+        let mut lines = raw.lines();
+        let synopsis = lines.next().unwrap_or_default().trim().into();
+        let extended = lines
+            .map(|line| {
+                let line = line.strip_prefix([' ', '\t']).unwrap_or(line).trim_end();
+                if line == "." { "" } else { line }.into()
+            })
+            .collect();
+        Self { synopsis, extended }
+    }
+
+    /// The folded value in the form the parser produces, with a leading space and trailing newline
+    pub fn to_field_value(&self) -> String {
+        format!(" {self}\n")
+    }
+}
+
+/// The folded value as it appears after `Description: `, without a trailing newline
+impl Display for Description {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.synopsis)?;
+        for line in &self.extended {
+            if line.trim().is_empty() {
+                f.write_str("\n .")?;
+            } else {
+                write!(f, "\n {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+//! `.dsc` files, which describe a source package: its build dependencies and the
+//! tarballs and patches it is built from.
+
+use crate::{
+    DebianVersion, Error, ListedFile, PackageMap, Relation, find_field, pack, parse_control,
+    parse_file_list, parse_relations, strip_signature,
+};
+
+/// A parsed `.dsc` file. Its signature, if any, is stripped but not verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dsc {
+    pub source: Box<str>,
+    pub version: DebianVersion,
+    /// like `3.0 (quilt)`
+    pub format: Box<str>,
+    /// the binary packages built from this source
+    pub binaries: Vec<Box<str>>,
+    pub architecture: Vec<Box<str>>,
+    pub build_depends: Vec<Relation>,
+    pub build_depends_arch: Vec<Relation>,
+    pub build_depends_indep: Vec<Relation>,
+    /// from `Checksums-Sha256`, so every checksum is a sha256
+    pub files: Vec<ListedFile>,
+    /// from `Files`, so every checksum is an md5
+    pub files_md5: Vec<ListedFile>,
+    /// every field, unmodified
+    pub fields: PackageMap,
+}
+
+pub fn parse_dsc(input: &str) -> Result<Dsc, Error> {
+    let text = strip_signature(input)?;
+    let fields = parse_control(&text)?;
+    let get = |name: &'static str| find_field(&fields, name).ok_or(Error::MissingField(name));
+    let relations = |name: &str| match find_field(&fields, name) {
+        Some(value) => parse_relations(value),
+        None => Ok(Vec::new()),
+    };
+    let binaries = match find_field(&fields, "Binary") {
+        Some(value) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(Into::into)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    Ok(Dsc {
+        source: get("Source")?.trim().into(),
+        version: get("Version")?.parse()?,
+        format: get("Format")?.trim().into(),
+        binaries,
+        architecture: get("Architecture")?
+            .split_whitespace()
+            .map(Into::into)
+            .collect(),
+        build_depends: relations("Build-Depends")?,
+        build_depends_arch: relations("Build-Depends-Arch")?,
+        build_depends_indep: relations("Build-Depends-Indep")?,
+        files: parse_file_list(get("Checksums-Sha256")?)?,
+        files_md5: parse_file_list(get("Files")?)?,
+        fields: fields.iter().map(|(k, v)| pack((*k, *v))).collect(),
+    })
+}
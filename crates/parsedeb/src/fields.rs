@@ -0,0 +1,157 @@
+//! Looking fields up by name. Policy makes field names case-insensitive, so `package`,
+//! `Package` and `PACKAGE` all name the same field.
+
+use std::borrow::Cow;
+
+use indexmap::IndexMap;
+
+use crate::{Description, RELATION_FIELDS};
+
+/// The usual capitalization of fields found in control files, .changes, .dsc and indexes,
+/// on top of [`RELATION_FIELDS`]
+const KNOWN_FIELDS: &[&str] = &[
+    "Package",
+    "Package-Type",
+    "Source",
+    "Version",
+    "Architecture",
+    "Subarchitecture",
+    "Kernel-Version",
+    "Installer-Menu-Item",
+    "Auto-Built-Package",
+    "Build-Ids",
+    "Build-Essential",
+    "Important",
+    "Task",
+    "Python-Version",
+    "Ruby-Versions",
+    "Go-Import-Path",
+    "Rules-Requires-Root",
+    "Essential",
+    "Protected",
+    "Multi-Arch",
+    "Section",
+    "Priority",
+    "Maintainer",
+    "Original-Maintainer",
+    "Uploaders",
+    "Changed-By",
+    "Installed-Size",
+    "Homepage",
+    "Bugs",
+    "Origin",
+    "Tag",
+    "Description",
+    "Description-md5",
+    "Filename",
+    "Size",
+    "MD5sum",
+    "SHA1",
+    "SHA256",
+    "SHA512",
+    "Format",
+    "Binary",
+    "Standards-Version",
+    "Build-Depends",
+    "Build-Depends-Arch",
+    "Build-Depends-Indep",
+    "Build-Conflicts",
+    "Build-Conflicts-Arch",
+    "Build-Conflicts-Indep",
+    "Testsuite",
+    "Vcs-Browser",
+    "Vcs-Git",
+    "Date",
+    "Distribution",
+    "Urgency",
+    "Changes",
+    "Closes",
+    "Files",
+    "Checksums-Sha1",
+    "Checksums-Sha256",
+];
+
+/// Case-insensitive lookups on parsed fields
+pub trait ControlFields {
+    /// A field's value as written, with its leading space and trailing newline
+    fn raw_field(&self, name: &str) -> Option<&str>;
+
+    /// A field's value with surrounding whitespace trimmed
+    fn field(&self, name: &str) -> Option<&str> {
+        self.raw_field(name).map(str::trim)
+    }
+}
+
+impl<K: AsRef<str>, V: AsRef<str>> ControlFields for IndexMap<K, V> {
+    fn raw_field(&self, name: &str) -> Option<&str> {
+        find_field(self, name)
+    }
+}
+
+pub(crate) fn find_field<'a, K: AsRef<str>, V: AsRef<str>>(
+    fields: &'a IndexMap<K, V>,
+    name: &str,
+) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(key, _)| key.as_ref().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_ref())
+}
+
+/// The usual spelling of a field, if it is one we know about
+pub(crate) fn known_field(name: &str) -> Option<&'static str> {
+    KNOWN_FIELDS
+        .iter()
+        .chain(&RELATION_FIELDS)
+        .find(|known| known.eq_ignore_ascii_case(name))
+        .copied()
+}
+
+/// How a field name is usually spelled: known fields get their usual capitalization,
+/// and anything else gets each hyphenated word capitalized, like `X-Custom-Field`
+pub fn canonical_field_name(name: &str) -> Cow<'_, str> {
+    if let Some(known) = known_field(name) {
+        return Cow::Borrowed(known);
+    }
+    if name
+        .split('-')
+        .all(|word| !word.starts_with(|c: char| c.is_ascii_lowercase()))
+    {
+        return Cow::Borrowed(name);
+    }
+    let words: Vec<String> = name
+        .split('-')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+                .collect()
+        })
+        .collect();
+    Cow::Owned(words.join("-"))
+}
+
+/// Rewrite every key to its [`canonical_field_name`], keeping their order.
+/// Keys that differ only in case, which the parser rejects, are merged into the first.
+pub fn normalize_keys(fields: &mut IndexMap<Box<str>, Box<str>>) {
+    for (key, value) in std::mem::take(fields) {
+        let renamed: Option<Box<str>> = match canonical_field_name(&key) {
+            Cow::Borrowed(name) => (name != &*key).then(|| name.into()),
+            Cow::Owned(name) => Some(name.into()),
+        };
+        fields.entry(renamed.unwrap_or(key)).or_insert(value);
+    }
+}
+
+/// Refold Description into its canonical form, see [`Description`]
+pub fn normalize_description(fields: &mut IndexMap<Box<str>, Box<str>>) {
+    if let Some((_, value)) = fields
+        .iter_mut()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Description"))
+    {
+        *value = Description::parse(value).to_field_value().into();
+    }
+}
@@ -1,18 +1,64 @@
-use std::{collections::HashMap, io::Read, str::FromStr};
+use std::{borrow::Cow, collections::HashMap, io::Read, str::FromStr};
 
 use indexmap::IndexMap;
 
+mod architecture;
+mod archive;
+#[cfg(feature = "tokio")]
+mod async_deb;
+mod changes;
+#[cfg(feature = "debsig")]
+mod debsig;
+mod description;
+mod dsc;
+mod fields;
+mod limits;
+mod names;
+mod relation;
+mod slice;
+mod source;
 #[cfg(test)]
 mod tests;
+mod triplet;
+mod validate;
+mod version;
+mod warnings;
+
+pub use architecture::is_known_architecture;
+pub use archive::{ArMember, ArReader, ArchiveError};
+#[cfg(feature = "tokio")]
+pub use async_deb::{deb_to_control_async, deb_to_control_async_with};
+pub use changes::{Changes, ListedFile, parse_changes, parse_file_list, strip_signature};
+#[cfg(feature = "debsig")]
+pub use debsig::{Signer, verify_deb_signatures};
+pub use description::Description;
+pub use dsc::{Dsc, parse_dsc};
+pub(crate) use fields::find_field;
+pub use fields::{ControlFields, canonical_field_name, normalize_description, normalize_keys};
+pub use limits::{Limit, Limits};
+pub use names::{Architecture, PackageName, Version};
+pub use relation::{
+    ArchRestriction, Dependency, ProfileTerm, RELATION_FIELDS, Relation, RelationError,
+    VersionConstraint, VersionOperator, parse_relations,
+};
+pub use slice::control_from_slice;
+#[cfg(feature = "mmap")]
+pub use slice::map_deb;
+pub use source::{SourcePackage, SourceRef, group_by_source};
+pub use triplet::PackageTriplet;
+pub use validate::{FieldChecks, field_value_warnings};
+pub use version::{DebianVersion, VersionError};
+pub use warnings::{Warning, control_warnings, control_warnings_with, get_control_with_warnings};
 
 type PackageMap = IndexMap<Box<str>, Box<str>>;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Required fields, trimmed
 pub struct RequiredFields {
-    pub package: Box<str>,
-    pub version: Box<str>,
-    pub architecture: Box<str>,
+    pub package: PackageName,
+    pub version: Version,
+    pub architecture: Architecture,
     pub maintainer: Box<str>,
     pub description: Box<str>,
 }
@@ -20,35 +66,137 @@ pub struct RequiredFields {
 impl RequiredFields {
     /// Get a struct of the fields that are required for debian binary packages
     /// this trims whitespaces and otherwise loses data! do not use for hashing!
-    pub fn from_map(input: &IndexMap<Box<str>, Box<str>>) -> Option<RequiredFields> {
-        let mut requireds: HashMap<RequiredField, Box<str>> = input
+    pub fn from_map(input: &IndexMap<Box<str>, Box<str>>) -> Result<RequiredFields, Error> {
+        Self::from_map_for(input, PackageKind::Deb)
+    }
+
+    /// [`RequiredFields::from_map`] for a package of `kind`. Fields it doesn't need are left
+    /// empty when they're missing.
+    pub fn from_map_for(
+        input: &IndexMap<Box<str>, Box<str>>,
+        kind: PackageKind,
+    ) -> Result<RequiredFields, Error> {
+        let mut requireds: HashMap<RequiredField, &str> = input
             .iter()
-            .filter_map(|(k, v)| Some((RequiredField::from_str(k).ok()?, v.trim().into())))
+            .filter_map(|(k, v)| Some((RequiredField::from_str(k).ok()?, v.trim())))
             .collect();
+        let missing: Vec<RequiredField> = kind
+            .required_fields()
+            .iter()
+            .copied()
+            .filter(|field| !requireds.contains_key(field))
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::MissingFields(missing));
+        }
+        let mut take = |field| requireds.remove(&field).unwrap_or_default();
 
-        let required = RequiredFields {
-            package: requireds.remove(&RequiredField::Package)?,
-            version: requireds.remove(&RequiredField::Version)?,
-            architecture: requireds.remove(&RequiredField::Architecture)?,
-            maintainer: requireds.remove(&RequiredField::Maintainer)?,
-            description: requireds.remove(&RequiredField::Description)?,
-        };
-        Some(required)
+        Ok(RequiredFields {
+            package: take(RequiredField::Package).parse()?,
+            version: take(RequiredField::Version).parse()?,
+            architecture: take(RequiredField::Architecture).parse()?,
+            maintainer: take(RequiredField::Maintainer).into(),
+            description: take(RequiredField::Description).into(),
+        })
+    }
+}
+
+/// Commonly used optional fields, trimmed and parsed
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OptionalFields {
+    pub section: Option<Box<str>>,
+    pub priority: Option<Box<str>>,
+    pub homepage: Option<Box<str>>,
+    /// in KiB
+    pub installed_size: Option<u64>,
+    /// empty when there is no Depends field
+    pub depends: Vec<Relation>,
+    /// packages that have to be unpacked and configured before this one is unpacked
+    pub pre_depends: Vec<Relation>,
+    /// `Essential: yes`, so apt refuses to remove it and dpkg expects it to always work
+    pub essential: bool,
+    /// `Protected: yes`, so apt refuses to remove it without being forced
+    pub protected: bool,
+    pub source: Option<SourceRef>,
+}
+
+impl OptionalFields {
+    pub fn from_map(input: &IndexMap<Box<str>, Box<str>>) -> Result<OptionalFields, Error> {
+        let get = |name: &str| input.field(name);
+        let installed_size = get("Installed-Size")
+            .map(|size| {
+                size.parse()
+                    .map_err(|_| Error::InvalidInstalledSize(size.to_owned()))
+            })
+            .transpose()?;
+        Ok(OptionalFields {
+            section: get("Section").map(Into::into),
+            priority: get("Priority").map(Into::into),
+            homepage: get("Homepage").map(Into::into),
+            installed_size,
+            depends: get("Depends")
+                .map(parse_relations)
+                .transpose()?
+                .unwrap_or_default(),
+            pre_depends: get("Pre-Depends")
+                .map(parse_relations)
+                .transpose()?
+                .unwrap_or_default(),
+            essential: parse_yes_no(input, "Essential")?,
+            protected: parse_yes_no(input, "Protected")?,
+            source: get("Source").map(SourceRef::from_str).transpose()?,
+        })
+    }
+}
+
+/// A field that is either `yes` or `no`, where leaving it out means `no`
+pub(crate) fn parse_yes_no(
+    fields: &impl ControlFields,
+    field: &'static str,
+) -> Result<bool, Error> {
+    match fields.field(field) {
+        None | Some("no") => Ok(false),
+        Some("yes") => Ok(true),
+        Some(value) => Err(Error::InvalidYesNo {
+            field,
+            value: value.to_owned(),
+        }),
     }
 }
 
 impl RequiredFields {
-    pub fn name(&self) -> &str {
+    pub fn name(&self) -> &PackageName {
         &self.package
     }
 }
 
 pub fn deb_to_control(deb: impl std::io::Read) -> Result<(PackageMap, Box<str>), Error> {
-    let raw_controlfile = parse_debfile(deb)?;
-    let package_map = get_control(&raw_controlfile)?
+    deb_to_control_with(deb, &ParseOptions::default())
+}
+
+/// [`deb_to_control`], with non-default options
+pub fn deb_to_control_with(
+    deb: impl std::io::Read,
+    options: &ParseOptions,
+) -> Result<(PackageMap, Box<str>), Error> {
+    let (raw_controlfile, installed_size) = if options.compute_installed_size {
+        parse_debfile_with_installed_size(deb, options)?
+    } else {
+        let raw_controlfile = parse_debfile(deb, &options.limits)?;
+        (clean_if_lenient(raw_controlfile, options), None)
+    };
+    let mut package_map: PackageMap = get_control_with(&raw_controlfile, options)?
         .into_iter()
         .map(pack)
         .collect();
+    if let Some(size) = installed_size {
+        // where dpkg-gencontrol puts it
+        let index = package_map
+            .get_index_of("Maintainer")
+            .map_or(package_map.len(), |i| i + 1);
+        package_map.shift_insert(index, "Installed-Size".into(), format!(" {size}\n").into());
+    }
     Ok((package_map, raw_controlfile))
 }
 
@@ -73,10 +221,12 @@ impl<'a, T: std::fmt::Display> std::fmt::Display for UnbracketedList<'a, T> {
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("no control.tar.gz file found")]
+    #[error("no control.tar file found")]
     NoControlBundle,
     #[error("no control file found")]
     NoControl,
+    #[error("no data.tar file found")]
+    NoDataBundle,
     #[error("control file has a first field other than the package name")]
     DoesNotStartWithPackage,
     #[error("missing field- this error state should be a bug")]
@@ -85,22 +235,132 @@ pub enum Error {
     MissingFields(Vec<RequiredField>),
     #[error("includes forbidden fields {}", UnbracketedList(.0))]
     ForbiddenFields(Vec<ForbiddenField>),
+    #[error("unknown architecture `{0}`")]
+    UnknownArchitecture(String),
+    #[error("invalid version: {0}")]
+    InvalidVersion(#[from] VersionError),
+    #[error("missing field `{0}`")]
+    MissingField(&'static str),
+    #[error("invalid file list entry `{0}`")]
+    InvalidFileEntry(String),
+    #[error("signed message has no signature")]
+    UnterminatedSignature,
+    #[error("invalid relationship field: {0}")]
+    InvalidRelation(#[from] RelationError),
+    #[error("Installed-Size `{0}` is not a number")]
+    InvalidInstalledSize(String),
+    #[error("invalid Source field `{0}`")]
+    InvalidSourceField(String),
+    #[error("{0} limit exceeded")]
+    LimitExceeded(Limit),
+    #[error("invalid line in {member}: `{line}`")]
+    InvalidControlMember { member: &'static str, line: String },
+    #[error("package has no embedded signatures")]
+    NotSigned,
+    #[error("`{0}` signature is not from a trusted key")]
+    UntrustedSignature(String),
+    #[cfg(feature = "debsig")]
+    #[error("invalid signature")]
+    Signature(#[from] pgp::errors::Error),
+    #[error("control file is not UTF-8")]
+    NotUtf8(#[from] std::str::Utf8Error),
+    #[error("invalid package name `{0}`")]
+    InvalidPackageName(String),
+    #[error("{0}")]
+    InvalidFieldValue(Warning),
+    #[error("{field} must be `yes` or `no`, not `{value}`")]
+    InvalidYesNo { field: &'static str, value: String },
+    #[error("Essential packages are not accepted")]
+    EssentialPackage,
+    #[error("invalid archive: {0}")]
+    InvalidArchive(#[from] ArchiveError),
     #[error("I/O error")]
-    InvalidRead(#[from] std::io::Error),
+    InvalidRead(#[source] std::io::Error),
     #[error("parse error: {0}")]
     Parse(#[from] ParseError),
 }
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        // archive errors come back wrapped when they happen inside a member's reader
+        match e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<ArchiveError>())
+        {
+            Some(archive) => Self::InvalidArchive(archive.clone()),
+            None => Self::InvalidRead(e),
+        }
+    }
+}
+
+impl Error {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NoControlBundle => "GV1101",
+            Self::NoControl => "GV1102",
+            Self::DoesNotStartWithPackage => "GV1103",
+            Self::MissingUnknownFields => "GV1104",
+            Self::MissingFields(_) => "GV1105",
+            Self::ForbiddenFields(_) => "GV1106",
+            Self::InvalidRead(_) => "GV1107",
+            Self::Parse(e) => e.code(),
+            Self::NoDataBundle => "GV1108",
+            Self::UnknownArchitecture(_) => "GV1109",
+            Self::InvalidVersion(e) => e.code(),
+            Self::MissingField(_) => "GV1110",
+            Self::InvalidFileEntry(_) => "GV1111",
+            Self::UnterminatedSignature => "GV1112",
+            Self::InvalidRelation(e) => e.code(),
+            Self::InvalidInstalledSize(_) => "GV1113",
+            Self::InvalidSourceField(_) => "GV1114",
+            Self::LimitExceeded(_) => "GV1115",
+            Self::InvalidControlMember { .. } => "GV1116",
+            Self::NotSigned => "GV1117",
+            Self::UntrustedSignature(_) => "GV1118",
+            #[cfg(feature = "debsig")]
+            Self::Signature(_) => "GV1119",
+            Self::NotUtf8(_) => "GV1120",
+            Self::InvalidArchive(e) => e.code(),
+            Self::InvalidPackageName(_) => "GV1121",
+            Self::InvalidFieldValue(warning) => warning.code(),
+            Self::InvalidYesNo { .. } => "GV1122",
+            Self::EssentialPackage => "GV1123",
+        }
+    }
+}
+
 pub fn get_control(control: &str) -> Result<IndexMap<&str, &str>, Error> {
-    let parsed_map = parse_control(control)?;
+    get_control_with(control, &ParseOptions::default())
+}
+
+/// [`get_control`], with non-default options.
+/// A control file that declares `Package-Type: udeb` is always checked as a udeb.
+pub fn get_control_with<'a>(
+    control: &'a str,
+    options: &ParseOptions,
+) -> Result<IndexMap<&'a str, &'a str>, Error> {
+    let parsed_map = parse_control_with(control, options.duplicate_policy)?;
+    if parsed_map.len() > options.limits.max_fields {
+        return Err(Error::LimitExceeded(Limit::Fields));
+    }
 
     let keys = extract_keys(&parsed_map);
     if keys.first.is_none_or(|v| v != RequiredField::Package) {
         return Err(Error::DoesNotStartWithPackage);
     }
 
-    let missing_fields: Vec<RequiredField> = RequiredField::ALL
-        .into_iter()
+    let declares_udeb =
+        find_field(&parsed_map, "Package-Type").is_some_and(|value| value.trim() == "udeb");
+    let kind = if declares_udeb {
+        PackageKind::Udeb
+    } else {
+        options.kind
+    };
+    let missing_fields: Vec<RequiredField> = kind
+        .required_fields()
+        .iter()
+        .copied()
         .filter(|req| !keys.required.contains(req))
         .collect();
     if !missing_fields.is_empty() {
@@ -111,6 +371,28 @@ pub fn get_control(control: &str) -> Result<IndexMap<&str, &str>, Error> {
         return Err(Error::ForbiddenFields(keys.forbidden));
     }
 
+    let field = |wanted: RequiredField| {
+        parsed_map
+            .iter()
+            .find(|(key, _)| RequiredField::from_str(key) == Ok(wanted))
+            .map(|(_, value)| *value)
+    };
+    if let Some(architecture) = field(RequiredField::Architecture) {
+        architecture::check_architecture(architecture)
+            .map_err(|term| Error::UnknownArchitecture(term.to_owned()))?;
+    }
+    if let Some(version) = field(RequiredField::Version) {
+        version.parse::<DebianVersion>()?;
+    }
+    if options.reject_essential && parse_yes_no(&parsed_map, "Essential")? {
+        return Err(Error::EssentialPackage);
+    }
+    if options.field_checks == FieldChecks::Reject
+        && let Some(warning) = field_value_warnings(&parsed_map).into_iter().next()
+    {
+        return Err(Error::InvalidFieldValue(warning));
+    }
+
     Ok(parsed_map)
 }
 
@@ -133,6 +415,77 @@ impl RequiredField {
     ];
 }
 
+/// How strictly, and how far, to parse a package
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ParseOptions {
+    pub kind: PackageKind,
+    pub limits: Limits,
+    /// add an `Installed-Size` worked out from data.tar to packages that don't have one.
+    /// The raw control file is returned as is.
+    pub compute_installed_size: bool,
+    /// accept CRLF line endings and a missing final newline, by running control files read out of
+    /// a package through [`clean_control`]. Text passed to [`get_control_with`] has to be cleaned first.
+    pub lenient: bool,
+    pub duplicate_policy: DuplicatePolicy,
+    /// whether to check Maintainer, Priority and Section, see [`field_value_warnings`]
+    pub field_checks: FieldChecks,
+    /// fail with [`Error::EssentialPackage`] on packages marked `Essential: yes`
+    pub reject_essential: bool,
+}
+
+/// What to do with a field that appears twice in one control file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DuplicatePolicy {
+    /// fail with [`ParseError::DuplicateKey`], like dpkg does
+    #[default]
+    Reject,
+    /// allow repeats with the same value, since there's no doubt about what they mean
+    AllowIdentical,
+    KeepFirst,
+    /// replace the value, keeping the field where it first appeared
+    KeepLast,
+}
+
+/// Whether a package is a regular .deb or a debian-installer .udeb
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PackageKind {
+    #[default]
+    Deb,
+    Udeb,
+}
+
+impl PackageKind {
+    /// Tell the kinds apart by file extension, returning `None` for anything else
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        if name.ends_with(".deb") {
+            Some(Self::Deb)
+        } else if name.ends_with(".udeb") {
+            Some(Self::Udeb)
+        } else {
+            None
+        }
+    }
+
+    /// The fields a control file must have. udebs are only installed by debian-installer,
+    /// which doesn't need a maintainer or description, so they are often left out.
+    /// [`RequiredFields::from_map`] still needs all of them, unlike
+    /// [`RequiredFields::from_map_for`].
+    pub fn required_fields(self) -> &'static [RequiredField] {
+        match self {
+            Self::Deb => &RequiredField::ALL,
+            Self::Udeb => &RequiredField::ALL[..3],
+        }
+    }
+
+    /// The file extension packages of this kind have, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Deb => "deb",
+            Self::Udeb => "udeb",
+        }
+    }
+}
+
 impl std::fmt::Display for RequiredField {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = match self {
@@ -213,6 +566,14 @@ enum ParseState<'a> {
 
 /// returns an unmodified but otherwise parsed controlfile
 pub fn parse_control(input: &str) -> Result<IndexMap<&str, &str>, ParseError> {
+    parse_control_with(input, DuplicatePolicy::Reject)
+}
+
+/// [`parse_control`], with a choice of what to do about repeated fields
+pub fn parse_control_with(
+    input: &str,
+    duplicates: DuplicatePolicy,
+) -> Result<IndexMap<&str, &str>, ParseError> {
     let mut output = IndexMap::new();
 
     let mut state = ParseState::CreatingKey(0);
@@ -232,7 +593,8 @@ pub fn parse_control(input: &str) -> Result<IndexMap<&str, &str>, ParseError> {
             }
             ParseState::SkippingColon(s) => {
                 if char == '\n' {
-                    return Err(ParseError::IncompleteKey(idx));
+                    // the value may start on the next line, like the file lists in .changes files
+                    ParseState::ValueNewLine(s, idx)
                 } else {
                     ParseState::CreatingValue(s, idx)
                 }
@@ -248,8 +610,11 @@ pub fn parse_control(input: &str) -> Result<IndexMap<&str, &str>, ParseError> {
                 if char == '\t' || char == ' ' {
                     ParseState::CreatingValue(k, s)
                 } else {
-                    if output.insert(k, &input[s..idx]).is_some() {
-                        return Err(ParseError::DuplicateKey(k.to_owned()));
+                    if &input[s..idx] == "\n" {
+                        return Err(ParseError::IncompleteKey(Span::at(input, s)));
+                    }
+                    if !insert_field(&mut output, k, &input[s..idx], duplicates) {
+                        return Err(ParseError::duplicate_key(input, k));
                     }
                     if char == '#' {
                         ParseState::SkippingComment
@@ -279,12 +644,22 @@ pub fn parse_control(input: &str) -> Result<IndexMap<&str, &str>, ParseError> {
     #[cfg(test)]
     eprintln!("final: {idx} {state:?}");
     match state {
-        ParseState::CreatingKey(s) => return Err(ParseError::IncompleteKey(s)),
-        ParseState::SkippingColon(k) => return Err(ParseError::NoValueForKey(k.to_owned())),
-        ParseState::CreatingValue(_, _) => return Err(ParseError::MustEndInNewline),
+        ParseState::CreatingKey(s) => return Err(ParseError::IncompleteKey(Span::at(input, s))),
+        ParseState::SkippingColon(k) => {
+            return Err(ParseError::NoValueForKey {
+                key: k.to_owned(),
+                span: Span::at(input, offset_in(input, k)),
+            });
+        }
+        ParseState::CreatingValue(_, _) => {
+            return Err(ParseError::MustEndInNewline(Span::at(input, input.len())));
+        }
         ParseState::ValueNewLine(k, s) => {
-            if output.insert(k, &input[s..idx]).is_some() {
-                return Err(ParseError::DuplicateKey(k.to_owned()));
+            if &input[s..idx] == "\n" {
+                return Err(ParseError::IncompleteKey(Span::at(input, s)));
+            }
+            if !insert_field(&mut output, k, &input[s..idx], duplicates) {
+                return Err(ParseError::duplicate_key(input, k));
             }
         }
         ParseState::SkippingComment | ParseState::SkippingNewlineComment => {}
@@ -292,30 +667,511 @@ pub fn parse_control(input: &str) -> Result<IndexMap<&str, &str>, ParseError> {
     Ok(output)
 }
 
-fn parse_debfile(deb: impl std::io::Read) -> Result<Box<str>, Error> {
-    let mut raw_ar = ar::Archive::new(deb);
-    while let Some(entry) = raw_ar.next_entry().transpose()? {
-        let tar_reader: Box<dyn Read> = match entry.header().identifier() {
-            b"control.tar" => Box::new(entry),
-            b"control.tar.gz" => Box::new(flate2::read::GzDecoder::new(entry)),
-            b"control.tar.xz" => Box::new(liblzma::read::XzDecoder::new(entry)),
-            b"control.tar.zst" => Box::new(zstd::Decoder::new(entry)?),
-            _ => continue,
+/// The inverse of [`parse_control`]: values are written back exactly as they were parsed, leading
+/// whitespace and continuation lines included, so a parsed stanza comes out byte-identical
+/// apart from any comments. Values that don't end in a newline, like ones built by hand, get one.
+pub fn serialize_control<K: AsRef<str>, V: AsRef<str>>(fields: &IndexMap<K, V>) -> String {
+    let mut output = String::new();
+    for (key, value) in fields {
+        let value = value.as_ref();
+        output.push_str(key.as_ref());
+        output.push(':');
+        output.push_str(value);
+        if !value.ends_with('\n') {
+            output.push('\n');
+        }
+    }
+    output
+}
+
+/// Decompress an ar member if it is the tarball `stem` (`control` or `data`) in any supported compression
+fn tar_member<'a>(
+    identifier: &[u8],
+    stem: &str,
+    entry: impl Read + 'a,
+) -> Result<Option<Box<dyn Read + 'a>>, Error> {
+    let Some(extension) = identifier
+        .strip_prefix(stem.as_bytes())
+        .and_then(|rest| rest.strip_prefix(b".tar"))
+    else {
+        return Ok(None);
+    };
+    let reader: Box<dyn Read> = match extension {
+        b"" => Box::new(entry),
+        b".gz" => Box::new(flate2::read::GzDecoder::new(entry)),
+        b".xz" => Box::new(liblzma::read::XzDecoder::new(entry)),
+        b".zst" => Box::new(zstd::Decoder::new(entry)?),
+        // neither is produced by current dpkg-deb, but old packages still use them
+        b".bz2" => Box::new(bzip2::read::BzDecoder::new(entry)),
+        b".lzma" => Box::new(liblzma::read::XzDecoder::new_stream(
+            entry,
+            liblzma::stream::Stream::new_lzma_decoder(u64::MAX).map_err(std::io::Error::from)?,
+        )),
+        _ => return Ok(None),
+    };
+    Ok(Some(reader))
+}
+
+/// Find the tarball `stem` in a .deb and hand it to `f`, or return `missing` if there isn't one
+/// The control tarball is decompressed into memory up to the size limit first, data.tar is streamed.
+fn with_tar_member<T>(
+    deb: impl std::io::Read,
+    stem: &str,
+    missing: Error,
+    limits: &Limits,
+    f: impl FnOnce(&mut tar::Archive<Box<dyn Read + '_>>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut archive = ArReader::new(deb, limits)?;
+    while let Some(entry) = archive.next_member()? {
+        let identifier = entry.identifier().to_vec();
+        let Some(tar_reader) = tar_member(&identifier, stem, entry)? else {
+            continue;
         };
-        let mut untared = tar::Archive::new(tar_reader);
-        let Some(control) = untared.entries()?.find(|r| {
-            r.as_ref()
-                .is_ok_and(|r| *r.path_bytes() == *b"control" || *r.path_bytes() == *b"./control")
-        }) else {
-            return Err(Error::NoControl);
+        if stem == "control" {
+            return f(&mut buffer_control_tarball(tar_reader, limits)?);
+        }
+        return f(&mut tar::Archive::new(tar_reader));
+    }
+    Err(missing)
+}
+
+/// The file name of a control.tar member, if it is at the top of the tarball. dpkg-deb writes
+/// `./control`, but other tools write `control`, `/control` or `././control`. Long names in
+/// GNU or PAX headers are already resolved by [`tar::Entry::path_bytes`].
+fn control_member_name(path: &[u8]) -> Option<&[u8]> {
+    let mut components = path
+        .split(|b| *b == b'/')
+        .filter(|component| !component.is_empty() && *component != b".");
+    let name = components.next()?;
+    components.next().is_none().then_some(name)
+}
+
+/// Parse a file of several blank-line separated paragraphs, like a Packages or Sources index.
+/// Paragraphs holding nothing but comments are skipped.
+pub fn parse_stanzas(input: &str) -> Result<Vec<IndexMap<&str, &str>>, ParseError> {
+    let mut stanzas = Vec::new();
+    let mut start = 0;
+    let mut line_start = 0;
+    for line in input.split_inclusive('\n') {
+        let line_end = line_start + line.len();
+        if line.trim().is_empty() {
+            push_stanza(input, start..line_start, &mut stanzas)?;
+            start = line_end;
+        }
+        line_start = line_end;
+    }
+    push_stanza(input, start..input.len(), &mut stanzas)?;
+    Ok(stanzas)
+}
+
+fn push_stanza<'a>(
+    input: &'a str,
+    range: std::ops::Range<usize>,
+    stanzas: &mut Vec<IndexMap<&'a str, &'a str>>,
+) -> Result<(), ParseError> {
+    let stanza = &input[range.clone()];
+    if stanza.trim().is_empty() {
+        return Ok(());
+    }
+    let parsed = parse_control(stanza).map_err(|e| e.rebase(input, range.start))?;
+    if !parsed.is_empty() {
+        stanzas.push(parsed);
+    }
+    Ok(())
+}
+
+/// Decompress a control tarball into memory, up to the size limit
+fn buffer_control_tarball<'a>(
+    tar_reader: Box<dyn Read + 'a>,
+    limits: &Limits,
+) -> Result<tar::Archive<Box<dyn Read + 'a>>, Error> {
+    let tarball = limits::read_limited(tar_reader, limits.max_control_size)?;
+    let tar_reader: Box<dyn Read> = Box::new(std::io::Cursor::new(tarball));
+    Ok(tar::Archive::new(tar_reader))
+}
+
+/// [`parse_debfile`], also walking data.tar if the control file has no Installed-Size
+fn parse_debfile_with_installed_size(
+    deb: impl std::io::Read,
+    options: &ParseOptions,
+) -> Result<(Box<str>, Option<u64>), Error> {
+    let limits = &options.limits;
+    let mut archive = ArReader::new(deb, limits)?;
+    let mut control = None;
+    while let Some(mut entry) = archive.next_member()? {
+        let identifier = entry.identifier().to_vec();
+        if let Some(tar_reader) = tar_member(&identifier, "control", &mut entry)? {
+            let raw_controlfile =
+                read_control(&mut buffer_control_tarball(tar_reader, limits)?, limits)?;
+            let raw_controlfile = clean_if_lenient(raw_controlfile, options);
+            let parsed = parse_control_with(&raw_controlfile, options.duplicate_policy)?;
+            if find_field(&parsed, "Installed-Size").is_some() {
+                return Ok((raw_controlfile, None));
+            }
+            control = Some(raw_controlfile);
+        } else if let Some(tar_reader) = tar_member(&identifier, "data", &mut entry)? {
+            // dpkg requires the control tarball to come first
+            let raw_controlfile = control.ok_or(Error::NoControlBundle)?;
+            let entries = list_data_entries(&mut tar::Archive::new(tar_reader), limits)?;
+            return Ok((raw_controlfile, Some(installed_size(&entries))));
+        }
+    }
+    match control {
+        Some(_) => Err(Error::NoDataBundle),
+        None => Err(Error::NoControlBundle),
+    }
+}
+
+fn parse_debfile(deb: impl std::io::Read, limits: &Limits) -> Result<Box<str>, Error> {
+    with_tar_member(deb, "control", Error::NoControlBundle, limits, |untared| {
+        read_control(untared, limits)
+    })
+}
+
+/// Read the control file out of a control tarball
+fn read_control(
+    untared: &mut tar::Archive<Box<dyn Read + '_>>,
+    limits: &Limits,
+) -> Result<Box<str>, Error> {
+    read_control_entry(untared, "control", limits)?.ok_or(Error::NoControl)
+}
+
+/// Read a text file like `control` or `md5sums` out of a control tarball
+fn read_control_entry(
+    untared: &mut tar::Archive<Box<dyn Read + '_>>,
+    name: &str,
+    limits: &Limits,
+) -> Result<Option<Box<str>>, Error> {
+    let mut members = 0;
+    for entry in untared.entries()? {
+        limits::count_member(&mut members, limits.max_members)?;
+        let Ok(mut entry) = entry else {
+            continue;
         };
-        let mut control = control?;
-        let mut out_buf = String::with_capacity(control.size().try_into().unwrap_or(0));
-        control.read_to_string(&mut out_buf)?;
+        if control_member_name(&entry.path_bytes()) != Some(name.as_bytes()) {
+            continue;
+        }
+        let mut out_buf = String::with_capacity(entry_capacity(&entry, limits));
+        entry.read_to_string(&mut out_buf)?;
+        return Ok(Some(out_buf.into_boxed_str()));
+    }
+    Ok(None)
+}
+
+/// Preallocate for the size an entry claims, but never more than a control tarball can hold
+fn entry_capacity(entry: &tar::Entry<'_, impl Read>, limits: &Limits) -> usize {
+    entry
+        .size()
+        .min(limits.max_control_size)
+        .try_into()
+        .unwrap_or(0)
+}
 
-        return Ok(out_buf.into_boxed_str());
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MaintainerScriptKind {
+    Preinst,
+    Postinst,
+    Prerm,
+    Postrm,
+    /// debconf configuration script
+    Config,
+    /// not a script, but declares triggers that run scripts
+    Triggers,
+}
+
+impl MaintainerScriptKind {
+    pub const ALL: [MaintainerScriptKind; 6] = [
+        Self::Preinst,
+        Self::Postinst,
+        Self::Prerm,
+        Self::Postrm,
+        Self::Config,
+        Self::Triggers,
+    ];
+
+    /// The name of this script inside control.tar
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Preinst => "preinst",
+            Self::Postinst => "postinst",
+            Self::Prerm => "prerm",
+            Self::Postrm => "postrm",
+            Self::Config => "config",
+            Self::Triggers => "triggers",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MaintainerScript {
+    pub kind: MaintainerScriptKind,
+    /// permission bits, like `0o755`
+    pub mode: u32,
+    /// usually a shell script, but dpkg will run any executable
+    pub contents: Box<[u8]>,
+}
+
+/// Extract the maintainer scripts and triggers file from the control tarball of a .deb
+pub fn deb_to_maintainer_scripts(deb: impl std::io::Read) -> Result<Vec<MaintainerScript>, Error> {
+    let limits = Limits::default();
+    with_tar_member(deb, "control", Error::NoControlBundle, &limits, |untared| {
+        let mut scripts = Vec::new();
+        for entry in untared.entries()? {
+            let mut entry = entry?;
+            let name = entry.path_bytes();
+            let Some(kind) = MaintainerScriptKind::ALL
+                .into_iter()
+                .find(|kind| control_member_name(&name) == Some(kind.file_name().as_bytes()))
+            else {
+                continue;
+            };
+            let mode = entry.header().mode()? & 0o7777;
+            let mut contents = Vec::with_capacity(entry_capacity(&entry, &limits));
+            entry.read_to_end(&mut contents)?;
+            scripts.push(MaintainerScript {
+                kind,
+                mode,
+                contents: contents.into_boxed_slice(),
+            });
+        }
+        Ok(scripts)
+    })
+}
+
+/// A configuration file dpkg preserves local changes to, from the `conffiles` control member
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Conffile {
+    /// absolute install path, like `/etc/hello.conf`
+    pub path: Box<str>,
+    /// dpkg removes the file on upgrade instead of keeping it
+    pub remove_on_upgrade: bool,
+}
+
+/// List the conffiles of a .deb, which is empty if it has none
+pub fn deb_to_conffiles(deb: impl std::io::Read) -> Result<Vec<Conffile>, Error> {
+    match deb_to_control_entry(deb, "conffiles")? {
+        Some(conffiles) => parse_conffiles(&conffiles),
+        None => Ok(Vec::new()),
     }
-    Err(Error::NoControlBundle)
+}
+
+pub fn parse_conffiles(conffiles: &str) -> Result<Vec<Conffile>, Error> {
+    conffiles
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (flag, path) = match line.split_once(' ') {
+                Some((flag, path)) if !flag.starts_with('/') => (Some(flag), path.trim()),
+                _ => (None, line),
+            };
+            let remove_on_upgrade = match flag {
+                None => false,
+                Some("remove-on-upgrade") => true,
+                Some(_) => return Err(invalid_member_line("conffiles", line)),
+            };
+            if !path.starts_with('/') {
+                return Err(invalid_member_line("conffiles", line));
+            }
+            Ok(Conffile {
+                path: path.into(),
+                remove_on_upgrade,
+            })
+        })
+        .collect()
+}
+
+/// One line of the `md5sums` control member
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Md5sum {
+    /// absolute install path, like `/usr/bin/hello`
+    pub path: Box<str>,
+    pub md5: [u8; 16],
+}
+
+/// List the md5sums a .deb ships for its files, which is empty if it has none
+pub fn deb_to_md5sums(deb: impl std::io::Read) -> Result<Vec<Md5sum>, Error> {
+    match deb_to_control_entry(deb, "md5sums")? {
+        Some(md5sums) => parse_md5sums(&md5sums),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub fn parse_md5sums(md5sums: &str) -> Result<Vec<Md5sum>, Error> {
+    md5sums
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            // the hash and path are separated by two spaces, and paths may contain spaces
+            let (hex, path) = line
+                .split_once(' ')
+                .ok_or_else(|| invalid_member_line("md5sums", line))?;
+            let md5 = decode_md5(hex).ok_or_else(|| invalid_member_line("md5sums", line))?;
+            let path = path.trim_start();
+            if path.is_empty() {
+                return Err(invalid_member_line("md5sums", line));
+            }
+            Ok(Md5sum {
+                path: install_path(path),
+                md5,
+            })
+        })
+        .collect()
+}
+
+fn deb_to_control_entry(deb: impl std::io::Read, name: &str) -> Result<Option<Box<str>>, Error> {
+    let limits = Limits::default();
+    with_tar_member(deb, "control", Error::NoControlBundle, &limits, |untared| {
+        read_control_entry(untared, name, &limits)
+    })
+}
+
+fn decode_md5(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut md5 = [0; 16];
+    for (byte, pair) in md5.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(md5)
+}
+
+fn invalid_member_line(member: &'static str, line: &str) -> Error {
+    Error::InvalidControlMember {
+        member,
+        line: line.to_owned(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataEntryKind {
+    File,
+    Directory,
+    Symlink,
+    HardLink,
+    /// devices, fifos, and anything else packages rarely ship
+    Other,
+}
+
+/// Something a package installs, from its data tarball
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DataEntry {
+    /// absolute install path, like `/usr/bin/hello`
+    pub path: Box<str>,
+    pub size: u64,
+    /// permission bits, like `0o755`
+    pub mode: u32,
+    pub kind: DataEntryKind,
+    /// where symlinks and hard links point
+    pub link_target: Option<Box<str>>,
+}
+
+/// The contents of a file a package installs
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DataFile {
+    /// absolute install path, like `/usr/share/metainfo/hello.metainfo.xml`
+    pub path: Box<str>,
+    pub contents: Box<[u8]>,
+}
+
+/// Read the regular files in the data tarball of a .deb whose install path `wanted` accepts.
+/// They're read into memory, so files bigger than the control size limit are left out.
+pub fn deb_to_data_files(
+    deb: impl std::io::Read,
+    limits: &Limits,
+    wanted: impl Fn(&str) -> bool,
+) -> Result<Vec<DataFile>, Error> {
+    with_tar_member(deb, "data", Error::NoDataBundle, limits, |untared| {
+        let mut files = Vec::new();
+        let mut entries = 0;
+        for entry in untared.entries()? {
+            limits::count_data_entry(&mut entries, limits.max_data_entries)?;
+            let entry = entry?;
+            let header = entry.header();
+            if !matches!(
+                header.entry_type(),
+                tar::EntryType::Regular | tar::EntryType::Continuous
+            ) || header.size()? > limits.max_control_size
+            {
+                continue;
+            }
+            let path = install_path(&entry.path()?.to_string_lossy());
+            if !wanted(&path) {
+                continue;
+            }
+            let contents = limits::read_limited(entry, limits.max_control_size)?;
+            files.push(DataFile {
+                path,
+                contents: contents.into_boxed_slice(),
+            });
+        }
+        Ok(files)
+    })
+}
+
+/// List everything the data tarball of a .deb installs
+pub fn deb_to_file_list(deb: impl std::io::Read) -> Result<Vec<DataEntry>, Error> {
+    let limits = Limits::default();
+    with_tar_member(deb, "data", Error::NoDataBundle, &limits, |untared| {
+        list_data_entries(untared, &limits)
+    })
+}
+
+fn list_data_entries(
+    untared: &mut tar::Archive<Box<dyn Read + '_>>,
+    limits: &Limits,
+) -> Result<Vec<DataEntry>, Error> {
+    let mut files = Vec::new();
+    let mut entries = 0;
+    for file in untared.entries()? {
+        limits::count_data_entry(&mut entries, limits.max_data_entries)?;
+        let file = file?;
+        let header = file.header();
+        let kind = match header.entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => DataEntryKind::File,
+            tar::EntryType::Directory => DataEntryKind::Directory,
+            tar::EntryType::Symlink => DataEntryKind::Symlink,
+            tar::EntryType::Link => DataEntryKind::HardLink,
+            _ => DataEntryKind::Other,
+        };
+        let link_target = file
+            .link_name()?
+            .map(|target| target.to_string_lossy().into());
+        files.push(DataEntry {
+            path: install_path(&file.path()?.to_string_lossy()),
+            size: header.size()?,
+            mode: header.mode()? & 0o7777,
+            kind,
+            link_target,
+        });
+    }
+    Ok(files)
+}
+
+/// The Installed-Size dpkg-gencontrol would give a package with these files, in KiB:
+/// file and symlink sizes rounded up to a whole KiB each, plus a KiB for every directory
+/// and other entry. Hard links share the size of the file they point to.
+pub fn installed_size(entries: &[DataEntry]) -> u64 {
+    entries
+        .iter()
+        .map(|entry| match entry.kind {
+            DataEntryKind::File => entry.size.div_ceil(1024),
+            DataEntryKind::Symlink => {
+                let target_len = entry.link_target.as_deref().map_or(0, str::len);
+                (target_len as u64).div_ceil(1024)
+            }
+            DataEntryKind::HardLink => 0,
+            DataEntryKind::Directory | DataEntryKind::Other => 1,
+        })
+        .sum()
+}
+
+/// Tarball paths look like `./usr/bin/hello`, installed paths like `/usr/bin/hello`
+fn install_path(tar_path: &str) -> Box<str> {
+    let relative = tar_path.trim_start_matches("./").trim_start_matches('/');
+    let relative = relative.trim_end_matches('/');
+    format!("/{relative}").into_boxed_str()
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -339,14 +1195,146 @@ fn extract_keys(vals: &IndexMap<&str, &str>) -> ExtractedKeys {
     }
 }
 
+/// Where in a control file a [`ParseError`] happened
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// in bytes, from the start of the input
+    pub offset: usize,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    /// the whole line, without its newline
+    pub snippet: String,
+}
+
+impl Span {
+    fn at(input: &str, offset: usize) -> Self {
+        let before = &input[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[offset..]
+            .find('\n')
+            .map_or(input.len(), |i| offset + i);
+        Self {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: input[line_start..offset].chars().count() + 1,
+            snippet: input[line_start..line_end].to_owned(),
+        }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: `{}`",
+            self.line, self.column, self.snippet
+        )
+    }
+}
+
+/// Add a field, returning false if it is a duplicate the policy rejects.
+/// Field names are case-insensitive, so `package` repeats `Package`.
+fn insert_field<'a>(
+    output: &mut IndexMap<&'a str, &'a str>,
+    key: &'a str,
+    value: &'a str,
+    duplicates: DuplicatePolicy,
+) -> bool {
+    let Some(index) = output.keys().position(|k| k.eq_ignore_ascii_case(key)) else {
+        output.insert(key, value);
+        return true;
+    };
+    match duplicates {
+        DuplicatePolicy::Reject => false,
+        DuplicatePolicy::AllowIdentical => output[index].trim() == value.trim(),
+        DuplicatePolicy::KeepFirst => true,
+        DuplicatePolicy::KeepLast => {
+            output[index] = value;
+            true
+        }
+    }
+}
+
+/// Fix the harmless ways control files written by hand go wrong:
+/// CRLF line endings, and no newline at the end of the file
+pub fn clean_control(input: &str) -> Cow<'_, str> {
+    let missing_newline = !input.is_empty() && !input.ends_with('\n');
+    if !missing_newline && !input.contains("\r\n") {
+        return Cow::Borrowed(input);
+    }
+    let mut cleaned = input.replace("\r\n", "\n");
+    if missing_newline {
+        cleaned.push('\n');
+    }
+    Cow::Owned(cleaned)
+}
+
+pub(crate) fn clean_if_lenient(raw_controlfile: Box<str>, options: &ParseOptions) -> Box<str> {
+    if !options.lenient {
+        return raw_controlfile;
+    }
+    let cleaned = match clean_control(&raw_controlfile) {
+        Cow::Owned(cleaned) => Some(cleaned),
+        Cow::Borrowed(_) => None,
+    };
+    cleaned.map_or(raw_controlfile, Into::into)
+}
+
+/// Where `part`, which must be a slice of `input`, starts in it
+fn offset_in(input: &str, part: &str) -> usize {
+    part.as_ptr() as usize - input.as_ptr() as usize
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, thiserror::Error)]
 pub enum ParseError {
-    #[error("duplicate key: `{0}`")]
-    DuplicateKey(String),
-    #[error("key without value: `{0}`")]
-    NoValueForKey(String),
+    #[error("duplicate key `{key}` at {span}")]
+    DuplicateKey { key: String, span: Span },
+    #[error("key `{key}` without value at {span}")]
+    NoValueForKey { key: String, span: Span },
     #[error("key not complete at {0}")]
-    IncompleteKey(usize),
-    #[error("file must end in newline")]
-    MustEndInNewline,
+    IncompleteKey(Span),
+    #[error("file must end in newline, at {0}")]
+    MustEndInNewline(Span),
+}
+
+impl ParseError {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::DuplicateKey { .. } => "GV1001",
+            Self::NoValueForKey { .. } => "GV1002",
+            Self::IncompleteKey(_) => "GV1003",
+            Self::MustEndInNewline(_) => "GV1004",
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            Self::DuplicateKey { span, .. }
+            | Self::NoValueForKey { span, .. }
+            | Self::IncompleteKey(span)
+            | Self::MustEndInNewline(span) => span,
+        }
+    }
+
+    fn duplicate_key(input: &str, key: &str) -> Self {
+        Self::DuplicateKey {
+            key: key.to_owned(),
+            span: Span::at(input, offset_in(input, key)),
+        }
+    }
+
+    /// Move the span of an error in a slice of `input` starting at `base` to be relative to all of `input`
+    fn rebase(mut self, input: &str, base: usize) -> Self {
+        let span = match &mut self {
+            Self::DuplicateKey { span, .. }
+            | Self::NoValueForKey { span, .. }
+            | Self::IncompleteKey(span)
+            | Self::MustEndInNewline(span) => span,
+        };
+        *span = Span::at(input, base + span.offset);
+        self
+    }
 }
@@ -0,0 +1,83 @@
+//! Bounds on how much a package may make us decompress and allocate. Uploads are
+//! untrusted, and a few kilobytes of xz can expand to gigabytes.
+
+use std::{fmt::Display, io::Read};
+
+use crate::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Limits {
+    /// members of the ar archive, and entries of the control tarball, each
+    pub max_members: usize,
+    /// the decompressed size of the control tarball, in bytes
+    pub max_control_size: u64,
+    /// fields in the control file
+    pub max_fields: usize,
+    /// the size of any one ar member, in bytes
+    pub max_member_size: u64,
+    /// entries of the data tarball, when it's read at all
+    pub max_data_entries: usize,
+}
+
+impl Default for Limits {
+    /// Far beyond anything dpkg-deb produces
+    fn default() -> Self {
+        Self {
+            max_members: 64,
+            max_control_size: 16 * 1024 * 1024,
+            max_fields: 1024,
+            max_member_size: 64 * 1024 * 1024 * 1024,
+            max_data_entries: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    Members,
+    ControlSize,
+    Fields,
+    MemberSize,
+    DataEntries,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Self::Members => "member count",
+            Self::ControlSize => "control tarball size",
+            Self::Fields => "field count",
+            Self::MemberSize => "archive member size",
+            Self::DataEntries => "data tarball entry count",
+        };
+        f.write_str(str)
+    }
+}
+
+/// Read all of `reader`, failing once it goes over `max` bytes
+pub(crate) fn read_limited(reader: impl Read, max: u64) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    reader.take(max.saturating_add(1)).read_to_end(&mut buf)?;
+    if buf.len() as u64 > max {
+        return Err(Error::LimitExceeded(Limit::ControlSize));
+    }
+    Ok(buf)
+}
+
+/// Count one more member, failing once there are more than `max`
+pub(crate) fn count_member(count: &mut usize, max: usize) -> Result<(), Error> {
+    count_up_to(count, max, Limit::Members)
+}
+
+/// Count one more entry of the data tarball, failing once there are more than `max`
+pub(crate) fn count_data_entry(count: &mut usize, max: usize) -> Result<(), Error> {
+    count_up_to(count, max, Limit::DataEntries)
+}
+
+fn count_up_to(count: &mut usize, max: usize, limit: Limit) -> Result<(), Error> {
+    *count += 1;
+    if *count > max {
+        return Err(Error::LimitExceeded(limit));
+    }
+    Ok(())
+}
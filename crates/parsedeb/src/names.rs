@@ -0,0 +1,199 @@
+//! Package names, architectures and versions, checked once when they are parsed so nothing
+//! that holds one has to check it again. Each derefs to the trimmed string it was parsed from.
+
+use std::{fmt::Display, hash::Hash, ops::Deref, str::FromStr};
+
+use crate::{DebianVersion, Error, relation::valid_package_name};
+
+/// A package name: lowercase alphanumerics and `+`, `-` and `.`, starting with an alphanumeric
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct PackageName(Box<str>);
+
+/// A single architecture like `amd64` or `all`, never a wildcard or a list
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct Architecture(Box<str>);
+
+/// A version as it was written, so `1.0` and `1.00` are different versions here even though
+/// dpkg orders them the same. Compare [`Version::parsed`] to order them.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct Version {
+    text: Box<str>,
+    parsed: DebianVersion,
+}
+
+impl PackageName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Architecture {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Version {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn parsed(&self) -> &DebianVersion {
+        &self.parsed
+    }
+}
+
+impl FromStr for PackageName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        if !valid_package_name(s) {
+            return Err(Error::InvalidPackageName(s.to_owned()));
+        }
+        Ok(Self(s.into()))
+    }
+}
+
+impl FromStr for Architecture {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        let valid = !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !valid {
+            return Err(Error::UnknownArchitecture(s.to_owned()));
+        }
+        Ok(Self(s.into()))
+    }
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        Ok(Self {
+            text: s.into(),
+            parsed: s.parse()?,
+        })
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Eq for Version {}
+
+impl Hash for Version {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.text.hash(state);
+    }
+}
+
+impl Deref for PackageName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Deref for Architecture {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Deref for Version {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for PackageName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self)
+    }
+}
+
+impl Display for Architecture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self)
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<String> for PackageName {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Error> {
+        value.parse()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<String> for Architecture {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Error> {
+        value.parse()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<String> for Version {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Error> {
+        value.parse()
+    }
+}
+
+impl From<PackageName> for String {
+    fn from(value: PackageName) -> Self {
+        value.0.into()
+    }
+}
+
+impl From<Architecture> for String {
+    fn from(value: Architecture) -> Self {
+        value.0.into()
+    }
+}
+
+impl From<Version> for String {
+    fn from(value: Version) -> Self {
+        value.text.into()
+    }
+}
@@ -0,0 +1,311 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::{DebianVersion, VersionError};
+
+/// Every control field whose value is a list of package relationships
+pub const RELATION_FIELDS: [&str; 11] = [
+    "Depends",
+    "Pre-Depends",
+    "Recommends",
+    "Suggests",
+    "Enhances",
+    "Breaks",
+    "Conflicts",
+    "Provides",
+    "Replaces",
+    "Built-Using",
+    "Static-Built-Using",
+];
+
+/// One comma-separated item of a relationship field, satisfied by any of its alternatives
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Relation {
+    pub alternatives: Vec<Dependency>,
+}
+
+/// A single package reference, like `libc6:any (>= 2.36) [amd64 arm64] <!nocheck>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dependency {
+    pub name: Box<str>,
+    /// the multiarch qualifier after the colon, like `any` or `native`
+    pub arch_qualifier: Option<Box<str>>,
+    pub version: Option<VersionConstraint>,
+    /// architecture restrictions from build relationships, empty when unrestricted
+    pub architectures: Vec<ArchRestriction>,
+    /// build profile formulas, each an AND of terms, satisfied if any one of them is
+    pub profiles: Vec<Vec<ProfileTerm>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VersionConstraint {
+    pub operator: VersionOperator,
+    pub version: DebianVersion,
+}
+
+impl VersionConstraint {
+    pub fn matches(&self, version: &DebianVersion) -> bool {
+        let ordering = version.cmp(&self.version);
+        match self.operator {
+            VersionOperator::Earlier => ordering.is_lt(),
+            VersionOperator::EarlierOrEqual => ordering.is_le(),
+            VersionOperator::Equal => ordering.is_eq(),
+            VersionOperator::LaterOrEqual => ordering.is_ge(),
+            VersionOperator::Later => ordering.is_gt(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VersionOperator {
+    /// `<<`
+    Earlier,
+    /// `<=`, or the obsolete `<`
+    EarlierOrEqual,
+    /// `=`
+    Equal,
+    /// `>=`, or the obsolete `>`
+    LaterOrEqual,
+    /// `>>`
+    Later,
+}
+
+impl VersionOperator {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Earlier => "<<",
+            Self::EarlierOrEqual => "<=",
+            Self::Equal => "=",
+            Self::LaterOrEqual => ">=",
+            Self::Later => ">>",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchRestriction {
+    pub negated: bool,
+    pub architecture: Box<str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProfileTerm {
+    pub negated: bool,
+    pub profile: Box<str>,
+}
+
+/// Parse the value of a relationship field like `Depends` into its items.
+/// Empty items, such as those left by a trailing comma, are skipped.
+pub fn parse_relations(value: &str) -> Result<Vec<Relation>, RelationError> {
+    value
+        .split(',')
+        .filter(|item| !item.trim().is_empty())
+        .map(|item| {
+            let alternatives = item
+                .split('|')
+                .map(parse_dependency)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Relation { alternatives })
+        })
+        .collect()
+}
+
+fn parse_dependency(input: &str) -> Result<Dependency, RelationError> {
+    let mut rest = input.trim();
+    if rest.is_empty() {
+        return Err(RelationError::EmptyAlternative);
+    }
+
+    let name_end = rest
+        .find(|c: char| c.is_whitespace() || matches!(c, '(' | '[' | '<' | ':'))
+        .unwrap_or(rest.len());
+    let name = &rest[..name_end];
+    if !valid_package_name(name) {
+        return Err(RelationError::InvalidName(name.to_owned()));
+    }
+    rest = &rest[name_end..];
+
+    let mut arch_qualifier = None;
+    if let Some(after) = rest.strip_prefix(':') {
+        let end = after
+            .find(|c: char| c.is_whitespace() || matches!(c, '(' | '[' | '<'))
+            .unwrap_or(after.len());
+        if end == 0 {
+            return Err(RelationError::Unexpected(rest.to_owned()));
+        }
+        arch_qualifier = Some(after[..end].into());
+        rest = &after[end..];
+    }
+    rest = rest.trim_start();
+
+    let mut version = None;
+    if let Some(after) = rest.strip_prefix('(') {
+        let (inside, after) = after.split_once(')').ok_or(RelationError::Unclosed('('))?;
+        version = Some(parse_constraint(inside)?);
+        rest = after.trim_start();
+    }
+
+    let mut architectures = Vec::new();
+    if let Some(after) = rest.strip_prefix('[') {
+        let (inside, after) = after.split_once(']').ok_or(RelationError::Unclosed('['))?;
+        architectures = inside
+            .split_whitespace()
+            .map(|term| {
+                let (negated, architecture) = negation(term);
+                ArchRestriction {
+                    negated,
+                    architecture: architecture.into(),
+                }
+            })
+            .collect();
+        rest = after.trim_start();
+    }
+
+    let mut profiles = Vec::new();
+    while let Some(after) = rest.strip_prefix('<') {
+        let (inside, after) = after.split_once('>').ok_or(RelationError::Unclosed('<'))?;
+        profiles.push(
+            inside
+                .split_whitespace()
+                .map(|term| {
+                    let (negated, profile) = negation(term);
+                    ProfileTerm {
+                        negated,
+                        profile: profile.into(),
+                    }
+                })
+                .collect(),
+        );
+        rest = after.trim_start();
+    }
+
+    if !rest.is_empty() {
+        return Err(RelationError::Unexpected(rest.to_owned()));
+    }
+    Ok(Dependency {
+        name: name.into(),
+        arch_qualifier,
+        version,
+        architectures,
+        profiles,
+    })
+}
+
+fn parse_constraint(inside: &str) -> Result<VersionConstraint, RelationError> {
+    let inside = inside.trim();
+    let operator_len = inside
+        .find(|c: char| c.is_whitespace() || c.is_ascii_alphanumeric())
+        .unwrap_or(inside.len());
+    let operator = match &inside[..operator_len] {
+        "<<" => VersionOperator::Earlier,
+        "<=" | "<" => VersionOperator::EarlierOrEqual,
+        "=" => VersionOperator::Equal,
+        ">=" | ">" => VersionOperator::LaterOrEqual,
+        ">>" => VersionOperator::Later,
+        other => return Err(RelationError::InvalidOperator(other.to_owned())),
+    };
+    let version = inside[operator_len..].trim().parse()?;
+    Ok(VersionConstraint { operator, version })
+}
+
+fn negation(term: &str) -> (bool, &str) {
+    match term.strip_prefix('!') {
+        Some(term) => (true, term),
+        None => (false, term),
+    }
+}
+
+/// Lowercase letters, digits, and `+-.`, at least two characters, starting alphanumeric
+pub(crate) fn valid_package_name(name: &str) -> bool {
+    name.len() >= 2
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '+' | '-' | '.'))
+}
+
+impl Display for Relation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        for (i, alternative) in self.alternatives.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" | ")?;
+            }
+            write!(f, "{alternative}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Dependency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(&self.name)?;
+        if let Some(qualifier) = &self.arch_qualifier {
+            write!(f, ":{qualifier}")?;
+        }
+        if let Some(version) = &self.version {
+            write!(f, " ({} {})", version.operator.as_str(), version.version)?;
+        }
+        if !self.architectures.is_empty() {
+            f.write_str(" [")?;
+            for (i, arch) in self.architectures.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(" ")?;
+                }
+                if arch.negated {
+                    f.write_str("!")?;
+                }
+                f.write_str(&arch.architecture)?;
+            }
+            f.write_str("]")?;
+        }
+        for formula in &self.profiles {
+            f.write_str(" <")?;
+            for (i, term) in formula.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(" ")?;
+                }
+                if term.negated {
+                    f.write_str("!")?;
+                }
+                f.write_str(&term.profile)?;
+            }
+            f.write_str(">")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum RelationError {
+    #[error("empty alternative in relationship")]
+    EmptyAlternative,
+    #[error("invalid package name `{0}`")]
+    InvalidName(String),
+    #[error("unclosed `{0}` in relationship")]
+    Unclosed(char),
+    #[error("invalid version operator `{0}`")]
+    InvalidOperator(String),
+    #[error("invalid version in relationship: {0}")]
+    Version(#[from] VersionError),
+    #[error("unexpected `{0}` in relationship")]
+    Unexpected(String),
+}
+
+impl RelationError {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::EmptyAlternative => "GV1301",
+            Self::InvalidName(_) => "GV1302",
+            Self::Unclosed(_) => "GV1303",
+            Self::InvalidOperator(_) => "GV1304",
+            Self::Version(e) => e.code(),
+            Self::Unexpected(_) => "GV1305",
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Parsing a deb that is already in memory, like a mapped file. The ar container is split up in
+//! place, and the control file of an uncompressed control tarball is used without being copied.
+
+use std::borrow::Cow;
+
+use crate::{
+    Error, Limit, Limits, ParseOptions,
+    archive::{
+        AR_HEADER_LEN, AR_MAGIC, ArchiveError, FORMAT_MEMBER, MemberChecks, ar_header,
+        bsd_name_len, check_format,
+    },
+    clean_control, control_member_name, limits, tar_member,
+};
+
+/// The raw control file of a deb held in memory, for [`get_control_with`](crate::get_control_with)
+/// to parse in place. It is borrowed from `deb` when the control tarball is uncompressed,
+/// otherwise the tarball is decompressed and only the control file is copied out of it.
+/// Only the control file is checked to be UTF-8.
+pub fn control_from_slice<'a>(
+    deb: &'a [u8],
+    options: &ParseOptions,
+) -> Result<Cow<'a, str>, Error> {
+    let limits = &options.limits;
+    for member in ArMembers::new(deb, limits)? {
+        let (identifier, contents) = member?;
+        let control: Cow<'a, [u8]> = if identifier == b"control.tar" {
+            if contents.len() as u64 > limits.max_control_size {
+                return Err(Error::LimitExceeded(Limit::ControlSize));
+            }
+            Cow::Borrowed(find_in_tarball(contents, "control", limits)?.ok_or(Error::NoControl)?)
+        } else if let Some(tar_reader) = tar_member(identifier, "control", contents)? {
+            let tarball = limits::read_limited(tar_reader, limits.max_control_size)?;
+            let control = find_in_tarball(&tarball, "control", limits)?.ok_or(Error::NoControl)?;
+            Cow::Owned(control.to_vec())
+        } else {
+            continue;
+        };
+
+        let control = match control {
+            Cow::Borrowed(bytes) => Cow::Borrowed(std::str::from_utf8(bytes)?),
+            Cow::Owned(bytes) => Cow::Owned(String::from_utf8(bytes).map_err(|e| e.utf8_error())?),
+        };
+        if options.lenient
+            && let Cow::Owned(cleaned) = clean_control(&control)
+        {
+            return Ok(Cow::Owned(cleaned));
+        }
+        return Ok(control);
+    }
+    Err(Error::NoControlBundle)
+}
+
+/// The contents of the file `name` in an uncompressed tarball, as a slice of it
+fn find_in_tarball<'a>(
+    tarball: &'a [u8],
+    name: &str,
+    limits: &Limits,
+) -> Result<Option<&'a [u8]>, Error> {
+    let mut archive = tar::Archive::new(tarball);
+    let mut members = 0;
+    for entry in archive.entries()? {
+        limits::count_member(&mut members, limits.max_members)?;
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if control_member_name(&entry.path_bytes()) != Some(name.as_bytes()) {
+            continue;
+        }
+        let start = usize::try_from(entry.raw_file_position()).ok();
+        let len = usize::try_from(entry.size()).ok();
+        let contents = start
+            .zip(len)
+            .and_then(|(start, len)| tarball.get(start..start.checked_add(len)?))
+            .ok_or(ArchiveError::Truncated)?;
+        return Ok(Some(contents));
+    }
+    Ok(None)
+}
+
+/// The members of an ar archive as `(identifier, contents)` slices of it
+struct ArMembers<'a> {
+    rest: &'a [u8],
+    checks: MemberChecks,
+}
+
+impl<'a> ArMembers<'a> {
+    fn new(archive: &'a [u8], limits: &Limits) -> Result<Self, Error> {
+        let rest = archive.strip_prefix(AR_MAGIC).ok_or(ArchiveError::NotAr)?;
+        Ok(Self {
+            rest,
+            checks: MemberChecks::new(limits),
+        })
+    }
+
+    fn next_member(&mut self) -> Result<(&'a [u8], &'a [u8]), Error> {
+        let truncated = ArchiveError::Truncated;
+        let (header, rest) = self
+            .rest
+            .split_at_checked(AR_HEADER_LEN)
+            .ok_or(truncated.clone())?;
+        let (identifier, size) = ar_header(header)?;
+        self.checks.header(size)?;
+        let size = usize::try_from(size).map_err(|_| truncated.clone())?;
+        let (mut contents, rest) = rest.split_at_checked(size).ok_or(truncated)?;
+        // members start on even offsets
+        self.rest = rest.get(size % 2..).unwrap_or_default();
+
+        let mut identifier = identifier;
+        if let Some(len) = bsd_name_len(identifier, size as u64)? {
+            let (name, after) = contents.split_at(len as usize);
+            identifier = name.split(|b| *b == 0).next().unwrap_or_default();
+            contents = after;
+        }
+        self.checks.identifier(identifier)?;
+        if identifier == FORMAT_MEMBER {
+            check_format(contents)?;
+        }
+        Ok((identifier, contents))
+    }
+}
+
+impl<'a> Iterator for ArMembers<'a> {
+    type Item = Result<(&'a [u8], &'a [u8]), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let member = self.next_member();
+        if member.is_err() {
+            self.rest = &[];
+        }
+        Some(member)
+    }
+}
+
+/// Map a package file into memory, for [`control_from_slice`]
+///
+/// # Safety
+///
+/// The file must not be modified, or truncated, while the map is alive. Debs that are only
+/// ever replaced by renaming a new file over them are fine.
+#[cfg(feature = "mmap")]
+pub unsafe fn map_deb(path: &std::path::Path) -> Result<memmap2::Mmap, Error> {
+    let file = std::fs::File::open(path)?;
+    // SAFETY: upheld by the caller
+    Ok(unsafe { memmap2::Mmap::map(&file)? })
+}
@@ -0,0 +1,98 @@
+//! The source package a binary package was built from, and grouping binaries by it, which is
+//! how the pool is laid out: `pool/main/h/hello/` holds every package built from `hello`.
+
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+
+use crate::{ControlFields, Error, PackageName, Version};
+
+/// The `Source` field of a binary package, like `hello` or `hello (1.0-1)`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceRef {
+    pub name: PackageName,
+    /// only present when it differs from the binary package's version, like after a binNMU
+    pub version: Option<Version>,
+}
+
+impl FromStr for SourceRef {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidSourceField(value.trim().to_owned());
+        let (name, version) = match value.split_once('(') {
+            Some((name, rest)) => {
+                let version = rest.trim_end().strip_suffix(')').ok_or_else(invalid)?;
+                (name, Some(version.parse()?))
+            }
+            None => (value, None),
+        };
+        Ok(Self {
+            name: name.parse().map_err(|_| invalid())?,
+            version,
+        })
+    }
+}
+
+/// A source package and version, as every binary package built from it agrees on
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourcePackage {
+    pub name: PackageName,
+    pub version: Version,
+}
+
+impl SourcePackage {
+    /// The source a binary package was built from. Without a `Source` field it is the
+    /// package itself, and without a version in `Source` it has the package's version.
+    pub fn of(fields: &impl ControlFields) -> Result<Self, Error> {
+        let source = fields
+            .field("Source")
+            .map(SourceRef::from_str)
+            .transpose()?;
+        let version = match source.as_ref().and_then(|source| source.version.clone()) {
+            Some(version) => version,
+            None => fields
+                .field("Version")
+                .ok_or(Error::MissingField("Version"))?
+                .parse()?,
+        };
+        let name = match source {
+            Some(source) => source.name,
+            None => fields
+                .field("Package")
+                .ok_or(Error::MissingField("Package"))?
+                .parse()?,
+        };
+        Ok(Self { name, version })
+    }
+
+    /// Where packages built from this source go in `component`, like `pool/main/h/hello/`.
+    /// Libraries are split up further, into directories like `pool/main/libc/`.
+    pub fn pool_directory(&self, component: &str) -> String {
+        let name = self.name.as_str();
+        let prefix_len = if name.starts_with("lib") && name.len() > 3 {
+            4
+        } else {
+            1
+        };
+        // package names are ASCII
+        format!("pool/{component}/{}/{name}/", &name[..prefix_len])
+    }
+}
+
+/// Group binary packages by the source they were built from, keeping the order each source
+/// and each binary was first seen in
+pub fn group_by_source<'a, T: ControlFields + 'a>(
+    packages: impl IntoIterator<Item = &'a T>,
+) -> Result<IndexMap<SourcePackage, Vec<&'a T>>, Error> {
+    let mut groups: IndexMap<SourcePackage, Vec<&'a T>> = IndexMap::new();
+    for package in packages {
+        groups
+            .entry(SourcePackage::of(package)?)
+            .or_default()
+            .push(package);
+    }
+    Ok(groups)
+}
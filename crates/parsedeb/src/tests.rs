@@ -12,10 +12,8 @@ fn boring() {
 
 #[test]
 fn boring_nonewline() {
-    assert_eq!(
-        parse_control(include_str!("testfiles/noextranewline.control")).unwrap_err(),
-        ParseError::MustEndInNewline
-    );
+    let err = parse_control(include_str!("testfiles/noextranewline.control")).unwrap_err();
+    assert!(matches!(err, ParseError::MustEndInNewline(_)));
 }
 
 #[test]
@@ -44,7 +42,7 @@ fn multiline() {
 #[test]
 fn novalue() {
     let err = parse_control(include_str!("testfiles/novalue.control")).unwrap_err();
-    let invalid = matches!(err, ParseError::NoValueForKey(_));
+    let invalid = matches!(err, ParseError::NoValueForKey { .. });
     assert!(invalid);
 }
 
@@ -56,10 +54,1084 @@ fn nocolon() {
     assert!(invalid);
 }
 
+#[test]
+fn error_spans() {
+    let err = parse_control("Package: a\nDescription: d\n  ü\nbroken").unwrap_err();
+    let span = err.span();
+    assert_eq!((span.offset, span.line, span.column), (31, 4, 1));
+    assert_eq!(span.snippet, "broken");
+
+    let err = parse_control("Package: a\nVersion: 1\nPackage: b\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "duplicate key `Package` at line 3, column 1: `Package: b`"
+    );
+
+    let err = parse_control("Package: a\nDescription: ünterminated").unwrap_err();
+    assert_eq!((err.span().line, err.span().column), (2, 26));
+}
+
 #[test]
 fn duplicate() {
     let repeated = include_str!("testfiles/boring.control").repeat(2);
     let err = parse_control(&repeated).unwrap_err();
-    let invalid = matches!(err, ParseError::DuplicateKey(_));
+    let invalid = matches!(err, ParseError::DuplicateKey { .. });
     assert!(invalid);
+
+    let err = parse_control("Package: a\npackage: b\n").unwrap_err();
+    assert!(matches!(err, ParseError::DuplicateKey { key, .. } if key == "package"));
+}
+
+#[test]
+fn lenient() {
+    let crlf = "Package: hello\r\nVersion: 1.0-1\r\nArchitecture: all\r\nMaintainer: me\r\nDescription: hi";
+    assert!(get_control(crlf).is_err());
+    let cleaned = clean_control(crlf);
+    assert!(!cleaned.contains('\r') && cleaned.ends_with("hi\n"));
+    let fields = get_control(&cleaned).unwrap();
+    assert_eq!(fields["Version"], " 1.0-1\n");
+    assert!(matches!(clean_control("Package: a\n"), Cow::Borrowed(_)));
+
+    let repeated = "Package: a\nSection: x\nsection: x\n";
+    let err = parse_control(repeated).unwrap_err();
+    assert!(matches!(err, ParseError::DuplicateKey { .. }));
+    let fields = parse_control_with(repeated, DuplicatePolicy::AllowIdentical).unwrap();
+    assert_eq!(fields.len(), 2);
+    let conflicting = "Package: a\nSection: x\nSection: y\n";
+    assert!(parse_control_with(conflicting, DuplicatePolicy::AllowIdentical).is_err());
+    let first = parse_control_with(conflicting, DuplicatePolicy::KeepFirst).unwrap();
+    assert_eq!(first["Section"], " x\n");
+    let last = parse_control_with(conflicting, DuplicatePolicy::KeepLast).unwrap();
+    assert_eq!(last["Section"], " y\n");
+}
+
+#[test]
+fn warnings() {
+    let long = "a".repeat(81);
+    let control = format!(
+        "Package: hello\nVersion: 1.0-1\nArchitecture: all\nmaintainer: me\nX-Custom: yes\nFrobnicate: yes\nDescription: {long}\n more\n"
+    );
+    let (fields, warnings) = get_control_with_warnings(&control, &ParseOptions::default()).unwrap();
+    assert_eq!(fields.len(), 7);
+    assert_eq!(
+        warnings,
+        [
+            Warning::Capitalization {
+                found: "maintainer".into(),
+                expected: "Maintainer"
+            },
+            Warning::UnknownField("Frobnicate".into()),
+            Warning::LongSynopsis(81),
+        ]
+    );
+    assert_eq!(warnings[1].code(), "GV1401");
+
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    let (fields, _raw) = deb_to_control(&deb[..]).unwrap();
+    assert!(control_warnings(&fields).is_empty());
+}
+
+#[test]
+fn description_folding() {
+    let raw = " greets you \n a tiny package\n .\n\t  indented stays indented\n";
+    let description = Description::parse(raw);
+    assert_eq!(&*description.synopsis, "greets you");
+    assert_eq!(
+        description.extended,
+        [
+            "a tiny package".into(),
+            "".into(),
+            "  indented stays indented".into()
+        ] as [Box<str>; 3]
+    );
+    let folded = "greets you\n a tiny package\n .\n   indented stays indented";
+    assert_eq!(description.to_string(), folded);
+    assert_eq!(
+        Description::parse(&description.to_field_value()),
+        description
+    );
+
+    let mut fields: PackageMap = [("description".into(), raw.into())].into_iter().collect();
+    normalize_description(&mut fields);
+    assert_eq!(&*fields["description"], format!(" {folded}\n"));
+}
+
+#[test]
+fn field_lookup() {
+    let mut fields: PackageMap =
+        parse_control("package: hello\nx-custom-field: yes\nMD5SUM: abc\n")
+            .unwrap()
+            .into_iter()
+            .map(pack)
+            .collect();
+    assert_eq!(fields.field("Package"), Some("hello"));
+    assert_eq!(fields.raw_field("PACKAGE"), Some(" hello\n"));
+    assert_eq!(fields.field("Version"), None);
+
+    assert_eq!(canonical_field_name("pre-depends"), "Pre-Depends");
+    assert_eq!(canonical_field_name("X-Already-Fine"), "X-Already-Fine");
+    normalize_keys(&mut fields);
+    let keys: Vec<&str> = fields.keys().map(|key| &**key).collect();
+    assert_eq!(keys, ["Package", "X-Custom-Field", "MD5sum"]);
+}
+
+#[test]
+fn error_codes() {
+    let repeated = include_str!("testfiles/boring.control").repeat(2);
+    let err = Error::from(parse_control(&repeated).unwrap_err());
+    assert_eq!(err.code(), "GV1001");
+    assert_eq!(Error::NoControlBundle.code(), "GV1101");
+}
+
+#[test]
+fn file_list() {
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    let files = deb_to_file_list(&deb[..]).unwrap();
+    let hello = files.iter().find(|f| &*f.path == "/usr/bin/hello").unwrap();
+    assert_eq!(hello.kind, DataEntryKind::File);
+    assert_eq!(hello.size, 21);
+    assert_eq!(hello.mode, 0o755);
+
+    let hi = files.iter().find(|f| &*f.path == "/usr/bin/hi").unwrap();
+    assert_eq!(hi.kind, DataEntryKind::Symlink);
+    assert_eq!(hi.link_target.as_deref(), Some("hello"));
+
+    let root = files.first().unwrap();
+    assert_eq!((&*root.path, root.kind), ("/", DataEntryKind::Directory));
+}
+
+#[test]
+fn data_files() {
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    let files = deb_to_data_files(&deb[..], &Limits::default(), |path| {
+        path.starts_with("/usr/bin/")
+    })
+    .unwrap();
+    // the /usr/bin/hi symlink isn't a regular file
+    assert_eq!(files.len(), 1);
+    assert_eq!(&*files[0].path, "/usr/bin/hello");
+    assert_eq!(files[0].contents.len(), 21);
+}
+
+#[test]
+fn computed_installed_size() {
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    // seven directories, three small files and a symlink
+    assert_eq!(installed_size(&deb_to_file_list(&deb[..]).unwrap()), 11);
+
+    let options = ParseOptions {
+        compute_installed_size: true,
+        ..Default::default()
+    };
+    let (fields, raw) = deb_to_control_with(&deb[..], &options).unwrap();
+    assert_eq!(&*fields["Installed-Size"], " 11\n");
+    assert_eq!(fields.get_index_of("Installed-Size"), Some(4));
+    assert!(!raw.contains("Installed-Size"));
+
+    let (fields, _raw) = deb_to_control(&deb[..]).unwrap();
+    assert!(!fields.contains_key("Installed-Size"));
+
+    let limited = ParseOptions {
+        limits: Limits {
+            max_data_entries: 10,
+            ..Limits::default()
+        },
+        ..options
+    };
+    assert!(matches!(
+        deb_to_control_with(&deb[..], &limited),
+        Err(Error::LimitExceeded(Limit::DataEntries))
+    ));
+    assert!(matches!(
+        deb_to_data_files(&deb[..], &limited.limits, |_| true),
+        Err(Error::LimitExceeded(Limit::DataEntries))
+    ));
+}
+
+#[test]
+fn control_from_deb() {
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    let (fields, _raw) = deb_to_control(&deb[..]).unwrap();
+    let required = RequiredFields::from_map(&fields).unwrap();
+    assert_eq!(&*required.version, "1.0-1");
+}
+
+#[test]
+fn control_from_bytes() {
+    let options = ParseOptions::default();
+    let compressed = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    let (_fields, raw) = deb_to_control(&compressed[..]).unwrap();
+    let control = control_from_slice(compressed, &options).unwrap();
+    assert!(matches!(control, Cow::Owned(_)));
+    assert_eq!(*control, *raw);
+
+    let uncompressed = include_bytes!("testfiles/hello_1.0-1_all.uncompressed.deb");
+    let control = control_from_slice(uncompressed, &options).unwrap();
+    assert!(matches!(control, Cow::Borrowed(_)));
+    assert_eq!(*control, *raw);
+    assert_eq!(get_control(&control).unwrap()["Package"], " hello\n");
+
+    let truncated = &uncompressed[..uncompressed.len() / 2];
+    assert!(control_from_slice(truncated, &options).is_err());
+    assert!(control_from_slice(b"not a deb", &options).is_err());
+}
+
+#[test]
+fn maintainer_scripts() {
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    let scripts = deb_to_maintainer_scripts(&deb[..]).unwrap();
+    let kinds: Vec<_> = scripts.iter().map(|s| s.kind).collect();
+    assert_eq!(
+        kinds,
+        [MaintainerScriptKind::Postinst, MaintainerScriptKind::Prerm]
+    );
+    assert_eq!(scripts[0].mode, 0o755);
+    assert_eq!(
+        &*scripts[0].contents,
+        b"#!/bin/sh\nset -e\necho installed\n"
+    );
+}
+
+#[test]
+fn version_parts() {
+    let version: DebianVersion = "2:1.0-rc1-3ubuntu1".parse().unwrap();
+    assert_eq!(version.epoch(), 2);
+    assert_eq!(version.upstream(), "1.0-rc1");
+    assert_eq!(version.revision(), Some("3ubuntu1"));
+    assert_eq!(version.to_string(), "2:1.0-rc1-3ubuntu1");
+
+    assert_eq!(
+        "a:1".parse::<DebianVersion>().unwrap_err(),
+        VersionError::InvalidEpoch
+    );
+    assert_eq!(
+        "1.0-".parse::<DebianVersion>().unwrap_err(),
+        VersionError::EmptyRevision
+    );
+    assert_eq!(
+        "1:".parse::<DebianVersion>().unwrap_err(),
+        VersionError::EmptyUpstream
+    );
+}
+
+#[test]
+fn version_ordering() {
+    let v = |s: &str| s.parse::<DebianVersion>().unwrap();
+    // each version sorts before the next, per deb-version(7)
+    let ascending = [
+        "1.0~rc1", "1.0", "1.0-1", "1.0-1.1", "1.0a", "1.0+b1", "1.2", "1.10", "1:0.1",
+    ];
+    for pair in ascending.windows(2) {
+        assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+    }
+    assert!(v("1.0~~") < v("1.0~"));
+    assert_eq!(v("0:1.0"), v("1.0"));
+    assert_eq!(v("1.01"), v("1.1"));
+}
+
+#[test]
+fn relations() {
+    let relations = parse_relations(
+        "libc6 (>= 2.36), default-mta | mail-transport-agent,\n python3:any [amd64 !i386] <!nocheck> <cross>,",
+    )
+    .unwrap();
+    assert_eq!(relations.len(), 3);
+
+    let libc = &relations[0].alternatives[0];
+    assert_eq!(&*libc.name, "libc6");
+    let constraint = libc.version.as_ref().unwrap();
+    assert_eq!(constraint.operator, VersionOperator::LaterOrEqual);
+    assert!(constraint.matches(&"2.36-9".parse().unwrap()));
+    assert!(!constraint.matches(&"2.35".parse().unwrap()));
+
+    assert_eq!(relations[1].alternatives.len(), 2);
+
+    let python = &relations[2].alternatives[0];
+    assert_eq!(python.arch_qualifier.as_deref(), Some("any"));
+    assert_eq!(python.architectures.len(), 2);
+    assert!(python.architectures[1].negated);
+    assert_eq!(python.profiles.len(), 2);
+    assert_eq!(
+        python.to_string(),
+        "python3:any [amd64 !i386] <!nocheck> <cross>"
+    );
+}
+
+#[test]
+fn bad_relations() {
+    assert_eq!(
+        parse_relations("foo (~ 1.0)").unwrap_err(),
+        RelationError::InvalidOperator("~".into())
+    );
+    assert_eq!(
+        parse_relations("foo | | bar").unwrap_err(),
+        RelationError::EmptyAlternative
+    );
+    assert_eq!(
+        parse_relations("foo (>= 1.0").unwrap_err(),
+        RelationError::Unclosed('(')
+    );
+    assert!(matches!(
+        parse_relations("Foo").unwrap_err(),
+        RelationError::InvalidName(_)
+    ));
+}
+
+#[test]
+fn legacy_compression() {
+    // control.tar.bz2 and data.tar.lzma
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.legacy.deb");
+    let (fields, _raw) = deb_to_control(&deb[..]).unwrap();
+    assert_eq!(
+        &*RequiredFields::from_map(&fields).unwrap().package,
+        "hello"
+    );
+    let files = deb_to_file_list(&deb[..]).unwrap();
+    assert!(files.iter().any(|f| &*f.path == "/etc/hello.conf"));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_control() {
+    for deb in [
+        &include_bytes!("testfiles/hello_1.0-1_all.deb")[..],
+        &include_bytes!("testfiles/hello_1.0-1_all.legacy.deb")[..],
+    ] {
+        let (fields, raw) = deb_to_control_async(deb).await.unwrap();
+        assert_eq!((fields, raw), deb_to_control(deb).unwrap());
+    }
+    assert!(deb_to_control_async(&b"not a deb"[..]).await.is_err());
+    let format_3 = ar_archive(&[("debian-binary", &b"3.0\n"[..])]);
+    assert!(matches!(
+        deb_to_control_async(&format_3[..]).await,
+        Err(Error::InvalidArchive(ArchiveError::UnsupportedFormat(_)))
+    ));
+}
+
+#[test]
+fn stanzas() {
+    let stanzas = parse_stanzas(include_str!("testfiles/multiple.packages")).unwrap();
+    let names: Vec<&str> = stanzas.iter().map(|s| s["Package"].trim()).collect();
+    assert_eq!(names, ["hello", "goodbye", "farewell"]);
+    assert_eq!(
+        stanzas[0]["Description"],
+        " greets you\n a tiny package\n .\n with a blank description line\n"
+    );
+
+    let err = parse_stanzas("Package: a\n\nPackage: b\nPackage: c\n").unwrap_err();
+    let ParseError::DuplicateKey { key, span } = err else {
+        panic!("expected a duplicate key, got {err:?}");
+    };
+    assert_eq!(key, "Package");
+    // spans are relative to the whole file, not the paragraph
+    assert_eq!((span.offset, span.line, span.column), (23, 4, 1));
+    assert_eq!(span.snippet, "Package: c");
+}
+
+#[test]
+fn serialize_round_trip() {
+    for input in [
+        include_str!("testfiles/boring.control"),
+        include_str!("testfiles/multiline.control"),
+        "Package:tight\nDescription: tabbed\n\tcontinuation\n",
+    ] {
+        assert_eq!(serialize_control(&parse_control(input).unwrap()), input);
+    }
+
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    let (fields, raw) = deb_to_control(&deb[..]).unwrap();
+    assert_eq!(serialize_control(&fields), &*raw);
+
+    let built = IndexMap::from([("Package", " hand-made"), ("Version", " 1.0\n")]);
+    assert_eq!(
+        serialize_control(&built),
+        "Package: hand-made\nVersion: 1.0\n"
+    );
+}
+
+#[test]
+fn architectures() {
+    for known in ["amd64", "armhf", "hurd-i386", "musl-linux-arm64", "x32"] {
+        assert!(is_known_architecture(known), "{known}");
+    }
+    for unknown in ["amd46", "linux-amd64", "hurd-", "any", "all"] {
+        assert!(!is_known_architecture(unknown), "{unknown}");
+    }
+
+    let control = |arch: &str| {
+        format!("Package: a\nVersion: 1\nArchitecture: {arch}\nMaintainer: m\nDescription: d\n")
+    };
+    for valid in [
+        "all",
+        "any",
+        "amd64",
+        "linux-any any-arm64 kfreebsd-any",
+        "i386 armel",
+    ] {
+        assert!(get_control(&control(valid)).is_ok(), "{valid}");
+    }
+    let err = get_control(&control("amd64 amd46")).unwrap_err();
+    assert!(matches!(&err, Error::UnknownArchitecture(term) if term == "amd46"));
+    assert_eq!(err.code(), "GV1109");
+}
+
+#[test]
+fn version_characters() {
+    let invalid = |s: &str| s.parse::<DebianVersion>().unwrap_err();
+    assert_eq!(
+        invalid("1.0_beta"),
+        VersionError::InvalidCharacter {
+            character: '_',
+            position: 3
+        }
+    );
+    // hyphens are fine upstream, but not in the revision after the last one
+    assert!("2:1.0-rc1-3+b1".parse::<DebianVersion>().is_ok());
+    assert_eq!(
+        invalid("1:2:3"),
+        VersionError::InvalidCharacter {
+            character: ':',
+            position: 3
+        }
+    );
+    assert_eq!(
+        invalid("1.0-1 2"),
+        VersionError::InvalidCharacter {
+            character: ' ',
+            position: 5
+        }
+    );
+
+    let control = "Package: a\nVersion: 1.0@2\nArchitecture: all\nMaintainer: m\nDescription: d\n";
+    let err = get_control(control).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::InvalidVersion(VersionError::InvalidCharacter { character: '@', .. })
+    ));
+    assert_eq!(err.code(), "GV1204");
+}
+
+#[test]
+fn changes() {
+    let changes = parse_changes(include_str!("testfiles/hello_1.0-1_all.changes")).unwrap();
+    assert_eq!(&*changes.source, "hello");
+    assert_eq!(changes.version.to_string(), "1.0-1");
+    assert_eq!(changes.distribution, [Box::from("stable")]);
+    assert_eq!(changes.architecture, [Box::from("all")]);
+    assert_eq!(
+        changes.files,
+        [ListedFile {
+            checksum: "1a41e9fd5431301368e95de2174e70ccdff65b11385f1407a144fc78b8fa1974".into(),
+            size: 1112,
+            name: "hello_1.0-1_all.deb".into(),
+        }]
+    );
+    let md5s = parse_file_list(&changes.fields["Files"]).unwrap();
+    assert_eq!(&*md5s[0].checksum, "93839431010e5ace4ec29140ff041531");
+
+    // unsigned, with a dash-escaped line once signed
+    let unsigned = strip_signature(include_str!("testfiles/hello_1.0-1_all.changes")).unwrap();
+    assert_eq!(parse_changes(&unsigned).unwrap(), changes);
+    let escaped = "-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA256\n\nA: b\n- -c\n-----BEGIN PGP SIGNATURE-----\n";
+    assert_eq!(strip_signature(escaped).unwrap(), "A: b\n-c\n");
+
+    // file lists start on the line after their field name
+    let fields = parse_control("Files:\n abc 1 a.deb\n").unwrap();
+    assert_eq!(fields["Files"], "\n abc 1 a.deb\n");
+    assert!(matches!(
+        parse_control("Files:\nSource: a\n").unwrap_err(),
+        ParseError::IncompleteKey(Span { offset: 6, .. })
+    ));
+
+    let unterminated = "-----BEGIN PGP SIGNED MESSAGE-----\n\nSource: a\n";
+    assert!(matches!(
+        parse_changes(unterminated),
+        Err(Error::UnterminatedSignature)
+    ));
+    assert!(matches!(
+        parse_changes("Source: a\nVersion: 1\n"),
+        Err(Error::MissingField("Distribution"))
+    ));
+    assert!(matches!(
+        parse_file_list(" abc 12\n"),
+        Err(Error::InvalidFileEntry(_))
+    ));
+}
+
+#[test]
+fn dsc() {
+    let dsc = parse_dsc(include_str!("testfiles/hello_1.0-1.dsc")).unwrap();
+    assert_eq!(&*dsc.source, "hello");
+    assert_eq!(dsc.version.to_string(), "1.0-1");
+    assert_eq!(&*dsc.format, "3.0 (quilt)");
+    assert_eq!(dsc.binaries, [Box::from("hello"), Box::from("hello-doc")]);
+    assert_eq!(dsc.architecture, [Box::from("any"), Box::from("all")]);
+    let build_depends: Vec<String> = dsc.build_depends.iter().map(|r| r.to_string()).collect();
+    assert_eq!(
+        build_depends,
+        [
+            "debhelper-compat (= 13)",
+            "libfoo-dev (>= 1.2) [linux-any] <!nocheck>"
+        ]
+    );
+    assert!(dsc.build_depends_arch.is_empty());
+    assert_eq!(&*dsc.build_depends_indep[0].alternatives[0].name, "pandoc");
+    let names: Vec<&str> = dsc.files.iter().map(|f| &*f.name).collect();
+    assert_eq!(
+        names,
+        ["hello_1.0.orig.tar.xz", "hello_1.0-1.debian.tar.xz"]
+    );
+    assert_eq!(dsc.files_md5[1].size, 2048);
+
+    let err =
+        parse_dsc("Source: a\nVersion: 1\nFormat: 1.0\nArchitecture: any\nBuild-Depends: Bad\n")
+            .unwrap_err();
+    assert_eq!(err.code(), "GV1302");
+}
+
+#[test]
+fn udeb() {
+    assert_eq!(
+        PackageKind::from_file_name("hello_1.0-1_amd64.udeb"),
+        Some(PackageKind::Udeb)
+    );
+    assert_eq!(
+        PackageKind::from_file_name("hello_1.0-1_amd64.deb"),
+        Some(PackageKind::Deb)
+    );
+    assert_eq!(PackageKind::from_file_name("hello.dsc"), None);
+
+    let udeb = ParseOptions {
+        kind: PackageKind::Udeb,
+        ..ParseOptions::default()
+    };
+    let control = "Package: di-utils\nVersion: 1.0\nArchitecture: amd64\n";
+    assert!(matches!(
+        get_control(control),
+        Err(Error::MissingFields(fields))
+            if fields == [RequiredField::Maintainer, RequiredField::Description]
+    ));
+    assert!(get_control_with(control, &udeb).is_ok());
+    let declared = format!("{control}Package-Type: udeb\n");
+    assert!(get_control(&declared).is_ok());
+    assert!(matches!(
+        get_control_with("Package: a\nVersion: 1.0\n", &udeb),
+        Err(Error::MissingFields(fields)) if fields == [RequiredField::Architecture]
+    ));
+}
+
+#[test]
+fn optional_fields() {
+    let fields: PackageMap = parse_control(
+        "Package: hello\nsource: hello-src (1.0-1)\nSECTION: misc\nInstalled-Size: 12\nDepends: libc6 (>= 2.36), base-files\nHomepage:  https://example.com \n",
+    )
+    .unwrap()
+    .into_iter()
+    .map(pack)
+    .collect();
+    let optional = OptionalFields::from_map(&fields).unwrap();
+    assert_eq!(optional.section.as_deref(), Some("misc"));
+    assert_eq!(optional.priority, None);
+    assert_eq!(optional.homepage.as_deref(), Some("https://example.com"));
+    assert_eq!(optional.installed_size, Some(12));
+    assert_eq!(optional.depends.len(), 2);
+    let source = optional.source.unwrap();
+    assert_eq!(&*source.name, "hello-src");
+    assert_eq!(source.version.unwrap().to_string(), "1.0-1");
+
+    let bad = |field: &str| {
+        let fields: PackageMap = parse_control(field)
+            .unwrap()
+            .into_iter()
+            .map(pack)
+            .collect();
+        OptionalFields::from_map(&fields).unwrap_err().code()
+    };
+    assert_eq!(bad("Installed-Size: 12k\n"), "GV1113");
+    assert_eq!(bad("Source: hello (1.0\n"), "GV1114");
+    assert_eq!(bad("Depends: libc6 (>= 2.36\n"), "GV1303");
+    assert_eq!(
+        OptionalFields::from_map(&PackageMap::new()).unwrap(),
+        OptionalFields::default()
+    );
+}
+
+#[test]
+fn limits() {
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    let limited = |limits: Limits| {
+        let options = ParseOptions {
+            limits,
+            ..ParseOptions::default()
+        };
+        match deb_to_control_with(&deb[..], &options) {
+            Err(Error::LimitExceeded(limit)) => Some(limit),
+            Err(e) => panic!("unexpected error {e:?}"),
+            Ok(_) => None,
+        }
+    };
+    assert_eq!(limited(Limits::default()), None);
+    assert_eq!(
+        limited(Limits {
+            max_members: 1,
+            ..Limits::default()
+        }),
+        Some(Limit::Members)
+    );
+    assert_eq!(
+        limited(Limits {
+            max_control_size: 512,
+            ..Limits::default()
+        }),
+        Some(Limit::ControlSize)
+    );
+    assert_eq!(
+        limited(Limits {
+            max_fields: 3,
+            ..Limits::default()
+        }),
+        Some(Limit::Fields)
+    );
+    assert_eq!(
+        limited(Limits {
+            max_member_size: 100,
+            ..Limits::default()
+        }),
+        Some(Limit::MemberSize)
+    );
+}
+
+/// An ar archive with GNU-style headers
+fn ar_archive(members: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive = b"!<arch>\n".to_vec();
+    for (name, contents) in members {
+        let header = format!(
+            "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            format!("{name}/"),
+            0,
+            0,
+            0,
+            100644,
+            contents.len()
+        );
+        archive.extend_from_slice(header.as_bytes());
+        archive.extend_from_slice(contents);
+        if contents.len() % 2 == 1 {
+            archive.push(b'\n');
+        }
+    }
+    archive
+}
+
+#[test]
+fn malformed_archives() {
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    fn archive_error<T: std::fmt::Debug>(result: Result<T, Error>) -> ArchiveError {
+        match result {
+            Err(Error::InvalidArchive(e)) => e,
+            other => panic!("expected an archive error, got {other:?}"),
+        }
+    }
+
+    assert_eq!(
+        archive_error(deb_to_control(&b"not a deb"[..])),
+        ArchiveError::NotAr
+    );
+    assert_eq!(
+        archive_error(deb_to_control(&deb[..100])),
+        ArchiveError::Truncated
+    );
+
+    // the size of the control tarball, after the header and contents of debian-binary
+    let size_field = 8 + 60 + 4 + 48..8 + 60 + 4 + 58;
+    let mut bogus_size = deb.to_vec();
+    bogus_size[size_field.clone()].copy_from_slice(b"9999999999");
+    assert_eq!(
+        archive_error(deb_to_file_list(&bogus_size[..])),
+        ArchiveError::Truncated
+    );
+    bogus_size[size_field].copy_from_slice(b"-1        ");
+    assert_eq!(
+        archive_error(deb_to_control(&bogus_size[..])),
+        ArchiveError::BadSize
+    );
+
+    let members = [
+        ("debian-binary", &b"2.0\n"[..]),
+        ("control.tar", &[0; 1024][..]),
+        ("control.tar.gz", &[0; 10][..]),
+        ("data.tar", &[0; 1024][..]),
+    ];
+    assert_eq!(
+        archive_error(deb_to_file_list(&ar_archive(&members)[..])),
+        ArchiveError::DuplicateMember("control.tar".into())
+    );
+
+    let control = ("control.tar", &[0; 1024][..]);
+    let no_format = ar_archive(&[control]);
+    assert_eq!(
+        archive_error(deb_to_control(&no_format[..])),
+        ArchiveError::MissingFormat
+    );
+    let format_3 = ar_archive(&[("debian-binary", &b"3.0\n"[..]), control]);
+    for result in [
+        deb_to_control(&format_3[..]).map(drop),
+        deb_to_file_list(&format_3[..]).map(drop),
+        control_from_slice(&format_3, &ParseOptions::default()).map(drop),
+    ] {
+        assert_eq!(
+            archive_error(result),
+            ArchiveError::UnsupportedFormat("3.0".into())
+        );
+    }
+    let long_format = ar_archive(&[("debian-binary", &[b'2'; 100][..]), control]);
+    assert!(deb_to_control(&long_format[..]).is_err());
+
+    // whatever is cut off or overwritten, every way in fails cleanly instead of panicking
+    let check = |bytes: &[u8]| {
+        let _ = deb_to_control(bytes);
+        let _ = deb_to_file_list(bytes);
+        let _ = deb_to_md5sums(bytes);
+        let _ = control_from_slice(bytes, &ParseOptions::default());
+    };
+    for len in (0..deb.len()).step_by(7) {
+        check(&deb[..len]);
+    }
+    for i in 0..8 + 2 * 60 {
+        for byte in [b'0', b'9', b' ', b'/', 0xFF] {
+            let mut corrupt = deb.to_vec();
+            corrupt[i] = byte;
+            check(&corrupt);
+        }
+    }
+}
+
+#[test]
+fn conffiles_and_md5sums() {
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    assert_eq!(
+        deb_to_conffiles(&deb[..]).unwrap(),
+        [Conffile {
+            path: "/etc/hello.conf".into(),
+            remove_on_upgrade: false,
+        }]
+    );
+    let md5sums = deb_to_md5sums(&deb[..]).unwrap();
+    let paths: Vec<&str> = md5sums.iter().map(|sum| &*sum.path).collect();
+    assert_eq!(paths, ["/usr/bin/hello", "/usr/share/doc/hello/copyright"]);
+    assert_eq!(md5sums[0].md5[..4], [0xD6, 0x04, 0xA2, 0x20]);
+
+    // the legacy fixture has the same control tarball, just compressed differently
+    let legacy = include_bytes!("testfiles/hello_1.0-1_all.legacy.deb");
+    assert_eq!(deb_to_md5sums(&legacy[..]).unwrap(), md5sums);
+
+    let conffiles = parse_conffiles("/etc/a\nremove-on-upgrade /etc/b c\n").unwrap();
+    assert!(!conffiles[0].remove_on_upgrade);
+    assert_eq!(&*conffiles[1].path, "/etc/b c");
+    assert!(conffiles[1].remove_on_upgrade);
+    assert!(parse_conffiles("keep-forever /etc/a\n").is_err());
+    assert!(parse_conffiles("etc/a\n").is_err());
+
+    let sums = parse_md5sums("d604a220708aa59433ba410986cd4ffa  usr/share/a b\n").unwrap();
+    assert_eq!(&*sums[0].path, "/usr/share/a b");
+    let err = parse_md5sums("+604a220708aa59433ba410986cd4ffa  usr/bin/a\n").unwrap_err();
+    assert_eq!(err.code(), "GV1116");
+}
+
+#[cfg(feature = "debsig")]
+#[test]
+fn unsigned_deb() {
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    let err = verify_deb_signatures(std::io::Cursor::new(&deb[..]), &[]).unwrap_err();
+    assert!(matches!(err, Error::NotSigned));
+    assert_eq!(err.code(), "GV1117");
+}
+
+#[test]
+fn package_triplet() {
+    let triplet = PackageTriplet::new("hello", "1:1.0-1", "amd64").unwrap();
+    assert_eq!(triplet.file_name(), "hello_1:1.0-1_amd64.deb");
+    assert_eq!(
+        triplet.file_name_for(PackageKind::Udeb),
+        "hello_1:1.0-1_amd64.udeb"
+    );
+
+    let err = PackageTriplet::new("../../etc", "1.0", "amd64").unwrap_err();
+    assert_eq!(err.code(), "GV1121");
+    assert!(PackageTriplet::new("hello", "1.0/../x", "amd64").is_err());
+    assert!(PackageTriplet::new("hello", "1.0", "../amd64").is_err());
+    assert!(PackageTriplet::new("hello", "1.0", "amd64 arm64").is_err());
+    assert!(PackageTriplet::new("hello", "1.0", "").is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let (control, _) =
+        deb_to_control(&include_bytes!("testfiles/hello_1.0-1_all.deb")[..]).unwrap();
+    let json = serde_json::to_string(&control).unwrap();
+    let back: IndexMap<Box<str>, Box<str>> = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, control);
+    assert!(back.keys().eq(control.keys()), "field order is kept");
+
+    let optional = OptionalFields::from_map(&control).unwrap();
+    let json = serde_json::to_string(&optional).unwrap();
+    assert_eq!(
+        serde_json::from_str::<OptionalFields>(&json).unwrap(),
+        optional
+    );
+
+    let relations = parse_relations("libc6 (>= 2.36) [amd64] | musl, foo:any").unwrap();
+    let json = serde_json::to_value(&relations).unwrap();
+    assert_eq!(json[0]["alternatives"][0]["version"]["version"], "2.36");
+    assert_eq!(
+        serde_json::from_value::<Vec<Relation>>(json).unwrap(),
+        relations
+    );
+
+    assert!(serde_json::from_str::<DebianVersion>("\"1:2.0-1\"").is_ok());
+    assert!(serde_json::from_str::<DebianVersion>("\"-1\"").is_err());
+    let traversal = r#"{"package":"../../etc","version":"1.0","architecture":"amd64"}"#;
+    assert!(serde_json::from_str::<PackageTriplet>(traversal).is_err());
+}
+
+/// An uncompressed tarball of raw `(type, name, contents)` entries, with names written as given
+fn raw_tarball(entries: &[(tar::EntryType, &[u8], &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (kind, name, contents) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name);
+        header.set_entry_type(*kind);
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, *contents).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn control_tarball_paths() {
+    let control = &b"Package: hello\nVersion: 1.0\nArchitecture: all\nMaintainer: a <a@example.com>\nDescription: hi\n"[..];
+    let regular = tar::EntryType::Regular;
+    let long_name = format!("{}control\0", "./".repeat(60));
+    let tarballs = [
+        raw_tarball(&[(regular, b"control", control)]),
+        raw_tarball(&[(regular, b"/control", control)]),
+        raw_tarball(&[(regular, b".//./control", control)]),
+        raw_tarball(&[
+            (
+                tar::EntryType::GNULongName,
+                b"././@LongLink",
+                long_name.as_bytes(),
+            ),
+            (regular, b"././././truncated", control),
+        ]),
+        raw_tarball(&[
+            (
+                tar::EntryType::XHeader,
+                b"PaxHeaders/control",
+                b"18 path=./control\n",
+            ),
+            (regular, b"something-else", control),
+        ]),
+    ];
+    for tarball in &tarballs {
+        let deb = ar_archive(&[("debian-binary", b"2.0\n"), ("control.tar", tarball)]);
+        let (fields, _) = deb_to_control(&deb[..]).unwrap();
+        assert_eq!(fields.field("Package"), Some("hello"));
+        let raw = control_from_slice(&deb, &ParseOptions::default()).unwrap();
+        assert_eq!(raw.as_bytes(), control);
+    }
+
+    // files in subdirectories aren't the control file, even if they are named like it
+    for name in [&b"./debian/control"[..], b"../control"] {
+        let tarball = raw_tarball(&[(regular, name, control)]);
+        let deb = ar_archive(&[("debian-binary", b"2.0\n"), ("control.tar", &tarball)]);
+        assert!(matches!(deb_to_control(&deb[..]), Err(Error::NoControl)));
+    }
+}
+
+#[test]
+fn validated_names() {
+    let name: PackageName = " libfoo2.0+dfsg ".parse().unwrap();
+    assert_eq!(name.as_str(), "libfoo2.0+dfsg");
+    assert!("Foo".parse::<PackageName>().is_err());
+    assert!("a".parse::<PackageName>().is_err());
+    assert!("".parse::<Architecture>().is_err());
+    assert!("linux-any".parse::<Architecture>().is_ok());
+
+    let a: Version = "1.0".parse().unwrap();
+    let b: Version = "1.00".parse().unwrap();
+    assert_ne!(a, b, "versions keep how they were written");
+    assert_eq!(a.parsed(), b.parsed());
+    assert!("1.0 beta".parse::<Version>().is_err());
+
+    let mut fields = IndexMap::new();
+    for (key, value) in [
+        ("Package", " ../../etc\n"),
+        ("Version", " 1.0\n"),
+        ("Architecture", " all\n"),
+        ("Maintainer", " a <a@example.com>\n"),
+        ("Description", " hi\n"),
+    ] {
+        fields.insert(key.into(), value.into());
+    }
+    assert!(matches!(
+        RequiredFields::from_map(&fields),
+        Err(Error::InvalidPackageName(_))
+    ));
+    fields.shift_remove("Maintainer");
+    assert!(matches!(
+        RequiredFields::from_map(&fields),
+        Err(Error::MissingFields(missing)) if missing == [RequiredField::Maintainer]
+    ));
+    fields.insert("Package".into(), " hello-udeb\n".into());
+    let required = RequiredFields::from_map_for(&fields, PackageKind::Udeb).unwrap();
+    assert_eq!(&*required.maintainer, "");
+}
+
+#[test]
+fn source_grouping() {
+    let stanzas = parse_stanzas(
+        "Package: hello\nVersion: 1.0-1\n\n\
+         Package: hello-doc\nSource: hello\nVersion: 1.0-1\n\n\
+         Package: libhello1\nSource: hello (1.0-1)\nVersion: 1.0-1+b1\n\n\
+         Package: libfoo\nVersion: 2\n",
+    )
+    .unwrap();
+    let groups = group_by_source(&stanzas).unwrap();
+    let sources: Vec<_> = groups
+        .iter()
+        .map(|(source, binaries)| (source.pool_directory("main"), binaries.len()))
+        .collect();
+    assert_eq!(
+        sources,
+        [
+            ("pool/main/h/hello/".to_owned(), 3),
+            ("pool/main/libf/libfoo/".to_owned(), 1)
+        ]
+    );
+
+    let source: SourceRef = "hello (1:2.0) ".parse().unwrap();
+    assert_eq!(source.version.unwrap().as_str(), "1:2.0");
+    assert!("hello (1.0".parse::<SourceRef>().is_err());
+    assert!("Hello".parse::<SourceRef>().is_err());
+}
+
+#[test]
+fn ar_members() {
+    let deb = include_bytes!("testfiles/hello_1.0-1_all.deb");
+    let mut archive = ArReader::new(&deb[..], &Limits::default()).unwrap();
+    let mut members = Vec::new();
+    while let Some(mut member) = archive.next_member().unwrap() {
+        let name = member.name().into_owned();
+        let (size, mtime) = (member.size(), member.mtime());
+        // only read some members, the rest are skipped
+        if name == "debian-binary" {
+            let mut contents = String::new();
+            member.read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, "2.0\n");
+        }
+        members.push((name, size, mtime));
+    }
+    let names: Vec<_> = members.iter().map(|(name, ..)| name.as_str()).collect();
+    assert_eq!(names, ["debian-binary", "control.tar.xz", "data.tar.xz"]);
+    assert_eq!(members[1].1, 540);
+    assert_eq!(members[1].2, 1700000000);
+    // the magic, then each header, member and padding byte
+    let archive_len: u64 = 8 + members
+        .iter()
+        .map(|(_, size, _)| 60 + size + size % 2)
+        .sum::<u64>();
+    assert_eq!(archive_len, deb.len() as u64);
+}
+
+#[test]
+fn field_values() {
+    let control = "Package: hello\nVersion: 1.0\nArchitecture: all\n\
+                   Maintainer: Jane Doe jane@example.com\nSection: contrib/utilz\n\
+                   Priority: optional\nDescription: hi\n";
+    let fields = get_control(control).unwrap();
+    assert_eq!(
+        field_value_warnings(&fields),
+        [
+            Warning::InvalidMaintainer("Jane Doe jane@example.com".into()),
+            Warning::UnknownSection("contrib/utilz".into()),
+        ]
+    );
+
+    let options = |field_checks| ParseOptions {
+        field_checks,
+        ..ParseOptions::default()
+    };
+    let (_, warnings) = get_control_with_warnings(control, &options(FieldChecks::Warn)).unwrap();
+    assert_eq!(warnings.len(), 2);
+    let (_, warnings) = get_control_with_warnings(control, &options(FieldChecks::Off)).unwrap();
+    assert!(warnings.is_empty());
+    let err = get_control_with(control, &options(FieldChecks::Reject)).unwrap_err();
+    assert_eq!(err.code(), "GV1404");
+
+    let fields = IndexMap::from([("Architecture", "all"), ("Multi-Arch", "same")]);
+    assert_eq!(
+        field_value_warnings(&fields),
+        [Warning::MultiArchSameForAll]
+    );
+    let fields = IndexMap::from([("Multi-Arch", "Foreign")]);
+    assert_eq!(
+        field_value_warnings(&fields),
+        [Warning::UnknownMultiArch("Foreign".into())]
+    );
+
+    for maintainer in [
+        "Jane Doe <jane@example.com>",
+        "\"Doe, Jane\" <jane@example.com>",
+        "Debian QA Group <packages@qa.debian.org>",
+    ] {
+        let fields = IndexMap::from([("Maintainer", maintainer)]);
+        assert!(field_value_warnings(&fields).is_empty(), "{maintainer}");
+    }
+    for maintainer in [
+        "Doe, Jane <jane@example.com>",
+        "<jane@example.com>",
+        "Jane <jane>",
+        "Jane <jane@example.com>, John <john@example.com>",
+    ] {
+        let fields = IndexMap::from([("Maintainer", maintainer)]);
+        assert!(!field_value_warnings(&fields).is_empty(), "{maintainer}");
+    }
+}
+
+#[test]
+fn essential_and_pre_depends() {
+    let control = "Package: hello\nVersion: 1.0\nArchitecture: all\nMaintainer: a <a@example.com>\n\
+                   Essential: yes\nPre-Depends: libc6 (>= 2.36)\nDescription: hi\n";
+    let fields: PackageMap = get_control(control)
+        .unwrap()
+        .into_iter()
+        .map(pack)
+        .collect();
+    let optional = OptionalFields::from_map(&fields).unwrap();
+    assert!(optional.essential);
+    assert!(!optional.protected);
+    assert_eq!(optional.pre_depends[0].to_string(), "libc6 (>= 2.36)");
+
+    let options = ParseOptions {
+        reject_essential: true,
+        ..ParseOptions::default()
+    };
+    let err = get_control_with(control, &options).unwrap_err();
+    assert_eq!(err.code(), "GV1123");
+    let not_essential = control.replace("Essential: yes", "Essential: no");
+    assert!(get_control_with(&not_essential, &options).is_ok());
+
+    let mut fields = fields;
+    fields.insert("Protected".into(), " maybe\n".into());
+    assert!(matches!(
+        OptionalFields::from_map(&fields),
+        Err(Error::InvalidYesNo {
+            field: "Protected",
+            ..
+        })
+    ));
 }
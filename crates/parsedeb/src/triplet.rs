@@ -0,0 +1,57 @@
+use crate::{Architecture, Error, PackageKind, PackageName, RequiredFields, Version};
+
+/// The package name, version and architecture of a package. None of them can hold a slash or
+/// whitespace, or be `.` or `..`, so they are safe to build file names and paths from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PackageTriplet {
+    package: PackageName,
+    version: Version,
+    architecture: Architecture,
+}
+
+impl PackageTriplet {
+    pub fn new(package: &str, version: &str, architecture: &str) -> Result<Self, Error> {
+        Ok(Self {
+            package: package.parse()?,
+            version: version.parse()?,
+            architecture: architecture.parse()?,
+        })
+    }
+
+    pub fn from_fields(fields: &RequiredFields) -> Self {
+        Self {
+            package: fields.package.clone(),
+            version: fields.version.clone(),
+            architecture: fields.architecture.clone(),
+        }
+    }
+
+    pub fn package(&self) -> &PackageName {
+        &self.package
+    }
+
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    pub fn architecture(&self) -> &Architecture {
+        &self.architecture
+    }
+
+    /// `name_version_architecture.deb`
+    pub fn file_name(&self) -> String {
+        self.file_name_for(PackageKind::Deb)
+    }
+
+    /// [`PackageTriplet::file_name`], ending in `.udeb` for udebs
+    pub fn file_name_for(&self, kind: PackageKind) -> String {
+        format!(
+            "{}_{}_{}.{}",
+            self.package,
+            self.version,
+            self.architecture,
+            kind.extension()
+        )
+    }
+}
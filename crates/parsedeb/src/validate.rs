@@ -0,0 +1,141 @@
+//! Checks on the values of common fields, which dpkg itself doesn't make. Packages built by
+//! hand or by other tools get these wrong, and apt frontends show them to users as they are.
+
+use indexmap::IndexMap;
+
+use crate::{ControlFields, Warning};
+
+/// Priorities from policy §2.5. `extra` is deprecated in favour of `optional`, but still common.
+const PRIORITIES: [&str; 5] = ["required", "important", "standard", "optional", "extra"];
+
+/// Sections from policy §2.4, which can be prefixed with an archive area like `contrib/`
+const SECTIONS: [&str; 59] = [
+    "admin",
+    "cli-mono",
+    "comm",
+    "database",
+    "debian-installer",
+    "debug",
+    "devel",
+    "doc",
+    "editors",
+    "education",
+    "electronics",
+    "embedded",
+    "fonts",
+    "games",
+    "gnome",
+    "gnu-r",
+    "gnustep",
+    "golang",
+    "graphics",
+    "hamradio",
+    "haskell",
+    "httpd",
+    "interpreters",
+    "introspection",
+    "java",
+    "javascript",
+    "kde",
+    "kernel",
+    "libdevel",
+    "libs",
+    "lisp",
+    "localization",
+    "mail",
+    "math",
+    "metapackages",
+    "misc",
+    "net",
+    "news",
+    "ocaml",
+    "oldlibs",
+    "otherosfs",
+    "perl",
+    "php",
+    "python",
+    "raku",
+    "ruby",
+    "rust",
+    "science",
+    "shells",
+    "sound",
+    "tasks",
+    "tex",
+    "text",
+    "utils",
+    "vcs",
+    "video",
+    "web",
+    "x11",
+    "xfce",
+];
+
+/// The values of Multi-Arch dpkg understands, from the multiarch spec
+const MULTI_ARCH: [&str; 4] = ["no", "same", "foreign", "allowed"];
+
+const AREAS: [&str; 4] = ["main", "contrib", "non-free", "non-free-firmware"];
+
+/// What to do about field values [`field_value_warnings`] finds fault with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FieldChecks {
+    /// don't check them
+    #[default]
+    Off,
+    /// report them alongside the other [`control_warnings`](crate::control_warnings)
+    Warn,
+    /// fail with [`Error::InvalidFieldValue`](crate::Error::InvalidFieldValue)
+    Reject,
+}
+
+/// Problems with the values of Maintainer, Priority, Section and Multi-Arch, in field order.
+/// Fields that are missing aren't a problem here.
+pub fn field_value_warnings<K: AsRef<str>, V: AsRef<str>>(fields: &IndexMap<K, V>) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for (key, value) in fields {
+        let (key, value) = (key.as_ref(), value.as_ref().trim());
+        if key.eq_ignore_ascii_case("Maintainer") && !valid_maintainer(value) {
+            warnings.push(Warning::InvalidMaintainer(value.into()));
+        } else if key.eq_ignore_ascii_case("Priority") && !PRIORITIES.contains(&value) {
+            warnings.push(Warning::UnknownPriority(value.into()));
+        } else if key.eq_ignore_ascii_case("Section") && !valid_section(value) {
+            warnings.push(Warning::UnknownSection(value.into()));
+        } else if key.eq_ignore_ascii_case("Multi-Arch") {
+            if !MULTI_ARCH.contains(&value) {
+                warnings.push(Warning::UnknownMultiArch(value.into()));
+            } else if value == "same" && fields.field("Architecture") == Some("all") {
+                warnings.push(Warning::MultiArchSameForAll);
+            }
+        }
+    }
+    warnings
+}
+
+/// A single `Full Name <address@example.com>`. Names with a comma have to be quoted,
+/// since a bare comma would make it look like a list of people.
+fn valid_maintainer(value: &str) -> bool {
+    let Some((name, address)) = value
+        .strip_suffix('>')
+        .and_then(|value| value.rsplit_once('<'))
+    else {
+        return false;
+    };
+    let name = name.trim();
+    let quoted = name.len() >= 2 && name.starts_with('"') && name.ends_with('"');
+    let name_ok =
+        !name.is_empty() && !name.contains(['<', '>']) && (quoted || !name.contains(['"', ',']));
+    let address_ok = address
+        .split_once('@')
+        .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'))
+        && !address.contains(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | ','));
+    name_ok && address_ok
+}
+
+fn valid_section(value: &str) -> bool {
+    let section = match value.split_once('/') {
+        Some((area, section)) if AREAS.contains(&area) => section,
+        Some(_) => return false,
+        None => value,
+    };
+    SECTIONS.contains(&section)
+}
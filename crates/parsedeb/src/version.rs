@@ -0,0 +1,216 @@
+use std::{cmp::Ordering, str::FromStr};
+
+/// A package version split into `[epoch:]upstream[-revision]`, ordered the way dpkg orders them
+#[derive(Debug, Clone)]
+pub struct DebianVersion {
+    epoch: Option<u32>,
+    upstream: Box<str>,
+    revision: Option<Box<str>>,
+}
+
+impl DebianVersion {
+    /// The epoch, which is 0 when not written out
+    pub fn epoch(&self) -> u32 {
+        self.epoch.unwrap_or(0)
+    }
+
+    pub fn upstream(&self) -> &str {
+        &self.upstream
+    }
+
+    pub fn revision(&self) -> Option<&str> {
+        self.revision.as_deref()
+    }
+}
+
+impl FromStr for DebianVersion {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (epoch, rest) = match s.split_once(':') {
+            Some((epoch, rest)) => {
+                if epoch.is_empty() || !epoch.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(VersionError::InvalidEpoch);
+                }
+                (
+                    Some(epoch.parse().map_err(|_| VersionError::InvalidEpoch)?),
+                    rest,
+                )
+            }
+            None => (None, s),
+        };
+        // the revision is everything after the last hyphen, so upstream versions may contain hyphens
+        let (upstream, revision) = match rest.rsplit_once('-') {
+            Some((_, "")) => return Err(VersionError::EmptyRevision),
+            Some((upstream, revision)) => (upstream, Some(revision)),
+            None => (rest, None),
+        };
+        if upstream.is_empty() {
+            return Err(VersionError::EmptyUpstream);
+        }
+
+        let upstream_start = s.len() - rest.len();
+        check_characters(upstream, upstream_start, |c| {
+            matches!(c, '.' | '+' | '~' | '-')
+        })?;
+        if let Some(revision) = revision {
+            check_characters(revision, s.len() - revision.len(), |c| {
+                matches!(c, '.' | '+' | '~')
+            })?;
+        }
+        let revision = revision.map(Into::into);
+        Ok(Self {
+            epoch,
+            upstream: upstream.into(),
+            revision,
+        })
+    }
+}
+
+impl std::fmt::Display for DebianVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(epoch) = self.epoch {
+            write!(f, "{epoch}:")?;
+        }
+        f.write_str(&self.upstream)?;
+        if let Some(revision) = &self.revision {
+            write!(f, "-{revision}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for DebianVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.epoch()
+            .cmp(&other.epoch())
+            .then_with(|| compare_part(&self.upstream, &other.upstream))
+            .then_with(|| {
+                compare_part(
+                    self.revision().unwrap_or(""),
+                    other.revision().unwrap_or(""),
+                )
+            })
+    }
+}
+
+impl PartialOrd for DebianVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Equal by dpkg's rules, so `1.0` equals `0:1.0` and `1.00`
+impl PartialEq for DebianVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for DebianVersion {}
+
+/// Serialized as the version string, like `1:2.0-1`
+#[cfg(feature = "serde")]
+impl serde::Serialize for DebianVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DebianVersion {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        version.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Policy allows alphanumerics and a few punctuation characters, which differ between the
+/// upstream version and the revision. `offset` is where `part` starts in the whole version.
+fn check_characters(
+    part: &str,
+    offset: usize,
+    allowed_punctuation: impl Fn(char) -> bool,
+) -> Result<(), VersionError> {
+    match part
+        .char_indices()
+        .find(|(_, c)| !c.is_ascii_alphanumeric() && !allowed_punctuation(*c))
+    {
+        Some((position, character)) => Err(VersionError::InvalidCharacter {
+            character,
+            position: offset + position,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// How dpkg weighs a non-digit character: `~` before the end of the string,
+/// the end before letters, and letters before everything else
+fn char_order(c: Option<u8>) -> i32 {
+    match c {
+        None => 0,
+        Some(b'~') => -1,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(c) if c.is_ascii_alphabetic() => i32::from(c),
+        Some(c) => i32::from(c) + 256,
+    }
+}
+
+/// Port of dpkg's `verrevcmp`, which alternates comparing non-digit runs by
+/// [`char_order`] and digit runs numerically
+fn compare_part(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    let non_digit = |s: &[u8]| s.first().is_some_and(|c| !c.is_ascii_digit());
+    while !a.is_empty() || !b.is_empty() {
+        while non_digit(a) || non_digit(b) {
+            let (ac, bc) = (
+                char_order(a.first().copied()),
+                char_order(b.first().copied()),
+            );
+            if ac != bc {
+                return ac.cmp(&bc);
+            }
+            a = a.get(1..).unwrap_or_default();
+            b = b.get(1..).unwrap_or_default();
+        }
+
+        let trim_zeros = |s: &[u8]| -> usize { s.iter().take_while(|c| **c == b'0').count() };
+        a = &a[trim_zeros(a)..];
+        b = &b[trim_zeros(b)..];
+        let digits = |s: &[u8]| -> usize { s.iter().take_while(|c| c.is_ascii_digit()).count() };
+        let (a_len, b_len) = (digits(a), digits(b));
+        // without leading zeros, a longer run of digits is a bigger number
+        let ordering = a_len.cmp(&b_len).then_with(|| a[..a_len].cmp(&b[..b_len]));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        a = &a[a_len..];
+        b = &b[b_len..];
+    }
+    Ordering::Equal
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum VersionError {
+    #[error("epoch must be a number")]
+    InvalidEpoch,
+    #[error("upstream version is empty")]
+    EmptyUpstream,
+    #[error("revision is empty")]
+    EmptyRevision,
+    #[error("invalid character `{character}` at position {position}")]
+    InvalidCharacter { character: char, position: usize },
+}
+
+impl VersionError {
+    /// A stable identifier for this kind of error, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidEpoch => "GV1201",
+            Self::EmptyUpstream => "GV1202",
+            Self::EmptyRevision => "GV1203",
+            Self::InvalidCharacter { .. } => "GV1204",
+        }
+    }
+}
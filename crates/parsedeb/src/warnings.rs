@@ -0,0 +1,128 @@
+//! Things about a control file worth pointing out, but not worth rejecting a package over
+
+use std::fmt::Display;
+
+use indexmap::IndexMap;
+
+use crate::{
+    Error, FieldChecks, ParseOptions, field_value_warnings, fields::known_field, get_control_with,
+};
+
+/// Policy asks for synopses under 80 characters, so they fit on a line in package managers
+const MAX_SYNOPSIS_LEN: usize = 80;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Warning {
+    /// a field dpkg and apt don't use, and that isn't marked user-defined with `X-`
+    UnknownField(Box<str>),
+    /// a known field, capitalized differently from usual
+    Capitalization {
+        found: Box<str>,
+        expected: &'static str,
+    },
+    /// the first line of Description, in characters
+    LongSynopsis(usize),
+    /// a Maintainer that isn't a single `Name <address>`
+    InvalidMaintainer(Box<str>),
+    UnknownPriority(Box<str>),
+    UnknownSection(Box<str>),
+    UnknownMultiArch(Box<str>),
+    /// `Multi-Arch: same` on an `Architecture: all` package, which dpkg refuses to install
+    MultiArchSameForAll,
+}
+
+impl Warning {
+    /// A stable identifier for this kind of warning, see ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnknownField(_) => "GV1401",
+            Self::Capitalization { .. } => "GV1402",
+            Self::LongSynopsis(_) => "GV1403",
+            Self::InvalidMaintainer(_) => "GV1404",
+            Self::UnknownPriority(_) => "GV1405",
+            Self::UnknownSection(_) => "GV1406",
+            Self::UnknownMultiArch(_) => "GV1407",
+            Self::MultiArchSameForAll => "GV1408",
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownField(field) => write!(f, "unknown field `{field}`"),
+            Self::Capitalization { found, expected } => {
+                write!(f, "field `{found}` is usually written `{expected}`")
+            }
+            Self::LongSynopsis(length) => write!(
+                f,
+                "Description synopsis is {length} characters, over {MAX_SYNOPSIS_LEN}"
+            ),
+            Self::InvalidMaintainer(maintainer) => {
+                write!(
+                    f,
+                    "Maintainer `{maintainer}` is not a name and email address"
+                )
+            }
+            Self::UnknownPriority(priority) => write!(f, "unknown Priority `{priority}`"),
+            Self::UnknownSection(section) => write!(f, "unknown Section `{section}`"),
+            Self::UnknownMultiArch(value) => write!(
+                f,
+                "Multi-Arch must be `same`, `foreign`, `allowed` or `no`, not `{value}`"
+            ),
+            Self::MultiArchSameForAll => {
+                write!(
+                    f,
+                    "`Architecture: all` packages can't be `Multi-Arch: same`"
+                )
+            }
+        }
+    }
+}
+
+/// Everything worth warning about in a parsed control file, in field order
+pub fn control_warnings<K: AsRef<str>, V: AsRef<str>>(fields: &IndexMap<K, V>) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for (key, value) in fields {
+        let key = key.as_ref();
+        match known_field(key) {
+            Some(expected) if expected != key => warnings.push(Warning::Capitalization {
+                found: key.into(),
+                expected,
+            }),
+            Some(_) => {}
+            None if key.starts_with("X-") => {}
+            None => warnings.push(Warning::UnknownField(key.into())),
+        }
+        if key.eq_ignore_ascii_case("Description") {
+            let synopsis = value.as_ref().lines().next().unwrap_or_default().trim();
+            let length = synopsis.chars().count();
+            if length > MAX_SYNOPSIS_LEN {
+                warnings.push(Warning::LongSynopsis(length));
+            }
+        }
+    }
+    warnings
+}
+
+/// [`control_warnings`], plus the [`field_value_warnings`] if `options` asks for them
+pub fn control_warnings_with<K: AsRef<str>, V: AsRef<str>>(
+    fields: &IndexMap<K, V>,
+    options: &ParseOptions,
+) -> Vec<Warning> {
+    let mut warnings = control_warnings(fields);
+    if options.field_checks == FieldChecks::Warn {
+        warnings.extend(field_value_warnings(fields));
+    }
+    warnings
+}
+
+/// [`get_control_with`], along with the [`control_warnings_with`] for what it parsed
+pub fn get_control_with_warnings<'a>(
+    control: &'a str,
+    options: &ParseOptions,
+) -> Result<(IndexMap<&'a str, &'a str>, Vec<Warning>), Error> {
+    let fields = get_control_with(control, options)?;
+    let warnings = control_warnings_with(&fields, options);
+    Ok((fields, warnings))
+}